@@ -1,20 +1,64 @@
-use std::fs;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
 use serde::Serialize;
 
 use axiom_kernel::adapters::iceberg::IcebergMetadata;
 use axiom_kernel::invariants::InvariantEngine;
-use axiom_kernel::log::{InMemoryLogStore, MetadataLog, TableEvent};
+use axiom_kernel::log::chain::ChainVerifier;
+use axiom_kernel::log::transfer::{export_ndjson, import_ndjson, migrate, TransferSummary};
+use axiom_kernel::log::{
+    ActorId, EngineKind, EventEncoding, EventFilter, EventType, FileLogStore, InMemoryLogStore,
+    LocalObjectStore, LogError, MetadataLog, MetadataLogStore, ObjectLogStore, SegmentConfig,
+    SegmentedLogStore, SqliteLogStore, TableEvent, TableId, TrustStore, Version,
+};
 use axiom_kernel::simulate::{simulate_table, SimulationResult};
+use axiom_kernel::state::lifecycle::Lifecycle;
 use axiom_kernel::state::policy_config::PolicyConfig;
 
 /// Axiom Control Plane CLI
 #[derive(Parser, Debug)]
 #[command(name = "axiom")]
 #[command(about = "Axiom data control plane (dry-run)", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// `axiom --log ... --iceberg ...` predates subcommands and still
+    /// runs `simulate`.
+    #[command(flatten)]
+    simulate: Option<SimulateArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replay a metadata log and compare it against Iceberg metadata
+    Simulate(SimulateArgs),
+
+    /// Check the hash chain integrity of a metadata log
+    Verify(VerifyArgs),
+
+    /// List events of a metadata log matching envelope criteria
+    Events(EventsArgs),
+
+    /// Export a metadata log as NDJSON, one event per line
+    Export(ExportArgs),
+
+    /// Import NDJSON events into a metadata log, validating every line
+    Import(ImportArgs),
+
+    /// Copy a metadata log from one storage backend to another
+    Migrate(MigrateArgs),
+}
+
+#[derive(Args, Debug)]
+struct SimulateArgs {
     /// Path to policy config JSON
     #[arg(long)]
     policy: Option<String>,
@@ -26,36 +70,219 @@ struct Cli {
     /// Path to Iceberg metadata JSON
     #[arg(long)]
     iceberg: String,
+
+    /// Path to trust store JSON; rejects unsigned or badly signed events
+    #[arg(long)]
+    trust: Option<String>,
+
+    /// Path to table lifecycle JSON; defaults to the built-in lifecycle
+    #[arg(long)]
+    lifecycle: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// Path to metadata log JSON (with stored hashes)
+    #[arg(long)]
+    log: String,
+
+    /// Path to trust store JSON; also checks event signatures
+    #[arg(long)]
+    trust: Option<String>,
 }
 
+#[derive(Args, Debug)]
+struct EventsArgs {
+    /// Path to metadata log JSON
+    #[arg(long)]
+    log: String,
+
+    /// Only events of this table (UUID)
+    #[arg(long)]
+    table: Option<TableId>,
+
+    /// Event type, e.g. SnapshotAdded
+    #[arg(long, value_parser = parse_event_type)]
+    event_type: Option<EventType>,
+
+    #[arg(long)]
+    actor: Option<String>,
+
+    /// Engine name, e.g. spark, flink or trino
+    #[arg(long)]
+    engine: Option<String>,
+
+    #[arg(long)]
+    job_id: Option<String>,
+
+    #[arg(long)]
+    run_id: Option<String>,
+
+    #[arg(long)]
+    correlation_id: Option<String>,
+
+    /// Required tag as KEY=VALUE (repeatable)
+    #[arg(long = "tag", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+
+    /// Committed at or after this Unix time in milliseconds
+    #[arg(long)]
+    since: Option<u64>,
+
+    /// Committed at or before this Unix time in milliseconds
+    #[arg(long)]
+    until: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+struct ExportArgs {
+    /// Log to export, as BACKEND:PATH (see `migrate --help`)
+    #[arg(long, value_parser = parse_store_spec)]
+    from: StoreSpec,
+
+    /// Only events of this table (UUID)
+    #[arg(long)]
+    table: Option<TableId>,
+
+    /// Output file; defaults to stdout
+    #[arg(long)]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ImportArgs {
+    /// NDJSON file to import, or `-` for stdin
+    #[arg(long)]
+    input: String,
+
+    /// Log to import into, as BACKEND:PATH (see `migrate --help`)
+    #[arg(long, value_parser = parse_store_spec)]
+    to: StoreSpec,
+
+    /// Only accept events of this table (UUID)
+    #[arg(long)]
+    table: Option<TableId>,
+
+    /// Encoding of events written to the target: json or binary
+    #[arg(long, default_value = "json")]
+    encoding: EventEncoding,
+
+    /// Path to trust store JSON; rejects unsigned or badly signed events
+    #[arg(long)]
+    trust: Option<String>,
+}
+
+/// Logs are given as BACKEND:PATH, where BACKEND is one of `json` (a
+/// JSON array of events, read-only), `file`, `segmented`, `sqlite` or
+/// `object` (a local object store root).
+#[derive(Args, Debug)]
+struct MigrateArgs {
+    /// Source log, as BACKEND:PATH
+    #[arg(long, value_parser = parse_store_spec)]
+    from: StoreSpec,
+
+    /// Target log, as BACKEND:PATH
+    #[arg(long, value_parser = parse_store_spec)]
+    to: StoreSpec,
+
+    /// Encoding of events written to the target: json or binary
+    #[arg(long, default_value = "json")]
+    encoding: EventEncoding,
+
+    /// Path to trust store JSON; rejects unsigned or badly signed events
+    #[arg(long)]
+    trust: Option<String>,
+}
+
+/// Storage backend of a log given on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Json,
+    File,
+    Segmented,
+    Sqlite,
+    Object,
+}
+
+#[derive(Debug, Clone)]
+struct StoreSpec {
+    backend: Backend,
+    path: PathBuf,
+}
+
+type DynLog = MetadataLog<Box<dyn MetadataLogStore>>;
+
 /// Wrapper for JSON output
 #[derive(Debug, Serialize)]
 struct CliOutput {
     expected_state: String,
+    expected_snapshot_id: Option<i64>,
+    expected_schema_id: Option<i32>,
     drift_report: serde_json::Value,
     decision_plan: serde_json::Value,
 }
 
-fn main() -> Result<()> {
+/// Chain integrity of a single table.
+#[derive(Debug, Serialize)]
+struct TableIntegrity {
+    table_id: TableId,
+    events: usize,
+    head_version: Version,
+    intact: bool,
+    first_tampered_version: Option<Version>,
+    first_rejected_signature: Option<Version>,
+    errors: Vec<EventError>,
+}
+
+/// A problem found with one event.
+#[derive(Debug, Serialize)]
+struct EventError {
+    version: Version,
+    error: String,
+}
+
+/// Output of `axiom verify`.
+#[derive(Debug, Serialize)]
+struct VerifyOutput {
+    intact: bool,
+    tables: Vec<TableIntegrity>,
+}
+
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
+    match (cli.command, cli.simulate) {
+        (Some(Command::Simulate(args)), _) | (None, Some(args)) => simulate(args),
+        (Some(Command::Verify(args)), _) => verify(args),
+        (Some(Command::Events(args)), _) => events(args),
+        (Some(Command::Export(args)), _) => export(args),
+        (Some(Command::Import(args)), _) => import(args),
+        (Some(Command::Migrate(args)), _) => migrate_log(args),
+        (None, None) => unreachable!("clap requires a subcommand or simulate's arguments"),
+    }
+}
 
+fn simulate(args: SimulateArgs) -> Result<ExitCode> {
     // ----------------------------
     // Load metadata log
     // ----------------------------
-    let log_data = fs::read_to_string(&cli.log)?;
+    let log_data = fs::read_to_string(&args.log)?;
     let events: Vec<TableEvent> = serde_json::from_str(&log_data)?;
 
     let store = InMemoryLogStore::default();
     let mut log = MetadataLog::new(store);
 
+    if let Some(path) = &args.trust {
+        log = log.with_trust_store(load_trust_store(path)?);
+    }
+
     for event in events {
-        log.append(event)?;
+        log.import(event)?;
     }
 
     // ----------------------------
     // Load Policy
     // ----------------------------
-    let policy = if let Some(path) = cli.policy {
+    let policy = if let Some(path) = args.policy {
         let data = fs::read_to_string(path)?;
         serde_json::from_str::<PolicyConfig>(&data)?
     } else {
@@ -65,34 +292,287 @@ fn main() -> Result<()> {
     // ----------------------------
     // Load Iceberg metadata
     // ----------------------------
-    let iceberg_data = fs::read_to_string(&cli.iceberg)?;
+    let iceberg_data = fs::read_to_string(&args.iceberg)?;
     let iceberg_meta: IcebergMetadata = serde_json::from_str(&iceberg_data)?;
     let iceberg_state = iceberg_meta.into_table_state();
+    let table_id = TableId(iceberg_state.table_uuid);
 
     // ----------------------------
-    // Invariants (empty for now)
+    // Invariants (empty for now) and lifecycle
     // ----------------------------
-    let invariants = InvariantEngine::new();
+    let mut invariants = InvariantEngine::new();
+    if let Some(path) = &args.lifecycle {
+        let data = fs::read_to_string(path)?;
+        let lifecycle =
+            Lifecycle::from_json(&data).with_context(|| format!("invalid lifecycle {path}"))?;
+        invariants.set_lifecycle(lifecycle)?;
+    }
 
     // ----------------------------
     // Run simulation
     // ----------------------------
     let SimulationResult {
-        expected_state,
+        expected,
         drift_report,
         decision_plan,
-    } = simulate_table(&log, &invariants, &iceberg_state, &policy)?;
+    } = simulate_table(&log, &table_id, &invariants, &iceberg_state, &policy)?;
 
     // ----------------------------
     // Output
     // ----------------------------
     let output = CliOutput {
-        expected_state: format!("{expected_state:?}"),
+        expected_state: format!("{:?}", expected.state),
+        expected_snapshot_id: expected.current_snapshot_id(),
+        expected_schema_id: expected.schema_id(),
         drift_report: serde_json::to_value(&drift_report)?,
         decision_plan: serde_json::to_value(&decision_plan)?,
     };
 
     println!("{}", serde_json::to_string_pretty(&output)?);
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(args: VerifyArgs) -> Result<ExitCode> {
+    let log_data = fs::read_to_string(&args.log)?;
+    let events: Vec<TableEvent> = serde_json::from_str(&log_data)?;
+    let trust = args.trust.as_deref().map(load_trust_store).transpose()?;
+
+    // Events are checked in file order, per table.
+    let mut by_table: BTreeMap<TableId, Vec<TableEvent>> = BTreeMap::new();
+    for event in events {
+        by_table.entry(event.table_id.clone()).or_default().push(event);
+    }
+
+    let tables: Vec<_> = by_table
+        .into_iter()
+        .map(|(table_id, events)| {
+            let mut verifier = ChainVerifier::new();
+            let mut chain_broken = false;
+            let mut first_tampered_version = None;
+            let mut first_rejected_signature = None;
+            let mut errors = Vec::new();
+
+            let mut previous = 0;
+            for event in &events {
+                let version = event.version;
+                if version != previous + 1 {
+                    errors.push(EventError {
+                        version,
+                        error: format!("expected version {}", previous + 1),
+                    });
+                }
+                previous = version;
+
+                // Later hashes chain onto the broken one, so only the
+                // first chain error is reported.
+                if !chain_broken {
+                    if let Err(e) = verifier.verify(event) {
+                        chain_broken = true;
+                        if let LogError::Tampered { .. } = e {
+                            first_tampered_version = Some(version);
+                        }
+                        errors.push(EventError {
+                            version,
+                            error: e.to_string(),
+                        });
+                    }
+                }
+
+                if let Some(Err(e)) = trust.as_ref().map(|trust| trust.verify(event)) {
+                    first_rejected_signature.get_or_insert(version);
+                    errors.push(EventError {
+                        version,
+                        error: e.to_string(),
+                    });
+                }
+            }
+
+            TableIntegrity {
+                table_id,
+                events: events.len(),
+                head_version: events.last().map_or(0, |e| e.version),
+                intact: errors.is_empty(),
+                first_tampered_version,
+                first_rejected_signature,
+                errors,
+            }
+        })
+        .collect();
+
+    let output = VerifyOutput {
+        intact: tables.iter().all(|t| t.intact),
+        tables,
+    };
+
+    println!("{}", serde_json::to_string_pretty(&output)?);
+
+    Ok(if output.intact {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn events(args: EventsArgs) -> Result<ExitCode> {
+    let log_data = fs::read_to_string(&args.log)?;
+    let events: Vec<TableEvent> = serde_json::from_str(&log_data)?;
+
+    let filter = EventFilter {
+        table_id: args.table,
+        event_type: args.event_type,
+        actor: args.actor.map(ActorId),
+        engine: args.engine.map(EngineKind::from),
+        job_id: args.job_id,
+        run_id: args.run_id,
+        correlation_id: args.correlation_id,
+        tags: args.tags.into_iter().collect(),
+        committed_after: args.since,
+        committed_before: args.until,
+    };
+
+    let matched: Vec<_> = events.into_iter().filter(|e| filter.matches(e)).collect();
+    println!("{}", serde_json::to_string_pretty(&matched)?);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn export(args: ExportArgs) -> Result<ExitCode> {
+    let log = open_log(&args.from, None, EventEncoding::Json)?;
+
+    let summary = match &args.output {
+        Some(path) => {
+            let out = BufWriter::new(File::create(path)?);
+            export_ndjson(&log, args.table.as_ref(), out)?
+        }
+        None => export_ndjson(&log, args.table.as_ref(), io::stdout().lock())?,
+    };
+
+    // stdout may be carrying the events.
+    eprintln!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn import(args: ImportArgs) -> Result<ExitCode> {
+    let trust = args.trust.as_deref().map(load_trust_store).transpose()?;
+    let mut log = open_writable_log(&args.to, trust, args.encoding)?;
+
+    let input: Box<dyn BufRead> = if args.input == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.input)?))
+    };
+
+    let summary = import_ndjson(&mut log, args.table.as_ref(), input)
+        .with_context(|| format!("failed to import {}", args.input))?;
+    print_summary(&summary)
+}
+
+fn migrate_log(args: MigrateArgs) -> Result<ExitCode> {
+    let trust = args.trust.as_deref().map(load_trust_store).transpose()?;
+    let source = open_log(&args.from, trust.clone(), EventEncoding::Json)?;
+    let mut target = open_writable_log(&args.to, trust, args.encoding)?;
+
+    let summary = migrate(&source, &mut target)?;
+    print_summary(&summary)
+}
+
+fn print_summary(summary: &TransferSummary) -> Result<ExitCode> {
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, summary)?;
+    writeln!(out)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Open a log; `encoding` applies to events appended to it. JSON arrays
+/// are loaded into memory.
+fn open_log(
+    spec: &StoreSpec,
+    trust: Option<TrustStore>,
+    encoding: EventEncoding,
+) -> Result<DynLog> {
+    let store: Box<dyn MetadataLogStore> = match spec.backend {
+        Backend::Json => Box::new(InMemoryLogStore::default()),
+        Backend::File => Box::new(FileLogStore::open(&spec.path)?.with_encoding(encoding)),
+        Backend::Segmented => Box::new(SegmentedLogStore::open_with_config(
+            &spec.path,
+            SegmentConfig {
+                encoding,
+                ..SegmentConfig::default()
+            },
+        )?),
+        Backend::Sqlite => Box::new(SqliteLogStore::open(&spec.path)?.with_encoding(encoding)),
+        Backend::Object => Box::new(ObjectLogStore::open(LocalObjectStore::new(&spec.path)?)?),
+    };
+
+    let mut log = MetadataLog::new(store);
+    if let Some(trust) = trust {
+        log = log.with_trust_store(trust);
+    }
+
+    if spec.backend == Backend::Json {
+        let data = fs::read_to_string(&spec.path)?;
+        let events: Vec<TableEvent> = serde_json::from_str(&data)?;
+        for event in events {
+            log.import(event)?;
+        }
+    }
+
+    Ok(log)
+}
+
+fn open_writable_log(
+    spec: &StoreSpec,
+    trust: Option<TrustStore>,
+    encoding: EventEncoding,
+) -> Result<DynLog> {
+    if spec.backend == Backend::Json {
+        bail!("json logs are read-only; use file, segmented, sqlite or object");
+    }
+    if spec.backend == Backend::Object && encoding != EventEncoding::Json {
+        bail!("object logs are always stored as json");
+    }
+    open_log(spec, trust, encoding)
+}
+
+fn parse_store_spec(s: &str) -> Result<StoreSpec, String> {
+    let (backend, path) = s
+        .split_once(':')
+        .filter(|(_, path)| !path.is_empty())
+        .ok_or_else(|| format!("expected BACKEND:PATH, got `{s}`"))?;
+
+    let backend = match backend {
+        "json" => Backend::Json,
+        "file" => Backend::File,
+        "segmented" => Backend::Segmented,
+        "sqlite" => Backend::Sqlite,
+        "object" => Backend::Object,
+        _ => {
+            return Err(format!(
+                "unknown backend `{backend}`; expected json, file, segmented, sqlite or object"
+            ))
+        }
+    };
+
+    Ok(StoreSpec {
+        backend,
+        path: PathBuf::from(path),
+    })
+}
+
+fn parse_event_type(s: &str) -> Result<EventType, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_string()))
+        .map_err(|_| format!("unknown event type `{s}`"))
+}
+
+fn parse_tag(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got `{s}`")),
+    }
+}
+
+fn load_trust_store(path: &str) -> Result<TrustStore> {
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
 }
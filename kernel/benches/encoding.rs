@@ -0,0 +1,111 @@
+// Event Encoding Benchmark
+//
+// Compares the JSON and binary event encodings on a hash-chained log of
+// one million events (override with AXIOM_BENCH_EVENTS): encoded size,
+// and encode/decode throughput.
+//
+// Half of the events carry a typed snapshot payload, the other half a
+// 128-byte opaque payload, all with a typical provenance envelope and a
+// fixed placeholder signature of real signature length.
+//
+//   cargo bench -p axiom-kernel --bench encoding
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use axiom_kernel::log::chain::event_hash;
+use axiom_kernel::log::codec;
+use axiom_kernel::log::payload::{SnapshotAddedPayload, SnapshotOperation};
+use axiom_kernel::log::{
+    ActorId, EngineKind, EventEncoding, EventEnvelope, EventType, TableEvent, TableId, TypedPayload,
+};
+use uuid::Uuid;
+
+const DEFAULT_EVENTS: u64 = 1_000_000;
+
+fn main() {
+    let count = std::env::var("AXIOM_BENCH_EVENTS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_EVENTS);
+
+    let events = generate(count);
+    println!("{count} events");
+    println!(
+        "{:<8} {:>12} {:>10} {:>14} {:>14}",
+        "encoding", "total MiB", "B/event", "encode ev/s", "decode ev/s"
+    );
+
+    for encoding in [EventEncoding::Json, EventEncoding::Binary] {
+        let start = Instant::now();
+        let encoded: Vec<Vec<u8>> = events
+            .iter()
+            .map(|event| encoding.encode(event).unwrap())
+            .collect();
+        let encode_time = start.elapsed();
+
+        let start = Instant::now();
+        for bytes in &encoded {
+            black_box(codec::decode(bytes).unwrap());
+        }
+        let decode_time = start.elapsed();
+
+        let total: usize = encoded.iter().map(Vec::len).sum();
+        println!(
+            "{:<8} {:>12.1} {:>10} {:>14.0} {:>14.0}",
+            encoding.name(),
+            total as f64 / (1024.0 * 1024.0),
+            total as u64 / count.max(1),
+            rate(count, encode_time),
+            rate(count, decode_time),
+        );
+    }
+}
+
+/// A sealed chain of `count` events of one table.
+fn generate(count: u64) -> Vec<TableEvent> {
+    let table_id = TableId(Uuid::from_u128(1));
+    let mut prev_hash: Option<String> = None;
+
+    (1..=count)
+        .map(|version| {
+            let mut event = if version % 2 == 0 {
+                TableEvent::new(
+                    table_id.clone(),
+                    version,
+                    EventType::SnapshotAdded,
+                    TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                        snapshot_id: version as i64,
+                        parent_snapshot_id: Some(version as i64 - 2),
+                        operation: SnapshotOperation::Append,
+                        schema_id: Some(1),
+                        mutation_id: None,
+                        branch: None,
+                    }),
+                )
+            } else {
+                let payload: Vec<u8> = (0..128).map(|i| (version as u8).wrapping_add(i)).collect();
+                TableEvent::new(table_id.clone(), version, EventType::SnapshotAdded, payload)
+            };
+
+            event.envelope = EventEnvelope {
+                committed_at: Some(1_700_000_000_000 + version),
+                actor: Some(ActorId("spark-etl".into())),
+                engine: Some(EngineKind::Spark),
+                job_id: Some(format!("job-{}", version / 100)),
+                ..Default::default()
+            };
+            // Signature bytes do not affect encoding cost; skip the
+            // Ed25519 work and use a fixed well-formed value.
+            event.signature = Some("5a".repeat(64));
+            let hash = event_hash(prev_hash.as_deref(), &event).unwrap();
+            event.hash = Some(hash.clone());
+            prev_hash = Some(hash);
+            event
+        })
+        .collect()
+}
+
+fn rate(count: u64, elapsed: Duration) -> f64 {
+    count as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
@@ -4,8 +4,12 @@
 // table state transitions. Violations are detected *before*
 // data corruption occurs.
 
+use sha2::{Digest, Sha256};
+
 use crate::log::TableEvent;
-use crate::state::TableState;
+use crate::state::lifecycle::{Lifecycle, LifecycleError};
+use crate::state::model::TableModel;
+use crate::state::{TableState, SEMANTICS_VERSION};
 
 /// Result of invariant evaluation.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -29,20 +33,38 @@ pub trait Invariant: Send + Sync {
         event: &TableEvent,
         next_state: &TableState,
     ) -> InvariantResult;
+
+    /// Validate a transition against the derived table model.
+    ///
+    /// `next` reflects the event: a change inside a mutation is still in
+    /// `next.pending`, while the rest of the model holds the content
+    /// before it. Defaults to `validate` on the states alone.
+    fn validate_model(
+        &self,
+        previous_state: &TableState,
+        event: &TableEvent,
+        next: &TableModel,
+    ) -> InvariantResult {
+        self.validate(previous_state, event, &next.state)
+    }
 }
 
-/// Invariant engine that evaluates a set of invariants.
+/// Invariant engine that evaluates a set of invariants, and the
+/// lifecycle whose transitions they guard.
 #[derive(Default)]
 pub struct InvariantEngine {
     invariants: Vec<Box<dyn Invariant>>,
+
+    /// Invariants only evaluated on the lifecycle transitions naming them.
+    guards: Vec<Box<dyn Invariant>>,
+
+    lifecycle: Lifecycle,
 }
 
 impl InvariantEngine {
-    /// Create a new invariant engine.
+    /// Create a new invariant engine for the built-in lifecycle.
     pub fn new() -> Self {
-        Self {
-            invariants: Vec::new(),
-        }
+        Self::default()
     }
 
     /// Register an invariant.
@@ -50,6 +72,67 @@ impl InvariantEngine {
         self.invariants.push(Box::new(invariant));
     }
 
+    /// Register an invariant that lifecycle transitions can name as a
+    /// guard.
+    pub fn register_guard<I: Invariant + 'static>(&mut self, guard: I) {
+        self.guards.push(Box::new(guard));
+    }
+
+    /// Enforce `lifecycle` instead of the built-in one. Its guards must
+    /// already be registered.
+    pub fn set_lifecycle(&mut self, lifecycle: Lifecycle) -> Result<(), LifecycleError> {
+        if let Some(guard) = lifecycle
+            .guards()
+            .find(|name| !self.guards.iter().any(|g| g.name() == *name))
+        {
+            return Err(LifecycleError::UnknownGuard {
+                lifecycle: lifecycle.name().into(),
+                guard: guard.into(),
+            });
+        }
+        self.lifecycle = lifecycle;
+        Ok(())
+    }
+
+    /// Lifecycle replay applies events under.
+    pub fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    /// Stable fingerprint of the registered invariant set.
+    ///
+    /// Derived state (e.g. checkpoints) is only reusable under the same
+    /// set of invariants, guards and lifecycle, and the same rules for
+    /// applying events. Registration order does not matter. The built-in
+    /// lifecycle contributes through `SEMANTICS_VERSION`, so state derived
+    /// before its rules changed is derived again.
+    pub fn fingerprint(&self) -> String {
+        let mut names: Vec<_> = self
+            .invariants
+            .iter()
+            .map(|i| i.name().to_string())
+            .chain(self.guards.iter().map(|g| format!("guard:{}", g.name())))
+            .chain([format!("semantics:{SEMANTICS_VERSION}")])
+            .collect();
+        if &self.lifecycle != Lifecycle::builtin() {
+            let definition = serde_json::to_vec(&self.lifecycle).unwrap_or_default();
+            names.push(format!("lifecycle:{:x}", Sha256::digest(definition)));
+        }
+        names.sort_unstable();
+
+        let mut hasher = Sha256::new();
+        for name in names {
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+        }
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
     /// Evaluate all invariants.
     ///
     /// Stops at the first failure.
@@ -57,10 +140,10 @@ impl InvariantEngine {
         &self,
         previous_state: &TableState,
         event: &TableEvent,
-        next_state: &TableState,
+        next: &TableModel,
     ) -> Result<(), InvariantViolation> {
         for invariant in &self.invariants {
-            match invariant.validate(previous_state, event, next_state) {
+            match invariant.validate_model(previous_state, event, next) {
                 InvariantResult::Pass => continue,
                 InvariantResult::Fail(reason) => {
                     return Err(InvariantViolation {
@@ -72,6 +155,30 @@ impl InvariantEngine {
         }
         Ok(())
     }
+
+    /// Evaluate the guards a transition names.
+    pub fn evaluate_guards(
+        &self,
+        guards: &[String],
+        previous_state: &TableState,
+        event: &TableEvent,
+        next: &TableModel,
+    ) -> Result<(), InvariantViolation> {
+        let named = self
+            .guards
+            .iter()
+            .filter(|g| guards.iter().any(|name| name == g.name()));
+        for guard in named {
+            if let InvariantResult::Fail(reason) = guard.validate_model(previous_state, event, next)
+            {
+                return Err(InvariantViolation {
+                    invariant: guard.name(),
+                    reason,
+                });
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Returned when an invariant is violated.
@@ -111,12 +218,7 @@ mod tests {
     }
 
     fn event(event_type: EventType) -> TableEvent {
-        TableEvent {
-            table_id: TableId(Uuid::new_v4()),
-            version: 1,
-            event_type,
-            payload: vec![],
-        }
+        TableEvent::new(TableId(Uuid::new_v4()), 1, event_type, vec![])
     }
 
     #[test]
@@ -125,7 +227,10 @@ mod tests {
         engine.register(NoMutationFromCreated);
 
         let previous = TableState::Created;
-        let next = TableState::Mutating;
+        let next = TableModel {
+            state: TableState::Mutating,
+            ..TableModel::default()
+        };
 
         let err = engine
             .evaluate(&previous, &event(EventType::SchemaUpdated), &next)
@@ -133,4 +238,93 @@ mod tests {
 
         assert!(err.to_string().contains("no-mutation-from-created"));
     }
+
+    struct AlwaysPass;
+
+    impl Invariant for AlwaysPass {
+        fn name(&self) -> &'static str {
+            "always-pass"
+        }
+
+        fn validate(&self, _: &TableState, _: &TableEvent, _: &TableState) -> InvariantResult {
+            InvariantResult::Pass
+        }
+    }
+
+    #[test]
+    fn fingerprint_ignores_registration_order() {
+        let mut a = InvariantEngine::new();
+        a.register(NoMutationFromCreated);
+        a.register(AlwaysPass);
+
+        let mut b = InvariantEngine::new();
+        b.register(AlwaysPass);
+        b.register(NoMutationFromCreated);
+
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), InvariantEngine::new().fingerprint());
+    }
+
+    #[test]
+    fn fingerprint_covers_the_builtin_semantics() {
+        // What an empty set fingerprinted to before the semantics were
+        // versioned: the hash of no names at all.
+        let unversioned = format!("{:x}", Sha256::digest(b""));
+        assert_ne!(InvariantEngine::new().fingerprint(), unversioned);
+    }
+
+    struct NeverFreeze;
+
+    impl Invariant for NeverFreeze {
+        fn name(&self) -> &'static str {
+            "never-freeze"
+        }
+
+        fn validate(&self, _: &TableState, _: &TableEvent, _: &TableState) -> InvariantResult {
+            InvariantResult::Fail("tables of this class stay writable".into())
+        }
+    }
+
+    #[test]
+    fn guards_only_apply_to_transitions_naming_them() {
+        let mut definition = Lifecycle::builtin().definition().clone();
+        definition
+            .transitions
+            .iter_mut()
+            .find(|t| t.event == EventType::TableFrozen)
+            .unwrap()
+            .guards
+            .push("never-freeze".into());
+        let lifecycle = Lifecycle::try_from(definition).unwrap();
+
+        let mut engine = InvariantEngine::new();
+        assert!(matches!(
+            engine.set_lifecycle(lifecycle.clone()),
+            Err(LifecycleError::UnknownGuard { .. })
+        ));
+
+        engine.register_guard(NeverFreeze);
+        let without_lifecycle = engine.fingerprint();
+        engine.set_lifecycle(lifecycle).unwrap();
+        assert_ne!(engine.fingerprint(), without_lifecycle);
+
+        let next = TableModel::default();
+        let frozen = event(EventType::TableFrozen);
+        engine
+            .evaluate(&TableState::Active, &frozen, &next)
+            .unwrap();
+        engine
+            .evaluate_guards(&[], &TableState::Active, &frozen, &next)
+            .unwrap();
+
+        let guards = &engine
+            .lifecycle()
+            .transition(&TableState::Active, &EventType::TableFrozen)
+            .unwrap()
+            .guards;
+        let err = engine
+            .evaluate_guards(guards, &TableState::Active, &frozen, &next)
+            .unwrap_err();
+        assert_eq!(err.invariant, "never-freeze");
+    }
 }
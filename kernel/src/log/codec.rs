@@ -0,0 +1,456 @@
+// Event Encodings
+//
+// Stores persist events either as JSON (the interchange format, see
+// `upcast`) or in a compact binary format. JSON is the default: it is
+// readable with standard tools and carries opaque payloads as arrays of
+// integers. The binary format stores payload bytes as they are and is
+// much faster to decode.
+//
+// Binary layout (all integers big-endian, byte strings length prefixed
+// with a u32):
+//
+//   0xae | format (u8) | content length (u32) | canonical content
+//   | [0x10 | signature (64 bytes)] | [0x11 | signature text]
+//   | [0x12 | committed_at (u64)]
+//   | [0x13 | hash (32 bytes)] | [0x14 | hash text]
+//   | [0x15 if the event is a legacy change]
+//
+// The canonical content is exactly `chain::canonical_bytes`, the bytes
+// that signatures cover, so it can be hashed or verified without
+// re-encoding the event. Fields outside it follow with one-byte tags in
+// ascending order. Signatures and hashes are stored as raw bytes when
+// they are lowercase hex of the expected length, and as text otherwise,
+// so every event decodes exactly as it was encoded.
+//
+// 0xae never starts a JSON document, so readers tell the two encodings
+// apart per record and a store may hold both.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::chain::{
+    canonical_bytes, from_hex, put_bytes, to_hex, TAG_ACTOR, TAG_CORRELATION_ID, TAG_ENGINE,
+    TAG_IDEMPOTENCY_KEY, TAG_JOB_ID, TAG_JSON_PAYLOAD, TAG_RUN_ID, TAG_TAGS,
+};
+use super::record::storage;
+use super::{ActorId, EngineKind, EventEnvelope, EventPayload, LogError, TableEvent, TableId};
+
+/// First byte of every binary-encoded event.
+pub const BINARY_MAGIC: u8 = 0xae;
+
+/// Binary format version written by this version of Axiom.
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+const TAG_SIGNATURE: u8 = 0x10;
+const TAG_SIGNATURE_TEXT: u8 = 0x11;
+const TAG_COMMITTED_AT: u8 = 0x12;
+const TAG_HASH: u8 = 0x13;
+const TAG_HASH_TEXT: u8 = 0x14;
+const TAG_LEGACY: u8 = 0x15;
+
+/// Encoding a store writes events in.
+///
+/// Stores decode both encodings regardless of the one they write, so
+/// switching only affects events appended afterwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventEncoding {
+    #[default]
+    Json,
+    Binary,
+}
+
+impl EventEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventEncoding::Json => "json",
+            EventEncoding::Binary => "binary",
+        }
+    }
+
+    pub fn encode(&self, event: &TableEvent) -> Result<Vec<u8>, LogError> {
+        match self {
+            EventEncoding::Json => serde_json::to_vec(event).map_err(storage),
+            EventEncoding::Binary => encode_binary(event),
+        }
+    }
+}
+
+impl std::str::FromStr for EventEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(EventEncoding::Json),
+            "binary" => Ok(EventEncoding::Binary),
+            other => Err(format!("unknown event encoding '{other}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for EventEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Decode an event in either encoding.
+pub fn decode(bytes: &[u8]) -> Result<TableEvent, String> {
+    match bytes.first() {
+        Some(&BINARY_MAGIC) => decode_binary(bytes),
+        _ => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+    }
+}
+
+/// Encode an event in the binary format.
+pub fn encode_binary(event: &TableEvent) -> Result<Vec<u8>, LogError> {
+    let content = canonical_bytes(event)?;
+
+    let mut buf = Vec::with_capacity(6 + content.len() + 128);
+    buf.push(BINARY_MAGIC);
+    buf.push(BINARY_FORMAT_VERSION);
+    put_bytes(&mut buf, &content);
+
+    if let Some(signature) = &event.signature {
+        put_hex::<64>(&mut buf, TAG_SIGNATURE, TAG_SIGNATURE_TEXT, signature);
+    }
+    if let Some(committed_at) = event.envelope.committed_at {
+        buf.push(TAG_COMMITTED_AT);
+        buf.extend_from_slice(&committed_at.to_be_bytes());
+    }
+    if let Some(hash) = &event.hash {
+        put_hex::<32>(&mut buf, TAG_HASH, TAG_HASH_TEXT, hash);
+    }
+    if event.legacy {
+        buf.push(TAG_LEGACY);
+    }
+
+    Ok(buf)
+}
+
+/// Decode a binary-encoded event.
+pub fn decode_binary(bytes: &[u8]) -> Result<TableEvent, String> {
+    let mut reader = Reader(bytes);
+    let content = header(&mut reader)?;
+    let mut event = decode_content(content)?;
+
+    let mut last_tag = 0;
+    while !reader.is_empty() {
+        let tag = next_tag(&mut reader, &mut last_tag)?;
+        match tag {
+            TAG_SIGNATURE => set_once(&mut event.signature, to_hex(reader.take(64)?), "signature")?,
+            TAG_SIGNATURE_TEXT => set_once(&mut event.signature, reader.string()?, "signature")?,
+            TAG_COMMITTED_AT => event.envelope.committed_at = Some(reader.u64()?),
+            TAG_HASH => set_once(&mut event.hash, to_hex(reader.take(32)?), "hash")?,
+            TAG_HASH_TEXT => set_once(&mut event.hash, reader.string()?, "hash")?,
+            TAG_LEGACY => event.legacy = true,
+            _ => return Err(format!("unknown field tag {tag:#04x}")),
+        }
+    }
+
+    Ok(event)
+}
+
+/// The canonical content embedded in a binary-encoded event: the bytes
+/// its signature covers.
+pub fn canonical_content(bytes: &[u8]) -> Result<&[u8], String> {
+    header(&mut Reader(bytes))
+}
+
+/// Check the magic and format version, returning the content section.
+fn header<'a>(reader: &mut Reader<'a>) -> Result<&'a [u8], String> {
+    if reader.u8()? != BINARY_MAGIC {
+        return Err("not a binary-encoded event".into());
+    }
+
+    let format = reader.u8()?;
+    if format != BINARY_FORMAT_VERSION {
+        return Err(format!(
+            "binary event format {format} is not supported (expected {BINARY_FORMAT_VERSION})"
+        ));
+    }
+
+    reader.bytes()
+}
+
+fn decode_content(content: &[u8]) -> Result<TableEvent, String> {
+    let mut reader = Reader(content);
+
+    let table_id = TableId(Uuid::from_slice(reader.take(16)?).map_err(|e| e.to_string())?);
+    let version = reader.u64()?;
+    let event_type =
+        serde_json::from_value(Value::String(reader.string()?)).map_err(|e| e.to_string())?;
+    let payload = reader.bytes()?;
+
+    let mut envelope = EventEnvelope::default();
+    let mut json_payload = false;
+
+    let mut last_tag = 0;
+    while !reader.is_empty() {
+        let tag = next_tag(&mut reader, &mut last_tag)?;
+        match tag {
+            TAG_ACTOR => envelope.actor = Some(ActorId(reader.string()?)),
+            TAG_ENGINE => envelope.engine = Some(EngineKind::from(reader.string()?)),
+            TAG_JOB_ID => envelope.job_id = Some(reader.string()?),
+            TAG_RUN_ID => envelope.run_id = Some(reader.string()?),
+            TAG_CORRELATION_ID => envelope.correlation_id = Some(reader.string()?),
+            TAG_TAGS => {
+                for _ in 0..reader.u32()? {
+                    let key = reader.string()?;
+                    if envelope
+                        .tags
+                        .insert(key.clone(), reader.string()?)
+                        .is_some()
+                    {
+                        return Err(format!("duplicate tag '{key}'"));
+                    }
+                }
+            }
+            TAG_JSON_PAYLOAD => json_payload = true,
+            TAG_IDEMPOTENCY_KEY => envelope.idempotency_key = Some(reader.string()?),
+            _ => return Err(format!("unknown content tag {tag:#04x}")),
+        }
+    }
+
+    let payload = if json_payload {
+        decode_json_payload(payload)?
+    } else {
+        EventPayload::Opaque(payload.to_vec())
+    };
+
+    Ok(TableEvent {
+        table_id,
+        version,
+        event_type,
+        payload,
+        envelope,
+        signature: None,
+        hash: None,
+        legacy: false,
+    })
+}
+
+/// Structured payloads decode as typed when this version knows their
+/// kind and fields, like JSON-encoded ones.
+fn decode_json_payload(bytes: &[u8]) -> Result<EventPayload, String> {
+    serde_json::from_slice(bytes)
+        .map(EventPayload::structured)
+        .map_err(|e| format!("invalid JSON payload: {e}"))
+}
+
+/// Tags appear at most once and in ascending order, so an encoding has
+/// a single valid byte sequence.
+fn next_tag(reader: &mut Reader<'_>, last_tag: &mut u8) -> Result<u8, String> {
+    let tag = reader.u8()?;
+    if tag <= *last_tag {
+        return Err(format!("field tag {tag:#04x} out of order"));
+    }
+    *last_tag = tag;
+    Ok(tag)
+}
+
+fn set_once(slot: &mut Option<String>, value: String, field: &str) -> Result<(), String> {
+    if slot.is_some() {
+        return Err(format!("{field} encoded twice"));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+fn put_hex<const N: usize>(buf: &mut Vec<u8>, raw_tag: u8, text_tag: u8, hex: &str) {
+    match from_hex::<N>(hex) {
+        Some(raw) if to_hex(&raw) == hex => {
+            buf.push(raw_tag);
+            buf.extend_from_slice(&raw);
+        }
+        _ => {
+            buf.push(text_tag);
+            put_bytes(buf, hex.as_bytes());
+        }
+    }
+}
+
+/// Cursor over an encoded event.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("truncated binary event".into());
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::chain::event_hash;
+    use crate::log::payload::{SnapshotAddedPayload, SnapshotOperation};
+    use crate::log::{EventSigner, EventType, InMemoryLogStore, MetadataLog, TypedPayload};
+    use std::collections::BTreeMap;
+
+    fn table() -> TableId {
+        TableId(Uuid::from_u128(7))
+    }
+
+    fn json(event: &TableEvent) -> Value {
+        serde_json::to_value(event).unwrap()
+    }
+
+    /// A sealed, signed event with every envelope field set.
+    fn full_event() -> TableEvent {
+        let signer = EventSigner::from_seed(ActorId("spark-etl".into()), &[7; 32]);
+        let mut event = TableEvent::new(
+            table(),
+            1,
+            EventType::SnapshotAdded,
+            TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id: 42,
+                parent_snapshot_id: None,
+                operation: SnapshotOperation::Append,
+                schema_id: Some(1),
+                mutation_id: Some("m-1".into()),
+                branch: None,
+            }),
+        )
+        .with_envelope(EventEnvelope {
+            engine: Some(EngineKind::Spark),
+            job_id: Some("job-1".into()),
+            run_id: Some("run-1".into()),
+            correlation_id: Some("corr-1".into()),
+            tags: BTreeMap::from([("team".into(), "core".into())]),
+            idempotency_key: Some("commit-1".into()),
+            ..Default::default()
+        });
+        signer.sign(&mut event).unwrap();
+
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.append(event).unwrap();
+        log.replay(&table()).unwrap().remove(0)
+    }
+
+    #[test]
+    fn binary_round_trips_every_field() {
+        let event = full_event();
+        let bytes = encode_binary(&event).unwrap();
+        let decoded = decode(&bytes).unwrap();
+
+        assert_eq!(json(&decoded), json(&event));
+        assert_eq!(
+            event_hash(None, &decoded).unwrap(),
+            event.hash.clone().unwrap()
+        );
+        assert_eq!(encode_binary(&decoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn content_section_is_the_canonical_encoding() {
+        let event = full_event();
+        assert_eq!(
+            canonical_content(&encode_binary(&event).unwrap()).unwrap(),
+            canonical_bytes(&event).unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_payloads_and_non_hex_hashes_round_trip() {
+        let mut event = TableEvent::new(
+            table(),
+            3,
+            EventType::SchemaUpdated,
+            EventPayload::Unknown(serde_json::json!({"type": "future", "n": [1, 2]})),
+        );
+        event.hash = Some("NOT-HEX".into());
+        event.signature = Some("ab".into());
+
+        let decoded = decode_binary(&encode_binary(&event).unwrap()).unwrap();
+        assert_eq!(json(&decoded), json(&event));
+        assert!(matches!(decoded.payload, EventPayload::Unknown(_)));
+    }
+
+    #[test]
+    fn legacy_marks_round_trip() {
+        let mut event = TableEvent::new(table(), 2, EventType::SnapshotAdded, vec![1, 2]);
+        event.legacy = true;
+
+        let bytes = encode_binary(&event).unwrap();
+        assert_eq!(
+            canonical_content(&bytes).unwrap(),
+            canonical_bytes(&event).unwrap()
+        );
+        assert!(decode_binary(&bytes).unwrap().legacy);
+        assert!(
+            decode(&EventEncoding::Json.encode(&event).unwrap())
+                .unwrap()
+                .legacy
+        );
+    }
+
+    #[test]
+    fn opaque_payloads_are_stored_as_raw_bytes() {
+        let event = TableEvent::new(table(), 1, EventType::SnapshotAdded, vec![200; 1024]);
+        let binary = EventEncoding::Binary.encode(&event).unwrap();
+        let text = EventEncoding::Json.encode(&event).unwrap();
+
+        assert!(binary.len() < 1024 + 64, "{} bytes", binary.len());
+        assert!(text.len() > 3 * 1024);
+        assert_eq!(
+            json(&decode(&binary).unwrap()),
+            json(&decode(&text).unwrap())
+        );
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let bytes = encode_binary(&full_event()).unwrap();
+
+        for len in [1, 2, 10, bytes.len() - 1] {
+            assert!(decode_binary(&bytes[..len]).is_err(), "prefix of {len}");
+        }
+
+        let mut newer = bytes.clone();
+        newer[1] = BINARY_FORMAT_VERSION + 1;
+        assert!(decode(&newer).unwrap_err().contains("not supported"));
+
+        let mut repeated = bytes.clone();
+        repeated.extend_from_slice(&[TAG_COMMITTED_AT, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert!(decode(&repeated).unwrap_err().contains("out of order"));
+    }
+
+    #[test]
+    fn encoding_names_parse() {
+        for encoding in [EventEncoding::Json, EventEncoding::Binary] {
+            assert_eq!(encoding.name().parse(), Ok(encoding));
+        }
+        assert!("xml".parse::<EventEncoding>().is_err());
+    }
+}
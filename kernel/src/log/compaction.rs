@@ -0,0 +1,343 @@
+// Compaction Records
+//
+// Compaction folds the oldest events of a table into a signed record of
+// what replaying them produced: the table state machine with its derived
+// model, and a summary of the snapshot lineage. The raw events are copied to an
+// archive store and the live log discards them, keeping the event at
+// the boundary as the anchor of its hash chain.
+//
+// A record is bound to the log by the chain hash of that anchor event,
+// and to its author by an Ed25519 signature over:
+//
+//   "axiom.compaction.v1" | JSON encoding of the record without signature
+//
+// Only the latest record of each table is kept: it covers every earlier
+// compaction. Replay resumes from it instead of version 1 (see
+// `replay::compaction`).
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::envelope::now_millis;
+use super::payload::TypedPayload;
+use super::record::storage;
+use super::{ActorId, EventSigner, LogError, TableId, TrustStore, Version};
+use crate::state::TableStateMachine;
+
+const DOMAIN: &[u8] = b"axiom.compaction.v1";
+
+/// Summary of the snapshots committed by compacted events.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotLineage {
+    /// Most recently added snapshot that is still live.
+    pub current_snapshot_id: Option<i64>,
+
+    /// Snapshots added and not removed since, in commit order.
+    pub live_snapshot_ids: Vec<i64>,
+
+    pub snapshots_added: u64,
+    pub snapshots_removed: u64,
+
+    /// Completed rollbacks.
+    #[serde(default)]
+    pub rollbacks: u64,
+}
+
+impl SnapshotLineage {
+    /// Fold a committed payload into the summary.
+    pub fn record(&mut self, payload: &TypedPayload) {
+        match payload {
+            TypedPayload::SnapshotAdded(added) => {
+                self.live_snapshot_ids.push(added.snapshot_id);
+                self.snapshots_added += 1;
+            }
+            TypedPayload::SnapshotRemoved(removed) => {
+                self.live_snapshot_ids
+                    .retain(|id| !removed.snapshot_ids.contains(id));
+                self.snapshots_removed += removed.snapshot_ids.len() as u64;
+            }
+            TypedPayload::RollbackCompleted(rollback) => {
+                // Later snapshots still exist, but are no longer current.
+                self.current_snapshot_id = Some(rollback.target_snapshot_id);
+                self.rollbacks += 1;
+                return;
+            }
+            TypedPayload::TableCreated(_)
+            | TypedPayload::MutationStarted(_)
+            | TypedPayload::MutationCommitted(_)
+            | TypedPayload::MutationAborted(_)
+            | TypedPayload::SchemaUpdated(_)
+            | TypedPayload::PropertiesUpdated(_)
+            | TypedPayload::RollbackStarted(_)
+            | TypedPayload::RollbackFailed(_)
+            | TypedPayload::TableFrozen(_)
+            | TypedPayload::TableUnfrozen(_)
+            | TypedPayload::TableDeprecated(_)
+            | TypedPayload::TableDropped(_)
+            | TypedPayload::TableUndropped(_) => return,
+        }
+        self.current_snapshot_id = self.live_snapshot_ids.last().copied();
+    }
+}
+
+/// Signed summary of a table's events up to and including `version`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactionRecord {
+    pub table_id: TableId,
+
+    /// Last version folded into the record. The event at this version
+    /// stays in the live log to anchor the hash chain of later events.
+    pub version: Version,
+
+    /// Chain hash of the event at `version`.
+    pub hash: String,
+
+    /// State machine derived by replaying versions `1..=version`,
+    /// including the schema in effect. It never has an open mutation.
+    #[serde(flatten)]
+    pub machine: TableStateMachine,
+
+    pub lineage: SnapshotLineage,
+
+    /// `InvariantEngine::fingerprint` of the invariants enforced.
+    pub invariant_set: String,
+
+    /// Unix milliseconds, stamped when signing.
+    pub compacted_at: Option<u64>,
+
+    pub actor: Option<ActorId>,
+
+    /// Hex-encoded Ed25519 signature.
+    pub signature: Option<String>,
+}
+
+impl CompactionRecord {
+    /// Stamp the signer's actor and the current time, and sign the record.
+    pub fn sign(&mut self, signer: &EventSigner) -> Result<(), LogError> {
+        self.actor = Some(signer.actor().clone());
+        self.compacted_at = Some(now_millis());
+        self.signature = Some(signer.sign_message(&self.signing_bytes()?));
+        Ok(())
+    }
+
+    /// Check that the record is signed by a key trusted for its actor.
+    pub fn verify(&self, trust: &TrustStore) -> Result<(), LogError> {
+        let reject = |reason: String| LogError::SignatureRejected {
+            table_id: self.table_id.clone(),
+            version: self.version,
+            reason: format!("compaction record: {reason}"),
+        };
+
+        let actor = self
+            .actor
+            .as_ref()
+            .ok_or_else(|| reject("record has no actor".into()))?;
+        let signature = self
+            .signature
+            .as_deref()
+            .ok_or_else(|| reject("record is unsigned".into()))?;
+
+        trust
+            .verify_message(actor, &self.signing_bytes()?, signature)
+            .map_err(reject)
+    }
+
+    fn signing_bytes(&self) -> Result<Vec<u8>, LogError> {
+        let unsigned = Self {
+            signature: None,
+            ..self.clone()
+        };
+
+        let mut message = DOMAIN.to_vec();
+        message.extend(serde_json::to_vec(&unsigned).map_err(storage)?);
+        Ok(message)
+    }
+}
+
+/// Persistence for the latest compaction record of each table.
+pub trait CompactionStore: Send + Sync {
+    /// Persist a record, replacing the table's previous one.
+    fn save(&mut self, record: &CompactionRecord) -> Result<(), LogError>;
+
+    /// Latest record of a table, if it was ever compacted.
+    fn latest(&self, table_id: &TableId) -> Result<Option<CompactionRecord>, LogError>;
+}
+
+/// In-memory compaction store.
+#[derive(Debug, Default)]
+pub struct InMemoryCompactionStore {
+    records: BTreeMap<TableId, CompactionRecord>,
+}
+
+impl CompactionStore for InMemoryCompactionStore {
+    fn save(&mut self, record: &CompactionRecord) -> Result<(), LogError> {
+        self.records.insert(record.table_id.clone(), record.clone());
+        Ok(())
+    }
+
+    fn latest(&self, table_id: &TableId) -> Result<Option<CompactionRecord>, LogError> {
+        Ok(self.records.get(table_id).cloned())
+    }
+}
+
+/// Directory-backed compaction store.
+///
+/// Layout: `<dir>/<table>.json`. Records are written to a temporary name
+/// and renamed, so a crash leaves either the old or the new record.
+#[derive(Debug)]
+pub struct FileCompactionStore {
+    dir: PathBuf,
+}
+
+impl FileCompactionStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, LogError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, table_id: &TableId) -> PathBuf {
+        self.dir.join(format!("{table_id}.json"))
+    }
+}
+
+impl CompactionStore for FileCompactionStore {
+    fn save(&mut self, record: &CompactionRecord) -> Result<(), LogError> {
+        let path = self.path(&record.table_id);
+        let tmp = path.with_extension("json.tmp");
+
+        let body = serde_json::to_vec_pretty(record).map_err(storage)?;
+        let mut file = File::create(&tmp).map_err(storage)?;
+        file.write_all(&body).map_err(storage)?;
+        file.sync_all().map_err(storage)?;
+        fs::rename(&tmp, &path).map_err(storage)?;
+
+        Ok(())
+    }
+
+    fn latest(&self, table_id: &TableId) -> Result<Option<CompactionRecord>, LogError> {
+        let path = self.path(table_id);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(storage(e)),
+        };
+
+        serde_json::from_slice(&data).map(Some).map_err(|e| {
+            LogError::Corrupt(format!(
+                "undecodable compaction record {}: {e}",
+                path.display()
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::payload::{
+        RollbackPayload, SnapshotAddedPayload, SnapshotOperation, SnapshotRemovedPayload,
+    };
+    use crate::state::TableState;
+    use uuid::Uuid;
+
+    fn spark() -> EventSigner {
+        EventSigner::from_seed(ActorId("spark-etl".into()), &[7; 32])
+    }
+
+    fn record() -> CompactionRecord {
+        CompactionRecord {
+            table_id: TableId(Uuid::nil()),
+            version: 3,
+            hash: "00".repeat(32),
+            machine: TableStateMachine::resume(TableState::Active, None),
+            lineage: SnapshotLineage::default(),
+            invariant_set: String::new(),
+            compacted_at: None,
+            actor: None,
+            signature: None,
+        }
+    }
+
+    fn added(snapshot_id: i64) -> TypedPayload {
+        TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+            snapshot_id,
+            parent_snapshot_id: None,
+            operation: SnapshotOperation::Append,
+            schema_id: None,
+            mutation_id: None,
+            branch: None,
+        })
+    }
+
+    #[test]
+    fn lineage_tracks_live_snapshots() {
+        let mut lineage = SnapshotLineage::default();
+        for id in [1, 2, 3] {
+            lineage.record(&added(id));
+        }
+        lineage.record(&TypedPayload::SnapshotRemoved(SnapshotRemovedPayload {
+            snapshot_ids: vec![1, 3],
+            mutation_id: None,
+        }));
+
+        assert_eq!(
+            lineage,
+            SnapshotLineage {
+                current_snapshot_id: Some(2),
+                live_snapshot_ids: vec![2],
+                snapshots_added: 3,
+                snapshots_removed: 2,
+                rollbacks: 0,
+            }
+        );
+
+        lineage.record(&added(4));
+        lineage.record(&TypedPayload::RollbackCompleted(RollbackPayload {
+            target_snapshot_id: 2,
+            reason: None,
+        }));
+        assert_eq!(lineage.current_snapshot_id, Some(2));
+        assert_eq!(lineage.rollbacks, 1);
+    }
+
+    #[test]
+    fn signed_record_verifies_until_altered() {
+        let signer = spark();
+        let mut trust = TrustStore::new();
+        trust.trust(signer.actor().clone(), signer.verifying_key());
+
+        let mut record = record();
+        assert!(record.verify(&trust).is_err(), "unsigned record accepted");
+
+        record.sign(&signer).unwrap();
+        assert!(record.compacted_at.is_some());
+        record.verify(&trust).unwrap();
+
+        record.machine = TableStateMachine::resume(TableState::RollingBack, None);
+        assert!(matches!(
+            record.verify(&trust),
+            Err(LogError::SignatureRejected { version: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn file_store_keeps_latest_record_across_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = FileCompactionStore::open(dir.path()).unwrap();
+        let table_id = TableId(Uuid::nil());
+        assert_eq!(store.latest(&table_id).unwrap(), None);
+
+        let mut newer = record();
+        newer.version = 7;
+        store.save(&record()).unwrap();
+        store.save(&newer).unwrap();
+
+        let store = FileCompactionStore::open(dir.path()).unwrap();
+        assert_eq!(store.latest(&table_id).unwrap(), Some(newer));
+    }
+}
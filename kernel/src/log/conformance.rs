@@ -0,0 +1,534 @@
+// Store Conformance Suite
+//
+// Checks of the `MetadataLogStore` contract, written once against the
+// trait so every backend -- ours or a third party's -- is validated the
+// same way. A backend provides a `StoreFactory` and either calls
+// `check_all` from a test or expands `store_conformance_tests!` to get
+// one test per check:
+//
+//   mod conformance {
+//       use super::*;
+//       axiom_kernel::store_conformance_tests!(MyFactory::default());
+//   }
+//
+// Checks panic with an assertion message on the first violation.
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::thread;
+
+use uuid::Uuid;
+
+use super::payload::{SnapshotAddedPayload, SnapshotOperation};
+use super::{
+    ActorId, AppendOutcome, EngineKind, EventEnvelope, EventType, LogError, MetadataLogStore,
+    TableEvent, TableId, TypedPayload, Version, VersionRange,
+};
+
+/// Opens stores for the conformance checks.
+pub trait StoreFactory {
+    type Store: MetadataLogStore;
+
+    /// Identifies backing storage: a path, a database URL, a bucket
+    /// prefix, ...
+    type Location;
+
+    /// A new, empty storage location.
+    fn create_location(&mut self) -> Self::Location;
+
+    /// Open a store on `location`.
+    fn open(&mut self, location: &Self::Location) -> Self::Store;
+
+    /// Whether reopening a location yields the events written to it
+    /// before. Restart checks are skipped for stores that do not persist.
+    fn persistent(&self) -> bool {
+        true
+    }
+
+    /// Whether several stores may be open on one location and append
+    /// concurrently (databases, object storage). Stores that assume a
+    /// single writer race through one shared handle instead.
+    fn shares_storage(&self) -> bool {
+        false
+    }
+}
+
+/// Run every check.
+pub fn check_all<F: StoreFactory>(factory: &mut F) {
+    versions_are_dense_and_start_at_one(factory);
+    versions_are_scoped_per_table(factory);
+    stream_respects_ranges(factory);
+    tables_are_listed_in_id_order(factory);
+    rejected_appends_create_no_table(factory);
+    events_round_trip_unchanged(factory);
+    idempotency_keys_are_deduplicated(factory);
+    reload_preserves_order(factory);
+    restart_is_durable(factory);
+    concurrent_appends_commit_each_event_once(factory);
+    truncation_keeps_later_versions(factory);
+    seeded_tables_start_at_their_anchor(factory);
+}
+
+/// One `#[test]` per conformance check, each with a fresh factory from
+/// `$factory`.
+#[macro_export]
+macro_rules! store_conformance_tests {
+    ($factory:expr) => {
+        $crate::store_conformance_tests!(@tests $factory;
+            versions_are_dense_and_start_at_one,
+            versions_are_scoped_per_table,
+            stream_respects_ranges,
+            tables_are_listed_in_id_order,
+            rejected_appends_create_no_table,
+            events_round_trip_unchanged,
+            idempotency_keys_are_deduplicated,
+            reload_preserves_order,
+            restart_is_durable,
+            concurrent_appends_commit_each_event_once,
+            truncation_keeps_later_versions,
+            seeded_tables_start_at_their_anchor,
+        );
+    };
+    (@tests $factory:expr; $($check:ident,)*) => {
+        $(
+            #[test]
+            fn $check() {
+                $crate::log::conformance::$check(&mut $factory);
+            }
+        )*
+    };
+}
+
+fn fresh<F: StoreFactory>(factory: &mut F) -> F::Store {
+    let location = factory.create_location();
+    factory.open(&location)
+}
+
+fn table(n: u128) -> TableId {
+    TableId(Uuid::from_u128(n))
+}
+
+fn event(table_id: &TableId, version: Version) -> TableEvent {
+    TableEvent::new(table_id.clone(), version, EventType::SnapshotAdded, vec![])
+}
+
+fn keyed(table_id: &TableId, version: Version, key: &str) -> TableEvent {
+    event(table_id, version).with_envelope(EventEnvelope {
+        idempotency_key: Some(key.into()),
+        ..Default::default()
+    })
+}
+
+fn versions<S: MetadataLogStore>(
+    store: &S,
+    table_id: &TableId,
+    range: VersionRange,
+) -> Vec<Version> {
+    store
+        .stream(table_id, range)
+        .unwrap()
+        .map(|e| e.unwrap().version)
+        .collect()
+}
+
+/// Appends must continue the table at exactly `head + 1`: stale versions
+/// conflict and gaps are rejected.
+pub fn versions_are_dense_and_start_at_one<F: StoreFactory>(factory: &mut F) {
+    let mut store = fresh(factory);
+    let t = table(1);
+    assert_eq!(store.current_version(&t).unwrap(), 0);
+
+    assert_eq!(
+        store.append(&event(&t, 2)).unwrap_err(),
+        LogError::VersionConflict {
+            expected: 1,
+            actual: 2
+        },
+        "gap before the first version"
+    );
+    assert_eq!(
+        store.append(&event(&t, 1)).unwrap(),
+        AppendOutcome::Appended(1)
+    );
+    assert_eq!(
+        store.append(&event(&t, 1)).unwrap_err(),
+        LogError::VersionConflict {
+            expected: 2,
+            actual: 1
+        },
+        "version written twice"
+    );
+    assert_eq!(
+        store.append(&event(&t, 3)).unwrap_err(),
+        LogError::VersionConflict {
+            expected: 2,
+            actual: 3
+        },
+        "gap after the head"
+    );
+    assert_eq!(
+        store.append(&event(&t, 2)).unwrap(),
+        AppendOutcome::Appended(2)
+    );
+    assert_eq!(store.current_version(&t).unwrap(), 2);
+    assert_eq!(versions(&store, &t, VersionRange::all()), vec![1, 2]);
+}
+
+pub fn versions_are_scoped_per_table<F: StoreFactory>(factory: &mut F) {
+    let mut store = fresh(factory);
+    let (a, b) = (table(1), table(2));
+    store.append(&event(&a, 1)).unwrap();
+    store.append(&event(&b, 1)).unwrap();
+    store.append(&event(&a, 2)).unwrap();
+
+    assert_eq!(store.current_version(&a).unwrap(), 2);
+    assert_eq!(store.current_version(&b).unwrap(), 1);
+    assert_eq!(versions(&store, &b, VersionRange::all()), vec![1]);
+}
+
+pub fn stream_respects_ranges<F: StoreFactory>(factory: &mut F) {
+    let mut store = fresh(factory);
+    let t = table(1);
+    for v in 1..=5 {
+        store.append(&event(&t, v)).unwrap();
+    }
+
+    let read = |range| versions(&store, &t, range);
+    assert_eq!(read(VersionRange::all()), vec![1, 2, 3, 4, 5]);
+    assert_eq!(read(VersionRange::between(2, 4)), vec![2, 3, 4]);
+    assert_eq!(read(VersionRange::starting_at(4)), vec![4, 5]);
+    assert_eq!(read(VersionRange::between(4, 99)), vec![4, 5]);
+    assert_eq!(read(VersionRange::between(4, Version::MAX)), vec![4, 5]);
+    assert!(read(VersionRange::starting_at(6)).is_empty());
+    assert!(read(VersionRange::between(4, 2)).is_empty());
+    assert!(versions(&store, &table(9), VersionRange::all()).is_empty());
+}
+
+pub fn tables_are_listed_in_id_order<F: StoreFactory>(factory: &mut F) {
+    let mut store = fresh(factory);
+    assert!(store.tables().unwrap().is_empty());
+
+    for n in [3, 1, 2] {
+        store.append(&event(&table(n), 1)).unwrap();
+    }
+    assert_eq!(store.tables().unwrap(), vec![table(1), table(2), table(3)]);
+}
+
+/// A table exists once its first event is stored: a rejected append
+/// leaves no trace of it.
+pub fn rejected_appends_create_no_table<F: StoreFactory>(factory: &mut F) {
+    let mut store = fresh(factory);
+    let t = table(1);
+    assert!(store.append(&event(&t, 2)).is_err());
+
+    assert!(store.tables().unwrap().is_empty());
+    assert_eq!(store.current_version(&t).unwrap(), 0);
+    assert!(versions(&store, &t, VersionRange::all()).is_empty());
+
+    store.append(&event(&t, 1)).unwrap();
+    assert_eq!(store.tables().unwrap(), vec![t]);
+}
+
+/// Stores must not alter events: every field, including the envelope,
+/// signature and hash, reads back as written.
+pub fn events_round_trip_unchanged<F: StoreFactory>(factory: &mut F) {
+    let mut store = fresh(factory);
+    let t = table(1);
+    let mut original = TableEvent::new(
+        t.clone(),
+        1,
+        EventType::SnapshotAdded,
+        TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+            snapshot_id: 7,
+            parent_snapshot_id: None,
+            operation: SnapshotOperation::Append,
+            schema_id: Some(0),
+            mutation_id: None,
+            branch: None,
+        }),
+    )
+    .with_envelope(EventEnvelope {
+        committed_at: Some(1_700_000_000_000),
+        actor: Some(ActorId("spark-etl".into())),
+        engine: Some(EngineKind::Spark),
+        job_id: Some("job".into()),
+        tags: [("team".to_string(), "ingest".to_string())].into(),
+        ..Default::default()
+    });
+    original.signature = Some("ab".repeat(64));
+    original.hash = Some("cd".repeat(32));
+
+    store.append(&original).unwrap();
+    let stored = store.load(&t).unwrap().remove(0);
+
+    assert_eq!(
+        serde_json::to_value(&stored).unwrap(),
+        serde_json::to_value(&original).unwrap()
+    );
+}
+
+pub fn idempotency_keys_are_deduplicated<F: StoreFactory>(factory: &mut F) {
+    let mut store = fresh(factory);
+    let t = table(1);
+    let commit = keyed(&t, 1, "commit-1");
+
+    store.append(&commit).unwrap();
+    store.append(&event(&t, 2)).unwrap();
+
+    assert_eq!(store.append(&commit).unwrap(), AppendOutcome::Duplicate(1));
+    assert_eq!(store.find_idempotency_key(&t, "commit-1").unwrap(), Some(1));
+    assert_eq!(store.find_idempotency_key(&t, "commit-2").unwrap(), None);
+    assert_eq!(
+        store.find_idempotency_key(&table(2), "commit-1").unwrap(),
+        None
+    );
+    assert_eq!(store.current_version(&t).unwrap(), 2);
+}
+
+/// Interleaved appends to several tables reload in per-table version
+/// order.
+pub fn reload_preserves_order<F: StoreFactory>(factory: &mut F) {
+    if !factory.persistent() {
+        return;
+    }
+
+    let location = factory.create_location();
+    let mut store = factory.open(&location);
+    let (a, b) = (table(1), table(2));
+    for v in 1..=20 {
+        store.append(&event(&a, v)).unwrap();
+        if v % 3 == 0 {
+            store.append(&event(&b, v / 3)).unwrap();
+        }
+    }
+    drop(store);
+
+    let store = factory.open(&location);
+    assert_eq!(store.tables().unwrap(), vec![a.clone(), b.clone()]);
+    assert_eq!(
+        versions(&store, &a, VersionRange::all()),
+        (1..=20).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        versions(&store, &b, VersionRange::all()),
+        (1..=6).collect::<Vec<_>>()
+    );
+}
+
+/// Acknowledged appends survive a restart, and the reopened store
+/// carries on from the same head and idempotency keys.
+pub fn restart_is_durable<F: StoreFactory>(factory: &mut F) {
+    if !factory.persistent() {
+        return;
+    }
+
+    let location = factory.create_location();
+    let mut store = factory.open(&location);
+    let t = table(1);
+    store.append(&keyed(&t, 1, "commit-1")).unwrap();
+    store.append(&event(&t, 2)).unwrap();
+    let before = store.load(&t).unwrap();
+    drop(store);
+
+    let mut store = factory.open(&location);
+    assert_eq!(store.current_version(&t).unwrap(), 2);
+    assert_eq!(
+        serde_json::to_value(store.load(&t).unwrap()).unwrap(),
+        serde_json::to_value(before).unwrap()
+    );
+    assert_eq!(
+        store.append(&keyed(&t, 3, "commit-1")).unwrap(),
+        AppendOutcome::Duplicate(1)
+    );
+    assert_eq!(
+        store.append(&event(&t, 2)).unwrap_err(),
+        LogError::VersionConflict {
+            expected: 3,
+            actual: 2
+        }
+    );
+    assert_eq!(
+        store.append(&event(&t, 3)).unwrap(),
+        AppendOutcome::Appended(3)
+    );
+}
+
+/// Writers racing for the same versions each commit every one of their
+/// events exactly once, and the log stays dense.
+pub fn concurrent_appends_commit_each_event_once<F: StoreFactory>(factory: &mut F) {
+    const WRITERS: usize = 4;
+    const COMMITS: usize = 25;
+
+    let location = factory.create_location();
+    let handles: Vec<Mutex<F::Store>> = if factory.shares_storage() {
+        (0..WRITERS)
+            .map(|_| Mutex::new(factory.open(&location)))
+            .collect()
+    } else {
+        vec![Mutex::new(factory.open(&location))]
+    };
+
+    let t = table(1);
+    thread::scope(|scope| {
+        for writer in 0..WRITERS {
+            let handle = &handles[writer % handles.len()];
+            let t = &t;
+            scope.spawn(move || {
+                for i in 0..COMMITS {
+                    let key = format!("writer-{writer}-{i}");
+                    loop {
+                        // Release the store between reading the head and
+                        // appending so other writers can get in between.
+                        let head = handle.lock().unwrap().current_version(t).unwrap();
+                        match handle.lock().unwrap().append(&keyed(t, head + 1, &key)) {
+                            Ok(_) => break,
+                            Err(LogError::VersionConflict { .. }) => continue,
+                            Err(e) => panic!("{key}: {e}"),
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let store = handles.into_iter().next().unwrap().into_inner().unwrap();
+    let events = store.load(&t).unwrap();
+    let total = WRITERS * COMMITS;
+
+    assert_eq!(
+        events.iter().map(|e| e.version).collect::<Vec<_>>(),
+        (1..=total as Version).collect::<Vec<_>>()
+    );
+    let keys: BTreeSet<_> = events
+        .iter()
+        .filter_map(|e| e.envelope.idempotency_key.clone())
+        .collect();
+    assert_eq!(keys.len(), total, "an event was committed twice");
+}
+
+/// Discarding compacted events keeps the requested version, everything
+/// after it and the head, and appends carry on from the head. Skipped
+/// for stores that do not support discarding events.
+pub fn truncation_keeps_later_versions<F: StoreFactory>(factory: &mut F) {
+    let location = factory.create_location();
+    let mut store = factory.open(&location);
+    let (t, other) = (table(1), table(2));
+    for v in 1..=10 {
+        store.append(&event(&t, v)).unwrap();
+    }
+    store.append(&event(&other, 1)).unwrap();
+
+    if !store.supports_truncation() {
+        assert!(matches!(
+            store.truncate_before(&t, 6),
+            Err(LogError::Storage(_))
+        ));
+        return;
+    }
+    store.truncate_before(&t, 6).unwrap();
+
+    let first = store.first_version(&t).unwrap();
+    assert!((1..=6).contains(&first), "version 6 was discarded");
+    assert_eq!(
+        versions(&store, &t, VersionRange::all()),
+        (first..=10).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        versions(&store, &t, VersionRange::between(7, 8)),
+        vec![7, 8]
+    );
+    assert_eq!(store.current_version(&t).unwrap(), 10);
+    assert_eq!(store.first_version(&other).unwrap(), 1);
+    assert_eq!(versions(&store, &other, VersionRange::all()), vec![1]);
+
+    assert_eq!(
+        store.append(&event(&t, 11)).unwrap(),
+        AppendOutcome::Appended(11)
+    );
+    store.truncate_before(&t, 99).unwrap();
+    assert_eq!(store.current_version(&t).unwrap(), 11, "head was discarded");
+    assert_eq!(
+        versions(&store, &t, VersionRange::starting_at(11)),
+        vec![11]
+    );
+
+    if factory.persistent() {
+        drop(store);
+        let mut store = factory.open(&location);
+        let first = store.first_version(&t).unwrap();
+        assert_eq!(
+            versions(&store, &t, VersionRange::all()),
+            (first..=11).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            store.append(&event(&t, 12)).unwrap(),
+            AppendOutcome::Appended(12)
+        );
+    }
+}
+
+/// A table seeded with an anchor event starts at the anchor's version,
+/// as if its older events had been discarded, and appends carry on from
+/// it. Only empty tables can be seeded. Skipped for stores that do not
+/// support discarding events.
+pub fn seeded_tables_start_at_their_anchor<F: StoreFactory>(factory: &mut F) {
+    let location = factory.create_location();
+    let mut store = factory.open(&location);
+    let t = table(1);
+
+    if !store.supports_truncation() {
+        assert!(matches!(
+            store.seed(&event(&t, 6)),
+            Err(LogError::Storage(_))
+        ));
+        assert_eq!(store.current_version(&t).unwrap(), 0);
+        return;
+    }
+
+    store.seed(&event(&t, 6)).unwrap();
+    assert_eq!(store.current_version(&t).unwrap(), 6);
+    assert_eq!(store.first_version(&t).unwrap(), 6);
+    assert_eq!(store.tables().unwrap(), vec![t.clone()]);
+    assert_eq!(
+        store.append(&event(&t, 7)).unwrap(),
+        AppendOutcome::Appended(7)
+    );
+    assert!(matches!(
+        store.seed(&event(&t, 8)),
+        Err(LogError::VersionConflict { expected: 8, .. })
+    ));
+    assert_eq!(versions(&store, &t, VersionRange::all()), vec![6, 7]);
+
+    if factory.persistent() {
+        drop(store);
+        let mut store = factory.open(&location);
+        assert_eq!(versions(&store, &t, VersionRange::all()), vec![6, 7]);
+        assert_eq!(
+            store.append(&event(&t, 8)).unwrap(),
+            AppendOutcome::Appended(8)
+        );
+    }
+}
+
+/// Fresh paths in a temporary directory, for factories of this crate's
+/// file-based stores.
+#[cfg(test)]
+pub(crate) struct TempLocations {
+    dir: tempfile::TempDir,
+    next: usize,
+}
+
+#[cfg(test)]
+impl TempLocations {
+    pub fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+            next: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> std::path::PathBuf {
+        self.next += 1;
+        self.dir.path().join(self.next.to_string())
+    }
+}
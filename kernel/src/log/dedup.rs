@@ -0,0 +1,129 @@
+// Idempotent Append Window
+//
+// Engines retry commits after timeouts without knowing whether the first
+// attempt landed. Events may carry an idempotency key; stores remember
+// the keys of each table's most recent versions so a retried event is
+// answered with the version it was originally committed at instead of
+// being written twice.
+//
+// The window is measured in versions per table. Stores rebuild it from
+// the tail of each table's log when they are reopened.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::{TableEvent, TableId, Version};
+
+/// Number of most recent versions per table whose keys are remembered.
+pub const DEFAULT_DEDUP_WINDOW: u64 = 1000;
+
+#[derive(Debug)]
+pub(crate) struct DedupIndex {
+    window: u64,
+    tables: HashMap<TableId, TableKeys>,
+}
+
+#[derive(Debug, Default)]
+struct TableKeys {
+    by_key: HashMap<String, Version>,
+    by_version: VecDeque<(Version, String)>,
+}
+
+impl DedupIndex {
+    /// Remember keys of the last `window` versions (0 disables dedup).
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            tables: HashMap::new(),
+        }
+    }
+
+    /// Version at which an event with `event`'s key was committed, if
+    /// it is still within the window.
+    pub fn lookup(&self, event: &TableEvent) -> Option<Version> {
+        let key = event.envelope.idempotency_key.as_ref()?;
+        self.find(&event.table_id, key)
+    }
+
+    pub fn find(&self, table_id: &TableId, key: &str) -> Option<Version> {
+        self.tables.get(table_id)?.by_key.get(key).copied()
+    }
+
+    /// Record a committed event. Must be called for every event, in
+    /// version order, so older keys fall out of the window.
+    pub fn record(&mut self, event: &TableEvent) {
+        if self.window == 0 {
+            return;
+        }
+
+        let keys = self.tables.entry(event.table_id.clone()).or_default();
+
+        if let Some(key) = &event.envelope.idempotency_key {
+            keys.by_key.insert(key.clone(), event.version);
+            keys.by_version.push_back((event.version, key.clone()));
+        }
+
+        while let Some((version, key)) = keys.by_version.front() {
+            if version + self.window > event.version {
+                break;
+            }
+            if keys.by_key.get(key) == Some(version) {
+                keys.by_key.remove(key);
+            }
+            keys.by_version.pop_front();
+        }
+    }
+
+    /// First version of `head`'s table that is still within the window.
+    pub fn window_start(&self, head: Version) -> Version {
+        (head + 1).saturating_sub(self.window).max(1)
+    }
+}
+
+impl Default for DedupIndex {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{EventEnvelope, EventType};
+    use uuid::Uuid;
+
+    fn event(version: Version, key: Option<&str>) -> TableEvent {
+        TableEvent::new(
+            TableId(Uuid::nil()),
+            version,
+            EventType::SnapshotAdded,
+            vec![],
+        )
+        .with_envelope(EventEnvelope {
+            idempotency_key: key.map(Into::into),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn keys_expire_after_the_window() {
+        let mut index = DedupIndex::new(3);
+        index.record(&event(1, Some("a")));
+        index.record(&event(2, None));
+        index.record(&event(3, Some("b")));
+
+        assert_eq!(index.lookup(&event(9, Some("a"))), Some(1));
+        assert_eq!(index.lookup(&event(9, None)), None);
+
+        index.record(&event(4, None));
+        assert_eq!(index.lookup(&event(9, Some("a"))), None);
+        assert_eq!(index.lookup(&event(9, Some("b"))), Some(3));
+        assert_eq!(index.window_start(4), 2);
+    }
+
+    #[test]
+    fn zero_window_disables_dedup() {
+        let mut index = DedupIndex::new(0);
+        index.record(&event(1, Some("a")));
+        assert_eq!(index.lookup(&event(2, Some("a"))), None);
+    }
+}
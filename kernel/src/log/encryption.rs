@@ -0,0 +1,682 @@
+// Encryption at Rest
+//
+// `EncryptedLogStore` wraps any store and encrypts event content before
+// it reaches storage. Each table has a random AES-256-GCM data key;
+// data keys are wrapped by a key-encryption key held by a `KeyProvider`
+// (a KMS, or a local keyfile) and travel, wrapped, with every record.
+//
+// Stored payload layout (integers big-endian, byte strings length
+// prefixed with a u32):
+//
+//   "AXE" | format (u8) | key id | wrapped data key | nonce (12 bytes)
+//   | AES-GCM ciphertext of the binary-encoded event (see `codec`)
+//
+// The ciphertext is bound to its table and version through the
+// associated data, so records cannot be swapped undetected. The stored
+// event keeps in the clear only what stores index: table id, version,
+// event type, commit time and idempotency key. Everything else --
+// payload, provenance, signature and chain hash -- is encrypted.
+//
+// Encryption is transparent to the log: reads return the original
+// events, so hash chains and signatures verify as usual. Reads fail
+// closed: an unencrypted record, or one whose key the provider does not
+// have, is an error rather than passed through.
+//
+// Rotation: `KeyProvider`s rotate their key-encryption keys (e.g.
+// `LocalKeyProvider::rotate`); `EncryptedLogStore::rotate_data_key`
+// starts a fresh data key wrapped by the provider's current key. Old
+// records stay readable while the provider holds the keys that wrapped
+// them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::chain::{from_hex, put_bytes, to_hex};
+use super::codec;
+use super::record::storage;
+use super::{
+    AppendOutcome, EventEnvelope, EventPayload, EventStream, LogError, MetadataLogStore,
+    TableEvent, TableId, Version, VersionRange,
+};
+
+const MAGIC: &[u8; 3] = b"AXE";
+const FORMAT_VERSION: u8 = 1;
+const RECORD_DOMAIN: &[u8] = b"axiom.encrypted.v1";
+const WRAP_DOMAIN: &[u8] = b"axiom.datakey.v1";
+const KEY_ID_DOMAIN: &[u8] = b"axiom.keyid.v1";
+const NONCE_LEN: usize = 12;
+
+/// Holds the key-encryption keys that wrap per-table data keys.
+///
+/// Providers must keep retired keys able to unwrap: records are never
+/// re-encrypted.
+pub trait KeyProvider: Send + Sync {
+    /// Id of the key new data keys are wrapped with.
+    fn current_key_id(&self) -> Result<String, LogError>;
+
+    /// Encrypt a data key under key `key_id`.
+    fn wrap(&self, key_id: &str, data_key: &[u8; 32]) -> Result<Vec<u8>, LogError>;
+
+    /// Decrypt a data key wrapped under `key_id`. A key the provider does
+    /// not hold is reported as `LogError::MissingKey`.
+    fn unwrap(&self, key_id: &str, wrapped: &[u8]) -> Result<[u8; 32], LogError>;
+}
+
+/// On-disk form of a local keyfile.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Keyfile {
+    /// Id of the key used for wrapping.
+    current: String,
+
+    /// Hex-encoded 256-bit keys by id, including retired ones.
+    keys: BTreeMap<String, String>,
+}
+
+/// Key provider backed by a local JSON keyfile:
+///
+///   { "current": "key-<id>", "keys": { "key-<id>": "<hex>", ... } }
+///
+/// Intended for single-node deployments and tests; the file must be
+/// protected like any other secret.
+#[derive(Debug)]
+pub struct LocalKeyProvider {
+    path: PathBuf,
+    keyfile: RwLock<Keyfile>,
+}
+
+impl LocalKeyProvider {
+    /// Open a keyfile, creating it with a fresh key if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LogError> {
+        let path = path.as_ref().to_path_buf();
+
+        let keyfile = match fs::read(&path) {
+            Ok(data) => {
+                let keyfile: Keyfile = serde_json::from_slice(&data).map_err(|e| {
+                    LogError::Storage(format!("invalid keyfile {}: {e}", path.display()))
+                })?;
+                if !keyfile.keys.contains_key(&keyfile.current) {
+                    return Err(LogError::MissingKey {
+                        key_id: keyfile.current,
+                    });
+                }
+                keyfile
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let keyfile = Keyfile::default();
+                let provider = Self {
+                    path,
+                    keyfile: RwLock::new(keyfile),
+                };
+                provider.rotate()?;
+                return Ok(provider);
+            }
+            Err(e) => return Err(storage(e)),
+        };
+
+        Ok(Self {
+            path,
+            keyfile: RwLock::new(keyfile),
+        })
+    }
+
+    /// Generate a new key, make it current and persist the keyfile.
+    /// Returns the new key id.
+    pub fn rotate(&self) -> Result<String, LogError> {
+        let mut keyfile = self
+            .keyfile
+            .write()
+            .map_err(|_| LogError::Storage("keyfile lock poisoned".into()))?;
+
+        let key = Aes256Gcm::generate_key(OsRng);
+        let key_id = key_id(&key);
+        let mut updated = keyfile.clone();
+        updated.keys.insert(key_id.clone(), to_hex(&key));
+        updated.current = key_id.clone();
+
+        save_keyfile(&self.path, &updated)?;
+        *keyfile = updated;
+        Ok(key_id)
+    }
+
+    fn key(&self, key_id: &str) -> Result<Aes256Gcm, LogError> {
+        let keyfile = self
+            .keyfile
+            .read()
+            .map_err(|_| LogError::Storage("keyfile lock poisoned".into()))?;
+
+        let key = keyfile
+            .keys
+            .get(key_id)
+            .and_then(|hex| from_hex::<32>(hex))
+            .ok_or_else(|| LogError::MissingKey {
+                key_id: key_id.to_string(),
+            })?;
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+}
+
+impl KeyProvider for LocalKeyProvider {
+    fn current_key_id(&self) -> Result<String, LogError> {
+        self.keyfile
+            .read()
+            .map(|keyfile| keyfile.current.clone())
+            .map_err(|_| LogError::Storage("keyfile lock poisoned".into()))
+    }
+
+    fn wrap(&self, key_id: &str, data_key: &[u8; 32]) -> Result<Vec<u8>, LogError> {
+        let aad = [WRAP_DOMAIN, key_id.as_bytes()].concat();
+        seal(&self.key(key_id)?, &aad, data_key)
+    }
+
+    fn unwrap(&self, key_id: &str, wrapped: &[u8]) -> Result<[u8; 32], LogError> {
+        let aad = [WRAP_DOMAIN, key_id.as_bytes()].concat();
+        open(&self.key(key_id)?, &aad, wrapped)
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or_else(|| LogError::Corrupt(format!("data key wrapped by {key_id} is invalid")))
+    }
+}
+
+/// Ids are derived from the key material, so keys of different
+/// keyfiles never share an id and a wrong keyfile reports the key as
+/// missing.
+fn key_id(key: &[u8]) -> String {
+    let digest = Sha256::new()
+        .chain_update(KEY_ID_DOMAIN)
+        .chain_update(key)
+        .finalize();
+    format!("key-{}", to_hex(&digest[..8]))
+}
+
+/// Write the keyfile to a temporary name and rename it into place.
+fn save_keyfile(path: &Path, keyfile: &Keyfile) -> Result<(), LogError> {
+    let tmp = path.with_extension("tmp");
+    let body = serde_json::to_vec_pretty(keyfile).map_err(storage)?;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file: File = options.open(&tmp).map_err(storage)?;
+    file.write_all(&body).map_err(storage)?;
+    file.sync_all().map_err(storage)?;
+    fs::rename(&tmp, path).map_err(storage)
+}
+
+/// A table's data key, with the wrapped form stored alongside records.
+struct DataKey {
+    key_id: String,
+    wrapped: Vec<u8>,
+    cipher: Aes256Gcm,
+}
+
+/// Store wrapper encrypting event content with per-table data keys.
+pub struct EncryptedLogStore<S> {
+    inner: S,
+    provider: Arc<dyn KeyProvider>,
+
+    /// Data key used for new events of each table.
+    current: BTreeMap<TableId, DataKey>,
+
+    /// Unwrapped data keys by key id and wrapped bytes.
+    unwrapped: Mutex<HashMap<(String, Vec<u8>), Aes256Gcm>>,
+}
+
+impl<S: MetadataLogStore> EncryptedLogStore<S> {
+    pub fn new(inner: S, provider: Arc<dyn KeyProvider>) -> Self {
+        Self {
+            inner,
+            provider,
+            current: BTreeMap::new(),
+            unwrapped: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The wrapped store, holding encrypted events.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Encrypt the table's future events with a fresh data key, wrapped
+    /// by the provider's current key. Returns that key's id.
+    pub fn rotate_data_key(&mut self, table_id: &TableId) -> Result<String, LogError> {
+        let key = self.generate_data_key()?;
+        let key_id = key.key_id.clone();
+        self.current.insert(table_id.clone(), key);
+        Ok(key_id)
+    }
+
+    fn generate_data_key(&self) -> Result<DataKey, LogError> {
+        let key_id = self.provider.current_key_id()?;
+        let key: [u8; 32] = Aes256Gcm::generate_key(OsRng).into();
+        let wrapped = self.provider.wrap(&key_id, &key)?;
+
+        Ok(DataKey {
+            key_id,
+            wrapped,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+
+    /// The table's data key: the one of its latest record after a
+    /// restart, or a new one for a new table.
+    fn data_key(&mut self, table_id: &TableId) -> Result<&DataKey, LogError> {
+        if !self.current.contains_key(table_id) {
+            let head = self.inner.current_version(table_id)?;
+            let key = match head {
+                0 => self.generate_data_key()?,
+                _ => {
+                    let stored = self
+                        .inner
+                        .stream(table_id, VersionRange::between(head, head))?
+                        .next()
+                        .ok_or_else(|| {
+                            LogError::Corrupt(format!("head of table {table_id} is unreadable"))
+                        })??;
+                    let sealed = SealedRecord::parse(&stored)?;
+                    DataKey {
+                        cipher: self.cipher(sealed.key_id, sealed.wrapped)?,
+                        key_id: sealed.key_id.to_string(),
+                        wrapped: sealed.wrapped.to_vec(),
+                    }
+                }
+            };
+            self.current.insert(table_id.clone(), key);
+        }
+        Ok(&self.current[table_id])
+    }
+
+    /// Unwrap (or recall) a data key.
+    fn cipher(&self, key_id: &str, wrapped: &[u8]) -> Result<Aes256Gcm, LogError> {
+        let mut cache = self
+            .unwrapped
+            .lock()
+            .map_err(|_| LogError::Storage("data key cache lock poisoned".into()))?;
+
+        let entry = (key_id.to_string(), wrapped.to_vec());
+        if let Some(cipher) = cache.get(&entry) {
+            return Ok(cipher.clone());
+        }
+
+        let key = self.provider.unwrap(key_id, wrapped)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        cache.insert(entry, cipher.clone());
+        Ok(cipher)
+    }
+
+    fn encrypt(&mut self, event: &TableEvent) -> Result<TableEvent, LogError> {
+        let key = self.data_key(&event.table_id)?;
+        let ciphertext = seal(
+            &key.cipher,
+            &record_aad(&event.table_id, event.version),
+            &codec::encode_binary(event)?,
+        )?;
+
+        let mut body = MAGIC.to_vec();
+        body.push(FORMAT_VERSION);
+        put_bytes(&mut body, key.key_id.as_bytes());
+        put_bytes(&mut body, &key.wrapped);
+        body.extend_from_slice(&ciphertext);
+
+        Ok(TableEvent {
+            table_id: event.table_id.clone(),
+            version: event.version,
+            event_type: event.event_type.clone(),
+            payload: EventPayload::Opaque(body),
+            envelope: EventEnvelope {
+                committed_at: event.envelope.committed_at,
+                idempotency_key: event.envelope.idempotency_key.clone(),
+                ..Default::default()
+            },
+            signature: None,
+            hash: None,
+            legacy: false,
+        })
+    }
+
+    fn decrypt(&self, stored: TableEvent) -> Result<TableEvent, LogError> {
+        let sealed = SealedRecord::parse(&stored)?;
+        let cipher = self.cipher(sealed.key_id, sealed.wrapped)?;
+
+        let tampered = || LogError::Tampered {
+            table_id: stored.table_id.clone(),
+            version: stored.version,
+        };
+
+        let plaintext = open(
+            &cipher,
+            &record_aad(&stored.table_id, stored.version),
+            sealed.ciphertext,
+        )
+        .ok_or_else(tampered)?;
+
+        codec::decode_binary(&plaintext).map_err(|e| {
+            LogError::Corrupt(format!(
+                "undecodable event {} of table {}: {e}",
+                stored.version, stored.table_id
+            ))
+        })
+    }
+}
+
+impl<S: MetadataLogStore> MetadataLogStore for EncryptedLogStore<S> {
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError> {
+        let stored = self.encrypt(event)?;
+        self.inner.append(&stored)
+    }
+
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError> {
+        self.inner.find_idempotency_key(table_id, key)
+    }
+
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError> {
+        let events = self.inner.stream(table_id, range)?;
+        Ok(Box::new(events.map(move |stored| {
+            stored.and_then(|stored| self.decrypt(stored))
+        })))
+    }
+
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        self.inner.current_version(table_id)
+    }
+
+    fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        self.inner.tables()
+    }
+
+    fn first_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        self.inner.first_version(table_id)
+    }
+
+    fn supports_truncation(&self) -> bool {
+        self.inner.supports_truncation()
+    }
+
+    fn truncate_before(&mut self, table_id: &TableId, version: Version) -> Result<(), LogError> {
+        self.inner.truncate_before(table_id, version)
+    }
+
+    fn seed(&mut self, anchor: &TableEvent) -> Result<(), LogError> {
+        let stored = self.encrypt(anchor)?;
+        self.inner.seed(&stored)
+    }
+}
+
+/// Parsed stored payload of an encrypted event.
+struct SealedRecord<'a> {
+    key_id: &'a str,
+    wrapped: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+impl<'a> SealedRecord<'a> {
+    fn parse(stored: &'a TableEvent) -> Result<Self, LogError> {
+        let corrupt = |reason: &str| {
+            LogError::Corrupt(format!(
+                "event {} of table {} {reason}",
+                stored.version, stored.table_id
+            ))
+        };
+
+        let body = match &stored.payload {
+            EventPayload::Opaque(body) if body.starts_with(MAGIC) => &body[MAGIC.len()..],
+            _ => return Err(corrupt("is not encrypted")),
+        };
+
+        let (&format, rest) = body.split_first().ok_or_else(|| corrupt("is truncated"))?;
+        if format != FORMAT_VERSION {
+            return Err(corrupt(&format!(
+                "uses unsupported encryption format {format}"
+            )));
+        }
+
+        let (key_id, rest) = take_bytes(rest).ok_or_else(|| corrupt("is truncated"))?;
+        let (wrapped, ciphertext) = take_bytes(rest).ok_or_else(|| corrupt("is truncated"))?;
+        let key_id = std::str::from_utf8(key_id).map_err(|_| corrupt("has an invalid key id"))?;
+
+        Ok(Self {
+            key_id,
+            wrapped,
+            ciphertext,
+        })
+    }
+}
+
+/// Split a u32-length-prefixed byte string off `buf`.
+fn take_bytes(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+    let rest = &buf[4..];
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+fn record_aad(table_id: &TableId, version: Version) -> Vec<u8> {
+    let mut aad = RECORD_DOMAIN.to_vec();
+    aad.extend_from_slice(table_id.0.as_bytes());
+    aad.extend_from_slice(&version.to_be_bytes());
+    aad
+}
+
+/// Encrypt with a random nonce, returning `nonce | ciphertext`.
+fn seal(cipher: &Aes256Gcm, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, LogError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| LogError::Storage("encryption failed".into()))?;
+
+    let mut out = nonce.to_vec();
+    out.extend(ciphertext);
+    Ok(out)
+}
+
+/// Decrypt `nonce | ciphertext`; `None` if it fails authentication.
+fn open(cipher: &Aes256Gcm, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::conformance::{StoreFactory, TempLocations};
+    use crate::log::payload::{Schema, SchemaField, TableCreatedPayload};
+    use crate::log::{EventType, InMemoryLogStore, MetadataLog, SegmentedLogStore, TypedPayload};
+    use uuid::Uuid;
+
+    fn table() -> TableId {
+        TableId(Uuid::from_u128(9))
+    }
+
+    fn provider(dir: &Path) -> Arc<LocalKeyProvider> {
+        Arc::new(LocalKeyProvider::open(dir.join("keys.json")).unwrap())
+    }
+
+    fn created(version: Version) -> TableEvent {
+        TableEvent::new(
+            table(),
+            version,
+            EventType::TableCreated,
+            TypedPayload::TableCreated(TableCreatedPayload {
+                schema: Schema {
+                    schema_id: 0,
+                    fields: vec![SchemaField {
+                        id: 1,
+                        name: "patient_ssn".into(),
+                        field_type: "string".into(),
+                        required: true,
+                    }],
+                },
+                location: None,
+                properties: Default::default(),
+            }),
+        )
+    }
+
+    fn event(version: Version) -> TableEvent {
+        TableEvent::new(table(), version, EventType::SnapshotAdded, vec![1, 2, 3])
+    }
+
+    struct Factory(TempLocations, tempfile::TempDir);
+
+    impl StoreFactory for Factory {
+        type Store = EncryptedLogStore<SegmentedLogStore>;
+        type Location = PathBuf;
+
+        fn create_location(&mut self) -> PathBuf {
+            self.0.next()
+        }
+
+        fn open(&mut self, dir: &PathBuf) -> Self::Store {
+            EncryptedLogStore::new(
+                SegmentedLogStore::open(dir).unwrap(),
+                provider(self.1.path()),
+            )
+        }
+    }
+
+    mod conformance {
+        use super::*;
+        crate::store_conformance_tests!(Factory(
+            TempLocations::new(),
+            tempfile::tempdir().unwrap()
+        ));
+    }
+
+    #[test]
+    fn content_is_encrypted_and_chain_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut plain = MetadataLog::new(InMemoryLogStore::default());
+        plain.append(created(1)).unwrap();
+        plain.append(event(2)).unwrap();
+
+        let mut store = EncryptedLogStore::new(InMemoryLogStore::default(), provider(dir.path()));
+        for event in plain.replay(&table()).unwrap() {
+            store.append(&event).unwrap();
+        }
+
+        let stored = store.inner().load(&table()).unwrap();
+        let raw = serde_json::to_string(&stored).unwrap();
+        assert!(!raw.contains("patient_ssn"));
+        assert!(stored.iter().all(|e| e.hash.is_none()));
+
+        let events = MetadataLog::new(store).replay(&table()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(serde_json::to_string(&events[0])
+            .unwrap()
+            .contains("patient_ssn"));
+    }
+
+    #[test]
+    fn missing_key_fails_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+
+        let keys = provider(dir.path());
+        let key_id = keys.current_key_id().unwrap();
+        let mut store = EncryptedLogStore::new(SegmentedLogStore::open(&path).unwrap(), keys);
+        store.append(&created(1)).unwrap();
+        drop(store);
+
+        let mut store = EncryptedLogStore::new(
+            SegmentedLogStore::open(&path).unwrap(),
+            provider(other.path()),
+        );
+        let missing = LogError::MissingKey { key_id };
+        assert_eq!(store.load(&table()).unwrap_err(), missing);
+        assert_eq!(store.append(&event(2)).unwrap_err(), missing);
+
+        // Plaintext records are refused rather than passed through.
+        let plain = SegmentedLogStore::open(dir.path().join("plain")).unwrap();
+        let mut plain = EncryptedLogStore::new(plain, provider(dir.path()));
+        plain.inner.append(&event(1)).unwrap();
+        assert!(matches!(plain.load(&table()), Err(LogError::Corrupt(_))));
+    }
+
+    #[test]
+    fn rotated_keys_keep_old_records_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = provider(dir.path());
+        let path = dir.path().join("log");
+
+        let first = keys.current_key_id().unwrap();
+        let mut store =
+            EncryptedLogStore::new(SegmentedLogStore::open(&path).unwrap(), keys.clone());
+        store.append(&created(1)).unwrap();
+
+        let second = keys.rotate().unwrap();
+        assert_ne!(second, first);
+        assert_eq!(store.rotate_data_key(&table()).unwrap(), second);
+        store.append(&event(2)).unwrap();
+        drop(store);
+
+        // Reopened stores continue with the table's latest data key.
+        let keys = provider(dir.path());
+        let mut store = EncryptedLogStore::new(SegmentedLogStore::open(&path).unwrap(), keys);
+        store.append(&event(3)).unwrap();
+
+        let key_ids: Vec<_> = store
+            .inner()
+            .load(&table())
+            .unwrap()
+            .iter()
+            .map(|e| SealedRecord::parse(e).unwrap().key_id.to_string())
+            .collect();
+        assert_eq!(key_ids, [first, second.clone(), second]);
+        assert_eq!(store.load(&table()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn moved_records_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = EncryptedLogStore::new(InMemoryLogStore::default(), provider(dir.path()));
+        store.append(&event(1)).unwrap();
+        store.append(&event(2)).unwrap();
+
+        let stored = store.inner().load(&table()).unwrap();
+        let mut moved = InMemoryLogStore::default();
+        let mut swapped = stored[1].clone();
+        swapped.version = 1;
+        moved.append(&swapped).unwrap();
+
+        let moved = EncryptedLogStore {
+            inner: moved,
+            ..store
+        };
+        assert_eq!(
+            moved.load(&table()).unwrap_err(),
+            LogError::Tampered {
+                table_id: table(),
+                version: 1
+            }
+        );
+    }
+}
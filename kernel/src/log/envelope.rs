@@ -0,0 +1,245 @@
+// Event Envelope
+//
+// Structured provenance carried by every event: who produced it, from
+// which engine and job, when the log committed it, and how it relates to
+// other events. The envelope travels with the event through storage and
+// replay, and can be queried with an `EventFilter`.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::{ActorId, EventType, TableEvent, TableId};
+
+/// Compute engine (or tool) that produced an event.
+///
+/// Serialized as a lowercase name; unknown names round-trip as `Other`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum EngineKind {
+    Spark,
+    Flink,
+    Trino,
+    Other(String),
+}
+
+impl EngineKind {
+    pub fn name(&self) -> &str {
+        match self {
+            EngineKind::Spark => "spark",
+            EngineKind::Flink => "flink",
+            EngineKind::Trino => "trino",
+            EngineKind::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for EngineKind {
+    fn from(name: String) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "spark" => EngineKind::Spark,
+            "flink" => EngineKind::Flink,
+            "trino" => EngineKind::Trino,
+            _ => EngineKind::Other(name),
+        }
+    }
+}
+
+impl From<EngineKind> for String {
+    fn from(engine: EngineKind) -> Self {
+        engine.name().to_string()
+    }
+}
+
+impl std::fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Provenance metadata of an event.
+///
+/// Every field is optional so that events written before the envelope
+/// existed (or by producers that know little about themselves) remain
+/// valid.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    /// Unix timestamp in milliseconds, stamped by `MetadataLog::append`
+    /// when the producer did not set one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committed_at: Option<u64>,
+
+    /// Producer of the event (covered by the signature).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<ActorId>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<EngineKind>,
+
+    /// Engine-side job (e.g. a Spark application or Flink job id).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+
+    /// Individual run or attempt of the job.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+
+    /// Groups events belonging to one logical operation, possibly across
+    /// tables.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+
+    /// Client-chosen key identifying a commit attempt. Retrying an
+    /// append with the same key returns the original version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+
+impl EventEnvelope {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Selects events by table, event type, provenance and commit time.
+///
+/// Unset criteria match everything; set criteria must all match.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub table_id: Option<TableId>,
+    pub event_type: Option<EventType>,
+    pub actor: Option<ActorId>,
+    pub engine: Option<EngineKind>,
+    pub job_id: Option<String>,
+    pub run_id: Option<String>,
+    pub correlation_id: Option<String>,
+
+    /// Tags that must all be present with the given values.
+    pub tags: BTreeMap<String, String>,
+
+    /// Inclusive lower bound on `committed_at` (Unix milliseconds).
+    pub committed_after: Option<u64>,
+
+    /// Inclusive upper bound on `committed_at` (Unix milliseconds).
+    pub committed_before: Option<u64>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &TableEvent) -> bool {
+        let envelope = &event.envelope;
+
+        fn check<T: PartialEq>(wanted: &Option<T>, actual: &Option<T>) -> bool {
+            wanted.is_none() || wanted == actual
+        }
+
+        let in_window = match envelope.committed_at {
+            Some(at) => {
+                self.committed_after.is_none_or(|after| at >= after)
+                    && self.committed_before.is_none_or(|before| at <= before)
+            }
+            None => self.committed_after.is_none() && self.committed_before.is_none(),
+        };
+
+        self.table_id
+            .as_ref()
+            .is_none_or(|id| *id == event.table_id)
+            && self
+                .event_type
+                .as_ref()
+                .is_none_or(|t| *t == event.event_type)
+            && check(&self.actor, &envelope.actor)
+            && check(&self.engine, &envelope.engine)
+            && check(&self.job_id, &envelope.job_id)
+            && check(&self.run_id, &envelope.run_id)
+            && check(&self.correlation_id, &envelope.correlation_id)
+            && self
+                .tags
+                .iter()
+                .all(|(k, v)| envelope.tags.get(k) == Some(v))
+            && in_window
+    }
+}
+
+/// Current wall-clock time in Unix milliseconds.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn spark_event(job: &str, committed_at: u64) -> TableEvent {
+        TableEvent::new(TableId(Uuid::nil()), 1, EventType::SnapshotAdded, vec![]).with_envelope(
+            EventEnvelope {
+                committed_at: Some(committed_at),
+                engine: Some(EngineKind::Spark),
+                job_id: Some(job.into()),
+                tags: BTreeMap::from([("team".into(), "ingest".into())]),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn engine_kind_round_trips_by_name() {
+        let json =
+            serde_json::to_string(&[EngineKind::Flink, EngineKind::Other("dbt".into())]).unwrap();
+        assert_eq!(json, r#"["flink","dbt"]"#);
+
+        let parsed: Vec<EngineKind> = serde_json::from_str(r#"["Spark","dbt"]"#).unwrap();
+        assert_eq!(
+            parsed,
+            vec![EngineKind::Spark, EngineKind::Other("dbt".into())]
+        );
+    }
+
+    #[test]
+    fn empty_envelope_is_omitted_from_json() {
+        let event = TableEvent::new(TableId(Uuid::nil()), 1, EventType::TableCreated, vec![]);
+        let json = serde_json::to_value(&event).unwrap();
+        assert!(json.get("envelope").is_none());
+
+        // Events written before envelopes existed still parse.
+        let old: TableEvent = serde_json::from_value(json).unwrap();
+        assert!(old.envelope.is_empty());
+    }
+
+    #[test]
+    fn filter_matches_all_set_criteria() {
+        let event = spark_event("nightly", 1_000);
+
+        let mut filter = EventFilter {
+            engine: Some(EngineKind::Spark),
+            job_id: Some("nightly".into()),
+            committed_after: Some(500),
+            ..Default::default()
+        };
+        assert!(filter.matches(&event));
+
+        filter.tags.insert("team".into(), "ingest".into());
+        assert!(filter.matches(&event));
+
+        filter.tags.insert("team".into(), "ml".into());
+        assert!(!filter.matches(&event));
+
+        let late = EventFilter {
+            committed_after: Some(2_000),
+            ..Default::default()
+        };
+        assert!(!late.matches(&event));
+        assert!(!late.matches(&TableEvent::new(
+            TableId(Uuid::nil()),
+            1,
+            EventType::TableCreated,
+            vec![]
+        )));
+    }
+}
@@ -1,31 +1,182 @@
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use uuid::Uuid;
 
+pub mod chain;
+pub mod codec;
+mod compaction;
+pub mod conformance;
+mod dedup;
+mod encryption;
+mod envelope;
+mod file;
+mod object;
+mod object_store;
+pub mod payload;
+mod raft;
+mod record;
+mod segmented;
+mod shared;
+pub mod signing;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 mod store;
-pub use store::MetadataLogStore;
+pub mod transfer;
+pub mod upcast;
+pub use codec::EventEncoding;
+pub use compaction::{
+    CompactionRecord, CompactionStore, FileCompactionStore, InMemoryCompactionStore,
+    SnapshotLineage,
+};
+pub use dedup::DEFAULT_DEDUP_WINDOW;
+pub use encryption::{EncryptedLogStore, KeyProvider, LocalKeyProvider};
+pub use envelope::{EngineKind, EventEnvelope, EventFilter};
+pub use file::FileLogStore;
+pub use object::ObjectLogStore;
+pub use object_store::{LocalObjectStore, ObjectStore, PutOutcome};
+pub use payload::{EventPayload, TypedPayload};
+pub use raft::{
+    FileRaftStorage, HardState, MemoryRaftStorage, NodeId, RaftCluster, RaftLogStore, RaftNode,
+    RaftStorage, SavedRaftState, TcpTransport, Transport,
+};
+pub use segmented::{SegmentConfig, SegmentedLogStore};
+pub use shared::SharedMetadataLog;
+pub use signing::{EventSigner, TrustStore};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteLogStore;
+pub use store::{AppendOutcome, EventStream, MetadataLogStore, VersionRange};
 
 /// Logical version of a table.
 pub type Version = u64;
 
 /// Stable identifier for a table.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct TableId(pub Uuid);
 
+impl std::fmt::Display for TableId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::str::FromStr for TableId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::parse_str(s).map(TableId)
+    }
+}
+
+/// Identity of the engine, service or person that produced an event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ActorId(pub String);
+
+impl std::fmt::Display for ActorId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EventType {
     TableCreated,
+
+    /// A writer opened a mutation. Schema and snapshot events are only
+    /// legal while it is open, and must reference it.
+    MutationStarted,
+
+    /// The open mutation's changes took effect.
+    MutationCommitted,
+
+    /// The open mutation was abandoned; its changes never took effect.
+    MutationAborted,
+
     SchemaUpdated,
     SnapshotAdded,
     SnapshotRemoved,
+    PropertiesUpdated,
+
+    /// A rollback to an earlier snapshot began.
+    RollbackStarted,
+
+    /// The table was restored to the rollback's target snapshot.
+    RollbackCompleted,
+
+    /// The rollback could not be carried out; the table stays rolling
+    /// back until a retry completes.
+    RollbackFailed,
+
+    /// The table became read-only, e.g. for the duration of an audit.
+    TableFrozen,
+
+    /// A frozen table accepts writes again.
+    TableUnfrozen,
+
+    /// Only allow-listed engines may still write to the table.
+    TableDeprecated,
+
+    /// The table was dropped. Nothing but an undrop within the grace
+    /// period is accepted afterwards.
+    TableDropped,
+
+    /// A dropped table was restored within its grace period.
+    TableUndropped,
 }
 
+/// A single table mutation.
+///
+/// Serialized with an explicit format version; events in older formats
+/// are upcast when decoded (see `upcast`).
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "upcast::EventRecord", try_from = "serde_json::Value")]
 pub struct TableEvent {
     pub table_id: TableId,
     pub version: Version,
     pub event_type: EventType,
-    pub payload: Vec<u8>,
+    pub payload: EventPayload,
+
+    /// Provenance: commit time, actor, engine, job and correlation ids.
+    pub envelope: EventEnvelope,
+
+    /// Hex-encoded Ed25519 signature over the canonical encoding.
+    pub signature: Option<String>,
+
+    /// Chained content hash, assigned by `MetadataLog::append`.
+    pub hash: Option<String>,
+
+    /// A change written before mutations were explicit, which references
+    /// none. Set by upcasting (see `upcast`) and outside the canonical
+    /// encoding, so old chains keep verifying; `MetadataLog::append`
+    /// rejects it, only imports may carry it.
+    pub legacy: bool,
+}
+
+impl TableEvent {
+    /// Create an unsigned, unsealed event with an empty envelope.
+    pub fn new(
+        table_id: TableId,
+        version: Version,
+        event_type: EventType,
+        payload: impl Into<EventPayload>,
+    ) -> Self {
+        Self {
+            table_id,
+            version,
+            event_type,
+            payload: payload.into(),
+            envelope: EventEnvelope::default(),
+            signature: None,
+            hash: None,
+            legacy: false,
+        }
+    }
+
+    pub fn with_envelope(mut self, envelope: EventEnvelope) -> Self {
+        self.envelope = envelope;
+        self
+    }
 }
 
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
@@ -33,19 +184,80 @@ pub enum LogError {
     #[error("version conflict: expected {expected}, got {actual}")]
     VersionConflict { expected: Version, actual: Version },
 
+    #[error("table {table_id} is at version {head}, expected {expected}")]
+    HeadMismatch {
+        table_id: TableId,
+        expected: Version,
+        head: Version,
+    },
+
     #[error("storage error: {0}")]
     Storage(String),
+
+    #[error("corrupt log: {0}")]
+    Corrupt(String),
+
+    #[error("tamper detected: event {version} of table {table_id} does not match its hash chain")]
+    Tampered { table_id: TableId, version: Version },
+
+    #[error("signature rejected for event {version} of table {table_id}: {reason}")]
+    SignatureRejected {
+        table_id: TableId,
+        version: Version,
+        reason: String,
+    },
+
+    #[error("event {version} of table {table_id} is committed at {committed_at}, before the event it follows ({previous})")]
+    Backdated {
+        table_id: TableId,
+        version: Version,
+        committed_at: u64,
+        previous: u64,
+    },
+
+    #[error("invalid payload for event {version} of table {table_id}: {reason}")]
+    InvalidPayload {
+        table_id: TableId,
+        version: Version,
+        reason: String,
+    },
+
+    #[error("events of table {table_id} before version {first_available} were compacted")]
+    Compacted {
+        table_id: TableId,
+        first_available: Version,
+    },
+
+    #[error("encryption key {key_id} is not available")]
+    MissingKey { key_id: String },
 }
 
 /// In-memory store (reference implementation).
 #[derive(Default)]
 pub struct InMemoryLogStore {
-    events: VecDeque<TableEvent>,
+    tables: BTreeMap<TableId, Vec<TableEvent>>,
+    dedup: dedup::DedupIndex,
+}
+
+impl InMemoryLogStore {
+    /// Remember idempotency keys of the last `window` versions per table.
+    pub fn with_dedup_window(mut self, window: u64) -> Self {
+        self.dedup = dedup::DedupIndex::new(window);
+        for event in self.tables.values().flatten() {
+            self.dedup.record(event);
+        }
+        self
+    }
 }
 
 impl MetadataLogStore for InMemoryLogStore {
-    fn append(&mut self, event: &TableEvent) -> Result<(), LogError> {
-        let expected = match self.events.back() {
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError> {
+        if let Some(version) = self.dedup.lookup(event) {
+            return Ok(AppendOutcome::Duplicate(version));
+        }
+
+        // Only create the table once the event is accepted
+        let expected = match self.tables.get(&event.table_id).and_then(|e| e.last()) {
             Some(last) => last.version + 1,
             None => 1,
         };
@@ -57,38 +269,733 @@ impl MetadataLogStore for InMemoryLogStore {
             });
         }
 
-        self.events.push_back(event.clone());
-        Ok(())
+        self.tables
+            .entry(event.table_id.clone())
+            .or_default()
+            .push(event.clone());
+        self.dedup.record(event);
+        Ok(AppendOutcome::Appended(event.version))
+    }
+
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError> {
+        Ok(self.dedup.find(table_id, key))
+    }
+
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError> {
+        let events = self.tables.get(table_id).map_or(&[][..], Vec::as_slice);
+        let base = events.first().map_or(1, |e| e.version);
+
+        // Versions are dense from the first retained one, so bounds map
+        // to indexes.
+        let start = range.from_version.saturating_sub(base) as usize;
+        let end = range.to_version.map_or(events.len(), |to| {
+            (to.saturating_add(1).saturating_sub(base) as usize).min(events.len())
+        });
+        let events = events.get(start..end).unwrap_or(&[]);
+
+        Ok(Box::new(events.iter().cloned().map(Ok)))
+    }
+
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        Ok(self
+            .tables
+            .get(table_id)
+            .and_then(|events| events.last())
+            .map(|e| e.version)
+            .unwrap_or(0))
     }
 
-    fn load(&self) -> Result<Vec<TableEvent>, LogError> {
-        Ok(self.events.iter().cloned().collect())
+    fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        Ok(self.tables.keys().cloned().collect())
     }
 
-    fn current_version(&self) -> Result<Version, LogError> {
-        Ok(self.events.back().map(|e| e.version).unwrap_or(0))
+    fn first_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        Ok(self
+            .tables
+            .get(table_id)
+            .and_then(|events| events.first())
+            .map_or(1, |e| e.version))
+    }
+
+    fn supports_truncation(&self) -> bool {
+        true
+    }
+
+    fn truncate_before(&mut self, table_id: &TableId, version: Version) -> Result<(), LogError> {
+        if let Some(events) = self.tables.get_mut(table_id) {
+            let discard = events
+                .iter()
+                .take(events.len().saturating_sub(1))
+                .take_while(|e| e.version < version)
+                .count();
+            events.drain(..discard);
+        }
+        Ok(())
+    }
+
+    fn seed(&mut self, anchor: &TableEvent) -> Result<(), LogError> {
+        let head = self.current_version(&anchor.table_id)?;
+        if head > 0 {
+            return Err(LogError::VersionConflict {
+                expected: head + 1,
+                actual: anchor.version,
+            });
+        }
+
+        self.tables
+            .insert(anchor.table_id.clone(), vec![anchor.clone()]);
+        self.dedup.record(anchor);
+        Ok(())
     }
 }
 
 /// Semantic metadata log backed by a store.
+///
+/// The log seals every appended event into its table's hash chain and
+/// verifies the chain on every read.
 pub struct MetadataLog<S: MetadataLogStore> {
     store: S,
+    trust: Option<Arc<TrustStore>>,
+    compactions: Option<Box<dyn CompactionStore>>,
 }
 
 impl<S: MetadataLogStore> MetadataLog<S> {
     pub fn new(store: S) -> Self {
-        Self { store }
+        Self {
+            store,
+            trust: None,
+            compactions: None,
+        }
+    }
+
+    /// Enable strict signature verification on reads.
+    ///
+    /// Every event must then be signed by a key the trust store lists for
+    /// its actor; unsigned or badly signed events fail the read.
+    pub fn with_trust_store(mut self, trust: TrustStore) -> Self {
+        self.trust = Some(Arc::new(trust));
+        self
+    }
+
+    /// Keep compaction records in `compactions`.
+    ///
+    /// Required to compact tables and to replay tables whose old events
+    /// the store has discarded.
+    pub fn with_compaction_store(mut self, compactions: impl CompactionStore + 'static) -> Self {
+        self.compactions = Some(Box::new(compactions));
+        self
     }
 
-    pub fn append(&mut self, event: TableEvent) -> Result<(), LogError> {
+    /// Append an event, chaining it to the current head of its table.
+    ///
+    /// Rejects typed payloads that are malformed or do not fit the event
+    /// type, changes that reference no mutation and legacy events, and
+    /// stamps the commit time, replacing any the producer set.
+    /// An event whose idempotency key was recently committed is not
+    /// written again; its original version is returned instead.
+    pub fn append(&mut self, mut event: TableEvent) -> Result<AppendOutcome, LogError> {
+        if event.legacy {
+            return Err(LogError::InvalidPayload {
+                table_id: event.table_id,
+                version: event.version,
+                reason: "only imported events may be legacy changes".into(),
+            });
+        }
+        event.envelope.committed_at = Some(envelope::now_millis());
+        self.append_committed(event)
+    }
+
+    /// Append an event keeping its commit time, stamping one only if it
+    /// has none.
+    fn append_committed(&mut self, mut event: TableEvent) -> Result<AppendOutcome, LogError> {
+        if let Some(version) = self.committed_key(&event)? {
+            return Ok(AppendOutcome::Duplicate(version));
+        }
+
+        check_payload(&event)?;
+
+        if event.envelope.committed_at.is_none() {
+            event.envelope.committed_at = Some(envelope::now_millis());
+        }
+
+        let prev_hash = match event.version {
+            0 | 1 => None,
+            v => self.hash_at(&event.table_id, v - 1)?,
+        };
+
+        event.hash = Some(chain::event_hash(prev_hash.as_deref(), &event)?);
         self.store.append(&event)
     }
 
-    pub fn replay(&self) -> Result<Vec<TableEvent>, LogError> {
-        self.store.load()
+    /// Append an event exported from another log, keeping its chain hash
+    /// and commit time.
+    ///
+    /// A sealed event must chain onto the current head of its table, and
+    /// in strict mode carry a trusted signature; unsealed events are
+    /// appended like new ones. Either is rejected if it was committed
+    /// before the event it follows. Unlike `append`, an event whose
+    /// idempotency key was already committed is rejected, since skipping
+    /// it would leave a gap in the imported history.
+    pub fn import(&mut self, mut event: TableEvent) -> Result<Version, LogError> {
+        self.check_commit_time(&event)?;
+
+        let Some(hash) = event.hash.take() else {
+            return match self.append_committed(event)? {
+                AppendOutcome::Appended(version) => Ok(version),
+                AppendOutcome::Duplicate(version) => Err(duplicate_on_import(version)),
+            };
+        };
+
+        check_payload(&event)?;
+
+        let expected = self.current_version(&event.table_id)? + 1;
+        if event.version != expected {
+            return Err(LogError::VersionConflict {
+                expected,
+                actual: event.version,
+            });
+        }
+
+        let prev_hash = match event.version {
+            1 => None,
+            v => self.hash_at(&event.table_id, v - 1)?,
+        };
+        if chain::event_hash(prev_hash.as_deref(), &event)? != hash {
+            return Err(LogError::Tampered {
+                table_id: event.table_id,
+                version: event.version,
+            });
+        }
+        if let Some(trust) = &self.trust {
+            trust.verify(&event)?;
+        }
+
+        event.hash = Some(hash);
+        match self.store.append(&event)? {
+            AppendOutcome::Appended(version) => Ok(version),
+            AppendOutcome::Duplicate(version) => Err(duplicate_on_import(version)),
+        }
+    }
+
+    /// Reject an event committed before the current head of its table.
+    fn check_commit_time(&self, event: &TableEvent) -> Result<(), LogError> {
+        let Some(committed_at) = event.envelope.committed_at else {
+            return Ok(());
+        };
+        let head = self.current_version(&event.table_id)?;
+        if head == 0 {
+            return Ok(());
+        }
+
+        let previous = self
+            .store
+            .stream(&event.table_id, VersionRange::between(head, head))?
+            .next()
+            .transpose()?
+            .and_then(|e| e.envelope.committed_at);
+        match previous {
+            Some(previous) if committed_at < previous => Err(LogError::Backdated {
+                table_id: event.table_id.clone(),
+                version: event.version,
+                committed_at,
+                previous,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Start an empty table from a compaction record and its anchor
+    /// event, as exported from a log that compacted the table.
+    ///
+    /// The anchor must be the event the record is bound to; later events
+    /// are then imported as usual. Requires a compaction store and a
+    /// store that can discard events. The record is saved first, so an
+    /// interrupted import can simply be retried.
+    pub fn import_compaction(
+        &mut self,
+        record: &CompactionRecord,
+        anchor: &TableEvent,
+    ) -> Result<(), LogError> {
+        self.check_truncation()?;
+
+        let head = self.current_version(&anchor.table_id)?;
+        if head > 0 {
+            return Err(LogError::VersionConflict {
+                expected: head + 1,
+                actual: anchor.version,
+            });
+        }
+
+        if record.table_id != anchor.table_id
+            || record.version != anchor.version
+            || anchor.hash.as_ref() != Some(&record.hash)
+        {
+            return Err(LogError::Tampered {
+                table_id: anchor.table_id.clone(),
+                version: anchor.version,
+            });
+        }
+        check_payload(anchor)?;
+        if let Some(trust) = &self.trust {
+            record.verify(trust)?;
+            trust.verify(anchor)?;
+        }
+
+        let compactions = self
+            .compactions
+            .as_mut()
+            .ok_or_else(|| LogError::Storage("no compaction store configured".into()))?;
+        compactions.save(record)?;
+
+        self.store.seed(anchor)
+    }
+
+    /// Append an event only if its table is still at `expected_version`.
+    ///
+    /// Fails with `LogError::HeadMismatch` if another writer committed
+    /// first. The event must be for version `expected_version + 1`.
+    ///
+    /// A retried event whose first attempt did commit is recognized by
+    /// its idempotency key and reported as a duplicate, not a conflict.
+    pub fn append_if(
+        &mut self,
+        expected_version: Version,
+        event: TableEvent,
+    ) -> Result<AppendOutcome, LogError> {
+        if let Some(version) = self.committed_key(&event)? {
+            return Ok(AppendOutcome::Duplicate(version));
+        }
+
+        let head = self.current_version(&event.table_id)?;
+        if head != expected_version {
+            return Err(LogError::HeadMismatch {
+                table_id: event.table_id,
+                expected: expected_version,
+                head,
+            });
+        }
+
+        if event.version != expected_version + 1 {
+            return Err(LogError::VersionConflict {
+                expected: expected_version + 1,
+                actual: event.version,
+            });
+        }
+
+        self.append(event)
+    }
+
+    /// Load and verify a table's full history.
+    ///
+    /// Fails with `LogError::Compacted` once old events were compacted;
+    /// they are then only in the archive.
+    pub fn replay(&self, table_id: &TableId) -> Result<Vec<TableEvent>, LogError> {
+        self.stream(table_id, VersionRange::all())?.collect()
     }
 
-    pub fn current_version(&self) -> Result<Version, LogError> {
-        self.store.current_version()
+    /// Stream a table's events within `range` without materializing the log.
+    ///
+    /// The hash chain is verified as events are read. A range that does
+    /// not start at version 1 is anchored on the stored hash of the
+    /// preceding event, so it must start after the first version the
+    /// store still holds.
+    pub fn stream(
+        &self,
+        table_id: &TableId,
+        range: VersionRange,
+    ) -> Result<EventStream<'_>, LogError> {
+        let first_available = self.first_version(table_id)?;
+        if first_available > 1 && range.from_version < first_available {
+            return Err(LogError::Compacted {
+                table_id: table_id.clone(),
+                first_available,
+            });
+        }
+
+        let prev_hash = match range.from_version {
+            0 | 1 => None,
+            v => self.hash_at(table_id, v - 1)?,
+        };
+
+        let mut verifier = chain::ChainVerifier::resume(prev_hash);
+        let trust = self.trust.clone();
+
+        let events = self.store.stream(table_id, range)?.map(move |item| {
+            let event = item?;
+            verifier.verify(&event)?;
+            if let Some(trust) = &trust {
+                trust.verify(&event)?;
+            }
+            Ok(event)
+        });
+
+        Ok(Box::new(events))
+    }
+
+    /// Verified events of all tables (or the filter's table) that match
+    /// `filter`, ordered by table and version.
+    pub fn query(&self, filter: &EventFilter) -> Result<Vec<TableEvent>, LogError> {
+        let tables = match &filter.table_id {
+            Some(id) => vec![id.clone()],
+            None => self.tables()?,
+        };
+
+        let mut matched = Vec::new();
+        for table_id in &tables {
+            let range = VersionRange::starting_at(self.first_version(table_id)?);
+            for event in self.stream(table_id, range)? {
+                let event = event?;
+                if filter.matches(&event) {
+                    matched.push(event);
+                }
+            }
+        }
+        Ok(matched)
+    }
+
+    /// First version of a table that can be read and verified: 1, or the
+    /// version after the oldest event the store kept after compaction.
+    pub fn first_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        match self.store.first_version(table_id)? {
+            1 => Ok(1),
+            first => Ok(first + 1),
+        }
+    }
+
+    /// Latest compaction record of a table, checked against the event it
+    /// anchors on and, in strict mode, against the trust store.
+    pub fn compaction(&self, table_id: &TableId) -> Result<Option<CompactionRecord>, LogError> {
+        let Some(compactions) = &self.compactions else {
+            return Ok(None);
+        };
+        let Some(record) = compactions.latest(table_id)? else {
+            return Ok(None);
+        };
+
+        if record.table_id != *table_id
+            || self.hash_at(table_id, record.version)?.as_ref() != Some(&record.hash)
+        {
+            return Err(LogError::Tampered {
+                table_id: table_id.clone(),
+                version: record.version,
+            });
+        }
+        if let Some(trust) = &self.trust {
+            record.verify(trust)?;
+        }
+        Ok(Some(record))
+    }
+
+    /// Latest compaction record of a table whose store discarded the
+    /// events it covers, with the anchor event it is bound to.
+    ///
+    /// The anchor's own chain cannot be verified, as its predecessor is
+    /// gone; it is checked against the record's hash instead.
+    pub fn compacted_start(
+        &self,
+        table_id: &TableId,
+    ) -> Result<Option<(CompactionRecord, TableEvent)>, LogError> {
+        let first_available = self.first_version(table_id)?;
+        if first_available == 1 {
+            return Ok(None);
+        }
+
+        let record = self
+            .compaction(table_id)?
+            .ok_or_else(|| LogError::Compacted {
+                table_id: table_id.clone(),
+                first_available,
+            })?;
+        let anchor = self
+            .store
+            .stream(
+                table_id,
+                VersionRange::between(record.version, record.version),
+            )?
+            .next()
+            .transpose()?
+            .ok_or_else(|| LogError::Tampered {
+                table_id: table_id.clone(),
+                version: record.version,
+            })?;
+        Ok(Some((record, anchor)))
+    }
+
+    /// Save a signed compaction record and let the store discard the
+    /// events it covers, except its anchor event.
+    ///
+    /// The events must already be archived: the store may drop them as
+    /// soon as the record is saved. Nothing is saved if the store cannot
+    /// discard events.
+    pub(crate) fn commit_compaction(&mut self, record: &CompactionRecord) -> Result<(), LogError> {
+        self.check_truncation()?;
+        if self.hash_at(&record.table_id, record.version)?.as_ref() != Some(&record.hash) {
+            return Err(LogError::Tampered {
+                table_id: record.table_id.clone(),
+                version: record.version,
+            });
+        }
+
+        let compactions = self
+            .compactions
+            .as_mut()
+            .ok_or_else(|| LogError::Storage("no compaction store configured".into()))?;
+        compactions.save(record)?;
+
+        self.store.truncate_before(&record.table_id, record.version)
+    }
+
+    /// Fail unless the store can discard compacted events.
+    pub(crate) fn check_truncation(&self) -> Result<(), LogError> {
+        if self.store.supports_truncation() {
+            Ok(())
+        } else {
+            Err(unsupported_truncation())
+        }
+    }
+
+    /// Version of the recent event sharing `event`'s idempotency key.
+    fn committed_key(&self, event: &TableEvent) -> Result<Option<Version>, LogError> {
+        match &event.envelope.idempotency_key {
+            Some(key) => self.store.find_idempotency_key(&event.table_id, key),
+            None => Ok(None),
+        }
+    }
+
+    /// Stored hash of a single event, if it exists.
+    pub(crate) fn hash_at(
+        &self,
+        table_id: &TableId,
+        version: Version,
+    ) -> Result<Option<String>, LogError> {
+        match self
+            .store
+            .stream(table_id, VersionRange::between(version, version))?
+            .next()
+        {
+            Some(event) => Ok(event?.hash),
+            None => Ok(None),
+        }
+    }
+
+    pub fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        self.store.current_version(table_id)
+    }
+
+    pub fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        self.store.tables()
+    }
+}
+
+fn unsupported_truncation() -> LogError {
+    LogError::Storage("store does not support discarding compacted events".into())
+}
+
+/// Check an event's payload, accepting legacy changes as they were
+/// written.
+fn check_payload(event: &TableEvent) -> Result<(), LogError> {
+    let checked = if event.legacy {
+        event.payload.validate_legacy(&event.event_type)
+    } else {
+        event.payload.validate(&event.event_type)
+    };
+    checked.map_err(|reason| LogError::InvalidPayload {
+        table_id: event.table_id.clone(),
+        version: event.version,
+        reason,
+    })
+}
+
+fn duplicate_on_import(version: Version) -> LogError {
+    LogError::Storage(format!(
+        "idempotency key already committed at version {version}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(table_id: &TableId, version: Version) -> TableEvent {
+        TableEvent::new(table_id.clone(), version, EventType::SnapshotAdded, vec![])
+    }
+
+    struct InMemoryFactory;
+
+    impl conformance::StoreFactory for InMemoryFactory {
+        type Store = InMemoryLogStore;
+        type Location = ();
+
+        fn create_location(&mut self) {}
+
+        fn open(&mut self, _: &()) -> InMemoryLogStore {
+            InMemoryLogStore::default()
+        }
+
+        fn persistent(&self) -> bool {
+            false
+        }
+    }
+
+    mod conformance_suite {
+        crate::store_conformance_tests!(super::InMemoryFactory);
+    }
+
+    #[test]
+    fn versions_are_scoped_per_table() {
+        let a = TableId(Uuid::new_v4());
+        let b = TableId(Uuid::new_v4());
+        let mut store = InMemoryLogStore::default();
+
+        store.append(&event(&a, 1)).unwrap();
+        store.append(&event(&b, 1)).unwrap();
+        store.append(&event(&a, 2)).unwrap();
+
+        assert_eq!(store.current_version(&a).unwrap(), 2);
+        assert_eq!(store.current_version(&b).unwrap(), 1);
+        assert_eq!(store.load(&b).unwrap().len(), 1);
+
+        let err = store.append(&event(&b, 3)).unwrap_err();
+        assert_eq!(
+            err,
+            LogError::VersionConflict {
+                expected: 2,
+                actual: 3
+            }
+        );
+    }
+
+    #[test]
+    fn tables_are_listed_in_id_order() {
+        let mut ids = vec![TableId(Uuid::new_v4()), TableId(Uuid::new_v4())];
+        let mut store = InMemoryLogStore::default();
+
+        for id in &ids {
+            store.append(&event(id, 1)).unwrap();
+        }
+        ids.sort();
+
+        assert_eq!(store.tables().unwrap(), ids);
+        assert_eq!(store.current_version(&TableId(Uuid::nil())).unwrap(), 0);
+    }
+
+    #[test]
+    fn stream_respects_version_bounds() {
+        let id = TableId(Uuid::new_v4());
+        let mut store = InMemoryLogStore::default();
+        for v in 1..=5 {
+            store.append(&event(&id, v)).unwrap();
+        }
+
+        let versions = |range| -> Vec<Version> {
+            store
+                .stream(&id, range)
+                .unwrap()
+                .map(|e| e.unwrap().version)
+                .collect()
+        };
+
+        assert_eq!(versions(VersionRange::between(2, 4)), vec![2, 3, 4]);
+        assert_eq!(versions(VersionRange::starting_at(4)), vec![4, 5]);
+        assert_eq!(versions(VersionRange::between(4, 99)), vec![4, 5]);
+        assert!(versions(VersionRange::starting_at(6)).is_empty());
+        assert!(versions(VersionRange::between(4, 2)).is_empty());
+    }
+
+    #[test]
+    fn log_stamps_commit_time_and_filters_by_envelope() {
+        let a = TableId(Uuid::new_v4());
+        let b = TableId(Uuid::new_v4());
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+
+        let flink = EventEnvelope {
+            engine: Some(EngineKind::Flink),
+            correlation_id: Some("backfill-7".into()),
+            ..Default::default()
+        };
+        log.append(event(&a, 1)).unwrap();
+        log.append(event(&a, 2).with_envelope(flink.clone()))
+            .unwrap();
+        log.append(event(&b, 1).with_envelope(flink)).unwrap();
+
+        let history = log.replay(&a).unwrap();
+        assert!(history.iter().all(|e| e.envelope.committed_at.is_some()));
+
+        let filter = EventFilter {
+            correlation_id: Some("backfill-7".into()),
+            ..Default::default()
+        };
+        let matched: Vec<_> = log
+            .query(&filter)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.table_id, e.version))
+            .collect();
+        let mut expected = vec![(a.clone(), 2), (b.clone(), 1)];
+        expected.sort();
+        assert_eq!(matched, expected);
+
+        // Envelope fields are part of the hash chain.
+        log.store.tables.get_mut(&a).unwrap()[1].envelope.job_id = Some("forged".into());
+        assert!(matches!(
+            log.replay(&a),
+            Err(LogError::Tampered { version: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn log_rejects_payload_for_another_event_type() {
+        let id = TableId(Uuid::new_v4());
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+
+        let removed = TypedPayload::SnapshotRemoved(payload::SnapshotRemovedPayload {
+            snapshot_ids: vec![1],
+            mutation_id: None,
+        });
+        let err = log
+            .append(TableEvent::new(
+                id.clone(),
+                1,
+                EventType::SnapshotAdded,
+                removed,
+            ))
+            .unwrap_err();
+
+        assert!(matches!(err, LogError::InvalidPayload { version: 1, .. }));
+        assert_eq!(log.current_version(&id).unwrap(), 0);
+    }
+
+    #[test]
+    fn log_detects_tampered_store() {
+        let id = TableId(Uuid::new_v4());
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        for v in 1..=4 {
+            log.append(event(&id, v)).unwrap();
+        }
+        assert_eq!(log.replay(&id).unwrap().len(), 4);
+
+        // Edit a stored event behind the log's back.
+        log.store.tables.get_mut(&id).unwrap()[1].payload = vec![9].into();
+
+        let err = log.replay(&id).unwrap_err();
+        assert_eq!(
+            err,
+            LogError::Tampered {
+                table_id: id.clone(),
+                version: 2
+            }
+        );
+
+        // A suffix read anchored after the edit still verifies.
+        assert_eq!(
+            log.stream(&id, VersionRange::starting_at(3))
+                .unwrap()
+                .count(),
+            2
+        );
     }
 }
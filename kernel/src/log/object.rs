@@ -0,0 +1,392 @@
+// Object Storage Log Layout
+//
+// Stores every event as its own immutable object next to the table's
+// data:
+//
+//   <table_id>/_axiom_log/<version, zero-padded to 20 digits>.json
+//
+// Committing version N means creating object N with put-if-absent, so
+// the object store itself arbitrates between concurrent writers, even
+// across processes and machines. Zero padding makes lexicographic
+// listing order equal to version order; the head of a table is the last
+// key listed under its log prefix.
+//
+// Heads and idempotency keys are cached per store and caught up from a
+// listing before each append, so commits by other writers are seen.
+//
+// Tables are found through an empty marker object per table, so neither
+// opening a store nor listing tables has to scan table data:
+//
+//   _axiom_tables/<table_id>
+//
+// The marker is created after the table's first committed event, and
+// again by the first append of any store that has not seen the table,
+// so a writer crashing in between leaves it missing only until the next
+// commit.
+//
+// Objects are always JSON: they sit next to table data where other
+// tools read them, and the key names the encoding, so writers using
+// different encodings could otherwise both create version N.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::dedup::{DedupIndex, DEFAULT_DEDUP_WINDOW};
+use super::object_store::{ObjectStore, PutOutcome};
+use super::record::storage;
+use super::{
+    AppendOutcome, EventStream, LogError, MetadataLogStore, TableEvent, TableId, Version,
+    VersionRange,
+};
+
+/// Directory holding a table's log objects.
+const LOG_DIR: &str = "_axiom_log";
+
+/// Directory holding one marker object per table.
+const TABLES_DIR: &str = "_axiom_tables/";
+
+/// Store writing one object per event to an `ObjectStore`.
+pub struct ObjectLogStore<O: ObjectStore> {
+    objects: O,
+    heads: BTreeMap<TableId, Version>,
+    dedup: DedupIndex,
+}
+
+impl<O: ObjectStore> ObjectLogStore<O> {
+    /// Open the log stored in `objects`.
+    pub fn open(objects: O) -> Result<Self, LogError> {
+        Self::open_with_dedup_window(objects, DEFAULT_DEDUP_WINDOW)
+    }
+
+    /// Open the log stored in `objects`, remembering idempotency keys of
+    /// the last `window` versions of each table (0 disables
+    /// deduplication).
+    pub fn open_with_dedup_window(objects: O, window: u64) -> Result<Self, LogError> {
+        let mut store = Self {
+            objects,
+            heads: BTreeMap::new(),
+            dedup: DedupIndex::new(window),
+        };
+        for table_id in store.tables()? {
+            let head = store.latest_version(&table_id)?;
+            store.catch_up(&table_id, head)?;
+        }
+        Ok(store)
+    }
+
+    /// The underlying object store.
+    pub fn objects(&self) -> &O {
+        &self.objects
+    }
+
+    /// Latest committed version of a table according to a listing.
+    fn latest_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        let cached = self.heads.get(table_id).copied().unwrap_or(0);
+        let start_after = (cached > 0).then(|| object_key(table_id, cached));
+
+        let keys = self
+            .objects
+            .list(&log_prefix(table_id), start_after.as_deref())?;
+
+        Ok(keys
+            .iter()
+            .rev()
+            .find_map(|key| parse_key(key).map(|(_, version)| version))
+            .unwrap_or(cached)
+            .max(cached))
+    }
+
+    /// Advance the cached head to `latest`, recording idempotency keys of
+    /// the events committed in between.
+    fn catch_up(&mut self, table_id: &TableId, latest: Version) -> Result<(), LogError> {
+        let cached = self.heads.get(table_id).copied().unwrap_or(0);
+        if latest <= cached {
+            return Ok(());
+        }
+
+        let from = self.dedup.window_start(latest).max(cached + 1);
+        let missed: Vec<_> = self
+            .stream(table_id, VersionRange::between(from, latest))?
+            .collect::<Result<_, _>>()?;
+
+        if missed.last().map(|e| e.version) != Some(latest) {
+            return Err(LogError::Corrupt(format!(
+                "table {table_id} is missing log objects before version {latest}"
+            )));
+        }
+
+        for event in &missed {
+            self.dedup.record(event);
+        }
+        self.heads.insert(table_id.clone(), latest);
+        Ok(())
+    }
+
+    fn read(&self, table_id: &TableId, version: Version) -> Result<Option<TableEvent>, LogError> {
+        let key = object_key(table_id, version);
+        let Some(bytes) = self.objects.get(&key)? else {
+            return Ok(None);
+        };
+
+        let event: TableEvent = serde_json::from_slice(&bytes)
+            .map_err(|e| LogError::Corrupt(format!("undecodable object {key}: {e}")))?;
+
+        if event.table_id != *table_id || event.version != version {
+            return Err(LogError::Corrupt(format!(
+                "object {key} holds version {} of table {}",
+                event.version, event.table_id
+            )));
+        }
+        Ok(Some(event))
+    }
+}
+
+impl<O: ObjectStore> MetadataLogStore for ObjectLogStore<O> {
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError> {
+        let table_id = &event.table_id;
+        let marked = self.heads.contains_key(table_id);
+        let head = self.latest_version(table_id)?;
+        self.catch_up(table_id, head)?;
+
+        if let Some(version) = self.dedup.lookup(event) {
+            return Ok(AppendOutcome::Duplicate(version));
+        }
+
+        let expected = head + 1;
+        if event.version != expected {
+            return Err(LogError::VersionConflict {
+                expected,
+                actual: event.version,
+            });
+        }
+
+        let key = object_key(table_id, event.version);
+        let bytes = serde_json::to_vec(event).map_err(storage)?;
+
+        if self.objects.put_if_absent(&key, &bytes)? == PutOutcome::AlreadyExists {
+            // An earlier attempt of this very put may have landed before
+            // its acknowledgement was lost; anything else lost the race.
+            if self.objects.get(&key)?.as_deref() != Some(bytes.as_slice()) {
+                let head = self.latest_version(table_id)?;
+                return Err(LogError::VersionConflict {
+                    expected: head + 1,
+                    actual: event.version,
+                });
+            }
+        }
+
+        // Stores know the tables they opened with from their markers
+        if !marked {
+            self.objects.put_if_absent(&table_marker(table_id), b"")?;
+        }
+        self.heads.insert(table_id.clone(), event.version);
+        self.dedup.record(event);
+        Ok(AppendOutcome::Appended(event.version))
+    }
+
+    /// Only sees keys of events this store has appended or caught up
+    /// with, which happens on open and before every append.
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError> {
+        Ok(self.dedup.find(table_id, key))
+    }
+
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError> {
+        let table_id = table_id.clone();
+        let mut next = range.from_version.max(1);
+        let mut done = false;
+
+        // Read objects one by one until the first missing version.
+        Ok(Box::new(std::iter::from_fn(move || {
+            if done || range.is_past(next) {
+                return None;
+            }
+            let item = self.read(&table_id, next).transpose();
+            done = !matches!(item, Some(Ok(_)));
+            next += 1;
+            item
+        })))
+    }
+
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        self.latest_version(table_id)
+    }
+
+    fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        let tables: BTreeSet<_> = self
+            .objects
+            .list(TABLES_DIR, None)?
+            .iter()
+            .filter_map(|key| key.strip_prefix(TABLES_DIR)?.parse().ok())
+            .collect();
+
+        Ok(tables.into_iter().collect())
+    }
+}
+
+fn table_marker(table_id: &TableId) -> String {
+    format!("{TABLES_DIR}{table_id}")
+}
+
+fn log_prefix(table_id: &TableId) -> String {
+    format!("{table_id}/{LOG_DIR}/")
+}
+
+fn object_key(table_id: &TableId, version: Version) -> String {
+    format!("{}{version:020}.json", log_prefix(table_id))
+}
+
+/// Table and version of a log object key; `None` for unrelated objects.
+fn parse_key(key: &str) -> Option<(TableId, Version)> {
+    let mut parts = key.split('/');
+    let (table, dir, name) = (parts.next()?, parts.next()?, parts.next()?);
+
+    if dir != LOG_DIR || parts.next().is_some() {
+        return None;
+    }
+
+    let version = name.strip_suffix(".json")?.parse().ok()?;
+    Some((table.parse().ok()?, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::conformance::{StoreFactory, TempLocations};
+    use crate::log::{EventEnvelope, EventType, LocalObjectStore, MetadataLog};
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    fn event(version: Version) -> TableEvent {
+        TableEvent::new(table(), version, EventType::SnapshotAdded, vec![])
+    }
+
+    fn open(dir: &std::path::Path) -> ObjectLogStore<LocalObjectStore> {
+        ObjectLogStore::open(LocalObjectStore::new(dir).unwrap()).unwrap()
+    }
+
+    struct Factory(TempLocations);
+
+    impl StoreFactory for Factory {
+        type Store = ObjectLogStore<LocalObjectStore>;
+        type Location = PathBuf;
+
+        fn create_location(&mut self) -> PathBuf {
+            self.0.next()
+        }
+
+        fn open(&mut self, root: &PathBuf) -> Self::Store {
+            open(root)
+        }
+
+        fn shares_storage(&self) -> bool {
+            true
+        }
+    }
+
+    mod conformance {
+        use super::*;
+        crate::store_conformance_tests!(Factory(TempLocations::new()));
+    }
+
+    #[test]
+    fn one_object_per_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = open(dir.path());
+        store.append(&event(1)).unwrap();
+        store.append(&event(2)).unwrap();
+
+        let keys = store.objects().list("", None).unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                format!("{}/_axiom_log/00000000000000000001.json", table()),
+                format!("{}/_axiom_log/00000000000000000002.json", table()),
+                format!("_axiom_tables/{}", table()),
+            ]
+        );
+
+        // Unrelated objects next to the log are ignored.
+        let data = format!("{}/data/part-0.parquet", table());
+        store.objects().put_if_absent(&data, b"").unwrap();
+        assert_eq!(open(dir.path()).current_version(&table()).unwrap(), 2);
+    }
+
+    #[test]
+    fn writers_sharing_a_bucket_race_for_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spark = open(dir.path());
+        let mut flink = open(dir.path());
+
+        spark.append(&event(1)).unwrap();
+        assert_eq!(flink.current_version(&table()).unwrap(), 1);
+        assert_eq!(
+            flink.append(&event(1)).unwrap_err(),
+            LogError::VersionConflict {
+                expected: 2,
+                actual: 1
+            }
+        );
+
+        // Flink rebases; Spark's stale guess now loses.
+        flink.append(&event(2)).unwrap();
+        assert!(matches!(
+            spark.append(&event(2)),
+            Err(LogError::VersionConflict { expected: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn keys_committed_by_other_writers_are_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spark = open(dir.path());
+        let mut retry = open(dir.path());
+
+        let keyed = event(1).with_envelope(EventEnvelope {
+            idempotency_key: Some("commit-1".into()),
+            ..Default::default()
+        });
+        spark.append(&keyed).unwrap();
+        spark.append(&event(2)).unwrap();
+
+        assert_eq!(retry.append(&keyed).unwrap(), AppendOutcome::Duplicate(1));
+        assert_eq!(
+            open(dir.path()).find_idempotency_key(&table(), "commit-1"),
+            Ok(Some(1))
+        );
+    }
+
+    #[test]
+    fn missing_table_markers_are_restored_by_the_next_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        open(dir.path()).append(&event(1)).unwrap();
+
+        // A writer crashed after committing version 1 but before marking
+        // the table.
+        std::fs::remove_dir_all(dir.path().join(TABLES_DIR)).unwrap();
+        assert_eq!(open(dir.path()).tables().unwrap(), vec![]);
+
+        open(dir.path()).append(&event(2)).unwrap();
+        let store = open(dir.path());
+        assert_eq!(store.tables().unwrap(), vec![table()]);
+        assert_eq!(store.current_version(&table()).unwrap(), 2);
+    }
+
+    #[test]
+    fn chain_verifies_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = MetadataLog::new(open(dir.path()));
+        for v in 1..=3 {
+            log.append(event(v)).unwrap();
+        }
+
+        let log = MetadataLog::new(open(dir.path()));
+        assert_eq!(log.replay(&table()).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,207 @@
+// Object Store Abstraction
+//
+// The minimal surface Axiom needs from S3/GCS/ADLS-style blob storage:
+// read an object, create an object only if the key is free, and list
+// keys in lexicographic order. Objects are never overwritten or deleted,
+// so no stronger consistency than conditional create is required.
+//
+// `LocalObjectStore` maps keys onto a directory tree and is used for
+// tests and single-machine deployments.
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use super::record::storage;
+use super::LogError;
+
+/// Result of a conditional put.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PutOutcome {
+    Created,
+    AlreadyExists,
+}
+
+/// Blob storage with conditional create.
+///
+/// Keys are `/`-separated paths without a leading slash.
+pub trait ObjectStore: Send + Sync {
+    /// Contents of `key`, or `None` if it does not exist.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, LogError>;
+
+    /// Create `key` with `bytes` unless it already exists. Must be atomic:
+    /// of concurrent puts to the same key exactly one is `Created`.
+    fn put_if_absent(&self, key: &str, bytes: &[u8]) -> Result<PutOutcome, LogError>;
+
+    /// Keys starting with `prefix` and sorting after `start_after`, in
+    /// lexicographic order.
+    fn list(&self, prefix: &str, start_after: Option<&str>) -> Result<Vec<String>, LogError>;
+}
+
+/// Object store backed by a local directory.
+#[derive(Debug, Clone)]
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+/// Prefix of in-flight uploads; never listed.
+const TMP_PREFIX: &str = ".tmp-";
+
+impl LocalObjectStore {
+    /// Use `root` (created if missing) as the bucket.
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, LogError> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).map_err(storage)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, LogError> {
+        let valid = !key.is_empty()
+            && key
+                .split('/')
+                .all(|part| !part.is_empty() && part != "." && part != "..");
+
+        if !valid {
+            return Err(LogError::Storage(format!("invalid object key {key:?}")));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl ObjectStore for LocalObjectStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, LogError> {
+        match fs::read(self.path(key)?) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(storage(e)),
+        }
+    }
+
+    fn put_if_absent(&self, key: &str, bytes: &[u8]) -> Result<PutOutcome, LogError> {
+        let path = self.path(key)?;
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir).map_err(storage)?;
+
+        // Write the full object under a private name, then link it into
+        // place: linking fails if the key exists, so readers never see a
+        // partial object and only one writer can win.
+        let tmp = dir.join(format!("{TMP_PREFIX}{}", Uuid::new_v4()));
+        let written = File::create(&tmp).and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        });
+
+        let linked = written.and_then(|_| fs::hard_link(&tmp, &path));
+        let _ = fs::remove_file(&tmp);
+
+        match linked {
+            Ok(()) => {
+                File::open(dir)
+                    .and_then(|d| d.sync_all())
+                    .map_err(storage)?;
+                Ok(PutOutcome::Created)
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(PutOutcome::AlreadyExists),
+            Err(e) => Err(storage(e)),
+        }
+    }
+
+    fn list(&self, prefix: &str, start_after: Option<&str>) -> Result<Vec<String>, LogError> {
+        // Only walk the deepest directory the prefix pins down.
+        let base = match prefix.rfind('/') {
+            Some(i) => &prefix[..=i],
+            None => "",
+        };
+
+        let mut keys = Vec::new();
+        walk(&self.root.join(base), base, &mut keys)?;
+
+        keys.retain(|key| key.starts_with(prefix) && start_after.is_none_or(|s| key.as_str() > s));
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+fn walk(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> Result<(), LogError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(storage(e)),
+    };
+
+    for entry in entries {
+        let entry = entry.map_err(storage)?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with(TMP_PREFIX) {
+            continue;
+        }
+
+        let key = format!("{prefix}{name}");
+        if entry.file_type().map_err(storage)?.is_dir() {
+            walk(&entry.path(), &format!("{key}/"), keys)?;
+        } else {
+            keys.push(key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_if_absent_creates_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalObjectStore::new(dir.path()).unwrap();
+
+        assert_eq!(store.get("a/b.json").unwrap(), None);
+        assert_eq!(
+            store.put_if_absent("a/b.json", b"first").unwrap(),
+            PutOutcome::Created
+        );
+        assert_eq!(
+            store.put_if_absent("a/b.json", b"second").unwrap(),
+            PutOutcome::AlreadyExists
+        );
+        assert_eq!(store.get("a/b.json").unwrap(), Some(b"first".to_vec()));
+    }
+
+    #[test]
+    fn list_is_ordered_and_prefix_scoped() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalObjectStore::new(dir.path()).unwrap();
+        for key in ["t/log/3", "t/log/1", "t/log/2", "u/log/1", "t/other"] {
+            store.put_if_absent(key, b"").unwrap();
+        }
+
+        assert_eq!(
+            store.list("t/log/", None).unwrap(),
+            vec!["t/log/1", "t/log/2", "t/log/3"]
+        );
+        assert_eq!(
+            store.list("t/log/", Some("t/log/1")).unwrap(),
+            vec!["t/log/2", "t/log/3"]
+        );
+        assert_eq!(store.list("", None).unwrap().len(), 5);
+        assert!(store.list("v/", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keys_cannot_escape_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalObjectStore::new(dir.path()).unwrap();
+
+        for key in ["", "/abs", "a/../../b", "a//b"] {
+            assert!(store.put_if_absent(key, b"").is_err(), "{key:?}");
+        }
+    }
+}
@@ -0,0 +1,733 @@
+// Typed Event Payloads
+//
+// Structured payloads for each event type, so that the state machine,
+// invariants and drift detection can see which schema or snapshot an
+// event refers to.
+//
+// Typed payloads are JSON objects tagged with a `type` field:
+//
+//   { "type": "snapshot_added", "snapshot_id": 42, "operation": "append" }
+//
+// Older logs carry opaque byte arrays (usually `[]`), and newer producers
+// may emit payload kinds this version does not know. Both are preserved
+// as-is and never rejected on read. So are payloads of a known kind
+// carrying fields this version does not know: they stay unknown, so that
+// their hash and signature cover the fields as written.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+
+use serde::de::value::MapDeserializer;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::{ActorId, EngineKind, EventType};
+
+/// Branch snapshots are committed to unless they name another.
+pub const MAIN_BRANCH: &str = "main";
+
+/// A column of a table schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaField {
+    pub id: i32,
+    pub name: String,
+
+    /// Engine-neutral type name, e.g. `long` or `decimal(10,2)`.
+    #[serde(rename = "type")]
+    pub field_type: String,
+
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schema {
+    pub schema_id: i32,
+    pub fields: Vec<SchemaField>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableCreatedPayload {
+    pub schema: Schema,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, String>,
+}
+
+/// Kind of change a mutation makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationKind {
+    SchemaChange,
+    Append,
+    Overwrite,
+    Replace,
+    Delete,
+
+    /// Removal of old snapshots.
+    Expire,
+
+    UpdateProperties,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MutationStartedPayload {
+    /// Identifier chosen by the writer, referenced by every event of the
+    /// mutation.
+    pub mutation_id: String,
+
+    pub kind: MutationKind,
+    pub writer: ActorId,
+}
+
+/// Payload of the events that close a mutation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MutationEndedPayload {
+    pub mutation_id: String,
+
+    /// Why the mutation was aborted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaUpdatedPayload {
+    pub schema: Schema,
+
+    /// Mutation the change belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation_id: Option<String>,
+}
+
+/// Kind of change a snapshot commits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotOperation {
+    Append,
+    Overwrite,
+    Replace,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotAddedPayload {
+    pub snapshot_id: i64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_snapshot_id: Option<i64>,
+
+    pub operation: SnapshotOperation,
+
+    /// Schema the snapshot was written with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_id: Option<i32>,
+
+    /// Mutation that committed the snapshot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation_id: Option<String>,
+
+    /// Branch the snapshot was committed to; `main` if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+}
+
+impl SnapshotAddedPayload {
+    pub fn branch(&self) -> &str {
+        self.branch.as_deref().unwrap_or(MAIN_BRANCH)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotRemovedPayload {
+    pub snapshot_ids: Vec<i64>,
+
+    /// Mutation that removed the snapshots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PropertiesUpdatedPayload {
+    /// Properties set, replacing any previous value.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub updates: BTreeMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removals: Vec<String>,
+
+    /// Mutation the change belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation_id: Option<String>,
+}
+
+/// Payload of the rollback events.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollbackPayload {
+    /// Snapshot the table is being rolled back to.
+    pub target_snapshot_id: i64,
+
+    /// Why the rollback was started, or why it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Payload of the freeze, unfreeze and undrop events.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LifecyclePayload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableDeprecatedPayload {
+    /// Engines that may keep writing to the table.
+    #[serde(default)]
+    pub allowed_engines: Vec<EngineKind>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableDroppedPayload {
+    /// How long after the drop was committed it can still be undone.
+    #[serde(default)]
+    pub grace_period_ms: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Payload understood by this version of Axiom.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TypedPayload {
+    TableCreated(TableCreatedPayload),
+    MutationStarted(MutationStartedPayload),
+    MutationCommitted(MutationEndedPayload),
+    MutationAborted(MutationEndedPayload),
+    SchemaUpdated(SchemaUpdatedPayload),
+    SnapshotAdded(SnapshotAddedPayload),
+    SnapshotRemoved(SnapshotRemovedPayload),
+    PropertiesUpdated(PropertiesUpdatedPayload),
+    RollbackStarted(RollbackPayload),
+    RollbackCompleted(RollbackPayload),
+    RollbackFailed(RollbackPayload),
+    TableFrozen(LifecyclePayload),
+    TableUnfrozen(LifecyclePayload),
+    TableDeprecated(TableDeprecatedPayload),
+    TableDropped(TableDroppedPayload),
+    TableUndropped(LifecyclePayload),
+}
+
+impl TypedPayload {
+    /// Event type this payload belongs to.
+    pub fn event_type(&self) -> EventType {
+        match self {
+            TypedPayload::TableCreated(_) => EventType::TableCreated,
+            TypedPayload::MutationStarted(_) => EventType::MutationStarted,
+            TypedPayload::MutationCommitted(_) => EventType::MutationCommitted,
+            TypedPayload::MutationAborted(_) => EventType::MutationAborted,
+            TypedPayload::SchemaUpdated(_) => EventType::SchemaUpdated,
+            TypedPayload::SnapshotAdded(_) => EventType::SnapshotAdded,
+            TypedPayload::SnapshotRemoved(_) => EventType::SnapshotRemoved,
+            TypedPayload::PropertiesUpdated(_) => EventType::PropertiesUpdated,
+            TypedPayload::RollbackStarted(_) => EventType::RollbackStarted,
+            TypedPayload::RollbackCompleted(_) => EventType::RollbackCompleted,
+            TypedPayload::RollbackFailed(_) => EventType::RollbackFailed,
+            TypedPayload::TableFrozen(_) => EventType::TableFrozen,
+            TypedPayload::TableUnfrozen(_) => EventType::TableUnfrozen,
+            TypedPayload::TableDeprecated(_) => EventType::TableDeprecated,
+            TypedPayload::TableDropped(_) => EventType::TableDropped,
+            TypedPayload::TableUndropped(_) => EventType::TableUndropped,
+        }
+    }
+
+    /// Mutation the event opens, closes or belongs to, if any.
+    pub fn mutation_id(&self) -> Option<&str> {
+        match self {
+            TypedPayload::MutationStarted(p) => Some(&p.mutation_id),
+            TypedPayload::MutationCommitted(p) | TypedPayload::MutationAborted(p) => {
+                Some(&p.mutation_id)
+            }
+            TypedPayload::SchemaUpdated(p) => p.mutation_id.as_deref(),
+            TypedPayload::SnapshotAdded(p) => p.mutation_id.as_deref(),
+            TypedPayload::SnapshotRemoved(p) => p.mutation_id.as_deref(),
+            TypedPayload::PropertiesUpdated(p) => p.mutation_id.as_deref(),
+            TypedPayload::TableCreated(_)
+            | TypedPayload::RollbackStarted(_)
+            | TypedPayload::RollbackCompleted(_)
+            | TypedPayload::RollbackFailed(_)
+            | TypedPayload::TableFrozen(_)
+            | TypedPayload::TableUnfrozen(_)
+            | TypedPayload::TableDeprecated(_)
+            | TypedPayload::TableDropped(_)
+            | TypedPayload::TableUndropped(_) => None,
+        }
+    }
+
+    /// Whether the payload changes the table's content, and so belongs
+    /// to a mutation.
+    fn is_change(&self) -> bool {
+        matches!(
+            self,
+            TypedPayload::SchemaUpdated(_)
+                | TypedPayload::SnapshotAdded(_)
+                | TypedPayload::SnapshotRemoved(_)
+                | TypedPayload::PropertiesUpdated(_)
+        )
+    }
+
+    fn validate(&self, legacy: bool) -> Result<(), String> {
+        match self.mutation_id() {
+            Some(id) if id.trim().is_empty() => return Err("empty mutation id".into()),
+            None if self.is_change() && !legacy => {
+                return Err("change references no mutation".into())
+            }
+            _ => {}
+        }
+
+        match self {
+            TypedPayload::TableCreated(p) => validate_schema(&p.schema),
+            TypedPayload::MutationStarted(_)
+            | TypedPayload::MutationCommitted(_)
+            | TypedPayload::MutationAborted(_) => Ok(()),
+            TypedPayload::SchemaUpdated(p) => validate_schema(&p.schema),
+            TypedPayload::SnapshotAdded(p) => {
+                if p.parent_snapshot_id == Some(p.snapshot_id) {
+                    return Err(format!("snapshot {} is its own parent", p.snapshot_id));
+                }
+                Ok(())
+            }
+            TypedPayload::SnapshotRemoved(p) => {
+                if p.snapshot_ids.is_empty() {
+                    return Err("no snapshots removed".into());
+                }
+                first_duplicate(p.snapshot_ids.iter())
+                    .map_or(Ok(()), |id| Err(format!("snapshot {id} removed twice")))
+            }
+            TypedPayload::PropertiesUpdated(p) => {
+                if p.updates.is_empty() && p.removals.is_empty() {
+                    return Err("no properties updated".into());
+                }
+                match p.removals.iter().find(|key| p.updates.contains_key(*key)) {
+                    Some(key) => Err(format!("property `{key}` both set and removed")),
+                    None => Ok(()),
+                }
+            }
+            TypedPayload::RollbackStarted(_) | TypedPayload::RollbackCompleted(_) => Ok(()),
+            TypedPayload::RollbackFailed(p) => match p.reason.as_deref() {
+                Some(reason) if !reason.trim().is_empty() => Ok(()),
+                _ => Err("failed rollback has no reason".into()),
+            },
+            TypedPayload::TableDeprecated(p) => {
+                first_duplicate(p.allowed_engines.iter().map(EngineKind::name))
+                    .map_or(Ok(()), |name| Err(format!("engine {name} allowed twice")))
+            }
+            TypedPayload::TableFrozen(_)
+            | TypedPayload::TableUnfrozen(_)
+            | TypedPayload::TableDropped(_)
+            | TypedPayload::TableUndropped(_) => Ok(()),
+        }
+    }
+}
+
+/// Payload of a `TableEvent`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum EventPayload {
+    Typed(TypedPayload),
+
+    /// Uninterpreted bytes, as written by older logs.
+    Opaque(Vec<u8>),
+
+    /// Structured payload of a kind this version does not know, or of a
+    /// known kind with fields it does not know.
+    Unknown(serde_json::Value),
+}
+
+impl<'de> Deserialize<'de> for EventPayload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        match Vec::<u8>::deserialize(&value) {
+            Ok(bytes) => Ok(EventPayload::Opaque(bytes)),
+            Err(_) => Ok(EventPayload::structured(value)),
+        }
+    }
+}
+
+impl EventPayload {
+    pub fn typed(&self) -> Option<&TypedPayload> {
+        match self {
+            EventPayload::Typed(payload) => Some(payload),
+            _ => None,
+        }
+    }
+
+    /// Classify a structured payload, keeping it typed only if parsing
+    /// it loses none of its fields.
+    pub(crate) fn structured(value: Value) -> Self {
+        match TypedPayload::deserialize(&value) {
+            Ok(typed) if serde_json::to_value(&typed).is_ok_and(|v| covers(&v, &value)) => {
+                EventPayload::Typed(typed)
+            }
+            _ => EventPayload::Unknown(value),
+        }
+    }
+
+    /// Check that the payload is well-formed and fits `event_type`.
+    ///
+    /// Opaque and unknown payloads are accepted, except for an unknown
+    /// payload claiming a known `type`: that is a typed payload that
+    /// failed to parse. One that parses once its unknown fields are
+    /// ignored is checked like a typed payload.
+    ///
+    /// Changes must reference the mutation they belong to.
+    pub fn validate(&self, event_type: &EventType) -> Result<(), String> {
+        self.check(event_type, false)
+    }
+
+    /// Like `validate`, but accepts changes that reference no mutation,
+    /// as written before mutations were explicit.
+    pub(crate) fn validate_legacy(&self, event_type: &EventType) -> Result<(), String> {
+        self.check(event_type, true)
+    }
+
+    fn check(&self, event_type: &EventType, legacy: bool) -> Result<(), String> {
+        match self {
+            EventPayload::Typed(payload) => {
+                if payload.event_type() != *event_type {
+                    return Err(format!(
+                        "{:?} payload on a {event_type:?} event",
+                        payload.event_type()
+                    ));
+                }
+                payload.validate(legacy)
+            }
+            EventPayload::Opaque(_) => Ok(()),
+            EventPayload::Unknown(value) => match TypedPayload::deserialize(value) {
+                Ok(typed) => EventPayload::Typed(typed).check(event_type, legacy),
+                Err(e) if claims_known_type(value) => Err(e.to_string()),
+                Err(_) => Ok(()),
+            },
+        }
+    }
+
+    /// Bytes of the payload as covered by hashes and signatures, and
+    /// whether they are a JSON encoding (as opposed to opaque bytes).
+    ///
+    /// Structured payloads are encoded as JSON with sorted keys, so the
+    /// encoding does not depend on whether this version knows the kind.
+    pub(crate) fn canonical_bytes(&self) -> Result<(Vec<u8>, bool), serde_json::Error> {
+        match self {
+            EventPayload::Opaque(bytes) => Ok((bytes.clone(), false)),
+            structured => {
+                let value = serde_json::to_value(structured)?;
+                Ok((serde_json::to_vec(&value)?, true))
+            }
+        }
+    }
+}
+
+impl Default for EventPayload {
+    fn default() -> Self {
+        EventPayload::Opaque(Vec::new())
+    }
+}
+
+impl From<Vec<u8>> for EventPayload {
+    fn from(bytes: Vec<u8>) -> Self {
+        EventPayload::Opaque(bytes)
+    }
+}
+
+impl From<TypedPayload> for EventPayload {
+    fn from(payload: TypedPayload) -> Self {
+        EventPayload::Typed(payload)
+    }
+}
+
+fn validate_schema(schema: &Schema) -> Result<(), String> {
+    if let Some(field) = schema.fields.iter().find(|f| f.name.is_empty()) {
+        return Err(format!("field {} has an empty name", field.id));
+    }
+    if let Some(id) = first_duplicate(schema.fields.iter().map(|f| f.id)) {
+        return Err(format!(
+            "duplicate field id {id} in schema {}",
+            schema.schema_id
+        ));
+    }
+    if let Some(name) = first_duplicate(schema.fields.iter().map(|f| &f.name)) {
+        return Err(format!(
+            "duplicate field `{name}` in schema {}",
+            schema.schema_id
+        ));
+    }
+    Ok(())
+}
+
+fn first_duplicate<T: Ord + Clone>(mut items: impl Iterator<Item = T>) -> Option<T> {
+    let mut seen = BTreeSet::new();
+    items.find(|item| !seen.insert(item.clone()))
+}
+
+/// Whether `parsed` keeps every field of `original`. Fields left out as
+/// `null` count as kept.
+fn covers(parsed: &Value, original: &Value) -> bool {
+    match (parsed, original) {
+        (Value::Object(parsed), Value::Object(original)) => {
+            original.iter().all(|(key, value)| match parsed.get(key) {
+                Some(parsed) => covers(parsed, value),
+                None => value.is_null(),
+            })
+        }
+        (Value::Array(parsed), Value::Array(original)) => {
+            parsed.len() == original.len() && parsed.iter().zip(original).all(|(p, o)| covers(p, o))
+        }
+        _ => parsed == original,
+    }
+}
+
+fn claims_known_type(value: &Value) -> bool {
+    value
+        .get("type")
+        .and_then(|t| t.as_str())
+        .is_some_and(|t| known_types().contains(&t))
+}
+
+/// `type` tags of `TypedPayload`, as listed by its `Deserialize` impl.
+fn known_types() -> &'static [&'static str] {
+    static KNOWN: OnceLock<&'static [&'static str]> = OnceLock::new();
+
+    KNOWN.get_or_init(|| {
+        // Rejecting an unknown tag reports the known ones
+        let probe = MapDeserializer::<_, VariantsError>::new(std::iter::once(("type", "")));
+        match TypedPayload::deserialize(probe) {
+            Err(VariantsError(Some(variants))) => variants,
+            _ => &[],
+        }
+    })
+}
+
+/// Deserialization error that only keeps the expected variants of an
+/// unknown-variant error.
+#[derive(Debug)]
+struct VariantsError(Option<&'static [&'static str]>);
+
+impl serde::de::Error for VariantsError {
+    fn custom<T: std::fmt::Display>(_: T) -> Self {
+        VariantsError(None)
+    }
+
+    fn unknown_variant(_: &str, expected: &'static [&'static str]) -> Self {
+        VariantsError(Some(expected))
+    }
+}
+
+impl std::fmt::Display for VariantsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected one of {:?}", self.0.unwrap_or_default())
+    }
+}
+
+impl std::error::Error for VariantsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema(fields: &[(i32, &str)]) -> Schema {
+        Schema {
+            schema_id: 1,
+            fields: fields
+                .iter()
+                .map(|&(id, name)| SchemaField {
+                    id,
+                    name: name.into(),
+                    field_type: "long".into(),
+                    required: false,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn payload_kinds_are_told_apart() {
+        let typed: EventPayload = serde_json::from_value(json!({
+            "type": "snapshot_added",
+            "snapshot_id": 42,
+            "parent_snapshot_id": 41,
+            "operation": "append"
+        }))
+        .unwrap();
+        assert_eq!(
+            typed.typed(),
+            Some(&TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id: 42,
+                parent_snapshot_id: Some(41),
+                operation: SnapshotOperation::Append,
+                schema_id: None,
+                mutation_id: None,
+                branch: None,
+            }))
+        );
+
+        let legacy: EventPayload = serde_json::from_value(json!([1, 2])).unwrap();
+        assert_eq!(legacy, EventPayload::Opaque(vec![1, 2]));
+
+        let future: EventPayload =
+            serde_json::from_value(json!({ "type": "branch_created", "name": "audit" })).unwrap();
+        assert!(matches!(future, EventPayload::Unknown(_)));
+        assert_eq!(future.validate(&EventType::SnapshotAdded), Ok(()));
+    }
+
+    #[test]
+    fn validation_rejects_malformed_typed_payloads() {
+        let created = |fields: &[(i32, &str)]| -> EventPayload {
+            TypedPayload::TableCreated(TableCreatedPayload {
+                schema: schema(fields),
+                location: None,
+                properties: Default::default(),
+            })
+            .into()
+        };
+
+        assert_eq!(
+            created(&[(1, "id"), (2, "ts")]).validate(&EventType::TableCreated),
+            Ok(())
+        );
+        assert!(created(&[(1, "id"), (1, "ts")])
+            .validate(&EventType::TableCreated)
+            .is_err());
+        assert!(created(&[(1, "id"), (2, "id")])
+            .validate(&EventType::TableCreated)
+            .is_err());
+        assert!(created(&[(1, "id")])
+            .validate(&EventType::SchemaUpdated)
+            .is_err());
+
+        let removed: EventPayload = TypedPayload::SnapshotRemoved(SnapshotRemovedPayload {
+            snapshot_ids: vec![],
+            mutation_id: None,
+        })
+        .into();
+        assert!(removed.validate(&EventType::SnapshotRemoved).is_err());
+
+        // Changes belong to a mutation, unless written before mutations
+        // were explicit.
+        let unreferenced: EventPayload = TypedPayload::SnapshotRemoved(SnapshotRemovedPayload {
+            snapshot_ids: vec![3],
+            mutation_id: None,
+        })
+        .into();
+        assert_eq!(
+            unreferenced.validate(&EventType::SnapshotRemoved),
+            Err("change references no mutation".into())
+        );
+        assert_eq!(
+            unreferenced.validate_legacy(&EventType::SnapshotRemoved),
+            Ok(())
+        );
+
+        let failed = |reason: Option<&str>| -> EventPayload {
+            TypedPayload::RollbackFailed(RollbackPayload {
+                target_snapshot_id: 7,
+                reason: reason.map(Into::into),
+            })
+            .into()
+        };
+        assert_eq!(
+            failed(Some("manifest missing")).validate(&EventType::RollbackFailed),
+            Ok(())
+        );
+        assert!(failed(None).validate(&EventType::RollbackFailed).is_err());
+        assert!(failed(Some("manifest missing"))
+            .validate(&EventType::RollbackStarted)
+            .is_err());
+
+        let started = |mutation_id: &str| -> EventPayload {
+            TypedPayload::MutationStarted(MutationStartedPayload {
+                mutation_id: mutation_id.into(),
+                kind: MutationKind::Append,
+                writer: ActorId("spark".into()),
+            })
+            .into()
+        };
+        assert_eq!(started("m-1").validate(&EventType::MutationStarted), Ok(()));
+        assert!(started(" ").validate(&EventType::MutationStarted).is_err());
+        assert!(started("m-1")
+            .validate(&EventType::MutationCommitted)
+            .is_err());
+
+        let deprecated: EventPayload = TypedPayload::TableDeprecated(TableDeprecatedPayload {
+            allowed_engines: vec![EngineKind::Spark, EngineKind::Spark],
+            reason: None,
+        })
+        .into();
+        assert!(deprecated.validate(&EventType::TableDeprecated).is_err());
+
+        // A known kind with missing fields is not silently tolerated.
+        let broken: EventPayload =
+            serde_json::from_value(json!({ "type": "snapshot_added", "operation": "append" }))
+                .unwrap();
+        assert!(broken.validate(&EventType::SnapshotAdded).is_err());
+    }
+
+    #[test]
+    fn structured_encoding_ignores_field_order() {
+        let typed = EventPayload::Typed(TypedPayload::SnapshotRemoved(SnapshotRemovedPayload {
+            snapshot_ids: vec![3],
+            mutation_id: None,
+        }));
+        let unknown =
+            EventPayload::Unknown(json!({ "snapshot_ids": [3], "type": "snapshot_removed" }));
+
+        assert_eq!(
+            typed.canonical_bytes().unwrap(),
+            unknown.canonical_bytes().unwrap()
+        );
+        assert_eq!(
+            EventPayload::Opaque(vec![7]).canonical_bytes().unwrap(),
+            (vec![7], false)
+        );
+    }
+
+    #[test]
+    fn unknown_fields_of_known_kinds_are_kept() {
+        let original = json!({
+            "type": "snapshot_removed",
+            "snapshot_ids": [3],
+            "mutation_id": "m-1",
+            "reason": "expired",
+        });
+        let payload: EventPayload = serde_json::from_value(original.clone()).unwrap();
+
+        assert_eq!(payload, EventPayload::Unknown(original.clone()));
+        assert!(payload.validate(&EventType::SnapshotRemoved).is_ok());
+        assert!(payload.validate(&EventType::SnapshotAdded).is_err());
+
+        let (bytes, _) = payload.canonical_bytes().unwrap();
+        assert_eq!(bytes, serde_json::to_vec(&original).unwrap());
+
+        // Optional fields written as null are not unknown.
+        let nulls: EventPayload = serde_json::from_value(json!({
+            "type": "snapshot_removed",
+            "snapshot_ids": [3],
+            "mutation_id": null,
+        }))
+        .unwrap();
+        assert!(nulls.typed().is_some());
+    }
+
+    #[test]
+    fn known_types_are_derived_from_the_enum() {
+        assert_eq!(known_types().len(), 16);
+        assert!(known_types().contains(&"table_undropped"));
+        assert!(claims_known_type(&json!({ "type": "mutation_started" })));
+        assert!(!claims_known_type(&json!({ "type": "table_renamed" })));
+    }
+}
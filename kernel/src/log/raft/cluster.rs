@@ -0,0 +1,272 @@
+// In-Process Cluster Simulation
+//
+// Runs a group of Raft nodes in one process over a simulated network.
+// Time advances in rounds: every node ticks once, then the messages in
+// flight are delivered in an order drawn from a seeded random number
+// generator, so a cluster built with the same seed and driven by the
+// same calls behaves identically on every run.
+//
+// The network can be partitioned into groups that cannot reach each
+// other and can drop a percentage of messages. Nodes keep their Raft
+// state in memory storage that outlives them, so a node can be restarted
+// to test recovery.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::node::{Applied, Command, Message, Node, NodeId, RequestId, Role};
+use super::storage::MemoryRaftStorage;
+use super::RaftLogStore;
+use crate::log::record;
+use crate::log::{LogError, MetadataLogStore};
+
+/// Rounds after which an unanswered request is submitted again.
+const RETRY_ROUNDS: usize = 10;
+
+/// Rounds after which a request fails (e.g. no quorum is reachable).
+const REQUEST_ROUNDS: usize = 300;
+
+/// Small deterministic random number generator (SplitMix64).
+#[derive(Debug, Clone)]
+pub(super) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform-ish value in `0..n` (`n > 0`).
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+pub(super) struct Simulation<S> {
+    nodes: Vec<Node<S>>,
+    in_flight: Vec<(NodeId, NodeId, Message)>,
+    /// Partition group of each node; messages only flow within a group.
+    groups: Vec<usize>,
+    loss_percent: u64,
+    rng: Rng,
+    next_request: RequestId,
+}
+
+impl<S: MetadataLogStore> Simulation<S> {
+    /// Advance time by one round.
+    fn round(&mut self) -> Result<(), LogError> {
+        for node in &mut self.nodes {
+            node.tick()?;
+        }
+        self.collect()?;
+
+        let mut batch = std::mem::take(&mut self.in_flight);
+        for i in (1..batch.len()).rev() {
+            let j = self.rng.below(i as u64 + 1) as usize;
+            batch.swap(i, j);
+        }
+
+        for (from, to, message) in batch {
+            let lost = self.loss_percent > 0 && self.rng.below(100) < self.loss_percent;
+            if self.groups[from] == self.groups[to] && !lost {
+                self.nodes[to].step(from, message)?;
+            }
+        }
+        self.collect()
+    }
+
+    /// Put the messages the nodes sent in flight. Like a real transport,
+    /// the network cannot carry messages larger than a frame.
+    fn collect(&mut self) -> Result<(), LogError> {
+        for (from, node) in self.nodes.iter_mut().enumerate() {
+            for (to, message) in node.take_outbox() {
+                record::frame(&message.encode()?)?;
+                self.in_flight.push((from, to, message));
+            }
+        }
+        Ok(())
+    }
+
+    /// Submit a command through `node` and run the cluster until the node
+    /// has applied it.
+    pub fn request(
+        &mut self,
+        node: NodeId,
+        command: impl FnOnce(RequestId) -> Command,
+    ) -> Result<Applied, LogError> {
+        let request = self.next_request;
+        self.next_request += 1;
+        let command = command(request);
+
+        for round in 0..REQUEST_ROUNDS {
+            if round % RETRY_ROUNDS == 0 {
+                self.nodes[node].submit(command.clone())?;
+            }
+            self.round()?;
+
+            if let Some(applied) = self.nodes[node].take_result(request) {
+                return Ok(applied);
+            }
+        }
+
+        self.nodes[node].abandon(request);
+        Err(LogError::Storage(format!(
+            "node {node} could not commit through a quorum"
+        )))
+    }
+
+    pub fn store(&self, node: NodeId) -> &S {
+        self.nodes[node].store()
+    }
+
+    #[cfg(test)]
+    pub fn node(&self, node: NodeId) -> &Node<S> {
+        &self.nodes[node]
+    }
+
+    /// Replace `node` with a new one that recovers from its storage.
+    fn restart(&mut self, node: NodeId) -> Result<(), LogError> {
+        let size = self.nodes.len();
+        let (store, storage) = self.nodes.remove(node).into_parts();
+        let seed = self.rng.next_u64();
+        self.nodes
+            .insert(node, Node::new(node, size, store, storage, seed)?);
+        Ok(())
+    }
+}
+
+/// A replication group of Raft nodes, each applying the committed log to
+/// its own store.
+pub struct RaftCluster<S: MetadataLogStore> {
+    size: usize,
+    sim: Arc<Mutex<Simulation<S>>>,
+}
+
+impl<S: MetadataLogStore> Clone for RaftCluster<S> {
+    fn clone(&self) -> Self {
+        Self {
+            size: self.size,
+            sim: Arc::clone(&self.sim),
+        }
+    }
+}
+
+impl<S: MetadataLogStore> RaftCluster<S> {
+    /// Create a cluster of `size` nodes. `new_store` creates the local
+    /// store of each node; `seed` determines election timeouts and
+    /// message delivery order.
+    pub fn new(size: usize, seed: u64, mut new_store: impl FnMut(NodeId) -> S) -> Self {
+        assert!(size > 0, "a cluster needs at least one node");
+
+        let nodes = (0..size)
+            .map(|id| {
+                let storage = Box::new(MemoryRaftStorage::default());
+                Node::new(
+                    id,
+                    size,
+                    new_store(id),
+                    storage,
+                    seed.wrapping_add(id as u64),
+                )
+                .expect("a new node has nothing to recover")
+            })
+            .collect();
+
+        Self {
+            size,
+            sim: Arc::new(Mutex::new(Simulation {
+                nodes,
+                in_flight: Vec::new(),
+                groups: vec![0; size],
+                loss_percent: 0,
+                rng: Rng::new(!seed),
+                next_request: 1,
+            })),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// A store handle that submits commands through `node`.
+    pub fn store(&self, node: NodeId) -> RaftLogStore<S> {
+        assert!(
+            node < self.size,
+            "no node {node} in a cluster of {}",
+            self.size
+        );
+        RaftLogStore::simulated(self.clone(), node)
+    }
+
+    /// Split the network: nodes can only reach nodes in the same group.
+    /// Nodes not listed in any group are isolated.
+    pub fn partition(&self, groups: &[&[NodeId]]) -> Result<(), LogError> {
+        let mut sim = self.lock()?;
+
+        // Unlisted nodes each get a group of their own.
+        sim.groups = (0..self.size).map(|node| groups.len() + node).collect();
+        for (group, members) in groups.iter().enumerate() {
+            for &node in *members {
+                sim.groups[node] = group;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconnect all nodes.
+    pub fn heal(&self) -> Result<(), LogError> {
+        self.lock()?.groups = vec![0; self.size];
+        Ok(())
+    }
+
+    /// Drop `percent` of all messages.
+    pub fn set_message_loss(&self, percent: u64) -> Result<(), LogError> {
+        self.lock()?.loss_percent = percent.min(100);
+        Ok(())
+    }
+
+    /// Advance time by `rounds` rounds.
+    pub fn run(&self, rounds: usize) -> Result<(), LogError> {
+        let mut sim = self.lock()?;
+        for _ in 0..rounds {
+            sim.round()?;
+        }
+        Ok(())
+    }
+
+    /// Restart `node`, as after a crash: it keeps only its store and what
+    /// its Raft storage saved.
+    pub fn restart(&self, node: NodeId) -> Result<(), LogError> {
+        self.lock()?.restart(node)
+    }
+
+    /// The leader of the highest term, as far as the nodes know.
+    pub fn leader(&self) -> Result<Option<NodeId>, LogError> {
+        let sim = self.lock()?;
+        Ok(sim
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.role() == Role::Leader)
+            .max_by_key(|(_, node)| node.term())
+            .map(|(id, _)| id))
+    }
+
+    /// Current term of every node.
+    pub fn terms(&self) -> Result<Vec<u64>, LogError> {
+        Ok(self.lock()?.nodes.iter().map(Node::term).collect())
+    }
+
+    pub(super) fn lock(&self) -> Result<MutexGuard<'_, Simulation<S>>, LogError> {
+        self.sim
+            .lock()
+            .map_err(|_| LogError::Storage("raft cluster lock poisoned".into()))
+    }
+}
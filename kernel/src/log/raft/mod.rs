@@ -0,0 +1,502 @@
+// Replicated Log Store (Raft)
+//
+// Removes the single point of failure of a one-node control plane: the
+// log is replicated across a group of Axiom nodes and an append is only
+// acknowledged once a majority has stored it. Every node applies the
+// committed log, in the same order, to a local `MetadataLogStore`, so
+// any node can serve reads and take over as leader.
+//
+// Appends sent to a follower are forwarded to the leader. Reads are
+// linearizable without being logged: before answering, a node learns
+// the leader's commit index, confirmed by a quorum, and waits until it
+// has applied up to it, so it never returns a version older than one
+// already acknowledged to another client. A node cut off from the
+// majority therefore fails requests instead of serving stale data.
+// Applied entries are compacted out of the Raft log once every node
+// holds them (see `node.rs`).
+//
+// In a deployment each node is a `RaftNode` (see `server.rs`) that saves
+// its term, vote and log to a `RaftStorage` (see `storage.rs`) and talks
+// to its peers over a `Transport` such as `TcpTransport` (see
+// `transport.rs`). `RaftCluster` runs the same nodes in-process on a
+// simulated, deterministic network (see `cluster.rs`), which is used to
+// test behaviour under partitions, message loss and restarts. Local
+// stores must behave deterministically: an append that fails on one node
+// only (e.g. a full disk) is not retried.
+
+mod cluster;
+mod node;
+mod server;
+mod storage;
+mod transport;
+
+pub use cluster::RaftCluster;
+pub use node::NodeId;
+pub use server::RaftNode;
+pub use storage::{FileRaftStorage, HardState, MemoryRaftStorage, RaftStorage, SavedRaftState};
+pub use transport::{TcpTransport, Transport};
+
+use std::sync::Arc;
+
+use node::{Applied, Command, RequestId};
+use server::Shared;
+
+use super::{
+    AppendOutcome, EventStream, LogError, MetadataLogStore, TableEvent, TableId, Version,
+    VersionRange,
+};
+
+/// Store handle that replicates appends through a node of a Raft group,
+/// either a `RaftNode` or a node of a simulated `RaftCluster`.
+///
+/// Handles for different nodes can be used concurrently.
+pub struct RaftLogStore<S: MetadataLogStore> {
+    via: Via<S>,
+}
+
+enum Via<S: MetadataLogStore> {
+    Simulated {
+        cluster: RaftCluster<S>,
+        node: NodeId,
+    },
+    Running(Arc<Shared<S>>),
+}
+
+impl<S: MetadataLogStore> RaftLogStore<S> {
+    fn simulated(cluster: RaftCluster<S>, node: NodeId) -> Self {
+        Self {
+            via: Via::Simulated { cluster, node },
+        }
+    }
+
+    fn running(shared: Arc<Shared<S>>) -> Self {
+        Self {
+            via: Via::Running(shared),
+        }
+    }
+
+    /// Node this handle talks to.
+    pub fn node(&self) -> NodeId {
+        match &self.via {
+            Via::Simulated { node, .. } => *node,
+            Via::Running(shared) => shared.id(),
+        }
+    }
+
+    /// Submit a command and wait until the node has applied it.
+    fn request(&self, command: impl FnOnce(RequestId) -> Command) -> Result<Applied, LogError> {
+        match &self.via {
+            Via::Simulated { cluster, node } => cluster.lock()?.request(*node, command),
+            Via::Running(shared) => shared.request(command),
+        }
+    }
+
+    /// Run `read` against the node's store once it has caught up with
+    /// everything committed so far.
+    fn read<R>(&self, read: impl FnOnce(&S) -> Result<R, LogError>) -> Result<R, LogError> {
+        match &self.via {
+            Via::Simulated { cluster, node } => {
+                let mut sim = cluster.lock()?;
+                sim.request(*node, |request| Command::Read { request })?;
+                read(sim.store(*node))
+            }
+            Via::Running(shared) => {
+                shared.request(|request| Command::Read { request })?;
+                shared.read(read)
+            }
+        }
+    }
+}
+
+impl<S: MetadataLogStore> MetadataLogStore for RaftLogStore<S> {
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError> {
+        let applied = self.request(|request| Command::Append {
+            request,
+            event: Box::new(event.clone()),
+        })?;
+
+        match applied {
+            Applied::Append(result) => result,
+            Applied::Read => unreachable!("append answered as a read"),
+        }
+    }
+
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError> {
+        self.read(|store| store.find_idempotency_key(table_id, key))
+    }
+
+    /// Reads the whole range up front, so the node is not locked while
+    /// the stream is consumed.
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError> {
+        let events = self.read(|store| {
+            store
+                .stream(table_id, range)?
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(Box::new(events.into_iter().map(Ok)))
+    }
+
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        self.read(|store| store.current_version(table_id))
+    }
+
+    fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        self.read(|store| store.tables())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::conformance::StoreFactory;
+    use crate::log::{EventPayload, EventType, InMemoryLogStore, MetadataLog};
+    use uuid::Uuid;
+
+    type Cluster = RaftCluster<InMemoryLogStore>;
+
+    fn cluster(size: usize, seed: u64) -> Cluster {
+        RaftCluster::new(size, seed, |_| InMemoryLogStore::default())
+    }
+
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    fn event(version: Version) -> TableEvent {
+        TableEvent::new(table(), version, EventType::SnapshotAdded, vec![])
+    }
+
+    /// Versions of the table as applied by `node`, without a barrier.
+    fn applied(cluster: &Cluster, node: NodeId) -> Vec<Version> {
+        let sim = cluster.lock().unwrap();
+        let events = sim.store(node).load(&table()).unwrap();
+        events.iter().map(|e| e.version).collect()
+    }
+
+    /// Each location is a three-node cluster; stores are opened on its
+    /// nodes in turn.
+    struct Factory {
+        seed: u64,
+        next_node: NodeId,
+    }
+
+    impl StoreFactory for Factory {
+        type Store = RaftLogStore<InMemoryLogStore>;
+        type Location = Cluster;
+
+        fn create_location(&mut self) -> Cluster {
+            self.seed += 1;
+            cluster(3, self.seed)
+        }
+
+        fn open(&mut self, cluster: &Cluster) -> Self::Store {
+            self.next_node += 1;
+            cluster.store(self.next_node % cluster.size())
+        }
+
+        fn shares_storage(&self) -> bool {
+            true
+        }
+    }
+
+    mod conformance {
+        crate::store_conformance_tests!(super::Factory {
+            seed: 0,
+            next_node: 0
+        });
+    }
+
+    #[test]
+    fn followers_forward_appends_and_all_nodes_apply_them() {
+        let cluster = cluster(3, 1);
+        for v in 1..=6 {
+            let mut store = cluster.store(v as usize % 3);
+            assert_eq!(store.append(&event(v)), Ok(AppendOutcome::Appended(v)));
+        }
+
+        cluster.run(10).unwrap();
+        for node in 0..3 {
+            assert_eq!(
+                applied(&cluster, node),
+                vec![1, 2, 3, 4, 5, 6],
+                "node {node}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_from_followers_are_linearizable() {
+        let cluster = cluster(5, 2);
+        let mut writer = cluster.store(0);
+
+        for v in 1..=3 {
+            writer.append(&event(v)).unwrap();
+            for node in 1..5 {
+                assert_eq!(cluster.store(node).current_version(&table()), Ok(v));
+            }
+        }
+    }
+
+    #[test]
+    fn minority_partition_cannot_commit_or_read() {
+        let cluster = cluster(5, 3);
+        let mut log = MetadataLog::new(cluster.store(0));
+        log.append(event(1)).unwrap();
+
+        let old_leader = cluster.leader().unwrap().unwrap();
+        let majority: Vec<_> = (0..5).filter(|&n| n != old_leader).collect();
+        cluster.partition(&[&[old_leader], &majority]).unwrap();
+
+        // The deposed leader can neither commit nor serve reads.
+        let mut isolated = cluster.store(old_leader);
+        assert!(matches!(
+            isolated.append(&event(2)),
+            Err(LogError::Storage(_))
+        ));
+        assert!(isolated.current_version(&table()).is_err());
+
+        // The majority elects a new leader and carries on.
+        let mut log = MetadataLog::new(cluster.store(majority[0]));
+        log.append(event(2)).unwrap();
+        log.append(event(3)).unwrap();
+        let new_leader = cluster.leader().unwrap().unwrap();
+        assert_ne!(new_leader, old_leader);
+
+        // After healing, the old leader drops its uncommitted entry and
+        // catches up.
+        cluster.heal().unwrap();
+        assert_eq!(isolated.current_version(&table()), Ok(3));
+        assert_eq!(log.replay(&table()).unwrap().len(), 3);
+        cluster.run(10).unwrap();
+        for node in 0..5 {
+            assert_eq!(applied(&cluster, node), vec![1, 2, 3], "node {node}");
+        }
+    }
+
+    #[test]
+    fn commits_survive_message_loss() {
+        let cluster = cluster(3, 4);
+        cluster.set_message_loss(20).unwrap();
+
+        for v in 1..=20 {
+            let mut store = cluster.store(v as usize % 3);
+            store.append(&event(v)).unwrap();
+        }
+
+        cluster.set_message_loss(0).unwrap();
+        let versions: Vec<_> = (1..=20).collect();
+        assert_eq!(
+            cluster
+                .store(2)
+                .load(&table())
+                .unwrap()
+                .iter()
+                .map(|e| e.version)
+                .collect::<Vec<_>>(),
+            versions
+        );
+    }
+
+    #[test]
+    fn same_seed_same_history() {
+        let history = |seed| {
+            let cluster = cluster(5, seed);
+            cluster.set_message_loss(10).unwrap();
+            let mut terms = Vec::new();
+            for v in 1..=5 {
+                cluster.store(v as usize % 5).append(&event(v)).unwrap();
+                cluster.partition(&[&[0, 1], &[2, 3, 4]]).unwrap();
+                cluster.run(40).unwrap();
+                cluster.heal().unwrap();
+                terms.push((cluster.leader().unwrap(), cluster.terms().unwrap()));
+            }
+            terms
+        };
+
+        assert_eq!(history(42), history(42));
+    }
+
+    #[test]
+    fn restarted_nodes_keep_their_term_vote_and_log() {
+        let cluster = cluster(3, 6);
+        for v in 1..=3 {
+            cluster.store(v as usize % 3).append(&event(v)).unwrap();
+        }
+        cluster.run(10).unwrap();
+        let terms = cluster.terms().unwrap();
+
+        for node in 0..3 {
+            cluster.restart(node).unwrap();
+        }
+        let restarted = cluster.terms().unwrap();
+        assert!(restarted
+            .iter()
+            .zip(&terms)
+            .all(|(after, before)| after >= before));
+
+        // Nothing is applied twice, and the cluster carries on.
+        let mut store = cluster.store(1);
+        assert_eq!(store.append(&event(4)), Ok(AppendOutcome::Appended(4)));
+        cluster.run(10).unwrap();
+        for node in 0..3 {
+            assert_eq!(applied(&cluster, node), vec![1, 2, 3, 4], "node {node}");
+        }
+    }
+
+    #[test]
+    fn nodes_replicate_over_tcp_and_recover_from_disk() {
+        use crate::log::FileLogStore;
+        use std::net::TcpListener;
+        use std::time::{Duration, Instant};
+
+        const KEY: &[u8] = b"an example cluster key, 32+ bytes";
+
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let listeners: Vec<_> = (0..3)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let peers: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+
+        let start = |id: NodeId, transport: TcpTransport| {
+            let dir = dirs[id].path();
+            let store = FileLogStore::open(dir.join("warehouse.log")).unwrap();
+            let storage = FileRaftStorage::open(dir.join("raft")).unwrap();
+            RaftNode::start(id, 3, store, Box::new(storage), transport).unwrap()
+        };
+        let mut nodes: Vec<_> = listeners
+            .into_iter()
+            .enumerate()
+            .map(|(id, listener)| {
+                let transport = TcpTransport::new(id, listener, peers.clone(), KEY).unwrap();
+                Some(start(id, transport))
+            })
+            .collect();
+        let store = |nodes: &[Option<RaftNode<FileLogStore>>], id: NodeId| {
+            nodes[id].as_ref().unwrap().store()
+        };
+
+        // Requests wait for a leader to be elected.
+        for v in 1..=3 {
+            let mut store = store(&nodes, v as usize % 3);
+            assert_eq!(store.append(&event(v)), Ok(AppendOutcome::Appended(v)));
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let leader = loop {
+            let leader = (0..3).find(|&id| nodes[id].as_ref().unwrap().is_leader().unwrap());
+            match leader {
+                Some(leader) => break leader,
+                None if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+                None => panic!("no leader elected"),
+            }
+        };
+
+        // The others elect a new leader and carry on without it.
+        drop(nodes[leader].take());
+        let mut survivor = store(&nodes, (leader + 1) % 3);
+        assert_eq!(survivor.append(&event(4)), Ok(AppendOutcome::Appended(4)));
+
+        // The old leader restarts from its files and catches up.
+        let transport = TcpTransport::bind(leader, peers.clone(), KEY).unwrap();
+        nodes[leader] = Some(start(leader, transport));
+        let restarted = store(&nodes, leader);
+        assert_eq!(restarted.current_version(&table()), Ok(4));
+        let versions: Vec<_> = restarted
+            .load(&table())
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect();
+        assert_eq!(versions, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn large_entries_are_sent_in_batches_that_fit_a_frame() {
+        let cluster = cluster(3, 7);
+        let large = |version, len| {
+            let payload = EventPayload::Unknown(serde_json::json!({ "blob": "x".repeat(len) }));
+            TableEvent::new(table(), version, EventType::SnapshotAdded, payload)
+        };
+
+        // No message could carry this one.
+        assert!(matches!(
+            cluster.store(1).append(&large(1, 17 << 20)),
+            Err(LogError::InvalidPayload { version: 1, .. })
+        ));
+
+        cluster.store(0).append(&event(1)).unwrap();
+        let leader = cluster.leader().unwrap().unwrap();
+        let lagging = (leader + 1) % 3;
+        cluster.partition(&[&[leader, (leader + 2) % 3]]).unwrap();
+
+        // Together, the two entries exceed a frame.
+        let mut store = cluster.store(leader);
+        for v in 2..=3 {
+            store.append(&large(v, 9 << 20)).unwrap();
+        }
+
+        cluster.heal().unwrap();
+        cluster.run(10).unwrap();
+        assert_eq!(applied(&cluster, lagging), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn reads_are_not_logged_and_applied_entries_are_compacted() {
+        let cluster = cluster(3, 8);
+        let logs = |cluster: &Cluster| {
+            let sim = cluster.lock().unwrap();
+            (0..3).map(|n| sim.node(n).log_range()).collect::<Vec<_>>()
+        };
+
+        let mut store = cluster.store(1);
+        store.append(&event(1)).unwrap();
+        cluster.run(10).unwrap();
+        let before = logs(&cluster);
+        for node in 0..3 {
+            for _ in 0..5 {
+                assert_eq!(cluster.store(node).current_version(&table()), Ok(1));
+            }
+        }
+        assert_eq!(logs(&cluster), before);
+
+        for v in 2..=40 {
+            store.append(&event(v)).unwrap();
+        }
+        cluster.run(10).unwrap();
+        assert!(logs(&cluster).iter().all(|&(first, _)| first > 1));
+
+        // A restarted node recovers from its compacted log.
+        cluster.restart(2).unwrap();
+        store.append(&event(41)).unwrap();
+
+        // Entries a node lacks are kept until it catches up.
+        let leader = cluster.leader().unwrap().unwrap();
+        cluster.partition(&[&[leader, (leader + 2) % 3]]).unwrap();
+        let mut store = cluster.store(leader);
+        for v in 42..=80 {
+            store.append(&event(v)).unwrap();
+        }
+        let (first, _) = logs(&cluster)[leader];
+        cluster.heal().unwrap();
+        store.append(&event(81)).unwrap();
+        cluster.run(20).unwrap();
+
+        let versions: Vec<_> = (1..=81).collect();
+        for node in 0..3 {
+            assert_eq!(applied(&cluster, node), versions, "node {node}");
+        }
+        assert!(logs(&cluster).iter().all(|&(now, _)| now > first));
+    }
+
+    #[test]
+    fn single_node_cluster_commits_alone() {
+        let cluster = cluster(1, 5);
+        let mut store = cluster.store(0);
+        store.append(&event(1)).unwrap();
+        assert_eq!(store.current_version(&table()), Ok(1));
+    }
+}
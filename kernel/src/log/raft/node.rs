@@ -0,0 +1,984 @@
+// Raft Consensus Node
+//
+// One member of a replication group, written as a deterministic state
+// machine: it reacts to clock ticks and incoming messages and queues the
+// messages it wants to send in an outbox, without doing any I/O itself.
+// Committed entries are applied, in log order, to the node's local
+// store, so every node's store holds the same events.
+//
+// Follows the Raft paper (Ongaro & Ousterhout, 2014), without membership
+// changes or snapshots. A new leader appends a no-op entry so that
+// entries from earlier terms commit as soon as possible.
+//
+// Reads do not go through the log (the paper's "read index"): the leader
+// notes its commit index when a read arrives, confirms with a round of
+// heartbeats that a quorum still follows it, and the node serving the
+// read waits until it has applied up to that index.
+//
+// The log is compacted: entries that every node has stored and applied
+// are discarded, except for the last `DEDUP_ENTRIES`. As there are no
+// snapshots to send instead, a node that is down stops the others from
+// compacting until it has caught up, and a node must not lose its store.
+//
+// Term, vote and log are saved to the node's `RaftStorage` before any
+// message leaves the outbox, and the applied index after every append
+// applied to the store. A restarted node resumes from them; only the
+// append applied last before the restart may already be in the store,
+// and is recognized there rather than applied twice. A node whose
+// storage fails must not be used again.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use super::cluster::Rng;
+use super::storage::{HardState, RaftStorage, SavedRaftState};
+use crate::log::record::{storage, MAX_RECORD_LEN};
+use crate::log::{AppendOutcome, LogError, MetadataLogStore, TableEvent, VersionRange};
+
+/// Index of a node within its cluster.
+pub type NodeId = usize;
+
+/// Identifies a client command across retries.
+pub(super) type RequestId = u64;
+
+/// Ticks without hearing from a leader before starting an election.
+const ELECTION_TIMEOUT: Range<u64> = 10..20;
+
+/// Ticks between two heartbeats of a leader.
+const HEARTBEAT_INTERVAL: u64 = 3;
+
+/// Ticks a leader waits for a quorum to confirm a read; clients retry
+/// reads that were not answered.
+const READ_TIMEOUT: u64 = ELECTION_TIMEOUT.end;
+
+/// Most bytes of encoded entries sent in one `AppendEntries` message,
+/// leaving room for the rest of the message in a transport frame. An
+/// entry must fit on its own, so larger appends are rejected.
+const MAX_APPEND_BYTES: usize = MAX_RECORD_LEN as usize - 4096;
+
+/// Applied entries whose requests are remembered, so a retried command
+/// is not applied twice. Compaction keeps them in the log, which lets a
+/// restarted node remember the same requests as the others.
+const DEDUP_ENTRIES: u64 = if cfg!(test) { 16 } else { 10_000 };
+
+/// Entries discarded at once, as compacting rewrites the saved log.
+const COMPACT_ENTRIES: u64 = if cfg!(test) { 8 } else { 10_000 };
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) enum Command {
+    Append {
+        request: RequestId,
+        event: Box<TableEvent>,
+    },
+
+    /// Wait until the node's store reflects every command committed
+    /// before the read was issued. Never appended to the log.
+    Read { request: RequestId },
+}
+
+impl Command {
+    fn request(&self) -> RequestId {
+        match self {
+            Command::Append { request, .. } | Command::Read { request } => *request,
+        }
+    }
+}
+
+/// Result of a command.
+#[derive(Debug)]
+pub(super) enum Applied {
+    Append(Result<AppendOutcome, LogError>),
+    Read,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct Entry {
+    term: u64,
+    /// `None` for a leader's initial no-op.
+    command: Option<Command>,
+
+    /// Length of the entry as encoded, set when it is added to a log.
+    #[serde(skip)]
+    len: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) enum Message {
+    RequestVote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+        /// Last index every node is known to have stored.
+        replicated: u64,
+        /// Echoed in the response, to confirm reads.
+        round: u64,
+    },
+    AppendResponse {
+        term: u64,
+        success: bool,
+        /// Last index known to match the leader's log.
+        match_index: u64,
+        round: u64,
+    },
+    /// A command submitted to a follower, passed on to its leader.
+    Forward {
+        command: Command,
+    },
+    /// A leader's answer to a forwarded read: the read may be served
+    /// once `index` is applied.
+    ReadIndex {
+        request: RequestId,
+        index: u64,
+    },
+}
+
+impl Message {
+    pub fn encode(&self) -> Result<Vec<u8>, LogError> {
+        serde_json::to_vec(self).map_err(storage)
+    }
+
+    /// `None` if `bytes` are not a message.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        serde_json::from_slice(bytes).ok()
+    }
+
+    fn term(&self) -> Option<u64> {
+        match self {
+            Message::RequestVote { term, .. }
+            | Message::Vote { term, .. }
+            | Message::AppendEntries { term, .. }
+            | Message::AppendResponse { term, .. } => Some(*term),
+            Message::Forward { .. } | Message::ReadIndex { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// A read a leader has yet to confirm.
+#[derive(Debug)]
+struct PendingRead {
+    request: RequestId,
+    /// Node serving the read.
+    from: NodeId,
+    index: u64,
+    /// Heartbeat round that confirms it.
+    round: u64,
+    /// Tick at which it is given up.
+    expires: u64,
+}
+
+pub(super) struct Node<S> {
+    id: NodeId,
+    size: usize,
+    rng: Rng,
+
+    // Persistent state.
+    term: u64,
+    voted_for: Option<NodeId>,
+    /// Entries after `compacted`.
+    log: Vec<Entry>,
+    /// Index and term of the last entry discarded by compaction.
+    compacted: u64,
+    compacted_term: u64,
+
+    // Volatile state.
+    role: Role,
+    leader: Option<NodeId>,
+    commit: u64,
+    applied: u64,
+    elapsed: u64,
+    election_timeout: u64,
+    votes: BTreeSet<NodeId>,
+    next_index: BTreeMap<NodeId, u64>,
+    match_index: BTreeMap<NodeId, u64>,
+    /// Last index every node is known to have stored.
+    replicated: u64,
+    /// Ticks since the node started.
+    clock: u64,
+
+    // Reads.
+    /// Latest heartbeat round of a leader, and the latest each peer
+    /// answered in its term.
+    round: u64,
+    answered: BTreeMap<NodeId, u64>,
+    pending_reads: Vec<PendingRead>,
+    /// Confirmed reads served by this node, with the index to apply
+    /// first.
+    reads: BTreeMap<RequestId, u64>,
+
+    // Durability.
+    storage: Box<dyn RaftStorage>,
+    /// Index of the first log entry not saved yet.
+    unsaved_from: Option<u64>,
+    /// Term or vote changed since they were saved.
+    unsaved_vote: bool,
+    /// Until the first append is applied after a restart: it may have
+    /// been applied just before.
+    recovering: bool,
+
+    // State machine.
+    store: S,
+    /// Requests of the last `DEDUP_ENTRIES` applied entries, with the
+    /// index of the latest entry holding each.
+    seen: HashMap<RequestId, u64>,
+    /// Requests submitted through this node whose results are wanted.
+    waiting: HashSet<RequestId>,
+    results: BTreeMap<RequestId, Applied>,
+
+    outbox: Vec<(NodeId, Message)>,
+}
+
+impl<S: MetadataLogStore> Node<S> {
+    /// Start a node from what `storage` saved, if anything.
+    pub fn new(
+        id: NodeId,
+        size: usize,
+        store: S,
+        mut storage: Box<dyn RaftStorage>,
+        seed: u64,
+    ) -> Result<Self, LogError> {
+        let SavedRaftState {
+            state,
+            compacted,
+            compacted_term,
+            entries,
+        } = storage.load()?;
+        let log = entries
+            .iter()
+            .map(|bytes| {
+                serde_json::from_slice(bytes).map(|entry| Entry {
+                    len: bytes.len(),
+                    ..entry
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| LogError::Corrupt(format!("unreadable raft entry: {e}")))?;
+        if state.applied > compacted + log.len() as u64 {
+            return Err(LogError::Corrupt(format!(
+                "raft entry {} was applied but not saved",
+                state.applied
+            )));
+        }
+        if state.applied < compacted {
+            return Err(LogError::Corrupt(format!(
+                "raft entry {compacted} was compacted but not applied"
+            )));
+        }
+
+        let mut node = Self {
+            id,
+            size,
+            rng: Rng::new(seed),
+            term: state.term,
+            voted_for: state.voted_for,
+            log,
+            compacted,
+            compacted_term,
+            role: Role::Follower,
+            leader: None,
+            commit: state.applied,
+            applied: state.applied,
+            elapsed: 0,
+            election_timeout: 0,
+            votes: BTreeSet::new(),
+            next_index: BTreeMap::new(),
+            match_index: BTreeMap::new(),
+            replicated: compacted,
+            clock: 0,
+            round: 0,
+            answered: BTreeMap::new(),
+            pending_reads: Vec::new(),
+            reads: BTreeMap::new(),
+            storage,
+            unsaved_from: None,
+            unsaved_vote: false,
+            recovering: true,
+            store,
+            seen: HashMap::new(),
+            waiting: HashSet::new(),
+            results: BTreeMap::new(),
+            outbox: Vec::new(),
+        };
+
+        let window = (node.applied.saturating_sub(DEDUP_ENTRIES) + 1).max(compacted + 1);
+        for index in window..=node.applied {
+            if let Some(command) = &node.entry(index).command {
+                node.seen.insert(command.request(), index);
+            }
+        }
+
+        node.reset_election_timer();
+        Ok(node)
+    }
+
+    /// The node's store and storage, to restart it with.
+    pub fn into_parts(self) -> (S, Box<dyn RaftStorage>) {
+        (self.store, self.storage)
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// First and last index of the entries the node holds.
+    #[cfg(test)]
+    pub fn log_range(&self) -> (u64, u64) {
+        (self.compacted + 1, self.last_index())
+    }
+
+    pub fn take_outbox(&mut self) -> Vec<(NodeId, Message)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    pub fn take_result(&mut self, request: RequestId) -> Option<Applied> {
+        self.results.remove(&request)
+    }
+
+    /// Stop waiting for the result of `request`.
+    pub fn abandon(&mut self, request: RequestId) {
+        self.waiting.remove(&request);
+    }
+
+    /// Handle `command` if leading, otherwise forward it to the known
+    /// leader. Safe to call again for the same command: it is applied
+    /// at most once.
+    ///
+    /// An append too large to replicate fails at once.
+    pub fn submit(&mut self, command: Command) -> Result<(), LogError> {
+        let request = command.request();
+        if self.seen.contains_key(&request) {
+            return Ok(());
+        }
+        if let Err(e) = check_len(&command) {
+            self.results.insert(request, Applied::Append(Err(e)));
+            return Ok(());
+        }
+        self.waiting.insert(request);
+
+        self.durably(|node| match (node.role, node.leader) {
+            (Role::Leader, _) => node.accept(node.id, command),
+            (_, Some(leader)) => {
+                node.send(leader, Message::Forward { command });
+                Ok(())
+            }
+            (_, None) => Ok(()),
+        })
+    }
+
+    /// Advance the node's clock by one tick.
+    pub fn tick(&mut self) -> Result<(), LogError> {
+        self.clock += 1;
+        self.elapsed += 1;
+
+        let clock = self.clock;
+        self.pending_reads.retain(|read| read.expires > clock);
+
+        self.durably(|node| match node.role {
+            Role::Leader if node.elapsed >= HEARTBEAT_INTERVAL => {
+                node.elapsed = 0;
+                node.broadcast_append();
+                Ok(())
+            }
+            Role::Follower | Role::Candidate if node.elapsed >= node.election_timeout => {
+                node.start_election()
+            }
+            _ => Ok(()),
+        })
+    }
+
+    /// Handle a message from another node.
+    pub fn step(&mut self, from: NodeId, message: Message) -> Result<(), LogError> {
+        self.durably(|node| node.receive(from, message))
+    }
+
+    /// Run `act`, then save what it changed. The outbox is dropped if
+    /// either fails, as its messages may rely on unsaved state.
+    fn durably(
+        &mut self,
+        act: impl FnOnce(&mut Self) -> Result<(), LogError>,
+    ) -> Result<(), LogError> {
+        let result = act(self).and_then(|_| self.persist());
+        if result.is_err() {
+            self.outbox.clear();
+        }
+        result
+    }
+
+    fn receive(&mut self, from: NodeId, message: Message) -> Result<(), LogError> {
+        if let Some(term) = message.term() {
+            if term > self.term {
+                self.become_follower(term);
+            }
+        }
+
+        match message {
+            Message::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => {
+                let ours = (self.term_at(self.last_index()), self.last_index());
+                let granted = term == self.term
+                    && self.voted_for.is_none_or(|v| v == from)
+                    && (last_log_term, last_log_index) >= ours;
+
+                if granted {
+                    self.voted_for = Some(from);
+                    self.unsaved_vote = true;
+                    self.reset_election_timer();
+                }
+                self.send(
+                    from,
+                    Message::Vote {
+                        term: self.term,
+                        granted,
+                    },
+                );
+            }
+
+            Message::Vote { term, granted } => {
+                if self.role == Role::Candidate && term == self.term && granted {
+                    self.votes.insert(from);
+                    if self.votes.len() >= self.quorum() {
+                        self.become_leader()?;
+                    }
+                }
+            }
+
+            Message::AppendEntries {
+                term,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+                replicated,
+                round,
+            } => {
+                if term == self.term {
+                    self.replicated = self.replicated.max(replicated);
+                }
+                let (success, match_index) =
+                    self.handle_append(from, term, prev_index, prev_term, entries, commit)?;
+                self.compact()?;
+                self.send(
+                    from,
+                    Message::AppendResponse {
+                        term: self.term,
+                        success,
+                        match_index,
+                        round,
+                    },
+                );
+            }
+
+            Message::AppendResponse {
+                term,
+                success,
+                match_index,
+                round,
+            } => {
+                if self.role != Role::Leader || term != self.term {
+                    return Ok(());
+                }
+
+                // Even a failed append shows the peer follows this term.
+                let answered = self.answered.entry(from).or_default();
+                *answered = round.max(*answered);
+                self.confirm_reads();
+
+                let matched = self.match_index.get(&from).copied().unwrap_or(0);
+                if success {
+                    let matched = matched.max(match_index);
+                    self.match_index.insert(from, matched);
+                    self.next_index.insert(from, matched + 1);
+                    self.advance_commit()?;
+                    self.compact()?;
+
+                    // Entries left over from a capped message.
+                    if matched < self.last_index() {
+                        self.send_append(from);
+                    }
+                } else {
+                    self.next_index.insert(from, match_index.max(matched) + 1);
+                    self.send_append(from);
+                }
+            }
+
+            Message::Forward { command } => {
+                // Only leaders accept forwarded commands; the client
+                // retries if the node it asked was mistaken.
+                if self.role == Role::Leader {
+                    self.accept(from, command)?;
+                }
+            }
+
+            Message::ReadIndex { request, index } => {
+                if self.waiting.contains(&request) {
+                    self.reads.insert(request, index);
+                    self.finish_reads();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Store what a leader sent; returns whether the log matched and the
+    /// last index known to match.
+    fn handle_append(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    ) -> Result<(bool, u64), LogError> {
+        if term < self.term {
+            return Ok((false, 0));
+        }
+
+        self.role = Role::Follower;
+        self.leader = Some(from);
+        self.reset_election_timer();
+
+        // Compacted entries were committed, so they match.
+        if prev_index < self.compacted {
+            return Ok((true, self.compacted));
+        }
+        if prev_index > self.last_index() || self.term_at(prev_index) != prev_term {
+            let hint = prev_index.saturating_sub(1).min(self.last_index());
+            return Ok((false, hint));
+        }
+
+        let mut index = prev_index;
+        for entry in entries {
+            index += 1;
+            if index <= self.last_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+                // Uncommitted entries of a deposed leader.
+                self.log.truncate((index - self.compacted) as usize - 1);
+            }
+            self.push(entry);
+        }
+
+        if commit > self.commit {
+            self.commit = commit.min(index);
+            self.apply()?;
+        }
+        Ok((true, index))
+    }
+
+    /// Handle a command submitted to the leader through node `from`.
+    fn accept(&mut self, from: NodeId, command: Command) -> Result<(), LogError> {
+        match command {
+            Command::Read { request } => {
+                self.read_index(from, request);
+                Ok(())
+            }
+            command if self.seen.contains_key(&command.request()) => Ok(()),
+            command => self.propose(command),
+        }
+    }
+
+    /// Note the index a read must wait for and start the heartbeat round
+    /// that confirms it.
+    fn read_index(&mut self, from: NodeId, request: RequestId) {
+        // Until it commits an entry of its term, a new leader may not
+        // know everything that was committed.
+        if self.term_at(self.commit) != self.term {
+            return;
+        }
+
+        self.round += 1;
+        self.pending_reads.push(PendingRead {
+            request,
+            from,
+            index: self.commit,
+            round: self.round,
+            expires: self.clock + READ_TIMEOUT,
+        });
+        self.broadcast_append();
+        self.confirm_reads();
+    }
+
+    /// Serve the reads whose heartbeat round a quorum has answered.
+    fn confirm_reads(&mut self) {
+        let quorum = self.quorum();
+        let answered = &self.answered;
+        let (confirmed, pending) = std::mem::take(&mut self.pending_reads)
+            .into_iter()
+            .partition(|read: &PendingRead| {
+                1 + answered.values().filter(|&&r| r >= read.round).count() >= quorum
+            });
+        self.pending_reads = pending;
+
+        for read in confirmed {
+            if read.from == self.id {
+                self.reads.insert(read.request, read.index);
+            } else {
+                let (request, index) = (read.request, read.index);
+                self.send(read.from, Message::ReadIndex { request, index });
+            }
+        }
+        self.finish_reads();
+    }
+
+    /// Answer the confirmed reads whose index is applied.
+    fn finish_reads(&mut self) {
+        let applied = self.applied;
+        let (waiting, results) = (&mut self.waiting, &mut self.results);
+        self.reads.retain(|request, index| {
+            if *index > applied {
+                return true;
+            }
+            if waiting.remove(request) {
+                results.insert(*request, Applied::Read);
+            }
+            false
+        });
+    }
+
+    /// Append `command` to the leader's log unless it is already waiting
+    /// to be committed.
+    fn propose(&mut self, command: Command) -> Result<(), LogError> {
+        // No message could carry it; the submitting node rejected it.
+        if check_len(&command).is_err() {
+            return Ok(());
+        }
+
+        let request = command.request();
+        let uncommitted = (self.commit - self.compacted) as usize;
+        let pending = self.log[uncommitted..].iter().any(|entry| {
+            entry
+                .command
+                .as_ref()
+                .is_some_and(|c| c.request() == request)
+        });
+        if pending {
+            return Ok(());
+        }
+
+        self.push(Entry {
+            term: self.term,
+            command: Some(command),
+            len: 0,
+        });
+        self.broadcast_append();
+        self.advance_commit()
+    }
+
+    fn start_election(&mut self) -> Result<(), LogError> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.id);
+        self.unsaved_vote = true;
+        self.leader = None;
+        self.votes = BTreeSet::from([self.id]);
+        self.reset_election_timer();
+
+        if self.votes.len() >= self.quorum() {
+            return self.become_leader();
+        }
+
+        let message = Message::RequestVote {
+            term: self.term,
+            last_log_index: self.last_index(),
+            last_log_term: self.term_at(self.last_index()),
+        };
+        for peer in self.peers() {
+            self.send(peer, message.clone());
+        }
+        Ok(())
+    }
+
+    fn become_follower(&mut self, term: u64) {
+        self.term = term;
+        self.voted_for = None;
+        self.unsaved_vote = true;
+        self.role = Role::Follower;
+        self.leader = None;
+        self.pending_reads.clear();
+    }
+
+    fn become_leader(&mut self) -> Result<(), LogError> {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.elapsed = 0;
+
+        let next = self.last_index() + 1;
+        self.next_index = self.peers().map(|peer| (peer, next)).collect();
+        self.match_index = self.peers().map(|peer| (peer, 0)).collect();
+        self.answered.clear();
+
+        self.push(Entry {
+            term: self.term,
+            command: None,
+            len: 0,
+        });
+        self.broadcast_append();
+        self.advance_commit()
+    }
+
+    /// Commit the latest entry of the current term stored on a quorum.
+    fn advance_commit(&mut self) -> Result<(), LogError> {
+        for index in (self.commit + 1..=self.last_index()).rev() {
+            if self.term_at(index) != self.term {
+                break;
+            }
+
+            let replicas = 1 + self.match_index.values().filter(|&&m| m >= index).count();
+            if replicas >= self.quorum() {
+                self.commit = index;
+                self.apply()?;
+                // Let followers apply without waiting for a heartbeat.
+                self.broadcast_append();
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply committed entries to the store. The log is saved first, so
+    /// an applied entry is never lost on restart.
+    fn apply(&mut self) -> Result<(), LogError> {
+        self.persist()?;
+
+        while self.applied < self.commit {
+            self.applied += 1;
+            self.forget(self.applied.saturating_sub(DEDUP_ENTRIES));
+
+            let Some(Command::Append { request, event }) = self.entry(self.applied).command.clone()
+            else {
+                continue;
+            };
+            if self.seen.insert(request, self.applied).is_some() {
+                continue;
+            }
+
+            let result = match self.recovering && self.holds(&event)? {
+                true => Ok(AppendOutcome::Appended(event.version)),
+                false => self.store.append(&event),
+            };
+            self.recovering = false;
+            self.save_state()?;
+
+            if self.waiting.remove(&request) {
+                self.results.insert(request, Applied::Append(result));
+            }
+        }
+
+        self.finish_reads();
+        self.compact()
+    }
+
+    /// Forget the request of the entry at `index` unless a later entry
+    /// holds it too.
+    fn forget(&mut self, index: u64) {
+        if index <= self.compacted {
+            return;
+        }
+        if let Some(command) = &self.entry(index).command {
+            let request = command.request();
+            if self.seen.get(&request) == Some(&index) {
+                self.seen.remove(&request);
+            }
+        }
+    }
+
+    /// Discard entries that every node has stored and this node applied,
+    /// but for the last `DEDUP_ENTRIES`.
+    fn compact(&mut self) -> Result<(), LogError> {
+        if self.role == Role::Leader {
+            let stored = self.match_index.values().min().copied();
+            let stored = stored.unwrap_or(self.last_index());
+            self.replicated = self.replicated.max(stored);
+        }
+
+        let index = self
+            .applied
+            .min(self.replicated)
+            .saturating_sub(DEDUP_ENTRIES);
+        if index < self.compacted + COMPACT_ENTRIES {
+            return Ok(());
+        }
+
+        // SavedRaftState entries only, and never ahead of the saved applied index.
+        self.persist()?;
+        self.save_state()?;
+        let term = self.term_at(index);
+        self.storage.compact(index, term)?;
+
+        self.log.drain(..(index - self.compacted) as usize);
+        self.compacted = index;
+        self.compacted_term = term;
+        Ok(())
+    }
+
+    /// Whether the store already holds `event`.
+    fn holds(&self, event: &TableEvent) -> Result<bool, LogError> {
+        let range = VersionRange::between(event.version, event.version);
+        let Some(stored) = self.store.stream(&event.table_id, range)?.next() else {
+            return Ok(false);
+        };
+        let encode = |event: &TableEvent| serde_json::to_value(event).map_err(storage);
+        Ok(encode(&stored?)? == encode(event)?)
+    }
+
+    fn push(&mut self, mut entry: Entry) {
+        entry.len = encoded_len(&entry);
+        let index = self.last_index() + 1;
+        self.unsaved_from = Some(self.unsaved_from.map_or(index, |from| from.min(index)));
+        self.log.push(entry);
+    }
+
+    /// Save the term, vote and log entries changed since the last save.
+    fn persist(&mut self) -> Result<(), LogError> {
+        if let Some(from) = self.unsaved_from {
+            let entries = self.log[(from - self.compacted) as usize - 1..]
+                .iter()
+                .map(|entry| serde_json::to_vec(entry).map_err(storage))
+                .collect::<Result<Vec<_>, _>>()?;
+            self.storage.save_entries(from, &entries)?;
+            self.unsaved_from = None;
+        }
+        if self.unsaved_vote {
+            self.save_state()?;
+        }
+        Ok(())
+    }
+
+    fn save_state(&mut self) -> Result<(), LogError> {
+        self.storage.save_state(&HardState {
+            term: self.term,
+            voted_for: self.voted_for,
+            applied: self.applied,
+        })?;
+        self.unsaved_vote = false;
+        Ok(())
+    }
+
+    fn broadcast_append(&mut self) {
+        for peer in self.peers().collect::<Vec<_>>() {
+            self.send_append(peer);
+        }
+    }
+
+    fn send_append(&mut self, peer: NodeId) {
+        // Every node stores the compacted entries.
+        let next = self.next_index.get(&peer).copied().unwrap_or(1);
+        let next = next.max(self.compacted + 1);
+        let prev_index = next - 1;
+
+        // At least one entry, however large, so replication progresses.
+        let mut entries = Vec::new();
+        let mut bytes = 0;
+        for entry in &self.log[(prev_index - self.compacted) as usize..] {
+            bytes += entry.len + 1;
+            if bytes > MAX_APPEND_BYTES && !entries.is_empty() {
+                break;
+            }
+            entries.push(entry.clone());
+        }
+
+        self.send(
+            peer,
+            Message::AppendEntries {
+                term: self.term,
+                prev_index,
+                prev_term: self.term_at(prev_index),
+                entries,
+                commit: self.commit,
+                replicated: self.replicated,
+                round: self.round,
+            },
+        );
+    }
+
+    fn reset_election_timer(&mut self) {
+        self.elapsed = 0;
+        let span = ELECTION_TIMEOUT.end - ELECTION_TIMEOUT.start;
+        self.election_timeout = ELECTION_TIMEOUT.start + self.rng.below(span);
+    }
+
+    fn send(&mut self, to: NodeId, message: Message) {
+        self.outbox.push((to, message));
+    }
+
+    fn peers(&self) -> impl Iterator<Item = NodeId> {
+        let id = self.id;
+        (0..self.size).filter(move |&peer| peer != id)
+    }
+
+    fn quorum(&self) -> usize {
+        self.size / 2 + 1
+    }
+
+    fn last_index(&self) -> u64 {
+        self.compacted + self.log.len() as u64
+    }
+
+    /// The entry at `index`, which must not be compacted.
+    fn entry(&self, index: u64) -> &Entry {
+        &self.log[(index - self.compacted) as usize - 1]
+    }
+
+    /// Term of the entry at `index`, which may be the last compacted.
+    fn term_at(&self, index: u64) -> u64 {
+        match index {
+            i if i == self.compacted => self.compacted_term,
+            i => self.entry(i).term,
+        }
+    }
+}
+
+/// Length of `value` as encoded in messages.
+fn encoded_len(value: &impl Serialize) -> usize {
+    serde_json::to_vec(value).map_or(usize::MAX, |bytes| bytes.len())
+}
+
+/// Commands must fit in a message on their own, within an entry.
+fn check_len(command: &Command) -> Result<(), LogError> {
+    let Command::Append { event, .. } = command else {
+        return Ok(());
+    };
+    let len = encoded_len(command).saturating_add(64);
+    if len <= MAX_APPEND_BYTES {
+        return Ok(());
+    }
+    Err(LogError::InvalidPayload {
+        table_id: event.table_id.clone(),
+        version: event.version,
+        reason: format!(
+            "event encodes to {len} bytes, more than the {MAX_APPEND_BYTES} that can be replicated"
+        ),
+    })
+}
@@ -0,0 +1,233 @@
+// Networked Node
+//
+// Runs one Raft node of a real deployment: a worker thread ticks the node
+// on a wall clock, hands it the messages its `Transport` receives and
+// sends what it has to say. Store handles submit commands to the node and
+// wait, retrying now and then, until it has applied them.
+//
+// The node's local store must be as durable as its `RaftStorage`: the
+// saved applied index says which entries the store already holds.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::node::{Applied, Command, Message, Node, NodeId, RequestId, Role};
+use super::storage::RaftStorage;
+use super::transport::Transport;
+use super::RaftLogStore;
+use crate::log::record;
+use crate::log::{LogError, MetadataLogStore};
+
+/// Wall-clock length of a node tick.
+const TICK: Duration = Duration::from_millis(10);
+
+/// How long to wait for a request to be applied before submitting it
+/// again.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a request may take (e.g. while no quorum is reachable).
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(super) struct Shared<S> {
+    id: NodeId,
+    state: Mutex<Running<S>>,
+    /// Notified whenever the node may have applied something.
+    progress: Condvar,
+    stop: AtomicBool,
+}
+
+struct Running<S> {
+    node: Node<S>,
+    /// Why the node stopped, if its storage failed.
+    failed: Option<String>,
+}
+
+impl<S: MetadataLogStore> Shared<S> {
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Submit a command and wait until the node has applied it.
+    pub fn request(&self, command: impl FnOnce(RequestId) -> Command) -> Result<Applied, LogError> {
+        // Ids must be unique across the cluster and across restarts.
+        let request = uuid::Uuid::new_v4().as_u64_pair().0;
+        let command = command(request);
+
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let mut retry_at = Instant::now();
+        let mut running = self.lock()?;
+        loop {
+            if let Some(reason) = &running.failed {
+                return Err(LogError::Storage(format!(
+                    "raft node {} failed: {reason}",
+                    self.id
+                )));
+            }
+            if let Some(applied) = running.node.take_result(request) {
+                return Ok(applied);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                running.node.abandon(request);
+                return Err(LogError::Storage(format!(
+                    "node {} could not commit through a quorum",
+                    self.id
+                )));
+            }
+            if now >= retry_at {
+                if let Err(e) = running.node.submit(command.clone()) {
+                    running.failed = Some(e.to_string());
+                    continue;
+                }
+                retry_at = now + RETRY_INTERVAL;
+            }
+
+            let wait = retry_at.min(deadline) - now;
+            running = self
+                .progress
+                .wait_timeout(running, wait)
+                .map_err(|_| poisoned())?
+                .0;
+        }
+    }
+
+    /// Run `read` against the node's store.
+    pub fn read<R>(&self, read: impl FnOnce(&S) -> Result<R, LogError>) -> Result<R, LogError> {
+        read(self.lock()?.node.store())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Running<S>>, LogError> {
+        self.state.lock().map_err(|_| poisoned())
+    }
+}
+
+fn poisoned() -> LogError {
+    LogError::Storage("raft node lock poisoned".into())
+}
+
+/// A Raft node serving its part of a cluster over a `Transport`.
+///
+/// Stops when dropped.
+pub struct RaftNode<S: MetadataLogStore> {
+    shared: Arc<Shared<S>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<S: MetadataLogStore + 'static> RaftNode<S> {
+    /// Start node `id` of a cluster of `size` nodes, resuming from what
+    /// `storage` saved.
+    pub fn start(
+        id: NodeId,
+        size: usize,
+        store: S,
+        storage: Box<dyn RaftStorage>,
+        transport: impl Transport + 'static,
+    ) -> Result<Self, LogError> {
+        assert!(id < size, "no node {id} in a cluster of {size}");
+
+        let seed = uuid::Uuid::new_v4().as_u64_pair().0;
+        let node = Node::new(id, size, store, storage, seed)?;
+        let shared = Arc::new(Shared {
+            id,
+            state: Mutex::new(Running { node, failed: None }),
+            progress: Condvar::new(),
+            stop: AtomicBool::new(false),
+        });
+
+        let worker = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name(format!("raft-node-{id}"))
+                .spawn(move || work(&shared, transport))
+                .map_err(record::storage)?
+        };
+
+        Ok(Self {
+            shared,
+            worker: Some(worker),
+        })
+    }
+}
+
+impl<S: MetadataLogStore> RaftNode<S> {
+    pub fn id(&self) -> NodeId {
+        self.shared.id
+    }
+
+    /// A store handle that submits commands through this node.
+    pub fn store(&self) -> RaftLogStore<S> {
+        RaftLogStore::running(Arc::clone(&self.shared))
+    }
+
+    /// Whether the node currently believes it leads the cluster.
+    pub fn is_leader(&self) -> Result<bool, LogError> {
+        Ok(self.shared.lock()?.node.role() == Role::Leader)
+    }
+
+    pub fn term(&self) -> Result<u64, LogError> {
+        Ok(self.shared.lock()?.node.term())
+    }
+}
+
+impl<S: MetadataLogStore> Drop for RaftNode<S> {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+
+        // Fail requests of handles that outlive the node.
+        if let Ok(mut running) = self.shared.lock() {
+            running.failed.get_or_insert_with(|| "stopped".into());
+        }
+        self.shared.progress.notify_all();
+    }
+}
+
+/// Drive the node until it is stopped or its storage fails.
+fn work<S: MetadataLogStore>(shared: &Shared<S>, mut transport: impl Transport) {
+    let mut next_tick = Instant::now() + TICK;
+    while !shared.stop.load(Ordering::SeqCst) {
+        let received = transport.receive(next_tick.saturating_duration_since(Instant::now()));
+
+        let outbox = {
+            let Ok(mut running) = shared.lock() else {
+                return;
+            };
+            let mut result = match received.and_then(|(from, m)| Some((from, Message::decode(&m)?)))
+            {
+                Some((from, message)) => running.node.step(from, message),
+                None => Ok(()),
+            };
+            if result.is_ok() && Instant::now() >= next_tick {
+                next_tick = Instant::now() + TICK;
+                result = running.node.tick();
+            }
+
+            if let Err(e) = result {
+                running.failed = Some(e.to_string());
+            }
+            shared.progress.notify_all();
+            if running.failed.is_some() {
+                return;
+            }
+            running.node.take_outbox()
+        };
+
+        for (to, message) in outbox {
+            let sent = message
+                .encode()
+                .and_then(|bytes| transport.send(to, &bytes));
+            if let Err(e) = sent {
+                if let Ok(mut running) = shared.lock() {
+                    running.failed = Some(format!("cannot send to node {to}: {e}"));
+                }
+                shared.progress.notify_all();
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,420 @@
+// Durable Raft State
+//
+// What a node must not forget across a restart: the current term and
+// the vote cast in it, the log, and how far the log was applied to the
+// node's store. Without them a restarted node could vote twice in a term
+// or acknowledge entries it then loses.
+//
+// Log entries are opaque to storage; the node encodes them. Entries the
+// node applied and no longer needs are compacted away, leaving the index
+// and term of the last one. Every call must be durable once it returns.
+//
+// `FileRaftStorage` keeps the state in a small JSON file, replaced
+// atomically, and the log in a file of framed records (see `record.rs`)
+// whose torn tail is dropped when it is loaded. The first record holds
+// the index and term of the last compacted entry (u64 each,
+// little-endian); compacting writes the remaining entries to a new file
+// that replaces the log.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use super::NodeId;
+use crate::log::record::{frame, read_frame, storage, Frame};
+use crate::log::LogError;
+
+/// Term, vote and applied index of a node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    pub term: u64,
+    pub voted_for: Option<NodeId>,
+
+    /// Index of the last log entry applied to the node's store.
+    pub applied: u64,
+}
+
+/// A node's saved state and log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavedRaftState {
+    pub state: HardState,
+
+    /// Index and term of the last compacted entry, or zeros.
+    pub compacted: u64,
+    pub compacted_term: u64,
+
+    /// Entries after the compacted ones.
+    pub entries: Vec<Vec<u8>>,
+}
+
+/// Durable storage of a node's `HardState` and log.
+pub trait RaftStorage: Send {
+    /// State and log saved so far; empty for a new node.
+    fn load(&mut self) -> Result<SavedRaftState, LogError>;
+
+    fn save_state(&mut self, state: &HardState) -> Result<(), LogError>;
+
+    /// Replace the log from `index` (1-based) on with `entries`. `index`
+    /// is after the compacted entries and at most one past the last
+    /// saved entry.
+    fn save_entries(&mut self, index: u64, entries: &[Vec<u8>]) -> Result<(), LogError>;
+
+    /// Discard the saved entries up to `index`, which is of term `term`.
+    fn compact(&mut self, index: u64, term: u64) -> Result<(), LogError>;
+}
+
+/// In-memory storage. Clones share their contents, so a node restarted
+/// with a clone of its storage recovers as if from disk.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRaftStorage {
+    saved: Arc<Mutex<SavedRaftState>>,
+}
+
+impl MemoryRaftStorage {
+    fn lock(&self) -> Result<MutexGuard<'_, SavedRaftState>, LogError> {
+        self.saved
+            .lock()
+            .map_err(|_| LogError::Storage("raft storage lock poisoned".into()))
+    }
+}
+
+impl RaftStorage for MemoryRaftStorage {
+    fn load(&mut self) -> Result<SavedRaftState, LogError> {
+        Ok(self.lock()?.clone())
+    }
+
+    fn save_state(&mut self, state: &HardState) -> Result<(), LogError> {
+        self.lock()?.state = state.clone();
+        Ok(())
+    }
+
+    fn save_entries(&mut self, index: u64, entries: &[Vec<u8>]) -> Result<(), LogError> {
+        let mut saved = self.lock()?;
+        let at = check_index(index, saved.compacted, saved.entries.len())?;
+        saved.entries.truncate(at);
+        saved.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn compact(&mut self, index: u64, term: u64) -> Result<(), LogError> {
+        let mut saved = self.lock()?;
+        let count = check_compact(index, saved.compacted, saved.entries.len())?;
+        saved.entries.drain(..count);
+        saved.compacted = index;
+        saved.compacted_term = term;
+        Ok(())
+    }
+}
+
+/// File-backed storage in a directory of its own.
+#[derive(Debug)]
+pub struct FileRaftStorage {
+    dir: PathBuf,
+    log: File,
+    /// Index and term of the last compacted entry.
+    compacted: (u64, u64),
+    /// Offset of every saved entry, and the end of the last one.
+    offsets: Vec<u64>,
+}
+
+impl FileRaftStorage {
+    /// Open (or create) the storage in `dir`.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, LogError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage)?;
+
+        let mut storage = Self {
+            log: open_log(&dir.join("log"))?,
+            dir,
+            compacted: (0, 0),
+            offsets: vec![0],
+        };
+        storage.read_log()?;
+        Ok(storage)
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.dir.join("state.json")
+    }
+
+    /// Read every entry, dropping a torn tail. A log without a complete
+    /// header was never written to, and starts again with one.
+    fn read_log(&mut self) -> Result<Vec<Vec<u8>>, LogError> {
+        let file_len = self.log.metadata().map_err(storage)?.len();
+        let mut reader = BufReader::new(&self.log);
+        reader.seek(SeekFrom::Start(0)).map_err(storage)?;
+
+        let Some(Frame::Body(header)) = read_frame(&mut reader)? else {
+            drop(reader);
+            let header = frame(&log_header(0, 0))?;
+            if file_len > header.len() as u64 {
+                return Err(LogError::Corrupt("unreadable raft log header".into()));
+            }
+            self.log
+                .set_len(0)
+                .and_then(|_| self.log.seek(SeekFrom::Start(0)))
+                .and_then(|_| self.log.write_all(&header))
+                .and_then(|_| self.log.sync_all())
+                .map_err(storage)?;
+            self.compacted = (0, 0);
+            self.offsets = vec![header.len() as u64];
+            return Ok(Vec::new());
+        };
+        let compacted = parse_header(&header)?;
+
+        let mut entries = Vec::new();
+        let mut offsets = vec![reader.stream_position().map_err(storage)?];
+        while let Some(Frame::Body(entry)) = read_frame(&mut reader)? {
+            entries.push(entry);
+            offsets.push(reader.stream_position().map_err(storage)?);
+        }
+
+        let valid_len = offsets[offsets.len() - 1];
+        if valid_len < file_len {
+            self.log.set_len(valid_len).map_err(storage)?;
+            self.log.sync_all().map_err(storage)?;
+        }
+        self.compacted = compacted;
+        self.offsets = offsets;
+        Ok(entries)
+    }
+}
+
+fn open_log(path: &Path) -> Result<File, LogError> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(storage)
+}
+
+fn log_header(index: u64, term: u64) -> Vec<u8> {
+    [index.to_le_bytes(), term.to_le_bytes()].concat()
+}
+
+fn parse_header(header: &[u8]) -> Result<(u64, u64), LogError> {
+    let corrupt = || LogError::Corrupt("unreadable raft log header".into());
+    let (index, term) = header
+        .split_at_checked(8)
+        .filter(|(_, term)| term.len() == 8)
+        .ok_or_else(corrupt)?;
+    let index = index.try_into().map_err(|_| corrupt())?;
+    let term = term.try_into().map_err(|_| corrupt())?;
+    Ok((u64::from_le_bytes(index), u64::from_le_bytes(term)))
+}
+
+impl RaftStorage for FileRaftStorage {
+    fn load(&mut self) -> Result<SavedRaftState, LogError> {
+        let state = match fs::read(self.state_path()) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| LogError::Corrupt(format!("unreadable raft state: {e}")))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HardState::default(),
+            Err(e) => return Err(storage(e)),
+        };
+        let entries = self.read_log()?;
+        Ok(SavedRaftState {
+            state,
+            compacted: self.compacted.0,
+            compacted_term: self.compacted.1,
+            entries,
+        })
+    }
+
+    /// Written to a temporary file and renamed into place.
+    fn save_state(&mut self, state: &HardState) -> Result<(), LogError> {
+        let path = self.state_path();
+        let tmp = path.with_extension("tmp");
+        let body = serde_json::to_vec(state).map_err(storage)?;
+
+        let mut file = File::create(&tmp).map_err(storage)?;
+        file.write_all(&body).map_err(storage)?;
+        file.sync_all().map_err(storage)?;
+        fs::rename(&tmp, &path).map_err(storage)?;
+        File::open(&self.dir)
+            .and_then(|d| d.sync_all())
+            .map_err(storage)
+    }
+
+    fn save_entries(&mut self, index: u64, entries: &[Vec<u8>]) -> Result<(), LogError> {
+        let at = check_index(index, self.compacted.0, self.offsets.len() - 1)?;
+
+        let records = entries
+            .iter()
+            .map(|entry| frame(entry))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let start = self.offsets[at];
+        self.offsets.truncate(at + 1);
+        self.log
+            .set_len(start)
+            .and_then(|_| self.log.seek(SeekFrom::Start(start)))
+            .and_then(|_| self.log.write_all(&records.concat()))
+            .and_then(|_| self.log.sync_data())
+            .map_err(storage)?;
+
+        let mut end = start;
+        for record in &records {
+            end += record.len() as u64;
+            self.offsets.push(end);
+        }
+        Ok(())
+    }
+
+    /// The remaining entries are copied to a new file, renamed over the
+    /// log.
+    fn compact(&mut self, index: u64, term: u64) -> Result<(), LogError> {
+        let count = check_compact(index, self.compacted.0, self.offsets.len() - 1)?;
+
+        let start = self.offsets[count];
+        let end = self.offsets[self.offsets.len() - 1];
+        let mut kept = vec![0; (end - start) as usize];
+        self.log
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| self.log.read_exact(&mut kept))
+            .map_err(storage)?;
+
+        let path = self.dir.join("log");
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp).map_err(storage)?;
+        file.write_all(&frame(&log_header(index, term))?)
+            .and_then(|_| file.write_all(&kept))
+            .and_then(|_| file.sync_all())
+            .map_err(storage)?;
+        fs::rename(&tmp, &path).map_err(storage)?;
+        File::open(&self.dir)
+            .and_then(|d| d.sync_all())
+            .map_err(storage)?;
+
+        self.log = open_log(&path)?;
+        self.read_log().map(drop)
+    }
+}
+
+/// Position among the saved entries of the entry at `index`, which may
+/// be one past the last.
+fn check_index(index: u64, compacted: u64, saved: usize) -> Result<usize, LogError> {
+    let last = compacted + saved as u64;
+    if index <= compacted || index > last + 1 {
+        return Err(LogError::Storage(format!(
+            "raft entries saved from index {index}, but entries {} to {last} are saved",
+            compacted + 1
+        )));
+    }
+    Ok((index - compacted) as usize - 1)
+}
+
+/// Number of saved entries compacting up to `index` discards.
+fn check_compact(index: u64, compacted: u64, saved: usize) -> Result<usize, LogError> {
+    let last = compacted + saved as u64;
+    if index < compacted || index > last {
+        return Err(LogError::Storage(format!(
+            "raft log compacted up to index {index}, but entries {} to {last} are saved",
+            compacted + 1
+        )));
+    }
+    Ok((index - compacted) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(range: std::ops::RangeInclusive<u8>) -> Vec<Vec<u8>> {
+        range.map(|b| vec![b; b as usize]).collect()
+    }
+
+    #[test]
+    fn file_storage_survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = HardState {
+            term: 3,
+            voted_for: Some(1),
+            applied: 2,
+        };
+
+        let mut storage = FileRaftStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.load().unwrap(), SavedRaftState::default());
+        storage.save_state(&state).unwrap();
+        storage.save_entries(1, &entries(1..=3)).unwrap();
+        storage.save_entries(4, &entries(4..=5)).unwrap();
+        drop(storage);
+
+        let mut storage = FileRaftStorage::open(dir.path()).unwrap();
+        let saved = storage.load().unwrap();
+        assert_eq!((saved.state, saved.entries), (state, entries(1..=5)));
+    }
+
+    #[test]
+    fn saving_entries_replaces_the_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = FileRaftStorage::open(dir.path()).unwrap();
+        let mut memory = MemoryRaftStorage::default();
+
+        for storage in [&mut file as &mut dyn RaftStorage, &mut memory] {
+            storage.save_entries(1, &entries(1..=4)).unwrap();
+            storage.save_entries(3, &entries(7..=7)).unwrap();
+            assert!(storage.save_entries(5, &entries(8..=8)).is_err());
+
+            let saved = storage.load().unwrap().entries;
+            assert_eq!(saved, [entries(1..=2), entries(7..=7)].concat());
+        }
+    }
+
+    #[test]
+    fn torn_entries_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FileRaftStorage::open(dir.path()).unwrap();
+        storage.save_entries(1, &entries(1..=3)).unwrap();
+        drop(storage);
+
+        let path = dir.path().join("log");
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let mut storage = FileRaftStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.load().unwrap().entries, entries(1..=2));
+        storage.save_entries(3, &entries(9..=9)).unwrap();
+        assert_eq!(
+            storage.load().unwrap().entries,
+            [entries(1..=2), entries(9..=9)].concat()
+        );
+    }
+
+    #[test]
+    fn compacting_keeps_the_later_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = FileRaftStorage::open(dir.path()).unwrap();
+        let mut memory = MemoryRaftStorage::default();
+
+        for storage in [&mut file as &mut dyn RaftStorage, &mut memory] {
+            storage.save_entries(1, &entries(1..=5)).unwrap();
+            storage.compact(3, 2).unwrap();
+            assert!(storage.compact(6, 2).is_err());
+            assert!(storage.save_entries(3, &entries(9..=9)).is_err());
+            storage.save_entries(5, &entries(9..=9)).unwrap();
+            storage.save_entries(6, &entries(6..=6)).unwrap();
+
+            let saved = storage.load().unwrap();
+            assert_eq!((saved.compacted, saved.compacted_term), (3, 2));
+            assert_eq!(
+                saved.entries,
+                [entries(4..=4), entries(9..=9), entries(6..=6)].concat()
+            );
+        }
+
+        drop(file);
+        let saved = FileRaftStorage::open(dir.path()).unwrap().load().unwrap();
+        assert_eq!((saved.compacted, saved.compacted_term), (3, 2));
+        assert_eq!(saved.entries.len(), 3);
+    }
+}
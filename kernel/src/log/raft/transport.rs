@@ -0,0 +1,505 @@
+// Network Transport
+//
+// Carries encoded messages between the nodes of a running cluster.
+// Delivery is best effort: Raft tolerates lost, duplicated and reordered
+// messages, so a transport may drop a message it cannot deliver rather
+// than block the node. A message too large to ever be delivered is an
+// error instead: nodes keep theirs within a frame (see `node.rs`).
+//
+// `TcpTransport` keeps one outgoing connection per peer, opened on first
+// use and reopened after a failure, and accepts the peers' connections on
+// a listener.
+//
+// Trust model: the nodes of a cluster share a secret key, and whoever
+// holds it can act as any node. Peers are authenticated with it and
+// every message is integrity-protected, but not encrypted: clusters that
+// replicate confidential metadata must run on a private network or
+// through a tunnel. A connection goes:
+//
+//   accepting node:  nonce (32 random bytes)
+//   connecting node: sender id (u64, little-endian)
+//                    | HMAC-SHA256(key, "axiom.raft.hello" | nonce | from | to)
+//   then, per message: frame (see `record.rs`) of
+//                    message | HMAC-SHA256(session key, sequence | message)
+//
+// where the session key is HMAC-SHA256(key, "axiom.raft.session" | nonce
+// | from | to) and the sequence counts the connection's messages from 0
+// (u64, little-endian). The nonce keeps a recorded connection from being
+// replayed, the sequence keeps its messages from being replayed,
+// reordered or dropped unnoticed. A connection that fails a check is
+// closed.
+
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::NodeId;
+use crate::log::record::{frame, read_frame, storage, Frame, MAX_RECORD_LEN};
+use crate::log::LogError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of nonces, session keys and message tags.
+const TAG_LEN: usize = 32;
+
+/// Shortest cluster key accepted.
+pub const MIN_KEY_LEN: usize = 32;
+
+/// How long to wait for a peer to accept a connection and send its
+/// nonce.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long a connecting peer may take to identify itself.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait for a peer to take a message.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait before reconnecting to a peer that failed.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Moves messages between the nodes of a cluster.
+pub trait Transport: Send {
+    /// Send `message` to node `to`, or drop it if it cannot be delivered
+    /// now. Fails only for a message the transport can never carry.
+    fn send(&mut self, to: NodeId, message: &[u8]) -> Result<(), LogError>;
+
+    /// Next message received, with its sender, waiting at most `timeout`.
+    fn receive(&mut self, timeout: Duration) -> Option<(NodeId, Vec<u8>)>;
+}
+
+/// Transport over TCP connections between the nodes' listen addresses,
+/// authenticated with a key shared by the cluster.
+pub struct TcpTransport {
+    id: NodeId,
+    peers: Vec<SocketAddr>,
+    key: Arc<[u8]>,
+    outgoing: Vec<Option<Connection>>,
+    /// When a failed peer may be connected to again.
+    retry_at: Vec<Option<Instant>>,
+    incoming: Receiver<(NodeId, Vec<u8>)>,
+    accepted: Arc<Mutex<Vec<TcpStream>>>,
+    stop: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+}
+
+/// An authenticated connection to a peer.
+struct Connection {
+    stream: TcpStream,
+    session: [u8; TAG_LEN],
+    /// Sequence number of the next message.
+    sent: u64,
+}
+
+impl TcpTransport {
+    /// Listen on `peers[id]`; `peers` holds the address of every node and
+    /// `key` the secret they share.
+    pub fn bind(id: NodeId, peers: Vec<SocketAddr>, key: &[u8]) -> Result<Self, LogError> {
+        let listener = TcpListener::bind(peers[id]).map_err(storage)?;
+        Self::new(id, listener, peers, key)
+    }
+
+    /// Accept connections on `listener`, which must be bound to
+    /// `peers[id]`.
+    pub fn new(
+        id: NodeId,
+        listener: TcpListener,
+        peers: Vec<SocketAddr>,
+        key: &[u8],
+    ) -> Result<Self, LogError> {
+        assert!(
+            id < peers.len(),
+            "no node {id} in a cluster of {}",
+            peers.len()
+        );
+        if key.len() < MIN_KEY_LEN {
+            return Err(LogError::Storage(format!(
+                "cluster key of {} bytes is shorter than {MIN_KEY_LEN}",
+                key.len()
+            )));
+        }
+
+        let key: Arc<[u8]> = key.into();
+        let (sender, incoming) = mpsc::channel();
+        let accepted = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let listener = {
+            let local = Local {
+                id,
+                size: peers.len(),
+                key: Arc::clone(&key),
+            };
+            let accepted = Arc::clone(&accepted);
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(format!("raft-listener-{id}"))
+                .spawn(move || accept(listener, local, sender, accepted, stop))
+                .map_err(storage)?
+        };
+
+        Ok(Self {
+            id,
+            outgoing: peers.iter().map(|_| None).collect(),
+            retry_at: peers.iter().map(|_| None).collect(),
+            peers,
+            key,
+            incoming,
+            accepted,
+            stop,
+            listener: Some(listener),
+        })
+    }
+
+    /// The connection to `to`, opened if needed.
+    fn connection(&mut self, to: NodeId) -> Option<&mut Connection> {
+        if self.outgoing[to].is_none() {
+            if self.retry_at[to].is_some_and(|at| Instant::now() < at) {
+                return None;
+            }
+            match self.connect(to) {
+                Ok(stream) => {
+                    self.outgoing[to] = Some(stream);
+                    self.retry_at[to] = None;
+                }
+                Err(_) => {
+                    self.retry_at[to] = Some(Instant::now() + RECONNECT_BACKOFF);
+                    return None;
+                }
+            }
+        }
+        self.outgoing[to].as_mut()
+    }
+
+    fn connect(&self, to: NodeId) -> std::io::Result<Connection> {
+        let mut stream = TcpStream::connect_timeout(&self.peers[to], CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+        let mut nonce = [0; TAG_LEN];
+        stream.read_exact(&mut nonce)?;
+        let mut hello = (self.id as u64).to_le_bytes().to_vec();
+        hello.extend_from_slice(&hello_tag(&self.key, &nonce, self.id, to));
+        stream.write_all(&hello)?;
+
+        Ok(Connection {
+            stream,
+            session: session_key(&self.key, &nonce, self.id, to),
+            sent: 0,
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, to: NodeId, message: &[u8]) -> Result<(), LogError> {
+        if to == self.id || to >= self.peers.len() {
+            return Ok(());
+        }
+        if message.len() + TAG_LEN > MAX_RECORD_LEN as usize {
+            return Err(LogError::Storage(format!(
+                "message of {} bytes is too large to send",
+                message.len()
+            )));
+        }
+        let Some(connection) = self.connection(to) else {
+            return Ok(());
+        };
+
+        let mut body = message.to_vec();
+        body.extend_from_slice(&message_tag(&connection.session, connection.sent, message));
+        let record = frame(&body)?;
+        connection.sent += 1;
+
+        if connection.stream.write_all(&record).is_err() {
+            self.outgoing[to] = None;
+            self.retry_at[to] = Some(Instant::now() + RECONNECT_BACKOFF);
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Option<(NodeId, Vec<u8>)> {
+        self.incoming.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for connection in self.outgoing.iter().flatten() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+        if let Ok(accepted) = self.accepted.lock() {
+            for stream in accepted.iter() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+
+        // Wake the listener so it sees the stop flag.
+        let _ = TcpStream::connect_timeout(&self.peers[self.id], CONNECT_TIMEOUT);
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+/// What the accepting node knows of the cluster.
+#[derive(Clone)]
+struct Local {
+    id: NodeId,
+    size: usize,
+    key: Arc<[u8]>,
+}
+
+/// Accept connections until stopped, reading each on a thread of its own.
+fn accept(
+    listener: TcpListener,
+    local: Local,
+    sender: Sender<(NodeId, Vec<u8>)>,
+    accepted: Arc<Mutex<Vec<TcpStream>>>,
+    stop: Arc<AtomicBool>,
+) {
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let Ok(reader) = stream.try_clone() else {
+            continue;
+        };
+        let Ok(mut streams) = accepted.lock() else {
+            break;
+        };
+        streams.push(stream);
+        drop(streams);
+
+        let local = local.clone();
+        let sender = sender.clone();
+        let _ = thread::Builder::new()
+            .name("raft-connection".into())
+            .spawn(move || read_messages(reader, &local, sender));
+    }
+}
+
+/// Authenticate the sender of one connection and forward its messages
+/// until it fails or sends something that is not an authentic message.
+fn read_messages(mut stream: TcpStream, local: &Local, sender: Sender<(NodeId, Vec<u8>)>) {
+    let nonce = nonce();
+    if stream
+        .set_write_timeout(Some(WRITE_TIMEOUT))
+        .and_then(|_| stream.set_read_timeout(Some(HELLO_TIMEOUT)))
+        .and_then(|_| stream.write_all(&nonce))
+        .is_err()
+    {
+        return;
+    }
+
+    let mut reader = BufReader::new(stream);
+    let mut hello = [0; 8 + TAG_LEN];
+    if reader.read_exact(&mut hello).is_err() {
+        return;
+    }
+    let (id, tag) = hello.split_at(8);
+    let from = u64::from_le_bytes(id.try_into().unwrap_or_default());
+    if from >= local.size as u64 || from as NodeId == local.id {
+        return;
+    }
+    let from = from as NodeId;
+    if !verify(hello_mac(&local.key, &nonce, from, local.id), tag) {
+        return;
+    }
+
+    let session = session_key(&local.key, &nonce, from, local.id);
+    if reader.get_ref().set_read_timeout(None).is_err() {
+        return;
+    }
+    let mut received = 0;
+    while let Ok(Some(Frame::Body(mut message))) = read_frame(&mut reader) {
+        let Some(at) = message.len().checked_sub(TAG_LEN) else {
+            return;
+        };
+        let tag = message.split_off(at);
+        if !verify(message_mac(&session, received, &message), &tag) {
+            return;
+        }
+        received += 1;
+
+        if sender.send((from, message)).is_err() {
+            return;
+        }
+    }
+}
+
+/// A nonce no earlier connection used.
+fn nonce() -> [u8; TAG_LEN] {
+    let mut nonce = [0; TAG_LEN];
+    nonce[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    nonce[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    nonce
+}
+
+fn keyed(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+fn hello_mac(key: &[u8], nonce: &[u8], from: NodeId, to: NodeId) -> HmacSha256 {
+    let mut mac = keyed(key);
+    mac.update(b"axiom.raft.hello");
+    mac.update(nonce);
+    mac.update(&(from as u64).to_le_bytes());
+    mac.update(&(to as u64).to_le_bytes());
+    mac
+}
+
+fn hello_tag(key: &[u8], nonce: &[u8], from: NodeId, to: NodeId) -> [u8; TAG_LEN] {
+    hello_mac(key, nonce, from, to)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+fn session_key(key: &[u8], nonce: &[u8], from: NodeId, to: NodeId) -> [u8; TAG_LEN] {
+    let mut mac = keyed(key);
+    mac.update(b"axiom.raft.session");
+    mac.update(nonce);
+    mac.update(&(from as u64).to_le_bytes());
+    mac.update(&(to as u64).to_le_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+fn message_mac(session: &[u8], sequence: u64, message: &[u8]) -> HmacSha256 {
+    let mut mac = keyed(session);
+    mac.update(&sequence.to_le_bytes());
+    mac.update(message);
+    mac
+}
+
+fn message_tag(session: &[u8], sequence: u64, message: &[u8]) -> [u8; TAG_LEN] {
+    message_mac(session, sequence, message)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Compare in constant time.
+fn verify(mac: HmacSha256, tag: &[u8]) -> bool {
+    mac.verify_slice(tag).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"an example cluster key, 32+ bytes";
+
+    fn cluster(keys: &[&[u8]]) -> (Vec<SocketAddr>, Vec<TcpTransport>) {
+        let listeners: Vec<_> = keys
+            .iter()
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let peers: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+        let transports = listeners
+            .into_iter()
+            .zip(keys)
+            .enumerate()
+            .map(|(id, (listener, key))| {
+                TcpTransport::new(id, listener, peers.clone(), key).unwrap()
+            })
+            .collect();
+        (peers, transports)
+    }
+
+    #[test]
+    fn messages_reach_peers_and_survive_reconnects() {
+        let (peers, mut transports) = cluster(&[KEY, KEY]);
+
+        let timeout = Duration::from_secs(5);
+        transports[0].send(1, b"hello").unwrap();
+        assert_eq!(transports[1].receive(timeout), Some((0, b"hello".to_vec())));
+        transports[1].send(0, b"hi").unwrap();
+        assert_eq!(transports[0].receive(timeout), Some((1, b"hi".to_vec())));
+
+        // Node 1 restarts on the same address.
+        drop(transports.pop());
+        transports[0].send(1, b"lost").unwrap();
+        let mut restarted = TcpTransport::bind(1, peers.clone(), KEY).unwrap();
+
+        let deadline = Instant::now() + timeout;
+        let received = loop {
+            transports[0].send(1, b"again").unwrap();
+            match restarted.receive(Duration::from_millis(50)) {
+                Some(message) => break Some(message),
+                None if Instant::now() < deadline => continue,
+                None => break None,
+            }
+        };
+        assert_eq!(received, Some((0, b"again".to_vec())));
+    }
+
+    #[test]
+    fn short_keys_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peers = vec![listener.local_addr().unwrap()];
+        assert!(TcpTransport::new(0, listener, peers, b"too short").is_err());
+    }
+
+    #[test]
+    fn peers_without_the_key_are_not_heard() {
+        let (_, mut transports) = cluster(&[KEY, b"a different key, also 32+ bytes long"]);
+
+        for _ in 0..5 {
+            transports[1].send(0, b"forged").unwrap();
+            assert_eq!(transports[0].receive(Duration::from_millis(50)), None);
+        }
+    }
+
+    #[test]
+    fn tampered_or_replayed_messages_close_the_connection() {
+        let (peers, mut transports) = cluster(&[KEY, KEY]);
+        let timeout = Duration::from_secs(5);
+
+        // Node 1, by hand.
+        let mut stream = TcpStream::connect(peers[0]).unwrap();
+        let mut nonce = [0; TAG_LEN];
+        stream.read_exact(&mut nonce).unwrap();
+        let mut hello = 1u64.to_le_bytes().to_vec();
+        hello.extend_from_slice(&hello_tag(KEY, &nonce, 1, 0));
+        stream.write_all(&hello).unwrap();
+
+        let session = session_key(KEY, &nonce, 1, 0);
+        let message = |sequence, body: &[u8]| {
+            let mut message = body.to_vec();
+            message.extend_from_slice(&message_tag(&session, sequence, body));
+            frame(&message).unwrap()
+        };
+
+        stream.write_all(&message(0, b"first")).unwrap();
+        assert_eq!(transports[0].receive(timeout), Some((1, b"first".to_vec())));
+
+        // The first message again, under its own sequence number.
+        stream.write_all(&message(0, b"first")).unwrap();
+        let _ = stream.write_all(&message(1, b"second"));
+        assert_eq!(transports[0].receive(Duration::from_millis(200)), None);
+
+        // A new connection, with a tampered message.
+        let mut stream = TcpStream::connect(peers[0]).unwrap();
+        stream.read_exact(&mut nonce).unwrap();
+        let mut hello = 1u64.to_le_bytes().to_vec();
+        hello.extend_from_slice(&hello_tag(KEY, &nonce, 1, 0));
+        stream.write_all(&hello).unwrap();
+        let session = session_key(KEY, &nonce, 1, 0);
+        let mut tampered = b"third".to_vec();
+        tampered.extend_from_slice(&message_tag(&session, 0, b"Third"));
+        stream.write_all(&frame(&tampered).unwrap()).unwrap();
+        assert_eq!(transports[0].receive(Duration::from_millis(200)), None);
+    }
+}
@@ -0,0 +1,307 @@
+// On-Disk Record Format
+//
+// Shared framing for file-based stores. Each record is length-prefixed
+// and checksummed so that torn writes can be detected on recovery.
+//
+// Record layout (little-endian):
+//
+//   +-----------+-----------+------------------+
+//   | len (u32) | crc (u32) | body (len bytes) |
+//   +-----------+-----------+------------------+
+//
+// `body` is a `TableEvent` in either encoding (see `codec.rs`) and `crc`
+// is the CRC-32 of `body`. Records of both encodings may share a file.
+// The same framing carries other bodies too, such as Raft log entries
+// and messages (see `raft/`).
+
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+
+use super::codec::{self, EventEncoding, BINARY_MAGIC};
+use super::{LogError, TableEvent, Version};
+
+/// Size of the fixed record header (length + checksum).
+pub(crate) const HEADER_LEN: u64 = 8;
+
+/// Largest record body a store writes or reads. A larger length can only
+/// come from a corrupted header.
+pub(crate) const MAX_RECORD_LEN: u32 = 16 * 1024 * 1024;
+
+/// Most body bytes `scan` checksums while looking for a valid record
+/// after an unreadable one.
+const SEARCH_BUDGET: u64 = 4 * (HEADER_LEN + MAX_RECORD_LEN as u64);
+
+pub(crate) enum Record {
+    Event(Box<TableEvent>),
+    /// Incomplete or checksum-failing record (possible torn write).
+    Torn,
+}
+
+/// A record body, before it is decoded.
+pub(crate) enum Frame {
+    Body(Vec<u8>),
+    /// Incomplete or checksum-failing record (possible torn write).
+    Torn,
+}
+
+pub(crate) fn encode_record(
+    event: &TableEvent,
+    encoding: EventEncoding,
+) -> Result<Vec<u8>, LogError> {
+    frame(&encoding.encode(event)?)
+}
+
+/// Prefix `body` with its length and checksum.
+pub(crate) fn frame(body: &[u8]) -> Result<Vec<u8>, LogError> {
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|len| *len <= MAX_RECORD_LEN)
+        .ok_or_else(|| LogError::Storage("record too large".into()))?;
+
+    let mut record = Vec::with_capacity(HEADER_LEN as usize + body.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+    record.extend_from_slice(body);
+    Ok(record)
+}
+
+/// Read the next record, returning `None` at a clean end of file.
+pub(crate) fn read_record<R: Read>(reader: &mut R) -> Result<Option<Record>, LogError> {
+    let body = match read_frame(reader)? {
+        Some(Frame::Body(body)) => body,
+        Some(Frame::Torn) => return Ok(Some(Record::Torn)),
+        None => return Ok(None),
+    };
+
+    let event =
+        codec::decode(&body).map_err(|e| LogError::Corrupt(format!("undecodable record: {e}")))?;
+    Ok(Some(Record::Event(Box::new(event))))
+}
+
+/// Read the next record body without decoding it, returning `None` at a
+/// clean end of file.
+pub(crate) fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Frame>, LogError> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    if !read_exact_or_eof(reader, &mut header)? {
+        return Ok(None);
+    }
+
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if len > MAX_RECORD_LEN {
+        return Err(LogError::Corrupt(format!(
+            "record length {len} exceeds the maximum of {MAX_RECORD_LEN}"
+        )));
+    }
+
+    let mut body = vec![0u8; len as usize];
+    if !read_exact_or_eof(reader, &mut body)? || crc32fast::hash(&body) != crc {
+        return Ok(Some(Frame::Torn));
+    }
+    Ok(Some(Frame::Body(body)))
+}
+
+/// Iterator over the records of a reader.
+///
+/// Stops at the first error. A torn record is reported as corruption:
+/// torn tails are truncated on open, so readers never expect one.
+pub(crate) struct Records<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: Read> Records<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = Result<TableEvent, LogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let item = match read_record(&mut self.reader) {
+            Ok(Some(Record::Event(event))) => Some(Ok(*event)),
+            Ok(Some(Record::Torn)) => Some(Err(LogError::Corrupt("torn record".into()))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        };
+
+        self.done = !matches!(item, Some(Ok(_)));
+        item
+    }
+}
+
+/// Scan records from the start of `file`, returning the length of the
+/// prefix made of complete, valid records.
+///
+/// `on_record` is called with the offset of every valid record and may
+/// reject it (e.g. on a version gap). Anything after the last complete
+/// record is a torn tail, provided it is no more than the start of a
+/// single record. A checksum failure followed by further data, or a bad
+/// record followed by a valid one, is not a torn write and is reported
+/// as `LogError::Corrupt`.
+///
+/// Telling a torn tail from a corrupted length means searching up to two
+/// maximum records past the last valid one for another record, which
+/// costs a checksum per candidate offset. Only files that do not end on a
+/// record boundary pay it, and candidates whose body cannot start an
+/// event are skipped; a tail that still needs more than `SEARCH_BUDGET`
+/// bytes checksummed is reported as corrupt rather than truncated.
+pub(crate) fn scan<F: Read + Seek>(
+    file: F,
+    file_len: u64,
+    mut on_record: impl FnMut(u64, &TableEvent) -> Result<(), LogError>,
+) -> Result<u64, LogError> {
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0)).map_err(storage)?;
+
+    let mut valid_len = 0;
+
+    while let Some(record) = read_record(&mut reader)? {
+        let event = match record {
+            Record::Event(event) => event,
+            Record::Torn => break,
+        };
+
+        on_record(valid_len, &event)?;
+        valid_len = reader.stream_position().map_err(storage)?;
+    }
+
+    if valid_len < file_len {
+        reader.seek(SeekFrom::Start(valid_len)).map_err(storage)?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        if read_exact_or_eof(&mut reader, &mut header)? {
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as u64;
+            if valid_len + HEADER_LEN + len < file_len {
+                return Err(LogError::Corrupt(format!(
+                    "checksum mismatch at offset {valid_len}"
+                )));
+            }
+        }
+
+        // A corrupted length can make a record in the middle of the file
+        // look like it runs past the end. The record following it starts
+        // within one maximum record of it.
+        let window = (file_len - valid_len).min(2 * (HEADER_LEN + MAX_RECORD_LEN as u64));
+        let mut tail = vec![0u8; window as usize];
+        reader.seek(SeekFrom::Start(valid_len)).map_err(storage)?;
+        reader.read_exact(&mut tail).map_err(storage)?;
+        if let Some(at) = find_record(&tail[1..], valid_len + 1, SEARCH_BUDGET)? {
+            return Err(LogError::Corrupt(format!(
+                "unreadable record at offset {valid_len} is followed by a valid record at offset {}",
+                valid_len + 1 + at as u64
+            )));
+        }
+    }
+
+    Ok(valid_len)
+}
+
+/// Offset of the first complete, valid record in `bytes`, which start at
+/// `offset` in the file, checksumming at most `budget` bytes.
+fn find_record(bytes: &[u8], offset: u64, budget: u64) -> Result<Option<usize>, LogError> {
+    let header_len = HEADER_LEN as usize;
+    let mut hashed = 0;
+
+    for at in 0..bytes.len().saturating_sub(header_len) {
+        let header = &bytes[at..at + header_len];
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        let body_start = at + header_len;
+        if len > MAX_RECORD_LEN || body_start + len as usize > bytes.len() {
+            continue;
+        }
+        let body = &bytes[body_start..body_start + len as usize];
+        // JSON events are objects; binary ones start with their magic.
+        if !matches!(body.first(), Some(b'{' | &BINARY_MAGIC)) {
+            continue;
+        }
+
+        hashed += len as u64;
+        if hashed > budget {
+            return Err(LogError::Corrupt(format!(
+                "unreadable data at offset {offset} may hide a valid record"
+            )));
+        }
+        if crc32fast::hash(body) == crc && codec::decode(body).is_ok() {
+            return Ok(Some(at));
+        }
+    }
+    Ok(None)
+}
+
+/// Check that `event` directly follows `last_version`.
+pub(crate) fn check_continuity(
+    offset: u64,
+    last_version: Version,
+    event: &TableEvent,
+) -> Result<(), LogError> {
+    if event.version == last_version + 1 {
+        return Ok(());
+    }
+
+    Err(LogError::Corrupt(format!(
+        "version gap for table {} at offset {offset}: expected {}, found {}",
+        event.table_id,
+        last_version + 1,
+        event.version
+    )))
+}
+
+/// Fill `buf` completely. Returns `false` if the stream ends first.
+///
+/// A partially filled buffer is treated the same as a clean EOF; the
+/// caller decides whether that constitutes a torn record.
+pub(crate) fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, LogError> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(storage(e)),
+    }
+}
+
+pub(crate) fn storage<E: std::fmt::Display>(e: E) -> LogError {
+    LogError::Storage(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{EventType, TableId};
+
+    #[test]
+    fn search_finds_records_and_gives_up_past_its_budget() {
+        let event = TableEvent::new(
+            TableId(uuid::Uuid::nil()),
+            1,
+            EventType::SnapshotAdded,
+            vec![],
+        );
+        let record = encode_record(&event, EventEncoding::Json).unwrap();
+        let mut bytes = vec![0xff; 5];
+        bytes.extend_from_slice(&record);
+        assert_eq!(find_record(&bytes, 0, SEARCH_BUDGET).unwrap(), Some(5));
+
+        // Plausible headers over bodies that fail their checksum.
+        let mut decoys = Vec::new();
+        for _ in 0..4 {
+            decoys.extend_from_slice(&64u32.to_le_bytes());
+            decoys.extend_from_slice(&0u32.to_le_bytes());
+            decoys.extend_from_slice(&[b'{'; 64]);
+        }
+        decoys.extend_from_slice(&record);
+        assert!(find_record(&decoys, 0, SEARCH_BUDGET).unwrap().is_some());
+        assert!(matches!(
+            find_record(&decoys, 0, 128),
+            Err(LogError::Corrupt(_))
+        ));
+    }
+}
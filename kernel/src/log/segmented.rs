@@ -0,0 +1,1022 @@
+// Segmented Write-Ahead Log Store
+//
+// Splits the metadata log into fixed-size segment files so that no
+// single file grows without bound. Each segment has a sparse index
+// mapping versions to byte offsets, allowing reads to seek directly to
+// a version instead of scanning from version 1.
+//
+// Directory layout (one subdirectory per table):
+//
+//   <dir>/<table>/00000000000000000001.log     records for versions 1..=N
+//   <dir>/<table>/00000000000000000001.index   sparse version -> offset index
+//   <dir>/<table>/<N+1, zero padded>.log       next segment
+//   ...
+//
+// Segment files use the record format in `record.rs`. Index entries are
+// fixed-size and checksummed:
+//
+//   +---------------+--------------+-----------+
+//   | version (u64) | offset (u64) | crc (u32) |
+//   +---------------+--------------+-----------+
+//
+// Only segment data is fsynced. Indexes are derived data: a missing,
+// torn or inconsistent index is rebuilt from its segment on open.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Take, Write};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use super::codec::EventEncoding;
+use super::dedup::{DedupIndex, DEFAULT_DEDUP_WINDOW};
+use super::record::{check_continuity, encode_record, scan, storage, Records};
+use super::{
+    AppendOutcome, EventStream, LogError, MetadataLogStore, TableEvent, TableId, Version,
+    VersionRange,
+};
+
+const INDEX_ENTRY_LEN: usize = 20;
+
+/// Tuning knobs for segment layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentConfig {
+    /// A new segment is started once the active one would exceed this size.
+    pub max_segment_bytes: u64,
+
+    /// Minimum number of bytes between two index entries.
+    pub index_interval_bytes: u64,
+
+    /// Number of most recent versions per table whose idempotency keys
+    /// are remembered (0 disables deduplication).
+    pub dedup_window: u64,
+
+    /// Encoding of appended events. Existing segments are read in
+    /// whatever encoding they were written in.
+    pub encoding: EventEncoding,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self {
+            max_segment_bytes: 64 * 1024 * 1024,
+            index_interval_bytes: 4 * 1024,
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+            encoding: EventEncoding::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IndexEntry {
+    version: Version,
+    offset: u64,
+}
+
+impl IndexEntry {
+    fn encode(&self) -> [u8; INDEX_ENTRY_LEN] {
+        let mut buf = [0u8; INDEX_ENTRY_LEN];
+        buf[..8].copy_from_slice(&self.version.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        let crc = crc32fast::hash(&buf[..16]);
+        buf[16..].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let crc = u32::from_le_bytes(buf[16..20].try_into().unwrap());
+        if crc32fast::hash(&buf[..16]) != crc {
+            return None;
+        }
+        Some(Self {
+            version: u64::from_le_bytes(buf[..8].try_into().unwrap()),
+            offset: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+        })
+    }
+}
+
+/// In-memory view of one segment.
+#[derive(Debug)]
+struct Segment {
+    base_version: Version,
+    last_version: Version,
+    len: u64,
+    index: Vec<IndexEntry>,
+}
+
+impl Segment {
+    fn is_empty(&self) -> bool {
+        self.last_version < self.base_version
+    }
+
+    /// Offset of the closest indexed record at or before `version`.
+    fn seek_offset(&self, version: Version) -> u64 {
+        match self.index.partition_point(|e| e.version <= version) {
+            0 => 0,
+            n => self.index[n - 1].offset,
+        }
+    }
+}
+
+/// Open handles for the segment currently being written.
+#[derive(Debug)]
+struct ActiveSegment {
+    log: File,
+    index: File,
+    bytes_since_index: u64,
+}
+
+/// Segmented, file-backed store.
+///
+/// Each table gets its own subdirectory (named by its id) holding that
+/// table's segments, so version indexes stay dense per table.
+#[derive(Debug)]
+pub struct SegmentedLogStore {
+    dir: PathBuf,
+    config: SegmentConfig,
+    tables: BTreeMap<TableId, TableSegments>,
+    dedup: DedupIndex,
+}
+
+impl SegmentedLogStore {
+    /// Open (or create) a segmented log in `dir` with default settings.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, LogError> {
+        Self::open_with_config(dir, SegmentConfig::default())
+    }
+
+    /// Open (or create) a segmented log in `dir`.
+    ///
+    /// Recovery truncates a torn tail in each table's active segment and
+    /// rebuilds any index that does not match its segment. Idempotency
+    /// keys are recovered from the tail of each table within the dedup
+    /// window.
+    pub fn open_with_config(
+        dir: impl AsRef<Path>,
+        config: SegmentConfig,
+    ) -> Result<Self, LogError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage)?;
+
+        let mut tables = BTreeMap::new();
+
+        for entry in fs::read_dir(&dir).map_err(storage)? {
+            let path = entry.map_err(storage)?.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            let table_id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| Uuid::parse_str(name).ok())
+                .map(TableId)
+                .ok_or_else(|| {
+                    LogError::Corrupt(format!("unexpected directory {}", path.display()))
+                })?;
+
+            let segments = TableSegments::open(path, config)?;
+            if segments.last_version() > 0 {
+                tables.insert(table_id, segments);
+            }
+        }
+
+        let mut dedup = DedupIndex::new(config.dedup_window);
+        if config.dedup_window > 0 {
+            for table in tables.values() {
+                let from = dedup.window_start(table.last_version());
+                for event in table.stream(VersionRange::starting_at(from)) {
+                    dedup.record(&event?);
+                }
+            }
+        }
+
+        Ok(Self {
+            dir,
+            config,
+            tables,
+            dedup,
+        })
+    }
+
+    /// Directory holding the per-table segment directories.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of segment files currently on disk for a table.
+    pub fn segment_count(&self, table_id: &TableId) -> usize {
+        self.tables
+            .get(table_id)
+            .map_or(0, |table| table.segments.len())
+    }
+}
+
+impl MetadataLogStore for SegmentedLogStore {
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError> {
+        if let Some(version) = self.dedup.lookup(event) {
+            return Ok(AppendOutcome::Duplicate(version));
+        }
+
+        if !self.tables.contains_key(&event.table_id) {
+            if event.version != 1 {
+                return Err(LogError::VersionConflict {
+                    expected: 1,
+                    actual: event.version,
+                });
+            }
+
+            let table_dir = self.dir.join(event.table_id.to_string());
+            let segments = TableSegments::open(table_dir, self.config)?;
+            sync_dir(&self.dir)?;
+            self.tables.insert(event.table_id.clone(), segments);
+        }
+
+        self.tables
+            .get_mut(&event.table_id)
+            .unwrap()
+            .append(event)?;
+        self.dedup.record(event);
+        Ok(AppendOutcome::Appended(event.version))
+    }
+
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError> {
+        Ok(self.dedup.find(table_id, key))
+    }
+
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError> {
+        match self.tables.get(table_id) {
+            Some(table) => Ok(table.stream(range)),
+            None => Ok(Box::new(std::iter::empty())),
+        }
+    }
+
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        Ok(self
+            .tables
+            .get(table_id)
+            .map_or(0, |table| table.last_version()))
+    }
+
+    fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        Ok(self.tables.keys().cloned().collect())
+    }
+
+    fn first_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        Ok(self
+            .tables
+            .get(table_id)
+            .map_or(1, |table| table.segments[0].base_version))
+    }
+
+    fn supports_truncation(&self) -> bool {
+        true
+    }
+
+    /// Deletes whole segments only: the segment holding `version` and
+    /// the active segment are kept.
+    fn truncate_before(&mut self, table_id: &TableId, version: Version) -> Result<(), LogError> {
+        match self.tables.get_mut(table_id) {
+            Some(table) => table.truncate_before(version),
+            None => Ok(()),
+        }
+    }
+
+    fn seed(&mut self, anchor: &TableEvent) -> Result<(), LogError> {
+        let head = self.current_version(&anchor.table_id)?;
+        if head > 0 {
+            return Err(LogError::VersionConflict {
+                expected: head + 1,
+                actual: anchor.version,
+            });
+        }
+
+        let table_dir = self.dir.join(anchor.table_id.to_string());
+        let mut segments = TableSegments::open(table_dir, self.config)?;
+        sync_dir(&self.dir)?;
+        segments.seed(anchor)?;
+
+        self.tables.insert(anchor.table_id.clone(), segments);
+        self.dedup.record(anchor);
+        Ok(())
+    }
+}
+
+/// Segments of a single table.
+#[derive(Debug)]
+struct TableSegments {
+    dir: PathBuf,
+    config: SegmentConfig,
+    segments: Vec<Segment>,
+    active: ActiveSegment,
+}
+
+impl TableSegments {
+    fn open(dir: PathBuf, config: SegmentConfig) -> Result<Self, LogError> {
+        fs::create_dir_all(&dir).map_err(storage)?;
+
+        let mut bases = list_segments(&dir)?;
+        if bases.is_empty() {
+            bases.push(1);
+        }
+
+        let mut segments = Vec::with_capacity(bases.len());
+        for (i, &base) in bases.iter().enumerate() {
+            let next_base = bases.get(i + 1).copied();
+            let segment = recover_segment(&dir, base, next_base, &config)?;
+
+            if let Some(prev) = segments.last().map(|s: &Segment| s.last_version) {
+                if prev + 1 != base {
+                    return Err(LogError::Corrupt(format!(
+                        "segment {base} does not follow version {prev}"
+                    )));
+                }
+            }
+
+            segments.push(segment);
+        }
+
+        let active = open_active(&dir, segments.last().unwrap())?;
+        sync_dir(&dir)?;
+
+        Ok(Self {
+            dir,
+            config,
+            segments,
+            active,
+        })
+    }
+
+    fn last_version(&self) -> Version {
+        self.segments.last().unwrap().last_version
+    }
+
+    /// Stream events within `range`, using the segment indexes to skip
+    /// directly to the first requested version.
+    fn stream(&self, range: VersionRange) -> EventStream<'_> {
+        let head = self.last_version();
+        let last = range.to_version.map_or(head, |to| to.min(head));
+        let from = range.from_version.max(1);
+
+        if from > last {
+            return Box::new(std::iter::empty());
+        }
+
+        let first = self
+            .segments
+            .partition_point(|s| s.base_version <= from)
+            .saturating_sub(1);
+
+        let events = self.segments[first..]
+            .iter()
+            .take_while(move |segment| segment.base_version <= last)
+            .filter(|segment| !segment.is_empty())
+            .flat_map(move |segment| -> EventStream<'_> {
+                match self.open_segment(segment, from) {
+                    Ok(records) => Box::new(records),
+                    Err(e) => Box::new(std::iter::once(Err(e))),
+                }
+            })
+            .filter(move |item| item.as_ref().map_or(true, |event| event.version >= from))
+            .scan(false, move |done, item| {
+                if *done {
+                    return None;
+                }
+                *done = item.as_ref().map_or(true, |event| event.version >= last);
+                Some(item)
+            });
+
+        Box::new(events)
+    }
+
+    /// Open a segment positioned at the closest indexed record before
+    /// `from_version`.
+    fn open_segment(
+        &self,
+        segment: &Segment,
+        from_version: Version,
+    ) -> Result<Records<Take<BufReader<File>>>, LogError> {
+        let start = segment.seek_offset(from_version);
+        let mut file =
+            File::open(segment_path(&self.dir, segment.base_version, "log")).map_err(storage)?;
+        file.seek(SeekFrom::Start(start)).map_err(storage)?;
+
+        Ok(Records::new(BufReader::new(file).take(segment.len - start)))
+    }
+
+    fn append(&mut self, event: &TableEvent) -> Result<(), LogError> {
+        let expected = self.last_version() + 1;
+
+        if event.version != expected {
+            return Err(LogError::VersionConflict {
+                expected,
+                actual: event.version,
+            });
+        }
+
+        let record = encode_record(event, self.config.encoding)?;
+        let record_len = record.len() as u64;
+
+        let current = self.segments.last().unwrap();
+        if !current.is_empty() && current.len + record_len > self.config.max_segment_bytes {
+            self.roll_segment(event.version)?;
+        }
+
+        let segment = self.segments.last_mut().unwrap();
+        let active = &mut self.active;
+
+        let written = active
+            .log
+            .write_all(&record)
+            .and_then(|_| active.log.sync_data());
+
+        if let Err(e) = written {
+            // Never leave a partial record behind a live writer.
+            let _ = active.log.set_len(segment.len);
+            return Err(storage(e));
+        }
+
+        let offset = segment.len;
+        segment.len += record_len;
+        segment.last_version = event.version;
+
+        if segment.index.is_empty() || active.bytes_since_index >= self.config.index_interval_bytes
+        {
+            let entry = IndexEntry {
+                version: event.version,
+                offset,
+            };
+            // The record is already durable; a failed index write only
+            // costs a rebuild on the next open.
+            if active.index.write_all(&entry.encode()).is_ok() {
+                segment.index.push(entry);
+                active.bytes_since_index = 0;
+            }
+        }
+        active.bytes_since_index += record_len;
+
+        Ok(())
+    }
+
+    /// Delete the sealed segments that end before `version`, oldest
+    /// first, so a crash midway leaves a contiguous suffix of the log.
+    fn truncate_before(&mut self, version: Version) -> Result<(), LogError> {
+        while self.segments.len() > 1 && self.segments[0].last_version < version {
+            let base = self.segments[0].base_version;
+            fs::remove_file(segment_path(&self.dir, base, "log")).map_err(storage)?;
+            self.segments.remove(0);
+
+            // Segments are listed by their log files, so a leftover index
+            // is harmless.
+            let _ = fs::remove_file(segment_path(&self.dir, base, "index"));
+        }
+        sync_dir(&self.dir)
+    }
+
+    /// Start an empty table at `anchor`'s version, replacing its empty
+    /// segments by one based there.
+    fn seed(&mut self, anchor: &TableEvent) -> Result<(), LogError> {
+        for segment in self.segments.drain(..) {
+            fs::remove_file(segment_path(&self.dir, segment.base_version, "log"))
+                .map_err(storage)?;
+            let _ = fs::remove_file(segment_path(&self.dir, segment.base_version, "index"));
+        }
+        self.roll_segment(anchor.version)?;
+        self.append(anchor)
+    }
+
+    fn roll_segment(&mut self, base_version: Version) -> Result<(), LogError> {
+        let segment = Segment {
+            base_version,
+            last_version: base_version - 1,
+            len: 0,
+            index: Vec::new(),
+        };
+
+        self.active = open_active(&self.dir, &segment)?;
+        sync_dir(&self.dir)?;
+        self.segments.push(segment);
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, base_version: Version, ext: &str) -> PathBuf {
+    dir.join(format!("{base_version:020}.{ext}"))
+}
+
+/// Base versions of all segments in `dir`, sorted ascending.
+fn list_segments(dir: &Path) -> Result<Vec<Version>, LogError> {
+    let mut bases = Vec::new();
+
+    for entry in fs::read_dir(dir).map_err(storage)? {
+        let path = entry.map_err(storage)?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("log") {
+            continue;
+        }
+        let base = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<Version>().ok())
+            .filter(|&b| b > 0)
+            .ok_or_else(|| LogError::Corrupt(format!("unexpected file {}", path.display())))?;
+        bases.push(base);
+    }
+
+    bases.sort_unstable();
+    Ok(bases)
+}
+
+/// Recover one segment from disk.
+///
+/// Sealed segments (those followed by another segment) are not rescanned
+/// when their index is consistent: their last version is implied by the
+/// next segment's base. The active segment is always scanned so a torn
+/// tail can be truncated.
+fn recover_segment(
+    dir: &Path,
+    base_version: Version,
+    next_base: Option<Version>,
+    config: &SegmentConfig,
+) -> Result<Segment, LogError> {
+    let log_path = segment_path(dir, base_version, "log");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&log_path)
+        .map_err(storage)?;
+    let file_len = file.metadata().map_err(storage)?.len();
+
+    let (len, last_version) = match next_base {
+        Some(next) => (file_len, next - 1),
+        None => {
+            let mut last_version = base_version - 1;
+            let valid_len = scan(&file, file_len, |offset, event| {
+                check_continuity(offset, last_version, event)?;
+                last_version = event.version;
+                Ok(())
+            })?;
+            if valid_len < file_len {
+                file.set_len(valid_len).map_err(storage)?;
+                file.sync_all().map_err(storage)?;
+            }
+            (valid_len, last_version)
+        }
+    };
+
+    let mut segment = Segment {
+        base_version,
+        last_version,
+        len,
+        index: Vec::new(),
+    };
+
+    let index_path = segment_path(dir, base_version, "index");
+    match read_index(&index_path, &segment)? {
+        Some(index) => segment.index = index,
+        None => rebuild_index(dir, &mut segment, &file, config)?,
+    }
+
+    Ok(segment)
+}
+
+/// Read and validate an index file. Returns `None` if it must be rebuilt.
+fn read_index(path: &Path, segment: &Segment) -> Result<Option<Vec<IndexEntry>>, LogError> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(storage(e)),
+    };
+
+    if bytes.len() % INDEX_ENTRY_LEN != 0 {
+        return Ok(None);
+    }
+
+    let mut entries = Vec::with_capacity(bytes.len() / INDEX_ENTRY_LEN);
+    for chunk in bytes.chunks_exact(INDEX_ENTRY_LEN) {
+        let Some(entry) = IndexEntry::decode(chunk) else {
+            return Ok(None);
+        };
+
+        let in_bounds = entry.offset < segment.len
+            && entry.version >= segment.base_version
+            && entry.version <= segment.last_version;
+        let ordered = entries.last().is_none_or(|prev: &IndexEntry| {
+            entry.version > prev.version && entry.offset > prev.offset
+        });
+
+        if !in_bounds || !ordered {
+            return Ok(None);
+        }
+        entries.push(entry);
+    }
+
+    // The first record must always be indexed.
+    let anchored = match entries.first() {
+        Some(first) => first.version == segment.base_version && first.offset == 0,
+        None => segment.is_empty(),
+    };
+
+    Ok(anchored.then_some(entries))
+}
+
+/// Rebuild a segment's index by scanning it, and atomically replace the
+/// index file.
+fn rebuild_index(
+    dir: &Path,
+    segment: &mut Segment,
+    file: &File,
+    config: &SegmentConfig,
+) -> Result<(), LogError> {
+    let interval = config.index_interval_bytes;
+    let mut index = Vec::new();
+    let mut last_indexed: Option<u64> = None;
+
+    let mut last_version = segment.base_version - 1;
+
+    let valid_len = scan(file, segment.len, |offset, event| {
+        check_continuity(offset, last_version, event)?;
+        last_version = event.version;
+
+        if last_indexed.is_none_or(|prev| offset - prev >= interval) {
+            index.push(IndexEntry {
+                version: event.version,
+                offset,
+            });
+            last_indexed = Some(offset);
+        }
+        Ok(())
+    })?;
+
+    if valid_len != segment.len || last_version != segment.last_version {
+        return Err(LogError::Corrupt(format!(
+            "segment {} is incomplete",
+            segment.base_version
+        )));
+    }
+
+    let tmp_path = segment_path(dir, segment.base_version, "index.tmp");
+    let mut tmp = File::create(&tmp_path).map_err(storage)?;
+    for entry in &index {
+        tmp.write_all(&entry.encode()).map_err(storage)?;
+    }
+    tmp.sync_all().map_err(storage)?;
+    fs::rename(&tmp_path, segment_path(dir, segment.base_version, "index")).map_err(storage)?;
+
+    segment.index = index;
+    Ok(())
+}
+
+fn open_active(dir: &Path, segment: &Segment) -> Result<ActiveSegment, LogError> {
+    let open = |ext| {
+        OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(segment_path(dir, segment.base_version, ext))
+            .map_err(storage)
+    };
+
+    let log = open("log")?;
+    let index = open("index")?;
+
+    // Bytes written since the last index entry, so the interval carries
+    // across restarts.
+    let bytes_since_index = segment
+        .index
+        .last()
+        .map_or(0, |entry| segment.len - entry.offset);
+
+    Ok(ActiveSegment {
+        log,
+        index,
+        bytes_since_index,
+    })
+}
+
+fn sync_dir(dir: &Path) -> Result<(), LogError> {
+    File::open(dir).and_then(|d| d.sync_all()).map_err(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::conformance::{StoreFactory, TempLocations};
+    use crate::log::{EventEnvelope, EventType, TableId};
+    use uuid::Uuid;
+
+    /// Version of the record stored at `offset` in a segment file.
+    fn version_at(path: &Path, offset: u64) -> Option<Version> {
+        let mut file = File::open(path).ok()?;
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        Records::new(file).next()?.ok().map(|e| e.version)
+    }
+
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    fn event(version: u64) -> TableEvent {
+        TableEvent::new(table(), version, EventType::SnapshotAdded, vec![7; 64])
+    }
+
+    fn small_segments() -> SegmentConfig {
+        SegmentConfig {
+            max_segment_bytes: 1024,
+            index_interval_bytes: 256,
+            ..SegmentConfig::default()
+        }
+    }
+
+    fn versions(events: &[TableEvent]) -> Vec<Version> {
+        events.iter().map(|e| e.version).collect()
+    }
+
+    struct Factory(TempLocations, EventEncoding);
+
+    impl StoreFactory for Factory {
+        type Store = SegmentedLogStore;
+        type Location = PathBuf;
+
+        fn create_location(&mut self) -> PathBuf {
+            self.0.next()
+        }
+
+        fn open(&mut self, dir: &PathBuf) -> SegmentedLogStore {
+            let config = SegmentConfig {
+                encoding: self.1,
+                ..small_segments()
+            };
+            SegmentedLogStore::open_with_config(dir, config).unwrap()
+        }
+    }
+
+    mod conformance {
+        use super::*;
+        crate::store_conformance_tests!(Factory(TempLocations::new(), EventEncoding::Json));
+    }
+
+    mod binary_conformance {
+        use super::*;
+        crate::store_conformance_tests!(Factory(TempLocations::new(), EventEncoding::Binary));
+    }
+
+    fn load_from(store: &SegmentedLogStore, table_id: &TableId, from: Version) -> Vec<TableEvent> {
+        store
+            .stream(table_id, VersionRange::starting_at(from))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn fill(store: &mut SegmentedLogStore, range: std::ops::RangeInclusive<u64>) {
+        for v in range {
+            store.append(&event(v)).unwrap();
+        }
+    }
+
+    #[test]
+    fn segments_roll_over_and_reload_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+
+        fill(&mut store, 1..=50);
+        assert!(store.segment_count(&table()) > 1);
+
+        drop(store);
+        let store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+
+        assert_eq!(store.current_version(&table()).unwrap(), 50);
+        assert_eq!(
+            versions(&store.load(&table()).unwrap()),
+            (1..=50).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn load_from_seeks_into_middle_of_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        fill(&mut store, 1..=50);
+
+        assert_eq!(
+            versions(&load_from(&store, &table(), 37)),
+            (37..=50).collect::<Vec<_>>()
+        );
+        assert!(load_from(&store, &table(), 51).is_empty());
+    }
+
+    #[test]
+    fn bounded_stream_stops_at_upper_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        fill(&mut store, 1..=50);
+
+        let events: Vec<_> = store
+            .stream(&table(), VersionRange::between(12, 31))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(versions(&events), (12..=31).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn index_entries_point_at_their_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        fill(&mut store, 1..=50);
+
+        for segment in &store.tables[&table()].segments {
+            let path = segment_path(
+                &dir.path().join(table().to_string()),
+                segment.base_version,
+                "log",
+            );
+            for entry in &segment.index {
+                assert_eq!(version_at(&path, entry.offset), Some(entry.version));
+            }
+        }
+    }
+
+    #[test]
+    fn writer_killed_mid_segment_recovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        fill(&mut store, 1..=20);
+
+        let active_base = store.tables[&table()].segments.last().unwrap().base_version;
+        drop(store);
+
+        // Crash partway through writing record 21.
+        let record = encode_record(&event(21), EventEncoding::Json).unwrap();
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(segment_path(
+                &dir.path().join(table().to_string()),
+                active_base,
+                "log",
+            ))
+            .unwrap();
+        log.write_all(&record[..record.len() - 5]).unwrap();
+        drop(log);
+
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        assert_eq!(store.current_version(&table()).unwrap(), 20);
+
+        fill(&mut store, 21..=30);
+        assert_eq!(
+            versions(&store.load(&table()).unwrap()),
+            (1..=30).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn torn_index_entry_is_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        fill(&mut store, 1..=20);
+
+        let base = store.tables[&table()].segments.last().unwrap().base_version;
+        let expected_index = store.tables[&table()]
+            .segments
+            .last()
+            .unwrap()
+            .index
+            .clone();
+        drop(store);
+
+        // Crash partway through writing an index entry.
+        let mut index = OpenOptions::new()
+            .append(true)
+            .open(segment_path(
+                &dir.path().join(table().to_string()),
+                base,
+                "index",
+            ))
+            .unwrap();
+        index.write_all(&[0xab; 7]).unwrap();
+        drop(index);
+
+        let store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        assert_eq!(
+            store.tables[&table()].segments.last().unwrap().index,
+            expected_index
+        );
+        assert_eq!(
+            versions(&load_from(&store, &table(), 15)),
+            (15..=20).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn corrupt_or_missing_index_is_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        fill(&mut store, 1..=50);
+        let first_base = store.tables[&table()].segments[0].base_version;
+        let second_base = store.tables[&table()].segments[1].base_version;
+        drop(store);
+
+        // Garble one index and delete another.
+        let first_index = segment_path(&dir.path().join(table().to_string()), first_base, "index");
+        let mut bytes = fs::read(&first_index).unwrap();
+        bytes[3] ^= 0xff;
+        fs::write(&first_index, bytes).unwrap();
+        fs::remove_file(segment_path(
+            &dir.path().join(table().to_string()),
+            second_base,
+            "index",
+        ))
+        .unwrap();
+
+        let store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        assert!(!store.tables[&table()].segments[0].index.is_empty());
+        assert!(segment_path(&dir.path().join(table().to_string()), second_base, "index").exists());
+        assert_eq!(
+            versions(&load_from(&store, &table(), 2)),
+            (2..=50).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn version_conflict_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SegmentedLogStore::open(dir.path()).unwrap();
+        store.append(&event(1)).unwrap();
+
+        let err = store.append(&event(1)).unwrap_err();
+        assert_eq!(
+            err,
+            LogError::VersionConflict {
+                expected: 2,
+                actual: 1
+            }
+        );
+    }
+
+    #[test]
+    fn tables_get_independent_segment_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = TableId(Uuid::new_v4());
+
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        fill(&mut store, 1..=5);
+        for v in 1..=3 {
+            store
+                .append(&TableEvent {
+                    table_id: other.clone(),
+                    ..event(v)
+                })
+                .unwrap();
+        }
+        drop(store);
+
+        let store = SegmentedLogStore::open_with_config(dir.path(), small_segments()).unwrap();
+        assert!(dir.path().join(other.to_string()).is_dir());
+        assert_eq!(store.current_version(&table()).unwrap(), 5);
+        assert_eq!(store.current_version(&other).unwrap(), 3);
+        assert_eq!(store.tables().unwrap().len(), 2);
+        assert_eq!(versions(&load_from(&store, &other, 2)), vec![2, 3]);
+    }
+
+    #[test]
+    fn idempotency_keys_are_recovered_across_sealed_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SegmentConfig {
+            dedup_window: 40,
+            ..small_segments()
+        };
+        let keyed = |version: u64| {
+            event(version).with_envelope(EventEnvelope {
+                idempotency_key: Some(format!("commit-{version}")),
+                ..Default::default()
+            })
+        };
+
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), config).unwrap();
+        for v in 1..=60 {
+            store.append(&keyed(v)).unwrap();
+        }
+        drop(store);
+
+        let mut store = SegmentedLogStore::open_with_config(dir.path(), config).unwrap();
+        assert!(store.segment_count(&table()) > 2);
+
+        // Version 25 is in a sealed segment but still inside the window.
+        assert_eq!(
+            store.append(&keyed(25)).unwrap(),
+            AppendOutcome::Duplicate(25)
+        );
+        assert!(matches!(
+            store.append(&keyed(20)),
+            Err(LogError::VersionConflict { expected: 61, .. })
+        ));
+        assert_eq!(store.current_version(&table()).unwrap(), 60);
+    }
+}
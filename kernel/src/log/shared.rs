@@ -0,0 +1,263 @@
+// Shared Log Handle & Optimistic Concurrency
+//
+// Engines commit concurrently: each reads the head of a table, builds an
+// event for the next version and tries to commit it with `append_if`.
+// Only one writer wins a version; the others get the new head back and
+// rebase their event on top of it (or give up).
+//
+// The handle is cheap to clone and can be shared across threads.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{
+    AppendOutcome, EventFilter, LogError, MetadataLog, MetadataLogStore, TableEvent, TableId,
+    Version, VersionRange,
+};
+
+/// Thread-safe, cloneable handle to a `MetadataLog`.
+pub struct SharedMetadataLog<S: MetadataLogStore> {
+    inner: Arc<Mutex<MetadataLog<S>>>,
+}
+
+impl<S: MetadataLogStore> Clone for SharedMetadataLog<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<S: MetadataLogStore> SharedMetadataLog<S> {
+    pub fn new(log: MetadataLog<S>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(log)),
+        }
+    }
+
+    /// Commit `event` if its table is still at `expected_version`.
+    ///
+    /// On conflict, returns `LogError::HeadMismatch` carrying the current
+    /// head so the caller can rebase.
+    pub fn append_if(
+        &self,
+        expected_version: Version,
+        event: TableEvent,
+    ) -> Result<AppendOutcome, LogError> {
+        self.lock()?.append_if(expected_version, event)
+    }
+
+    /// Commit `event`, rebasing it onto the current head after conflicts.
+    ///
+    /// `event.version` is the writer's first guess. After a conflict the
+    /// event is moved to the version after the new head, its signature is
+    /// cleared, and `rebase` is called with it and the events committed
+    /// in the meantime. `rebase` may re-sign or adjust the event, or
+    /// return an error to abandon the commit (e.g. if a concurrent schema
+    /// change invalidates it).
+    ///
+    /// Gives up after `max_attempts` conflicts with the last
+    /// `HeadMismatch`. Returns the committed version, which is the
+    /// original one if the event carries an idempotency key that was
+    /// already committed.
+    pub fn append_with_retry(
+        &self,
+        mut event: TableEvent,
+        max_attempts: usize,
+        mut rebase: impl FnMut(&mut TableEvent, &[TableEvent]) -> Result<(), LogError>,
+    ) -> Result<Version, LogError> {
+        let mut attempts = 0;
+
+        loop {
+            let expected = event.version.saturating_sub(1);
+            let err = match self.append_if(expected, event.clone()) {
+                Ok(outcome) => return Ok(outcome.version()),
+                Err(err) => err,
+            };
+
+            let LogError::HeadMismatch { head, .. } = err else {
+                return Err(err);
+            };
+
+            attempts += 1;
+            if attempts >= max_attempts {
+                return Err(err);
+            }
+
+            let missed = self.events_between(&event.table_id, expected + 1, head)?;
+            event.version = head + 1;
+            event.signature = None;
+            event.hash = None;
+            rebase(&mut event, &missed)?;
+        }
+    }
+
+    pub fn replay(&self, table_id: &TableId) -> Result<Vec<TableEvent>, LogError> {
+        self.lock()?.replay(table_id)
+    }
+
+    /// Verified events `from..=to` of a table.
+    pub fn events_between(
+        &self,
+        table_id: &TableId,
+        from: Version,
+        to: Version,
+    ) -> Result<Vec<TableEvent>, LogError> {
+        self.lock()?
+            .stream(table_id, VersionRange::between(from, to))?
+            .collect()
+    }
+
+    pub fn query(&self, filter: &EventFilter) -> Result<Vec<TableEvent>, LogError> {
+        self.lock()?.query(filter)
+    }
+
+    pub fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        self.lock()?.current_version(table_id)
+    }
+
+    pub fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        self.lock()?.tables()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, MetadataLog<S>>, LogError> {
+        self.inner
+            .lock()
+            .map_err(|_| LogError::Storage("metadata log lock poisoned".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{
+        ActorId, EventEnvelope, EventSigner, EventType, InMemoryLogStore, TrustStore,
+    };
+    use std::thread;
+    use uuid::Uuid;
+
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    fn event(version: Version) -> TableEvent {
+        TableEvent::new(table(), version, EventType::SnapshotAdded, vec![])
+    }
+
+    fn shared() -> SharedMetadataLog<InMemoryLogStore> {
+        SharedMetadataLog::new(MetadataLog::new(InMemoryLogStore::default()))
+    }
+
+    #[test]
+    fn append_if_reports_the_current_head() {
+        let log = shared();
+        log.append_if(0, event(1)).unwrap();
+        log.append_if(1, event(2)).unwrap();
+
+        let err = log.append_if(1, event(2)).unwrap_err();
+        assert_eq!(
+            err,
+            LogError::HeadMismatch {
+                table_id: table(),
+                expected: 1,
+                head: 2
+            }
+        );
+        assert_eq!(log.current_version(&table()).unwrap(), 2);
+    }
+
+    #[test]
+    fn rebase_sees_missed_events_and_can_abort() {
+        let log = shared();
+        log.append_if(0, event(1)).unwrap();
+        log.append_if(1, event(2)).unwrap();
+
+        let mut seen = Vec::new();
+        let version = log
+            .append_with_retry(event(1), 3, |_, missed| {
+                seen.extend(missed.iter().map(|e| e.version));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(version, 3);
+        assert_eq!(seen, vec![1, 2]);
+
+        let err = log
+            .append_with_retry(event(3), 3, |_, _| {
+                Err(LogError::Storage("superseded".into()))
+            })
+            .unwrap_err();
+        assert_eq!(err, LogError::Storage("superseded".into()));
+        assert_eq!(log.current_version(&table()).unwrap(), 3);
+    }
+
+    #[test]
+    fn retried_commit_is_not_a_conflict() {
+        let log = shared();
+        let commit = event(1).with_envelope(EventEnvelope {
+            idempotency_key: Some("flink-checkpoint-12".into()),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            log.append_if(0, commit.clone()).unwrap(),
+            AppendOutcome::Appended(1)
+        );
+        log.append_if(1, event(2)).unwrap();
+
+        // The writer never saw the first acknowledgement and retries.
+        assert_eq!(
+            log.append_if(0, commit.clone()).unwrap(),
+            AppendOutcome::Duplicate(1)
+        );
+        assert_eq!(log.append_with_retry(commit, 1, |_, _| Ok(())), Ok(1));
+        assert_eq!(log.current_version(&table()).unwrap(), 2);
+    }
+
+    #[test]
+    fn concurrent_writers_commit_every_event_exactly_once() {
+        let spark = EventSigner::from_seed(ActorId("spark".into()), &[1; 32]);
+        let flink = EventSigner::from_seed(ActorId("flink".into()), &[2; 32]);
+
+        let mut trust = TrustStore::new();
+        for signer in [&spark, &flink] {
+            trust.trust(signer.actor().clone(), signer.verifying_key());
+        }
+        let log = SharedMetadataLog::new(
+            MetadataLog::new(InMemoryLogStore::default()).with_trust_store(trust),
+        );
+
+        const COMMITS: usize = 50;
+        let writers: Vec<_> = [spark, flink]
+            .into_iter()
+            .map(|signer| {
+                let log = log.clone();
+                thread::spawn(move || {
+                    for _ in 0..COMMITS {
+                        let head = log.current_version(&table()).unwrap();
+                        let mut candidate = event(head + 1);
+                        signer.sign(&mut candidate).unwrap();
+
+                        log.append_with_retry(candidate, usize::MAX, |rebased, _| {
+                            signer.sign(rebased)
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let events = log.replay(&table()).unwrap();
+        assert_eq!(events.len(), 2 * COMMITS);
+        for actor in ["spark", "flink"] {
+            let count = events
+                .iter()
+                .filter(|e| e.envelope.actor == Some(ActorId(actor.into())))
+                .count();
+            assert_eq!(count, COMMITS, "{actor}");
+        }
+    }
+}
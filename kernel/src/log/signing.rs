@@ -0,0 +1,275 @@
+// Signed Events & Engine Identities
+//
+// Engines (or people) sign the canonical encoding of each event they
+// produce with an Ed25519 key. A trust store lists which public keys may
+// act for which actor, so replay can prove who produced every mutation.
+//
+// The signature covers the event content including its actor, but not
+// its chain hash: the hash is assigned by the log after signing.
+
+use std::collections::BTreeMap;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use super::chain::{canonical_bytes, from_hex, to_hex};
+use super::{ActorId, LogError, TableEvent};
+
+const DOMAIN: &[u8] = b"axiom.signature.v1";
+
+/// Signs events on behalf of an actor.
+pub struct EventSigner {
+    actor: ActorId,
+    key: SigningKey,
+}
+
+impl EventSigner {
+    pub fn new(actor: ActorId, key: SigningKey) -> Self {
+        Self { actor, key }
+    }
+
+    /// Create a signer from a 32-byte Ed25519 secret seed.
+    pub fn from_seed(actor: ActorId, seed: &[u8; 32]) -> Self {
+        Self::new(actor, SigningKey::from_bytes(seed))
+    }
+
+    pub fn actor(&self) -> &ActorId {
+        &self.actor
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    /// Stamp the signer's actor on `event` and sign it.
+    ///
+    /// Must be called after the event's version is final; rebasing an
+    /// event onto a new version requires signing it again. Fails if the
+    /// payload cannot be encoded.
+    pub fn sign(&self, event: &mut TableEvent) -> Result<(), LogError> {
+        event.envelope.actor = Some(self.actor.clone());
+        event.signature = Some(self.sign_message(&signing_bytes(event)?));
+        Ok(())
+    }
+
+    /// Hex-encoded signature of an arbitrary, domain-separated message.
+    pub(crate) fn sign_message(&self, message: &[u8]) -> String {
+        to_hex(&self.key.sign(message).to_bytes())
+    }
+}
+
+/// Public keys allowed to sign for each actor.
+///
+/// Serialized as a map of actor to hex-encoded public keys:
+///
+/// ```json
+/// { "spark-etl": ["3b6a27bc..."], "alice": ["d75a9801..."] }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "BTreeMap<ActorId, Vec<String>>")]
+#[serde(into = "BTreeMap<ActorId, Vec<String>>")]
+pub struct TrustStore {
+    keys: BTreeMap<ActorId, Vec<VerifyingKey>>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow `key` to sign events for `actor`.
+    pub fn trust(&mut self, actor: ActorId, key: VerifyingKey) {
+        let keys = self.keys.entry(actor).or_default();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    /// Check that `event` is signed by a key trusted for its actor.
+    pub fn verify(&self, event: &TableEvent) -> Result<(), LogError> {
+        let reject = |reason: String| LogError::SignatureRejected {
+            table_id: event.table_id.clone(),
+            version: event.version,
+            reason,
+        };
+
+        let actor = event
+            .envelope
+            .actor
+            .as_ref()
+            .ok_or_else(|| reject("event has no actor".into()))?;
+
+        let signature = event
+            .signature
+            .as_deref()
+            .ok_or_else(|| reject("event is unsigned".into()))?;
+
+        self.verify_message(actor, &signing_bytes(event)?, signature)
+            .map_err(reject)
+    }
+
+    /// Check a hex-encoded signature of `message` against the keys
+    /// trusted for `actor`.
+    pub(crate) fn verify_message(
+        &self,
+        actor: &ActorId,
+        message: &[u8],
+        signature: &str,
+    ) -> Result<(), String> {
+        let signature = from_hex::<64>(signature)
+            .map(|bytes| Signature::from_bytes(&bytes))
+            .ok_or("malformed signature")?;
+
+        let keys = self
+            .keys
+            .get(actor)
+            .ok_or_else(|| format!("actor `{actor}` is not trusted"))?;
+
+        if keys
+            .iter()
+            .any(|key| key.verify(message, &signature).is_ok())
+        {
+            Ok(())
+        } else {
+            Err(format!("signature does not match any key of `{actor}`"))
+        }
+    }
+}
+
+impl TryFrom<BTreeMap<ActorId, Vec<String>>> for TrustStore {
+    type Error = String;
+
+    fn try_from(map: BTreeMap<ActorId, Vec<String>>) -> Result<Self, Self::Error> {
+        let mut store = TrustStore::new();
+
+        for (actor, keys) in map {
+            for key in keys {
+                let key = from_hex::<32>(&key)
+                    .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                    .ok_or_else(|| format!("invalid public key for `{actor}`: {key}"))?;
+                store.trust(actor.clone(), key);
+            }
+        }
+
+        Ok(store)
+    }
+}
+
+impl From<TrustStore> for BTreeMap<ActorId, Vec<String>> {
+    fn from(store: TrustStore) -> Self {
+        store
+            .keys
+            .into_iter()
+            .map(|(actor, keys)| {
+                let keys = keys.iter().map(|k| to_hex(k.as_bytes())).collect();
+                (actor, keys)
+            })
+            .collect()
+    }
+}
+
+fn signing_bytes(event: &TableEvent) -> Result<Vec<u8>, LogError> {
+    let mut message = DOMAIN.to_vec();
+    message.extend_from_slice(&canonical_bytes(event)?);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{EventType, InMemoryLogStore, MetadataLog, TableId};
+    use uuid::Uuid;
+
+    fn spark() -> EventSigner {
+        EventSigner::from_seed(ActorId("spark-etl".into()), &[7; 32])
+    }
+
+    fn event(version: u64) -> TableEvent {
+        TableEvent::new(
+            TableId(Uuid::nil()),
+            version,
+            EventType::SnapshotAdded,
+            vec![1, 2],
+        )
+    }
+
+    fn trusting(signer: &EventSigner) -> TrustStore {
+        let mut trust = TrustStore::new();
+        trust.trust(signer.actor().clone(), signer.verifying_key());
+        trust
+    }
+
+    #[test]
+    fn signed_event_verifies() {
+        let signer = spark();
+        let mut e = event(1);
+        signer.sign(&mut e).unwrap();
+
+        assert_eq!(trusting(&signer).verify(&e), Ok(()));
+    }
+
+    #[test]
+    fn unsigned_and_altered_events_are_rejected() {
+        let signer = spark();
+        let trust = trusting(&signer);
+
+        assert!(trust.verify(&event(1)).is_err());
+
+        let mut altered = event(1);
+        signer.sign(&mut altered).unwrap();
+        altered.payload = vec![3].into();
+        assert!(matches!(
+            trust.verify(&altered),
+            Err(LogError::SignatureRejected { version: 1, .. })
+        ));
+
+        // Claiming another actor's identity invalidates the signature.
+        let mut spoofed = event(1);
+        signer.sign(&mut spoofed).unwrap();
+        spoofed.envelope.actor = Some(ActorId("alice".into()));
+        assert!(trust.verify(&spoofed).is_err());
+    }
+
+    #[test]
+    fn untrusted_key_is_rejected() {
+        let impostor = EventSigner::from_seed(ActorId("spark-etl".into()), &[9; 32]);
+        let mut e = event(1);
+        impostor.sign(&mut e).unwrap();
+
+        assert!(trusting(&spark()).verify(&e).is_err());
+    }
+
+    #[test]
+    fn strict_log_rejects_unsigned_events_on_replay() {
+        let signer = spark();
+        let table = TableId(Uuid::nil());
+        let mut log =
+            MetadataLog::new(InMemoryLogStore::default()).with_trust_store(trusting(&signer));
+
+        let mut signed = event(1);
+        signer.sign(&mut signed).unwrap();
+        log.append(signed).unwrap();
+        assert_eq!(log.replay(&table).unwrap().len(), 1);
+
+        log.append(event(2)).unwrap();
+        let err = log.replay(&table).unwrap_err();
+        assert!(matches!(
+            err,
+            LogError::SignatureRejected { version: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn trust_store_round_trips_through_json() {
+        let signer = spark();
+        let json = serde_json::to_string(&trusting(&signer)).unwrap();
+        let trust: TrustStore = serde_json::from_str(&json).unwrap();
+
+        let mut e = event(1);
+        signer.sign(&mut e).unwrap();
+        assert_eq!(trust.verify(&e), Ok(()));
+
+        let bad = r#"{ "spark-etl": ["not-a-key"] }"#;
+        assert!(serde_json::from_str::<TrustStore>(bad).is_err());
+    }
+}
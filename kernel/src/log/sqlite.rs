@@ -0,0 +1,564 @@
+// SQLite Log Store
+//
+// Embedded single-file store for small deployments. Events of all tables
+// live in one `events` table keyed by `(table_id, version)`; the primary
+// key enforces that a version is written at most once, and appends check
+// the table head inside a write transaction so no gaps can appear.
+//
+// Event type, commit time and idempotency key are stored in indexed
+// columns next to the encoded event, so they can be queried without
+// decoding every row. JSON bodies are stored as text and binary ones as
+// blobs; a database may hold both.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, TransactionBehavior};
+
+use super::codec::{self, EventEncoding};
+use super::dedup::DEFAULT_DEDUP_WINDOW;
+use super::record::storage;
+use super::{
+    AppendOutcome, EventFilter, EventStream, EventType, LogError, MetadataLogStore, TableEvent,
+    TableId, Version, VersionRange,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        table_id        TEXT    NOT NULL,
+        version         INTEGER NOT NULL CHECK (version > 0),
+        event_type      TEXT    NOT NULL,
+        committed_at    INTEGER,
+        idempotency_key TEXT,
+        body            TEXT    NOT NULL,
+        PRIMARY KEY (table_id, version)
+    );
+    CREATE INDEX IF NOT EXISTS events_by_type ON events (table_id, event_type, version);
+    CREATE INDEX IF NOT EXISTS events_by_time ON events (committed_at);
+    CREATE INDEX IF NOT EXISTS events_by_key ON events (table_id, idempotency_key)
+        WHERE idempotency_key IS NOT NULL;
+";
+
+/// How long to wait for another connection's write transaction.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Rows fetched per query while streaming.
+const PAGE_SIZE: i64 = 256;
+
+/// SQLite-backed store (single database file).
+pub struct SqliteLogStore {
+    conn: Mutex<Connection>,
+    dedup_window: u64,
+    encoding: EventEncoding,
+}
+
+impl SqliteLogStore {
+    /// Open (or create) a database file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LogError> {
+        Self::open_with_dedup_window(path, DEFAULT_DEDUP_WINDOW)
+    }
+
+    /// Open a database file, deduplicating idempotency keys of the last
+    /// `window` versions of each table (0 disables deduplication).
+    pub fn open_with_dedup_window(path: impl AsRef<Path>, window: u64) -> Result<Self, LogError> {
+        let conn = Connection::open(path).map_err(storage)?;
+        // Other connections to the file hold its write lock only briefly.
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(storage)?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(storage)?;
+        conn.pragma_update(None, "synchronous", "FULL")
+            .map_err(storage)?;
+        Self::init(conn, window)
+    }
+
+    /// Open a private in-memory database (for tests and tooling).
+    pub fn open_in_memory() -> Result<Self, LogError> {
+        Self::init(
+            Connection::open_in_memory().map_err(storage)?,
+            DEFAULT_DEDUP_WINDOW,
+        )
+    }
+
+    fn init(conn: Connection, dedup_window: u64) -> Result<Self, LogError> {
+        conn.execute_batch(SCHEMA).map_err(storage)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            dedup_window,
+            encoding: EventEncoding::default(),
+        })
+    }
+
+    /// Write appended events in `encoding` (JSON by default). Stored rows
+    /// keep their encoding.
+    pub fn with_encoding(mut self, encoding: EventEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Stored events matching `filter`, using the indexes on table, event
+    /// type and commit time. Ordered by table and version.
+    ///
+    /// Events are returned as stored, without hash chain verification.
+    pub fn query(&self, filter: &EventFilter) -> Result<Vec<TableEvent>, LogError> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT body FROM events
+                 WHERE (?1 IS NULL OR table_id = ?1)
+                   AND (?2 IS NULL OR event_type = ?2)
+                   AND (?3 IS NULL OR committed_at >= ?3)
+                   AND (?4 IS NULL OR committed_at <= ?4)
+                 ORDER BY table_id, version",
+            )
+            .map_err(storage)?;
+
+        let rows = stmt
+            .query_map(
+                params![
+                    filter.table_id.as_ref().map(ToString::to_string),
+                    filter.event_type.as_ref().map(event_type_name),
+                    filter.committed_after.map(|t| t as i64),
+                    filter.committed_before.map(|t| t as i64),
+                ],
+                |row| row.get::<_, Value>(0),
+            )
+            .map_err(storage)?;
+
+        let mut events = Vec::new();
+        for body in rows {
+            let event = decode(body.map_err(storage)?)?;
+            if filter.matches(&event) {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    fn page(
+        &self,
+        table_id: &str,
+        from: Version,
+        to: Version,
+    ) -> Result<VecDeque<TableEvent>, LogError> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT body FROM events
+                 WHERE table_id = ?1 AND version BETWEEN ?2 AND ?3
+                 ORDER BY version LIMIT ?4",
+            )
+            .map_err(storage)?;
+
+        let rows = stmt
+            .query_map(
+                params![
+                    table_id,
+                    from as i64,
+                    to.min(i64::MAX as u64) as i64,
+                    PAGE_SIZE
+                ],
+                |row| row.get::<_, Value>(0),
+            )
+            .map_err(storage)?;
+
+        rows.map(|body| decode(body.map_err(storage)?)).collect()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, LogError> {
+        self.conn
+            .lock()
+            .map_err(|_| LogError::Storage("sqlite connection lock poisoned".into()))
+    }
+
+    /// Insert `event` after the table's head, or as the first event of
+    /// an empty table at its own version when `seed` is set.
+    fn insert(&mut self, event: &TableEvent, seed: bool) -> Result<AppendOutcome, LogError> {
+        let bytes = self.encoding.encode(event)?;
+        let body = match self.encoding {
+            EventEncoding::Json => Value::Text(String::from_utf8(bytes).map_err(storage)?),
+            EventEncoding::Binary => Value::Blob(bytes),
+        };
+        let table_id = event.table_id.to_string();
+
+        let conn = self
+            .conn
+            .get_mut()
+            .map_err(|_| LogError::Storage("sqlite connection lock poisoned".into()))?;
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(storage)?;
+
+        let head = head_version(&tx, &table_id)?;
+
+        if let Some(key) = &event.envelope.idempotency_key {
+            if let Some(version) = find_key(&tx, &table_id, key, head, self.dedup_window)? {
+                return Ok(AppendOutcome::Duplicate(version));
+            }
+        }
+
+        let expected = match (seed, head) {
+            (true, 0) => event.version,
+            _ => head + 1,
+        };
+        if event.version != expected || (seed && head > 0) {
+            return Err(LogError::VersionConflict {
+                expected,
+                actual: event.version,
+            });
+        }
+
+        tx.execute(
+            "INSERT INTO events
+                 (table_id, version, event_type, committed_at, idempotency_key, body)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                table_id,
+                event.version as i64,
+                event_type_name(&event.event_type),
+                event.envelope.committed_at.map(|t| t as i64),
+                event.envelope.idempotency_key,
+                body,
+            ],
+        )
+        .map_err(|e| match e.sqlite_error_code() {
+            // Another connection to the same file won the version.
+            Some(ErrorCode::ConstraintViolation) => LogError::VersionConflict {
+                expected,
+                actual: event.version,
+            },
+            _ => storage(e),
+        })?;
+
+        tx.commit().map_err(storage)?;
+        Ok(AppendOutcome::Appended(event.version))
+    }
+}
+
+impl MetadataLogStore for SqliteLogStore {
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError> {
+        self.insert(event, false)
+    }
+
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError> {
+        let conn = self.lock()?;
+        let table_id = table_id.to_string();
+        let head = head_version(&conn, &table_id)?;
+        find_key(&conn, &table_id, key, head, self.dedup_window)
+    }
+
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError> {
+        Ok(Box::new(Pages {
+            store: self,
+            table_id: table_id.to_string(),
+            next: range.from_version.max(1),
+            last: range.to_version.unwrap_or(Version::MAX),
+            buffer: VecDeque::new(),
+            done: false,
+        }))
+    }
+
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        head_version(&*self.lock()?, &table_id.to_string())
+    }
+
+    fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare_cached("SELECT DISTINCT table_id FROM events")
+            .map_err(storage)?;
+
+        let mut tables = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(storage)?
+            .map(|id| {
+                let id = id.map_err(storage)?;
+                id.parse()
+                    .map_err(|_| LogError::Corrupt(format!("invalid table id {id}")))
+            })
+            .collect::<Result<Vec<TableId>, _>>()?;
+
+        tables.sort();
+        Ok(tables)
+    }
+
+    fn first_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        self.lock()?
+            .query_row(
+                "SELECT COALESCE(MIN(version), 1) FROM events WHERE table_id = ?1",
+                [table_id.to_string()],
+                |row| row.get::<_, i64>(0),
+            )
+            .map(|v| v as Version)
+            .map_err(storage)
+    }
+
+    fn supports_truncation(&self) -> bool {
+        true
+    }
+
+    fn truncate_before(&mut self, table_id: &TableId, version: Version) -> Result<(), LogError> {
+        let conn = self.lock()?;
+        let table_id = table_id.to_string();
+
+        // The head always stays, so versions keep counting from it.
+        let keep_from = version.min(head_version(&conn, &table_id)?);
+        conn.execute(
+            "DELETE FROM events WHERE table_id = ?1 AND version < ?2",
+            params![table_id, keep_from as i64],
+        )
+        .map_err(storage)?;
+        Ok(())
+    }
+
+    fn seed(&mut self, anchor: &TableEvent) -> Result<(), LogError> {
+        self.insert(anchor, true).map(|_| ())
+    }
+}
+
+/// Lazily fetches a table's events one page at a time, so the connection
+/// is never held between calls to `next`.
+struct Pages<'a> {
+    store: &'a SqliteLogStore,
+    table_id: String,
+    next: Version,
+    last: Version,
+    buffer: VecDeque<TableEvent>,
+    done: bool,
+}
+
+impl Iterator for Pages<'_> {
+    type Item = Result<TableEvent, LogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.done {
+            if self.next > self.last {
+                self.done = true;
+                return None;
+            }
+
+            match self.store.page(&self.table_id, self.next, self.last) {
+                Ok(page) => {
+                    self.done = (page.len() as i64) < PAGE_SIZE;
+                    self.buffer = page;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        let event = self.buffer.pop_front()?;
+        self.next = event.version + 1;
+        Some(Ok(event))
+    }
+}
+
+fn head_version(conn: &Connection, table_id: &str) -> Result<Version, LogError> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM events WHERE table_id = ?1",
+        [table_id],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|v| v as Version)
+    .map_err(storage)
+}
+
+fn find_key(
+    conn: &Connection,
+    table_id: &str,
+    key: &str,
+    head: Version,
+    window: u64,
+) -> Result<Option<Version>, LogError> {
+    if window == 0 {
+        return Ok(None);
+    }
+
+    let oldest = (head + 1).saturating_sub(window).max(1);
+    conn.query_row(
+        "SELECT MAX(version) FROM events
+         WHERE table_id = ?1 AND idempotency_key = ?2 AND version >= ?3",
+        params![table_id, key, oldest as i64],
+        |row| row.get::<_, Option<i64>>(0),
+    )
+    .optional()
+    .map(|v| v.flatten().map(|v| v as Version))
+    .map_err(storage)
+}
+
+fn decode(body: Value) -> Result<TableEvent, LogError> {
+    let bytes = match body {
+        Value::Text(text) => text.into_bytes(),
+        Value::Blob(bytes) => bytes,
+        other => {
+            return Err(LogError::Corrupt(format!(
+                "undecodable event: unexpected {:?} body",
+                other.data_type()
+            )))
+        }
+    };
+    codec::decode(&bytes).map_err(|e| LogError::Corrupt(format!("undecodable event: {e}")))
+}
+
+fn event_type_name(event_type: &EventType) -> String {
+    match serde_json::to_value(event_type) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{event_type:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::conformance::{StoreFactory, TempLocations};
+    use crate::log::{EventEnvelope, MetadataLog};
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    fn event(version: Version, event_type: EventType, committed_at: u64) -> TableEvent {
+        TableEvent::new(table(), version, event_type, vec![]).with_envelope(EventEnvelope {
+            committed_at: Some(committed_at),
+            ..Default::default()
+        })
+    }
+
+    struct Factory(TempLocations, EventEncoding);
+
+    impl StoreFactory for Factory {
+        type Store = SqliteLogStore;
+        type Location = PathBuf;
+
+        fn create_location(&mut self) -> PathBuf {
+            self.0.next()
+        }
+
+        fn open(&mut self, path: &PathBuf) -> SqliteLogStore {
+            SqliteLogStore::open(path).unwrap().with_encoding(self.1)
+        }
+
+        fn shares_storage(&self) -> bool {
+            true
+        }
+    }
+
+    mod conformance {
+        use super::*;
+        crate::store_conformance_tests!(Factory(TempLocations::new(), EventEncoding::Json));
+    }
+
+    mod binary_conformance {
+        use super::*;
+        crate::store_conformance_tests!(Factory(TempLocations::new(), EventEncoding::Binary));
+    }
+
+    #[test]
+    fn events_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("axiom.db");
+
+        let mut log = MetadataLog::new(SqliteLogStore::open(&path).unwrap());
+        for v in 1..=3 {
+            log.append(event(v, EventType::SnapshotAdded, v * 10))
+                .unwrap();
+        }
+        drop(log);
+
+        let log = MetadataLog::new(SqliteLogStore::open(&path).unwrap());
+        assert_eq!(log.current_version(&table()).unwrap(), 3);
+        assert_eq!(log.replay(&table()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn concurrent_connections_cannot_both_win_a_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("axiom.db");
+
+        let mut spark = SqliteLogStore::open(&path).unwrap();
+        let mut flink = SqliteLogStore::open(&path).unwrap();
+
+        spark.append(&event(1, EventType::TableCreated, 1)).unwrap();
+        let err = flink
+            .append(&event(1, EventType::TableCreated, 2))
+            .unwrap_err();
+
+        assert!(matches!(err, LogError::VersionConflict { .. }));
+        assert_eq!(flink.current_version(&table()).unwrap(), 1);
+    }
+
+    #[test]
+    fn query_by_event_type_and_time_range() {
+        let mut store = SqliteLogStore::open_in_memory().unwrap();
+        let types = [
+            EventType::TableCreated,
+            EventType::SnapshotAdded,
+            EventType::SchemaUpdated,
+            EventType::SnapshotAdded,
+            EventType::SnapshotAdded,
+        ];
+        for (i, event_type) in types.into_iter().enumerate() {
+            let v = i as u64 + 1;
+            store.append(&event(v, event_type, v * 100)).unwrap();
+        }
+
+        let found = |filter: EventFilter| -> Vec<Version> {
+            store
+                .query(&filter)
+                .unwrap()
+                .iter()
+                .map(|e| e.version)
+                .collect()
+        };
+
+        assert_eq!(
+            found(EventFilter {
+                event_type: Some(EventType::SnapshotAdded),
+                ..Default::default()
+            }),
+            vec![2, 4, 5]
+        );
+        assert_eq!(
+            found(EventFilter {
+                event_type: Some(EventType::SnapshotAdded),
+                committed_after: Some(250),
+                committed_before: Some(400),
+                ..Default::default()
+            }),
+            vec![4]
+        );
+        assert!(found(EventFilter {
+            table_id: Some(TableId(Uuid::new_v4())),
+            ..Default::default()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn stream_pages_through_long_logs() {
+        let mut store = SqliteLogStore::open_in_memory().unwrap();
+        let total = 2 * PAGE_SIZE as u64 + 3;
+        for v in 1..=total {
+            store
+                .append(&event(v, EventType::SnapshotAdded, v))
+                .unwrap();
+        }
+
+        let versions: Vec<_> = store
+            .stream(&table(), VersionRange::starting_at(2))
+            .unwrap()
+            .map(|e| e.unwrap().version)
+            .collect();
+        assert_eq!(versions, (2..=total).collect::<Vec<_>>());
+    }
+}
@@ -5,32 +5,206 @@
 //
 // This module defines *interfaces only*.
 
-use super::{LogError, TableEvent, Version};
+use super::{LogError, TableEvent, TableId, Version};
+
+/// Lazily evaluated sequence of events read from a store.
+pub type EventStream<'a> = Box<dyn Iterator<Item = Result<TableEvent, LogError>> + 'a>;
+
+/// Inclusive version bounds for a range read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionRange {
+    pub from_version: Version,
+
+    /// Upper bound, or `None` to read up to the current head.
+    pub to_version: Option<Version>,
+}
+
+impl VersionRange {
+    /// Every version of a table.
+    pub fn all() -> Self {
+        Self::starting_at(1)
+    }
+
+    /// All versions from `from_version` onwards (e.g. after a checkpoint).
+    pub fn starting_at(from_version: Version) -> Self {
+        Self {
+            from_version,
+            to_version: None,
+        }
+    }
+
+    /// Versions `from_version..=to_version`.
+    pub fn between(from_version: Version, to_version: Version) -> Self {
+        Self {
+            from_version,
+            to_version: Some(to_version),
+        }
+    }
+
+    pub fn contains(&self, version: Version) -> bool {
+        version >= self.from_version && self.to_version.is_none_or(|to| version <= to)
+    }
+
+    /// True once `version` is beyond the upper bound.
+    pub fn is_past(&self, version: Version) -> bool {
+        self.to_version.is_some_and(|to| version > to)
+    }
+}
+
+/// Result of a successful append.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendOutcome {
+    /// The event was written at this version.
+    Appended(Version),
+
+    /// An event with the same idempotency key was already committed at
+    /// this version; nothing was written.
+    Duplicate(Version),
+}
+
+impl AppendOutcome {
+    /// Version the event is committed at.
+    pub fn version(&self) -> Version {
+        match self {
+            AppendOutcome::Appended(version) | AppendOutcome::Duplicate(version) => *version,
+        }
+    }
+}
 
 /// Storage backend for the metadata log.
 ///
-/// Properties required from implementations:
+/// A single store multiplexes the logs of many tables. Versions are
+/// scoped per `TableId`: every table has its own sequence starting at 1.
+///
+/// Properties required from implementations (per table):
 /// - Append-only
 /// - Ordered
 /// - Durable
 /// - CAS semantics on version
+/// - Idempotency: an event whose idempotency key matches one of the
+///   table's recent events is not written again
 ///
 /// Implementations MUST NOT:
 /// - Reorder events
 /// - Mutate existing events
 /// - Allow version gaps
+///
+/// `conformance` checks these properties against any implementation.
 pub trait MetadataLogStore: Send + Sync {
-    /// Append an event to storage.
+    /// Append an event to its table's log.
     ///
     /// Implementations must enforce:
-    /// - event.version == last_version + 1
-    fn append(&mut self, event: &TableEvent) -> Result<(), LogError>;
+    /// - a known idempotency key yields `AppendOutcome::Duplicate`,
+    ///   whatever the event's version
+    /// - otherwise event.version == last_version(event.table_id) + 1
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError>;
+
+    /// Version of the recent event of a table carrying `key`, if it is
+    /// still within the store's dedup window.
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError>;
+
+    /// Stream the events of a table within `range`, in version order.
+    ///
+    /// Implementations should read lazily so long logs can be processed
+    /// in bounded memory. Unknown tables yield an empty stream.
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError>;
+
+    /// Load all events of a table in order.
+    ///
+    /// Used for deterministic replay. Unknown tables have no events.
+    fn load(&self, table_id: &TableId) -> Result<Vec<TableEvent>, LogError> {
+        self.stream(table_id, VersionRange::all())?.collect()
+    }
+
+    /// Return the current persisted version of a table (0 if unknown).
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError>;
+
+    /// List all tables with at least one event, in ascending id order.
+    fn tables(&self) -> Result<Vec<TableId>, LogError>;
 
-    /// Load all events in order.
+    /// First version of a table the store still holds: 1 unless older
+    /// events were discarded after compaction.
+    fn first_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        let _ = table_id;
+        Ok(1)
+    }
+
+    /// Whether `truncate_before` can discard events. Checked before a
+    /// compaction record is saved.
+    fn supports_truncation(&self) -> bool {
+        false
+    }
+
+    /// Discard a table's events before `version`, once compaction has
+    /// archived them.
     ///
-    /// Used for deterministic replay.
-    fn load(&self) -> Result<Vec<TableEvent>, LogError>;
+    /// Stores may keep some older events (e.g. whole segments), but must
+    /// keep `version` and everything after it, and never the table's
+    /// head. Versions keep counting from the head. Stores that cannot
+    /// discard events report a storage error.
+    fn truncate_before(&mut self, table_id: &TableId, version: Version) -> Result<(), LogError> {
+        let _ = (table_id, version);
+        Err(super::unsupported_truncation())
+    }
+
+    /// Store `anchor` as the first event of a table that has none, at its
+    /// own version, as if compaction had discarded everything before it.
+    ///
+    /// Used to copy compacted tables between logs. Stores supporting
+    /// `truncate_before` must support it; others report a storage error.
+    fn seed(&mut self, anchor: &TableEvent) -> Result<(), LogError> {
+        let _ = anchor;
+        Err(super::unsupported_truncation())
+    }
+}
+
+/// Lets callers pick a backend at runtime (e.g. from configuration).
+impl<S: MetadataLogStore + ?Sized> MetadataLogStore for Box<S> {
+    fn append(&mut self, event: &TableEvent) -> Result<AppendOutcome, LogError> {
+        (**self).append(event)
+    }
+
+    fn find_idempotency_key(
+        &self,
+        table_id: &TableId,
+        key: &str,
+    ) -> Result<Option<Version>, LogError> {
+        (**self).find_idempotency_key(table_id, key)
+    }
+
+    fn stream(&self, table_id: &TableId, range: VersionRange) -> Result<EventStream<'_>, LogError> {
+        (**self).stream(table_id, range)
+    }
+
+    fn load(&self, table_id: &TableId) -> Result<Vec<TableEvent>, LogError> {
+        (**self).load(table_id)
+    }
+
+    fn current_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        (**self).current_version(table_id)
+    }
+
+    fn tables(&self) -> Result<Vec<TableId>, LogError> {
+        (**self).tables()
+    }
+
+    fn first_version(&self, table_id: &TableId) -> Result<Version, LogError> {
+        (**self).first_version(table_id)
+    }
+
+    fn supports_truncation(&self) -> bool {
+        (**self).supports_truncation()
+    }
+
+    fn truncate_before(&mut self, table_id: &TableId, version: Version) -> Result<(), LogError> {
+        (**self).truncate_before(table_id, version)
+    }
 
-    /// Return the current persisted version.
-    fn current_version(&self) -> Result<Version, LogError>;
+    fn seed(&mut self, anchor: &TableEvent) -> Result<(), LogError> {
+        (**self).seed(anchor)
+    }
 }
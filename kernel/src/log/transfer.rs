@@ -0,0 +1,532 @@
+// Log Export, Import & Migration
+//
+// Logs are exchanged as NDJSON: one `TableEvent` per line, tables in id
+// order and each table's events in version order. Exported events keep
+// their chain hashes and signatures, so an imported log verifies exactly
+// like the original.
+//
+// A table whose oldest events were discarded by compaction starts with a
+// line holding its compaction record and the event anchoring it:
+//
+//   { "compaction": { "table_id": ..., "version": 40, ... }, "anchor": { ... } }
+//
+// Importing it saves the record and seeds the target with the anchor, so
+// the events after it chain on as usual.
+//
+// Both directions stream: neither side holds more than one event in
+// memory. Import validates every line before appending it -- JSON
+// shape, table id (when importing a single table), payload, version
+// continuity and hash chain -- and stops at the first bad line,
+// reporting its number. Events on earlier lines stay imported;
+// re-running the import skips them, so a fixed file can simply be
+// imported again.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    CompactionRecord, LogError, MetadataLog, MetadataLogStore, TableEvent, TableId, Version,
+    VersionRange,
+};
+
+/// Errors raised while moving events between logs.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TransferError {
+    /// The line is not a well-formed event.
+    #[error("line {line}: {reason}")]
+    Malformed { line: u64, reason: String },
+
+    /// The event is well-formed but the target log refused it.
+    #[error("line {line}: {error}")]
+    Rejected { line: u64, error: LogError },
+
+    /// The event belongs to another table than the one imported.
+    #[error("line {line}: event of table {table_id}, importing table {expected}")]
+    WrongTable {
+        line: u64,
+        table_id: TableId,
+        expected: TableId,
+    },
+
+    #[error(transparent)]
+    Log(#[from] LogError),
+
+    #[error("i/o error: {0}")]
+    Io(String),
+}
+
+/// Outcome of an export, import or migration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TransferSummary {
+    /// Events written to the target.
+    pub events: u64,
+
+    /// Events the target already held (re-run imports).
+    pub skipped: u64,
+
+    /// Last version transferred per table.
+    pub heads: BTreeMap<TableId, Version>,
+}
+
+/// Compaction record of a table and the event anchoring it, from which
+/// the table's later events chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompactedStart {
+    compaction: Box<CompactionRecord>,
+    anchor: TableEvent,
+}
+
+/// A transferred line.
+enum Entry {
+    Start(CompactedStart),
+    Event(TableEvent),
+}
+
+impl Entry {
+    fn parse(line: &str) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(line)?;
+        if value.get("compaction").is_some() {
+            serde_json::from_value(value).map(Entry::Start)
+        } else {
+            serde_json::from_value(value).map(Entry::Event)
+        }
+    }
+
+    /// Event the entry ends with.
+    fn event(&self) -> &TableEvent {
+        match self {
+            Entry::Start(start) => &start.anchor,
+            Entry::Event(event) => event,
+        }
+    }
+}
+
+impl TransferSummary {
+    fn record(&mut self, event: &TableEvent, written: bool) {
+        if written {
+            self.events += 1;
+        } else {
+            self.skipped += 1;
+        }
+        self.heads.insert(event.table_id.clone(), event.version);
+    }
+}
+
+/// Write the verified events of `table_id` (or of every table) as NDJSON.
+///
+/// Compacted tables are exported from their compaction record and its
+/// anchor event; the older events are in the compaction archive.
+pub fn export_ndjson<S: MetadataLogStore, W: Write>(
+    log: &MetadataLog<S>,
+    table_id: Option<&TableId>,
+    mut out: W,
+) -> Result<TransferSummary, TransferError> {
+    let mut summary = TransferSummary::default();
+
+    for_each_entry(log, table_id, |entry| {
+        match &entry {
+            Entry::Start(start) => serde_json::to_writer(&mut out, start),
+            Entry::Event(event) => serde_json::to_writer(&mut out, event),
+        }
+        .map_err(io)?;
+        out.write_all(b"\n").map_err(io)?;
+        summary.record(entry.event(), true);
+        Ok(())
+    })?;
+
+    out.flush().map_err(io)?;
+    Ok(summary)
+}
+
+/// Import NDJSON events of `table_id` (or of every table) into `log`,
+/// validating them line by line.
+///
+/// Blank lines are ignored. A sealed event whose version and hash the
+/// log already holds is skipped. An event of another table than
+/// `table_id` is rejected.
+pub fn import_ndjson<S: MetadataLogStore, R: BufRead>(
+    log: &mut MetadataLog<S>,
+    table_id: Option<&TableId>,
+    input: R,
+) -> Result<TransferSummary, TransferError> {
+    let mut summary = TransferSummary::default();
+
+    for (index, line) in input.lines().enumerate() {
+        let line_no = index as u64 + 1;
+        let line = line.map_err(io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = Entry::parse(&line).map_err(|e| TransferError::Malformed {
+            line: line_no,
+            reason: e.to_string(),
+        })?;
+
+        if let Some(expected) = table_id {
+            let found = &entry.event().table_id;
+            if found != expected {
+                return Err(TransferError::WrongTable {
+                    line: line_no,
+                    table_id: found.clone(),
+                    expected: expected.clone(),
+                });
+            }
+        }
+
+        let written = import_entry(log, &entry).map_err(|error| TransferError::Rejected {
+            line: line_no,
+            error,
+        })?;
+        summary.record(entry.event(), written);
+    }
+
+    Ok(summary)
+}
+
+/// Copy every table of `source` into `target`, verifying each event on
+/// the way. Works between any two backends; tables the target already
+/// holds resume after its head.
+pub fn migrate<S: MetadataLogStore, T: MetadataLogStore>(
+    source: &MetadataLog<S>,
+    target: &mut MetadataLog<T>,
+) -> Result<TransferSummary, TransferError> {
+    let mut summary = TransferSummary::default();
+
+    for_each_entry(source, None, |entry| {
+        let written = import_entry(target, &entry)?;
+        summary.record(entry.event(), written);
+        Ok(())
+    })?;
+
+    Ok(summary)
+}
+
+/// Stream the verified events of one or all tables into `f`, each
+/// compacted table starting from its compaction record.
+fn for_each_entry<S: MetadataLogStore>(
+    log: &MetadataLog<S>,
+    table_id: Option<&TableId>,
+    mut f: impl FnMut(Entry) -> Result<(), TransferError>,
+) -> Result<(), TransferError> {
+    let tables = match table_id {
+        Some(id) => vec![id.clone()],
+        None => log.tables()?,
+    };
+
+    for table_id in &tables {
+        let mut from = 1;
+        if let Some((compaction, anchor)) = log.compacted_start(table_id)? {
+            from = anchor.version + 1;
+            f(Entry::Start(CompactedStart {
+                compaction: Box::new(compaction),
+                anchor,
+            }))?;
+        }
+        for event in log.stream(table_id, VersionRange::starting_at(from))? {
+            f(Entry::Event(event?))?;
+        }
+    }
+    Ok(())
+}
+
+/// Import one entry; `false` if the log already holds it.
+fn import_entry<S: MetadataLogStore>(
+    log: &mut MetadataLog<S>,
+    entry: &Entry,
+) -> Result<bool, LogError> {
+    let event = entry.event();
+    if event.hash.is_some()
+        && event.version <= log.current_version(&event.table_id)?
+        && log.hash_at(&event.table_id, event.version)? == event.hash
+    {
+        return Ok(false);
+    }
+
+    match entry {
+        Entry::Start(start) => log.import_compaction(&start.compaction, &start.anchor)?,
+        Entry::Event(event) => {
+            log.import(event.clone())?;
+        }
+    }
+    Ok(true)
+}
+
+fn io(e: impl std::fmt::Display) -> TransferError {
+    TransferError::Io(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invariants::InvariantEngine;
+    use crate::log::payload::{
+        MutationEndedPayload, MutationKind, MutationStartedPayload, Schema, TableCreatedPayload,
+    };
+    use crate::log::{
+        ActorId, EventSigner, EventType, InMemoryCompactionStore, InMemoryLogStore,
+        SegmentedLogStore, TypedPayload,
+    };
+    use crate::replay::compaction::compact;
+    use crate::replay::replay_table_state;
+    use std::io::Cursor;
+    use uuid::Uuid;
+
+    fn table(n: u128) -> TableId {
+        TableId(Uuid::from_u128(n))
+    }
+
+    fn event(table_id: &TableId, version: Version) -> TableEvent {
+        TableEvent::new(table_id.clone(), version, EventType::SnapshotAdded, vec![])
+    }
+
+    fn source() -> MetadataLog<InMemoryLogStore> {
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        for v in 1..=3 {
+            log.append(event(&table(2), v)).unwrap();
+            log.append(event(&table(1), v)).unwrap();
+        }
+        log
+    }
+
+    fn export(log: &MetadataLog<InMemoryLogStore>) -> String {
+        let mut out = Vec::new();
+        export_ndjson(log, None, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn import(ndjson: &str) -> Result<TransferSummary, TransferError> {
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        import_ndjson(&mut log, None, Cursor::new(ndjson))
+    }
+
+    /// `ndjson` with line `n` (1-based) replaced.
+    fn replace_line(ndjson: &str, n: usize, line: &str) -> String {
+        let mut lines: Vec<_> = ndjson.lines().collect();
+        lines[n - 1] = line;
+        lines.join("\n")
+    }
+
+    #[test]
+    fn export_is_one_event_per_line_in_table_order() {
+        let ndjson = export(&source());
+        let events: Vec<TableEvent> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let order: Vec<_> = events
+            .iter()
+            .map(|e| (e.table_id.clone(), e.version))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (table(1), 1),
+                (table(1), 2),
+                (table(1), 3),
+                (table(2), 1),
+                (table(2), 2),
+                (table(2), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn import_round_trips_hashes_and_resumes() {
+        let ndjson = export(&source());
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+
+        let summary = import_ndjson(&mut log, None, Cursor::new(&ndjson)).unwrap();
+        assert_eq!(summary.events, 6);
+        assert_eq!(
+            summary.heads,
+            BTreeMap::from([(table(1), 3), (table(2), 3)])
+        );
+        assert_eq!(export(&log), ndjson);
+
+        // Importing the same file again changes nothing.
+        let again = import_ndjson(&mut log, None, Cursor::new(&ndjson)).unwrap();
+        assert_eq!((again.events, again.skipped), (0, 6));
+    }
+
+    #[test]
+    fn import_reports_the_failing_line() {
+        let ndjson = export(&source());
+        let line = |n: usize| ndjson.lines().nth(n - 1).unwrap().to_string();
+
+        let err = import(&replace_line(&ndjson, 2, "{not json")).unwrap_err();
+        assert!(
+            matches!(err, TransferError::Malformed { line: 2, .. }),
+            "{err}"
+        );
+
+        let bad_table = line(3).replace(&table(1).to_string(), "not-a-uuid");
+        let err = import(&replace_line(&ndjson, 3, &bad_table)).unwrap_err();
+        assert!(
+            matches!(err, TransferError::Malformed { line: 3, .. }),
+            "{err}"
+        );
+
+        // Version 2 of table 1 is missing.
+        let gap = format!("{}\n{}", line(1), line(3));
+        assert_eq!(
+            import(&gap).unwrap_err(),
+            TransferError::Rejected {
+                line: 2,
+                error: LogError::VersionConflict {
+                    expected: 2,
+                    actual: 3
+                }
+            }
+        );
+
+        let edited = line(2).replace("\"version\":2", "\"version\":2,\"signature\":\"00\"");
+        let err = import(&replace_line(&ndjson, 2, &edited)).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                TransferError::Rejected {
+                    line: 2,
+                    error: LogError::Tampered { version: 2, .. }
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn single_table_imports_reject_other_tables() {
+        let mut only_one = Vec::new();
+        export_ndjson(&source(), Some(&table(1)), &mut only_one).unwrap();
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        let summary = import_ndjson(&mut log, Some(&table(1)), Cursor::new(&only_one)).unwrap();
+        assert_eq!(summary.events, 3);
+
+        // Table 1 comes first; the first event of table 2 is line 4.
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        let err =
+            import_ndjson(&mut log, Some(&table(1)), Cursor::new(export(&source()))).unwrap_err();
+        assert_eq!(
+            err,
+            TransferError::WrongTable {
+                line: 4,
+                table_id: table(2),
+                expected: table(1),
+            }
+        );
+    }
+
+    #[test]
+    fn import_validates_payloads() {
+        let created = r#"{"table_id":"00000000-0000-0000-0000-000000000001","version":1,"event_type":"SnapshotAdded","payload":{"type":"table_created","schema":{"schema_id":0,"fields":[]}}}"#;
+        let err = import(&format!("\n{created}\n")).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                TransferError::Rejected {
+                    line: 2,
+                    error: LogError::InvalidPayload { .. }
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn unsealed_events_are_sealed_on_import() {
+        let raw = serde_json::to_string(&event(&table(1), 1)).unwrap();
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        import_ndjson(&mut log, None, Cursor::new(raw)).unwrap();
+
+        assert!(log.replay(&table(1)).unwrap()[0].hash.is_some());
+    }
+
+    #[test]
+    fn migrate_copies_every_table_between_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source();
+        let mut target = MetadataLog::new(SegmentedLogStore::open(dir.path()).unwrap());
+
+        let summary = migrate(&source, &mut target).unwrap();
+        assert_eq!(summary.events, 6);
+
+        let target = MetadataLog::new(SegmentedLogStore::open(dir.path()).unwrap());
+        for t in [table(1), table(2)] {
+            assert_eq!(
+                serde_json::to_value(target.replay(&t).unwrap()).unwrap(),
+                serde_json::to_value(source.replay(&t).unwrap()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn compacted_tables_transfer_from_their_compaction_record() {
+        let invariants = InvariantEngine::new();
+        let with_records = |store| {
+            MetadataLog::new(store).with_compaction_store(InMemoryCompactionStore::default())
+        };
+
+        let t = table(1);
+        let started = |id: &str| {
+            TypedPayload::MutationStarted(MutationStartedPayload {
+                mutation_id: id.into(),
+                kind: MutationKind::Append,
+                writer: ActorId("spark".into()),
+            })
+        };
+        let ended = |id: &str| MutationEndedPayload {
+            mutation_id: id.into(),
+            reason: None,
+        };
+        let payloads = [
+            TypedPayload::TableCreated(TableCreatedPayload {
+                schema: Schema {
+                    schema_id: 0,
+                    fields: vec![],
+                },
+                location: None,
+                properties: Default::default(),
+            }),
+            started("m-1"),
+            TypedPayload::MutationCommitted(ended("m-1")),
+            started("m-2"),
+            TypedPayload::MutationAborted(ended("m-2")),
+            started("m-3"),
+        ];
+
+        let mut source = with_records(InMemoryLogStore::default());
+        for (version, payload) in (1..).zip(payloads) {
+            let event = TableEvent::new(t.clone(), version, payload.event_type(), payload);
+            source.append(event).unwrap();
+        }
+        let signer = EventSigner::from_seed(ActorId("compactor".into()), &[3; 32]);
+        let mut archive = InMemoryLogStore::default();
+        compact(&mut source, &t, 3, &invariants, &mut archive, &signer).unwrap();
+        let state = replay_table_state(&source, &t, &invariants).unwrap();
+
+        let mut target = with_records(InMemoryLogStore::default());
+        let summary = migrate(&source, &mut target).unwrap();
+        assert_eq!(summary.events, 4);
+        assert_eq!(summary.heads, BTreeMap::from([(t.clone(), 6)]));
+        assert_eq!(replay_table_state(&target, &t, &invariants).unwrap(), state);
+
+        let ndjson = export(&source);
+        assert!(ndjson.starts_with("{\"compaction\":"));
+        let mut imported = with_records(InMemoryLogStore::default());
+        import_ndjson(&mut imported, None, Cursor::new(&ndjson)).unwrap();
+        assert_eq!(export(&imported), ndjson);
+        let again = import_ndjson(&mut imported, None, Cursor::new(&ndjson)).unwrap();
+        assert_eq!((again.events, again.skipped), (0, 4));
+
+        // The record has nowhere to go without a compaction store.
+        let mut plain = MetadataLog::new(InMemoryLogStore::default());
+        assert!(matches!(
+            migrate(&source, &mut plain),
+            Err(TransferError::Log(LogError::Storage(_)))
+        ));
+        assert_eq!(plain.current_version(&t).unwrap(), 0);
+    }
+}
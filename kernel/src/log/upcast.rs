@@ -0,0 +1,418 @@
+// Event Format Versions & Upcasters
+//
+// Every serialized event carries a `format_version`. Events written by
+// older versions of Axiom are upcast to the current format while they
+// are decoded, so stores, replay and tooling only ever see current
+// events.
+//
+// Format history:
+//
+//   1  No `format_version` field. The signer's `actor` is a top-level
+//      field (later versions of format 1 also carry an `envelope`).
+//   2  `actor` lives in the envelope; `format_version` is explicit.
+//   3  Schema, snapshot and property changes reference the mutation they
+//      belong to. Older changes that reference none are marked `legacy`,
+//      and only those may toggle an implicit mutation on replay.
+//
+// Upcasters only move content around: hashes and signatures are computed
+// over the canonical encoding, which must not change, so old chains keep
+// verifying after upcasting.
+//
+// Changing the serialized shape of `TableEvent` requires bumping
+// `CURRENT_FORMAT_VERSION`, registering an upcaster from the previous
+// version, and adding a golden file under `testdata/events`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{EventEnvelope, EventPayload, EventType, TableEvent, TableId, Version};
+
+/// Format version written by this version of Axiom.
+pub const CURRENT_FORMAT_VERSION: u32 = 3;
+
+/// Format assumed for events without a `format_version` field.
+const UNVERSIONED: u32 = 1;
+
+/// Transforms an event from `from_version` to `from_version + 1`.
+pub struct Upcaster {
+    pub from_version: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut Map<String, Value>) -> Result<(), String>,
+}
+
+/// Registered upcasters, one per historical format.
+pub static UPCASTERS: &[Upcaster] = &[
+    Upcaster {
+        from_version: 1,
+        description: "move the top-level actor into the envelope",
+        apply: actor_into_envelope,
+    },
+    Upcaster {
+        from_version: 2,
+        description: "mark changes that reference no mutation as legacy",
+        apply: mark_legacy_changes,
+    },
+];
+
+/// Upcast a serialized event to `CURRENT_FORMAT_VERSION`.
+pub fn upcast(mut value: Value) -> Result<Value, String> {
+    let event = value.as_object_mut().ok_or("event is not a JSON object")?;
+
+    let mut format = match event.get("format_version") {
+        None => UNVERSIONED,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| format!("invalid format_version {v}"))?,
+    };
+
+    if format > CURRENT_FORMAT_VERSION {
+        return Err(format!(
+            "event format version {format} is newer than supported version {CURRENT_FORMAT_VERSION}"
+        ));
+    }
+
+    while format < CURRENT_FORMAT_VERSION {
+        let upcaster = UPCASTERS
+            .iter()
+            .find(|u| u.from_version == format)
+            .ok_or_else(|| format!("no upcaster from event format version {format}"))?;
+
+        (upcaster.apply)(event)
+            .map_err(|e| format!("upcasting from format version {format}: {e}"))?;
+        format += 1;
+    }
+
+    event.insert("format_version".into(), format.into());
+    Ok(value)
+}
+
+fn actor_into_envelope(event: &mut Map<String, Value>) -> Result<(), String> {
+    let Some(actor) = event.remove("actor") else {
+        return Ok(());
+    };
+
+    let envelope = event
+        .entry("envelope")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or("envelope is not an object")?;
+
+    match envelope.get("actor") {
+        Some(existing) if *existing != actor => {
+            Err(format!("conflicting actors {actor} and {existing}"))
+        }
+        _ => {
+            envelope.insert("actor".into(), actor);
+            Ok(())
+        }
+    }
+}
+
+fn mark_legacy_changes(event: &mut Map<String, Value>) -> Result<(), String> {
+    let is_change = matches!(
+        event.get("event_type").and_then(Value::as_str),
+        Some("SchemaUpdated" | "SnapshotAdded" | "SnapshotRemoved" | "PropertiesUpdated")
+    );
+    let referenced = event
+        .get("payload")
+        .and_then(|p| p.get("mutation_id"))
+        .is_some_and(Value::is_string);
+
+    if is_change && !referenced {
+        event.insert("legacy".into(), true.into());
+    }
+    Ok(())
+}
+
+/// Serialized shape of the current event format.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EventRecord {
+    format_version: u32,
+    table_id: TableId,
+    version: Version,
+    event_type: EventType,
+    payload: EventPayload,
+
+    #[serde(default, skip_serializing_if = "EventEnvelope::is_empty")]
+    envelope: EventEnvelope,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    legacy: bool,
+}
+
+impl From<TableEvent> for EventRecord {
+    fn from(event: TableEvent) -> Self {
+        Self {
+            format_version: CURRENT_FORMAT_VERSION,
+            table_id: event.table_id,
+            version: event.version,
+            event_type: event.event_type,
+            payload: event.payload,
+            envelope: event.envelope,
+            signature: event.signature,
+            hash: event.hash,
+            legacy: event.legacy,
+        }
+    }
+}
+
+impl TryFrom<Value> for TableEvent {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let record: EventRecord =
+            serde_json::from_value(upcast(value)?).map_err(|e| e.to_string())?;
+
+        Ok(TableEvent {
+            table_id: record.table_id,
+            version: record.version,
+            event_type: record.event_type,
+            payload: record.payload,
+            envelope: record.envelope,
+            signature: record.signature,
+            hash: record.hash,
+            legacy: record.legacy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invariants::InvariantEngine;
+    use crate::log::{
+        ActorId, EngineKind, EventSigner, InMemoryLogStore, LogError, MetadataLog,
+        MetadataLogStore, TrustStore, TypedPayload,
+    };
+    use crate::replay::replay_table_state;
+    use crate::state::TableState;
+    use serde_json::json;
+
+    /// Every historical format, as written by the Axiom version that
+    /// introduced it.
+    const GOLDEN: &[(&str, &str)] = &[
+        (
+            "format-1-plain",
+            include_str!("../../testdata/events/format-1-plain.json"),
+        ),
+        (
+            "format-1-chained",
+            include_str!("../../testdata/events/format-1-chained.json"),
+        ),
+        (
+            "format-1-signed",
+            include_str!("../../testdata/events/format-1-signed.json"),
+        ),
+        (
+            "format-1-enveloped",
+            include_str!("../../testdata/events/format-1-enveloped.json"),
+        ),
+        (
+            "format-2",
+            include_str!("../../testdata/events/format-2.json"),
+        ),
+        (
+            "format-3",
+            include_str!("../../testdata/events/format-3.json"),
+        ),
+    ];
+
+    fn golden(name: &str) -> Vec<TableEvent> {
+        let (_, json) = GOLDEN.iter().find(|(n, _)| *n == name).unwrap();
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn trust() -> TrustStore {
+        let signer = EventSigner::from_seed(ActorId("spark-etl".into()), &[7; 32]);
+        let mut trust = TrustStore::new();
+        trust.trust(signer.actor().clone(), signer.verifying_key());
+        trust
+    }
+
+    /// Load events into a store as-is and replay them through the log.
+    fn replay(events: &[TableEvent], trust: Option<TrustStore>) -> Result<usize, LogError> {
+        let mut store = InMemoryLogStore::default();
+        for event in events {
+            store.append(event).unwrap();
+        }
+
+        let mut log = MetadataLog::new(store);
+        if let Some(trust) = trust {
+            log = log.with_trust_store(trust);
+        }
+        Ok(log.replay(&events[0].table_id)?.len())
+    }
+
+    /// Load events into a store and replay them into a table state.
+    ///
+    /// Events from before hash chains are imported through the log, which
+    /// chains them; the others are loaded as-is.
+    fn replay_state(events: &[TableEvent], trust: Option<TrustStore>) -> TableState {
+        let mut store = InMemoryLogStore::default();
+        let chained = events.iter().all(|e| e.hash.is_some());
+        if chained {
+            for event in events {
+                store.append(event).unwrap();
+            }
+        }
+
+        let mut log = MetadataLog::new(store);
+        if !chained {
+            for event in events {
+                log.import(event.clone()).unwrap();
+            }
+        }
+        if let Some(trust) = trust {
+            log = log.with_trust_store(trust);
+        }
+        replay_table_state(&log, &events[0].table_id, &InvariantEngine::new()).unwrap()
+    }
+
+    #[test]
+    fn every_historical_format_has_an_upcaster() {
+        for format in UNVERSIONED..CURRENT_FORMAT_VERSION {
+            let count = UPCASTERS
+                .iter()
+                .filter(|u| u.from_version == format)
+                .count();
+            assert_eq!(count, 1, "upcasters from format {format}");
+        }
+    }
+
+    #[test]
+    fn golden_files_decode() {
+        for (name, json) in GOLDEN {
+            let events: Vec<TableEvent> = serde_json::from_str(json)
+                .unwrap_or_else(|e| panic!("{name} does not decode: {e}"));
+            assert!(!events.is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn old_chains_still_verify() {
+        assert_eq!(replay(&golden("format-1-chained"), None), Ok(3));
+        assert_eq!(replay(&golden("format-1-signed"), Some(trust())), Ok(3));
+        assert_eq!(replay(&golden("format-1-enveloped"), Some(trust())), Ok(5));
+        assert_eq!(replay(&golden("format-2"), Some(trust())), Ok(5));
+        assert_eq!(replay(&golden("format-3"), Some(trust())), Ok(5));
+    }
+
+    #[test]
+    fn old_toggle_logs_still_replay() {
+        let expected = [
+            // Left mid-toggle
+            ("format-1-plain", None, TableState::Mutating),
+            ("format-1-chained", None, TableState::Active),
+            ("format-1-signed", Some(trust()), TableState::Active),
+            ("format-1-enveloped", Some(trust()), TableState::Active),
+            ("format-2", Some(trust()), TableState::Active),
+        ];
+        assert_eq!(expected.len(), GOLDEN.len() - 1);
+
+        for (name, trust, state) in expected {
+            let events = golden(name);
+            assert!(events.iter().any(|e| e.legacy), "{name}");
+            assert_eq!(replay_state(&events, trust), state, "{name}");
+        }
+    }
+
+    #[test]
+    fn only_old_unreferenced_changes_are_legacy() {
+        let legacy: Vec<_> = golden("format-2")
+            .iter()
+            .filter(|e| e.legacy)
+            .map(|e| e.version)
+            .collect();
+        assert_eq!(legacy, vec![2, 3, 4, 5]);
+        assert!(golden("format-3").iter().all(|e| !e.legacy));
+    }
+
+    #[test]
+    fn current_changes_must_reference_their_mutation() {
+        let mut events = golden("format-3");
+        let change = TableEvent {
+            version: 2,
+            signature: None,
+            hash: None,
+            ..events.remove(3)
+        };
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.import(events.remove(0)).unwrap();
+
+        let mut unreferenced = change.clone();
+        if let EventPayload::Typed(TypedPayload::SnapshotAdded(p)) = &mut unreferenced.payload {
+            p.mutation_id = None;
+        }
+        assert!(matches!(
+            log.append(unreferenced.clone()),
+            Err(LogError::InvalidPayload { .. })
+        ));
+
+        // Nor may writers pass a change off as one of an old log.
+        let legacy = TableEvent {
+            legacy: true,
+            ..unreferenced
+        };
+        assert!(matches!(
+            log.append(legacy),
+            Err(LogError::InvalidPayload { .. })
+        ));
+
+        // Opaque payloads are the log's to store, but replay rejects them.
+        let opaque = TableEvent {
+            payload: EventPayload::Opaque(vec![1]),
+            ..change
+        };
+        log.append(opaque).unwrap();
+        let table_id = &golden("format-3")[0].table_id;
+        assert!(replay_table_state(&log, table_id, &InvariantEngine::new()).is_err());
+    }
+
+    #[test]
+    fn top_level_actor_moves_into_envelope() {
+        let events = golden("format-1-signed");
+        assert!(events
+            .iter()
+            .all(|e| e.envelope.actor == Some(ActorId("spark-etl".into()))));
+
+        let conflicting = json!({
+            "table_id": "550e8400-e29b-41d4-a716-446655440000",
+            "version": 1,
+            "event_type": "TableCreated",
+            "payload": [],
+            "actor": "alice",
+            "envelope": { "actor": "bob" }
+        });
+        assert!(TableEvent::try_from(conflicting).is_err());
+    }
+
+    #[test]
+    fn current_format_round_trips_byte_for_byte() {
+        let (_, json) = GOLDEN.iter().find(|(n, _)| *n == "format-3").unwrap();
+        let events = golden("format-3");
+
+        assert_eq!(events[0].envelope.engine, Some(EngineKind::Spark));
+        assert_eq!(serde_json::to_string_pretty(&events).unwrap() + "\n", *json);
+    }
+
+    #[test]
+    fn newer_formats_are_rejected() {
+        let future = json!({
+            "format_version": CURRENT_FORMAT_VERSION + 1,
+            "table_id": "550e8400-e29b-41d4-a716-446655440000",
+            "version": 1,
+            "event_type": "TableCreated",
+            "payload": []
+        });
+
+        let err = TableEvent::try_from(future).unwrap_err();
+        assert!(err.contains("newer than supported"), "{err}");
+    }
+}
@@ -0,0 +1,630 @@
+// State Checkpoints & Incremental Replay
+//
+// A checkpoint records the state derived after applying a given version,
+// together with the fingerprint of the invariant set that was enforced
+// while deriving it and the chain hash of the event at that version.
+// Replay resumes from the latest checkpoint that is still valid and only
+// applies newer events. The hash ties a checkpoint to the history it was
+// derived from: once the log is re-imported, restored or rewritten, its
+// checkpoints no longer match and are ignored.
+//
+// Checkpoints are an optimization, never a source of truth: they can
+// always be discarded and re-derived from the log.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::compaction::compaction_base;
+use super::{replay_range, ReplayError};
+use crate::invariants::InvariantEngine;
+use crate::log::{MetadataLog, MetadataLogStore, TableId, Version, VersionRange};
+use crate::state::{TableState, TableStateMachine};
+
+/// Derived table state at a specific log version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub table_id: TableId,
+
+    /// Last version applied to produce `machine`.
+    pub version: Version,
+
+    /// State machine to resume replay from: the derived state and
+    /// whatever else the next transitions depend on.
+    #[serde(flatten)]
+    pub machine: TableStateMachine,
+
+    /// `InvariantEngine::fingerprint` of the invariants enforced.
+    pub invariant_set: String,
+
+    /// Chain hash of the event at `version`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// Errors produced by checkpoint storage or verification.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CheckpointError {
+    #[error("checkpoint storage error: {0}")]
+    Storage(String),
+
+    #[error("checkpoint at version {version} does not match replay: recorded {recorded:?}, derived {derived:?}")]
+    Mismatch {
+        version: Version,
+        recorded: Box<TableStateMachine>,
+        derived: Box<TableStateMachine>,
+    },
+}
+
+/// Persistence for checkpoints.
+pub trait CheckpointStore {
+    /// Persist a checkpoint, replacing any existing one at the same version.
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), CheckpointError>;
+
+    /// Versions of a table's checkpoints, in ascending order.
+    fn versions(&self, table_id: &TableId) -> Result<Vec<Version>, CheckpointError>;
+
+    /// The checkpoint of a table at `version`.
+    fn load(&self, table_id: &TableId, version: Version) -> Result<Checkpoint, CheckpointError>;
+
+    /// All readable checkpoints of a table, in ascending version order.
+    fn list(&self, table_id: &TableId) -> Result<Vec<Checkpoint>, CheckpointError> {
+        Ok(self
+            .versions(table_id)?
+            .into_iter()
+            .filter_map(|version| self.load(table_id, version).ok())
+            .collect())
+    }
+}
+
+/// How incremental replay uses checkpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointOptions {
+    /// Write a checkpoint whenever the applied version is a multiple of
+    /// this interval. Zero disables writing checkpoints.
+    pub interval: Version,
+
+    /// Re-derive state from scratch and compare it against the checkpoint
+    /// that would have been resumed from.
+    pub verify: bool,
+}
+
+impl Default for CheckpointOptions {
+    fn default() -> Self {
+        Self {
+            interval: 1000,
+            verify: false,
+        }
+    }
+}
+
+/// Replay a table, resuming from the latest valid checkpoint.
+///
+/// A checkpoint is valid when it can be read, was derived under the same
+/// invariant set, does not lie beyond the current head of the log and
+/// records the hash the log holds at its version. New checkpoints are
+/// written according to `options.interval`.
+pub fn replay_with_checkpoints<S: MetadataLogStore, C: CheckpointStore>(
+    log: &MetadataLog<S>,
+    table_id: &TableId,
+    invariants: &InvariantEngine,
+    checkpoints: &mut C,
+    options: &CheckpointOptions,
+) -> Result<TableState, ReplayError> {
+    let invariant_set = invariants.fingerprint();
+    let head = log.current_version(table_id)?;
+
+    let (base, base_version) = compaction_base(log, table_id, invariants)?;
+
+    // Checkpoints older than the latest compaction are not worth resuming.
+    let mut resume_from = None;
+    for version in checkpoints.versions(table_id)?.into_iter().rev() {
+        if version > head {
+            continue;
+        }
+        if version <= base_version {
+            break;
+        }
+        // Unreadable checkpoints are re-derived like missing ones.
+        let Ok(checkpoint) = checkpoints.load(table_id, version) else {
+            continue;
+        };
+        if checkpoint.invariant_set == invariant_set
+            && checkpoint.hash == log.hash_at(table_id, version)?
+        {
+            resume_from = Some(checkpoint);
+            break;
+        }
+    }
+
+    if options.verify {
+        if let Some(checkpoint) = &resume_from {
+            verify_checkpoint(log, invariants, checkpoint)?;
+        }
+    }
+
+    let (initial, after) = match resume_from {
+        Some(checkpoint) => (checkpoint.machine, checkpoint.version),
+        None => (base, base_version),
+    };
+
+    let (machine, _) = replay_range(
+        log,
+        table_id,
+        invariants,
+        initial,
+        VersionRange::starting_at(after + 1),
+        |event, machine| {
+            if options.interval > 0 && event.version % options.interval == 0 {
+                checkpoints.save(&Checkpoint {
+                    table_id: table_id.clone(),
+                    version: event.version,
+                    machine: machine.clone(),
+                    invariant_set: invariant_set.clone(),
+                    hash: event.hash.clone(),
+                })?;
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(machine.current_state().clone())
+}
+
+/// Re-derive state from scratch (or from the latest compaction record)
+/// up to the checkpoint's version and check that it matches the recorded
+/// state.
+pub fn verify_checkpoint<S: MetadataLogStore>(
+    log: &MetadataLog<S>,
+    invariants: &InvariantEngine,
+    checkpoint: &Checkpoint,
+) -> Result<(), ReplayError> {
+    let (initial, after) = match compaction_base(log, &checkpoint.table_id, invariants)? {
+        (machine, version) if version <= checkpoint.version => (machine, version),
+        _ => (TableStateMachine::new(), 0),
+    };
+
+    let (derived, _) = replay_range(
+        log,
+        &checkpoint.table_id,
+        invariants,
+        initial,
+        VersionRange::between(after + 1, checkpoint.version),
+        |_, _| Ok(()),
+    )?;
+
+    if derived != checkpoint.machine {
+        return Err(CheckpointError::Mismatch {
+            version: checkpoint.version,
+            recorded: Box::new(checkpoint.machine.clone()),
+            derived: Box::new(derived),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// In-memory checkpoint store.
+#[derive(Debug, Default)]
+pub struct InMemoryCheckpointStore {
+    checkpoints: BTreeMap<TableId, BTreeMap<Version, Checkpoint>>,
+}
+
+impl CheckpointStore for InMemoryCheckpointStore {
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        self.checkpoints
+            .entry(checkpoint.table_id.clone())
+            .or_default()
+            .insert(checkpoint.version, checkpoint.clone());
+        Ok(())
+    }
+
+    fn versions(&self, table_id: &TableId) -> Result<Vec<Version>, CheckpointError> {
+        Ok(self
+            .checkpoints
+            .get(table_id)
+            .map(|c| c.keys().copied().collect())
+            .unwrap_or_default())
+    }
+
+    fn load(&self, table_id: &TableId, version: Version) -> Result<Checkpoint, CheckpointError> {
+        self.checkpoints
+            .get(table_id)
+            .and_then(|c| c.get(&version))
+            .cloned()
+            .ok_or_else(|| missing(table_id, version))
+    }
+}
+
+/// Directory-backed checkpoint store.
+///
+/// Layout: `<dir>/<table>/<version, zero padded>.json`. Files are written
+/// to a temporary name and renamed, so a crash never leaves a partially
+/// written checkpoint behind.
+#[derive(Debug)]
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, CheckpointError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage)?;
+        Ok(Self { dir })
+    }
+
+    fn table_dir(&self, table_id: &TableId) -> PathBuf {
+        self.dir.join(table_id.to_string())
+    }
+
+    fn path(&self, table_id: &TableId, version: Version) -> PathBuf {
+        self.table_dir(table_id).join(format!("{version:020}.json"))
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        let dir = self.table_dir(&checkpoint.table_id);
+        fs::create_dir_all(&dir).map_err(storage)?;
+
+        let path = self.path(&checkpoint.table_id, checkpoint.version);
+        let tmp = path.with_extension("json.tmp");
+
+        let body = serde_json::to_vec_pretty(checkpoint).map_err(storage)?;
+        let mut file = File::create(&tmp).map_err(storage)?;
+        file.write_all(&body).map_err(storage)?;
+        file.sync_all().map_err(storage)?;
+        fs::rename(&tmp, &path).map_err(storage)?;
+
+        Ok(())
+    }
+
+    /// Versions are read from file names; no checkpoint is decoded.
+    fn versions(&self, table_id: &TableId) -> Result<Vec<Version>, CheckpointError> {
+        let dir = self.table_dir(table_id);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut versions = Vec::new();
+        for entry in fs::read_dir(&dir).map_err(storage)? {
+            let path = entry.map_err(storage)?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(version) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    fn load(&self, table_id: &TableId, version: Version) -> Result<Checkpoint, CheckpointError> {
+        let data = fs::read(self.path(table_id, version)).map_err(storage)?;
+        let checkpoint: Checkpoint = serde_json::from_slice(&data).map_err(storage)?;
+        if checkpoint.table_id != *table_id || checkpoint.version != version {
+            return Err(missing(table_id, version));
+        }
+        Ok(checkpoint)
+    }
+}
+
+fn storage<E: std::fmt::Display>(e: E) -> CheckpointError {
+    CheckpointError::Storage(e.to_string())
+}
+
+fn missing(table_id: &TableId, version: Version) -> CheckpointError {
+    CheckpointError::Storage(format!(
+        "no checkpoint of table {table_id} at version {version}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invariants::{Invariant, InvariantResult};
+    use crate::log::payload::{
+        MutationEndedPayload, MutationKind, MutationStartedPayload, SnapshotAddedPayload,
+        SnapshotOperation,
+    };
+    use crate::log::{ActorId, EventType, InMemoryLogStore, TableEvent, TypedPayload};
+    use crate::replay::replay_table_state;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    /// Counts how many transitions were evaluated.
+    struct CountingInvariant(Arc<AtomicUsize>);
+
+    impl Invariant for CountingInvariant {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn validate(&self, _: &TableState, _: &TableEvent, _: &TableState) -> InvariantResult {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            InvariantResult::Pass
+        }
+    }
+
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    fn log_with(versions: u64) -> MetadataLog<InMemoryLogStore> {
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.append(TableEvent::new(table(), 1, EventType::TableCreated, vec![]))
+            .unwrap();
+        extend(&mut log, 2..=versions);
+        log
+    }
+
+    /// Append three-event mutations (start, snapshot, commit) starting at
+    /// versions 2, 5, 8, ...
+    fn extend(log: &mut MetadataLog<InMemoryLogStore>, versions: std::ops::RangeInclusive<u64>) {
+        for version in versions {
+            let mutation_id = format!("m-{}", version - (version + 1) % 3);
+            let payload = match version % 3 {
+                2 => TypedPayload::MutationStarted(MutationStartedPayload {
+                    mutation_id,
+                    kind: MutationKind::Append,
+                    writer: ActorId("spark".into()),
+                }),
+                0 => TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                    snapshot_id: version as i64,
+                    parent_snapshot_id: None,
+                    operation: SnapshotOperation::Append,
+                    schema_id: None,
+                    mutation_id: Some(mutation_id),
+                    branch: None,
+                }),
+                _ => TypedPayload::MutationCommitted(MutationEndedPayload {
+                    mutation_id,
+                    reason: None,
+                }),
+            };
+            log.append(TableEvent::new(
+                table(),
+                version,
+                payload.event_type(),
+                payload,
+            ))
+            .unwrap();
+        }
+    }
+
+    fn counting_engine() -> (InvariantEngine, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut engine = InvariantEngine::new();
+        engine.register(CountingInvariant(count.clone()));
+        (engine, count)
+    }
+
+    fn every(interval: Version) -> CheckpointOptions {
+        CheckpointOptions {
+            interval,
+            verify: false,
+        }
+    }
+
+    #[test]
+    fn incremental_replay_matches_full_replay() {
+        let log = log_with(10);
+        let (invariants, _) = counting_engine();
+        let mut checkpoints = InMemoryCheckpointStore::default();
+
+        let full = replay_table_state(&log, &table(), &invariants).unwrap();
+        let incremental =
+            replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &every(4))
+                .unwrap();
+
+        assert_eq!(full, incremental);
+        let versions: Vec<_> = checkpoints
+            .list(&table())
+            .unwrap()
+            .iter()
+            .map(|c| c.version)
+            .collect();
+        assert_eq!(versions, vec![4, 8]);
+    }
+
+    #[test]
+    fn replay_resumes_after_latest_checkpoint() {
+        let mut log = log_with(8);
+        let (invariants, count) = counting_engine();
+        let mut checkpoints = InMemoryCheckpointStore::default();
+
+        replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &every(4)).unwrap();
+        assert_eq!(count.swap(0, Ordering::SeqCst), 8);
+
+        extend(&mut log, 9..=11);
+        let state =
+            replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &every(4))
+                .unwrap();
+
+        // Only versions 9..=11 are applied on top of the checkpoint at 8.
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            state,
+            replay_table_state(&log, &table(), &invariants).unwrap()
+        );
+    }
+
+    #[test]
+    fn checkpoint_keeps_the_open_mutation() {
+        let log = log_with(9);
+        let invariants = InvariantEngine::new();
+        let mut checkpoints = InMemoryCheckpointStore::default();
+
+        replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &every(4)).unwrap();
+
+        let latest = checkpoints.list(&table()).unwrap().pop().unwrap();
+        assert_eq!(latest.version, 8);
+        assert_eq!(latest.machine.current_state(), &TableState::Mutating);
+        assert_eq!(latest.machine.open_mutation().unwrap().mutation_id, "m-8");
+        verify_checkpoint(&log, &invariants, &latest).unwrap();
+
+        // The snapshot at 9 is only legal inside the resumed mutation.
+        let options = CheckpointOptions {
+            interval: 0,
+            verify: true,
+        };
+        let state =
+            replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &options)
+                .unwrap();
+        assert_eq!(state, TableState::Mutating);
+
+        let forgotten = Checkpoint {
+            machine: TableStateMachine::resume(TableState::Mutating, None),
+            ..latest
+        };
+        assert!(verify_checkpoint(&log, &invariants, &forgotten).is_err());
+    }
+
+    #[test]
+    fn checkpoint_from_other_invariant_set_is_ignored() {
+        let log = log_with(8);
+        let mut checkpoints = InMemoryCheckpointStore::default();
+
+        replay_with_checkpoints(
+            &log,
+            &table(),
+            &InvariantEngine::new(),
+            &mut checkpoints,
+            &every(4),
+        )
+        .unwrap();
+
+        let (invariants, count) = counting_engine();
+        replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &every(0)).unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn verify_mode_detects_bad_checkpoint() {
+        let log = log_with(8);
+        let invariants = InvariantEngine::new();
+        let mut checkpoints = InMemoryCheckpointStore::default();
+
+        checkpoints
+            .save(&Checkpoint {
+                table_id: table(),
+                version: 4,
+                machine: TableStateMachine::new(),
+                invariant_set: invariants.fingerprint(),
+                hash: log.hash_at(&table(), 4).unwrap(),
+            })
+            .unwrap();
+
+        let options = CheckpointOptions {
+            interval: 0,
+            verify: true,
+        };
+        let err = replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &options)
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            ReplayError::Checkpoint(CheckpointError::Mismatch { version: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn checkpoint_of_a_rewritten_log_is_ignored() {
+        let original = log_with(8);
+        let (invariants, count) = counting_engine();
+        let mut checkpoints = InMemoryCheckpointStore::default();
+        replay_with_checkpoints(
+            &original,
+            &table(),
+            &invariants,
+            &mut checkpoints,
+            &every(4),
+        )
+        .unwrap();
+        count.store(0, Ordering::SeqCst);
+
+        // Same versions, different history.
+        let mut rewritten = MetadataLog::new(InMemoryLogStore::default());
+        rewritten
+            .append(TableEvent::new(
+                table(),
+                1,
+                EventType::TableCreated,
+                vec![1],
+            ))
+            .unwrap();
+        extend(&mut rewritten, 2..=8);
+
+        let state = replay_with_checkpoints(
+            &rewritten,
+            &table(),
+            &invariants,
+            &mut checkpoints,
+            &every(0),
+        )
+        .unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 8);
+        assert_eq!(
+            state,
+            replay_table_state(&rewritten, &table(), &invariants).unwrap()
+        );
+    }
+
+    #[test]
+    fn unreadable_checkpoints_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut checkpoints = FileCheckpointStore::open(dir.path()).unwrap();
+        let log = log_with(10);
+        let (invariants, count) = counting_engine();
+        replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &every(4)).unwrap();
+
+        fs::write(checkpoints.path(&table(), 8), b"{ not json").unwrap();
+        assert_eq!(checkpoints.versions(&table()).unwrap(), vec![4, 8]);
+        assert_eq!(checkpoints.list(&table()).unwrap().len(), 1);
+
+        // Replay resumes from the checkpoint at 4 instead.
+        count.store(0, Ordering::SeqCst);
+        replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &every(0)).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn file_store_round_trips_in_version_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = FileCheckpointStore::open(dir.path()).unwrap();
+
+        for version in [12, 3, 7] {
+            store
+                .save(&Checkpoint {
+                    table_id: table(),
+                    version,
+                    machine: TableStateMachine::resume(TableState::Active, None),
+                    invariant_set: "set".into(),
+                    hash: None,
+                })
+                .unwrap();
+        }
+
+        let reopened = FileCheckpointStore::open(dir.path()).unwrap();
+        let versions: Vec<_> = reopened
+            .list(&table())
+            .unwrap()
+            .iter()
+            .map(|c| c.version)
+            .collect();
+
+        assert_eq!(versions, vec![3, 7, 12]);
+        assert!(reopened.list(&TableId(Uuid::new_v4())).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,706 @@
+// Log Compaction & Archival
+//
+// Long-lived tables accumulate histories that every replay must walk.
+// Compaction replays a table up to a retention boundary, copies the raw
+// events to an archive store and folds them into a signed
+// `CompactionRecord` holding the derived state machine, including its
+// table model, and a summary of the snapshot lineage. The live log then discards the
+// compacted events, keeping only the one at the boundary as the anchor
+// of its hash chain.
+//
+// A compaction boundary cannot fall inside an open mutation, so records
+// never carry one, and only the changes of committed mutations are folded
+// into the lineage.
+//
+// Replay starts from the latest record and only applies newer events,
+// so `replay_table_state` derives the same state before and after
+// compaction. The archive is an ordinary store holding the original,
+// chained events: it can be replayed and verified from version 1.
+
+use super::{replay_range, ReplayError};
+use crate::invariants::InvariantEngine;
+use crate::log::payload::TypedPayload;
+use crate::log::{
+    AppendOutcome, CompactionRecord, EventSigner, LogError, MetadataLog, MetadataLogStore,
+    SnapshotLineage, TableId, Version, VersionRange,
+};
+use crate::state::TableStateMachine;
+
+/// Errors specific to compaction.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CompactionError {
+    #[error("cannot compact table {table_id} through version {through}: already compacted through {compacted}, head is {head}")]
+    InvalidBoundary {
+        table_id: TableId,
+        through: Version,
+        compacted: Version,
+        head: Version,
+    },
+
+    #[error("compaction record of table {table_id} at version {version} was derived under a different invariant set")]
+    InvariantMismatch { table_id: TableId, version: Version },
+
+    #[error("cannot compact table {table_id} through version {through}: mutation {mutation_id} is still open")]
+    OpenMutation {
+        table_id: TableId,
+        through: Version,
+        mutation_id: String,
+    },
+}
+
+/// Compact a table's events `1..=through`.
+///
+/// Continues from the previous compaction, if any: only the events after
+/// it are replayed (enforcing `invariants`) and appended to `archive`.
+/// Events the archive already holds, e.g. from an interrupted attempt,
+/// are not appended again. The archive should not deduplicate by
+/// idempotency key (dedup window 0), since it must keep every event.
+///
+/// Fails if a mutation is open at `through`; the events archived until
+/// then are kept, and a later attempt continues from them.
+///
+/// The record is signed by `signer` and saved to the log's compaction
+/// store before the live store discards the compacted events.
+pub fn compact<S: MetadataLogStore, A: MetadataLogStore>(
+    log: &mut MetadataLog<S>,
+    table_id: &TableId,
+    through: Version,
+    invariants: &InvariantEngine,
+    archive: &mut A,
+    signer: &EventSigner,
+) -> Result<CompactionRecord, ReplayError> {
+    log.check_truncation()?;
+
+    let invariant_set = invariants.fingerprint();
+    let (initial, compacted, mut lineage) = match log.compaction(table_id)? {
+        Some(record) => {
+            check_invariant_set(&record, &invariant_set)?;
+            (record.machine, record.version, record.lineage)
+        }
+        None => (TableStateMachine::new(), 0, SnapshotLineage::default()),
+    };
+
+    let head = log.current_version(table_id)?;
+    if through <= compacted || through > head {
+        return Err(CompactionError::InvalidBoundary {
+            table_id: table_id.clone(),
+            through,
+            compacted,
+            head,
+        }
+        .into());
+    }
+
+    let archived = archive.current_version(table_id)?;
+    let mut hash = None;
+
+    // Changes of the open mutation, folded in once it commits.
+    let mut pending = Vec::new();
+
+    let (machine, _) = replay_range(
+        log,
+        table_id,
+        invariants,
+        initial,
+        VersionRange::between(compacted + 1, through),
+        |event, machine| {
+            if let Some(payload) = event.payload.typed() {
+                match payload {
+                    TypedPayload::MutationCommitted(_) => {
+                        for change in pending.drain(..) {
+                            lineage.record(&change);
+                        }
+                    }
+                    TypedPayload::MutationAborted(_) | TypedPayload::RollbackStarted(_) => {
+                        pending.clear()
+                    }
+                    change if machine.open_mutation().is_some_and(|m| !m.implicit) => {
+                        pending.push(change.clone())
+                    }
+                    payload => lineage.record(payload),
+                }
+            }
+
+            if event.version > archived {
+                if let AppendOutcome::Duplicate(_) = archive.append(event)? {
+                    return Err(LogError::Storage(format!(
+                        "archive dropped event {} of table {table_id} as a duplicate",
+                        event.version
+                    ))
+                    .into());
+                }
+            }
+
+            hash = event.hash.clone();
+            Ok(())
+        },
+    )?;
+
+    if let Some(mutation) = machine.open_mutation() {
+        return Err(CompactionError::OpenMutation {
+            table_id: table_id.clone(),
+            through,
+            mutation_id: mutation.mutation_id.clone(),
+        }
+        .into());
+    }
+
+    let hash = hash.ok_or_else(|| LogError::Tampered {
+        table_id: table_id.clone(),
+        version: through,
+    })?;
+
+    let mut record = CompactionRecord {
+        table_id: table_id.clone(),
+        version: through,
+        hash,
+        machine,
+        lineage,
+        invariant_set,
+        compacted_at: None,
+        actor: None,
+        signature: None,
+    };
+    record.sign(signer)?;
+
+    log.commit_compaction(&record)?;
+    Ok(record)
+}
+
+/// State machine and version replay of a table starts from: its latest
+/// compaction record, or the initial state before version 1.
+pub(crate) fn compaction_base<S: MetadataLogStore>(
+    log: &MetadataLog<S>,
+    table_id: &TableId,
+    invariants: &InvariantEngine,
+) -> Result<(TableStateMachine, Version), ReplayError> {
+    match log.compaction(table_id)? {
+        Some(record) => {
+            check_invariant_set(&record, &invariants.fingerprint())?;
+            Ok((record.machine, record.version))
+        }
+        None => Ok((TableStateMachine::new(), 0)),
+    }
+}
+
+/// Compacted events can no longer be checked against other invariants.
+fn check_invariant_set(record: &CompactionRecord, invariant_set: &str) -> Result<(), ReplayError> {
+    if record.invariant_set != invariant_set {
+        return Err(CompactionError::InvariantMismatch {
+            table_id: record.table_id.clone(),
+            version: record.version,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invariants::{Invariant, InvariantResult};
+    use crate::log::payload::{
+        MutationEndedPayload, MutationKind, MutationStartedPayload, Schema, SchemaField,
+        SchemaUpdatedPayload, SnapshotAddedPayload, SnapshotOperation, SnapshotRemovedPayload,
+        TableCreatedPayload,
+    };
+    use crate::log::{
+        ActorId, CompactionStore, InMemoryCompactionStore, InMemoryLogStore, SegmentConfig,
+        SegmentedLogStore, TableEvent, TrustStore,
+    };
+    use crate::replay::checkpoint::{
+        replay_with_checkpoints, CheckpointOptions, CheckpointStore, InMemoryCheckpointStore,
+    };
+    use crate::replay::replay_table_state;
+    use crate::state::TableState;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    struct AlwaysPass;
+
+    impl Invariant for AlwaysPass {
+        fn name(&self) -> &'static str {
+            "always-pass"
+        }
+
+        fn validate(&self, _: &TableState, _: &TableEvent, _: &TableState) -> InvariantResult {
+            InvariantResult::Pass
+        }
+    }
+
+    /// Compaction store whose records the test can reach after handing it
+    /// to a log.
+    #[derive(Clone, Default)]
+    struct SharedRecords(Arc<Mutex<InMemoryCompactionStore>>);
+
+    impl CompactionStore for SharedRecords {
+        fn save(&mut self, record: &CompactionRecord) -> Result<(), LogError> {
+            self.0.lock().unwrap().save(record)
+        }
+
+        fn latest(&self, table_id: &TableId) -> Result<Option<CompactionRecord>, LogError> {
+            self.0.lock().unwrap().latest(table_id)
+        }
+    }
+
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    fn signer() -> EventSigner {
+        EventSigner::from_seed(ActorId("compactor".into()), &[3; 32])
+    }
+
+    fn schema(schema_id: i32) -> Schema {
+        Schema {
+            schema_id,
+            fields: vec![SchemaField {
+                id: 1,
+                name: "id".into(),
+                field_type: "long".into(),
+                required: true,
+            }],
+        }
+    }
+
+    /// Event `version` of a table created at version 1, followed by
+    /// three-event mutations: mutation `n` starts at version `3n - 1` and
+    /// ends at `3n + 1`, which are the only versions no mutation is open
+    /// at. Each adds snapshot `n`, except that every fourth drops the
+    /// snapshot of mutation `n - 2`, every fifth evolves the schema and
+    /// every sixth is aborted. Events are signed by `signer`.
+    fn event(version: Version) -> TableEvent {
+        let n = (version + 1) / 3;
+        let mutation_id = format!("m-{n}");
+        let payload = match (version, version % 3) {
+            (1, _) => TypedPayload::TableCreated(TableCreatedPayload {
+                schema: schema(0),
+                location: None,
+                properties: Default::default(),
+            }),
+            (_, 2) => TypedPayload::MutationStarted(MutationStartedPayload {
+                mutation_id,
+                kind: match n {
+                    n if n.is_multiple_of(5) => MutationKind::SchemaChange,
+                    n if n.is_multiple_of(4) => MutationKind::Expire,
+                    _ => MutationKind::Append,
+                },
+                writer: ActorId("writer".into()),
+            }),
+            (_, 1) if n.is_multiple_of(6) => TypedPayload::MutationAborted(MutationEndedPayload {
+                mutation_id,
+                reason: Some("conflict".into()),
+            }),
+            (_, 1) => TypedPayload::MutationCommitted(MutationEndedPayload {
+                mutation_id,
+                reason: None,
+            }),
+            _ if n.is_multiple_of(5) => TypedPayload::SchemaUpdated(SchemaUpdatedPayload {
+                schema: schema(n as i32),
+                mutation_id: Some(mutation_id),
+            }),
+            _ if n.is_multiple_of(4) => TypedPayload::SnapshotRemoved(SnapshotRemovedPayload {
+                snapshot_ids: vec![n as i64 - 2],
+                mutation_id: Some(mutation_id),
+            }),
+            _ => TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id: n as i64,
+                parent_snapshot_id: Some(n as i64 - 1),
+                operation: SnapshotOperation::Append,
+                schema_id: None,
+                mutation_id: Some(mutation_id),
+                branch: None,
+            }),
+        };
+        let mut event = TableEvent::new(table(), version, payload.event_type(), payload);
+        event.envelope.committed_at = Some(1_700_000_000_000 + version);
+        signer().sign(&mut event).unwrap();
+        event
+    }
+
+    fn log_with(
+        store: InMemoryLogStore,
+        records: &SharedRecords,
+        versions: std::ops::RangeInclusive<Version>,
+    ) -> MetadataLog<InMemoryLogStore> {
+        let mut log = MetadataLog::new(store).with_compaction_store(records.clone());
+        for v in versions {
+            log.import(event(v)).unwrap();
+        }
+        log
+    }
+
+    fn invariants() -> InvariantEngine {
+        let mut invariants = InvariantEngine::new();
+        invariants.register(AlwaysPass);
+        invariants
+    }
+
+    #[test]
+    fn replay_is_identical_after_compaction() {
+        let records = SharedRecords::default();
+        let mut log = log_with(InMemoryLogStore::default(), &records, 1..=25);
+        let mut archive = InMemoryLogStore::default();
+        let invariants = invariants();
+
+        let before = replay_table_state(&log, &table(), &invariants).unwrap();
+        let record = compact(&mut log, &table(), 22, &invariants, &mut archive, &signer()).unwrap();
+
+        assert_eq!(
+            replay_table_state(&log, &table(), &invariants).unwrap(),
+            before
+        );
+        assert_eq!(record.version, 22);
+        assert_eq!(record.machine.current_state(), &TableState::Active);
+        assert_eq!(record.machine.model().schema, Some(schema(5)));
+
+        // The snapshot of the aborted sixth mutation never took effect.
+        assert_eq!(
+            record.lineage,
+            SnapshotLineage {
+                current_snapshot_id: Some(7),
+                live_snapshot_ids: vec![1, 3, 7],
+                snapshots_added: 4,
+                snapshots_removed: 1,
+                rollbacks: 0,
+            }
+        );
+
+        // The live log keeps the anchor and newer events; the archive
+        // holds the compacted history with its original chain.
+        assert_eq!(log.first_version(&table()).unwrap(), 23);
+        assert_eq!(
+            MetadataLog::new(archive).replay(&table()).unwrap().len(),
+            22
+        );
+    }
+
+    #[test]
+    fn boundary_cannot_split_a_mutation() {
+        let records = SharedRecords::default();
+        let mut log = log_with(InMemoryLogStore::default(), &records, 1..=6);
+        let mut archive = InMemoryLogStore::default();
+        let invariants = invariants();
+
+        assert!(matches!(
+            compact(&mut log, &table(), 6, &invariants, &mut archive, &signer()),
+            Err(ReplayError::Compaction(CompactionError::OpenMutation {
+                through: 6,
+                ref mutation_id,
+                ..
+            })) if mutation_id == "m-2"
+        ));
+        assert!(records.latest(&table()).unwrap().is_none());
+
+        // A later attempt reuses what was archived.
+        log.append(event(7)).unwrap();
+        compact(&mut log, &table(), 7, &invariants, &mut archive, &signer()).unwrap();
+        assert_eq!(MetadataLog::new(archive).replay(&table()).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn compacted_events_are_only_readable_from_the_archive() {
+        let records = SharedRecords::default();
+        let mut log = log_with(InMemoryLogStore::default(), &records, 1..=6);
+        compact(
+            &mut log,
+            &table(),
+            4,
+            &invariants(),
+            &mut InMemoryLogStore::default(),
+            &signer(),
+        )
+        .unwrap();
+
+        let compacted = LogError::Compacted {
+            table_id: table(),
+            first_available: 5,
+        };
+        assert_eq!(log.replay(&table()).unwrap_err(), compacted);
+        assert!(log.stream(&table(), VersionRange::starting_at(4)).is_err());
+        let rest = log.stream(&table(), VersionRange::starting_at(5)).unwrap();
+        assert_eq!(rest.count(), 2);
+    }
+
+    #[test]
+    fn stores_that_cannot_discard_events_are_not_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = SharedRecords::default();
+        let store = crate::log::FileLogStore::open(dir.path().join("log")).unwrap();
+        let mut log = MetadataLog::new(store).with_compaction_store(records.clone());
+        for v in 1..=6 {
+            log.append(event(v)).unwrap();
+        }
+
+        let mut archive = InMemoryLogStore::default();
+        let result = compact(
+            &mut log,
+            &table(),
+            4,
+            &invariants(),
+            &mut archive,
+            &signer(),
+        );
+        assert!(matches!(
+            result,
+            Err(ReplayError::Log(LogError::Storage(_)))
+        ));
+
+        assert_eq!(records.latest(&table()).unwrap(), None);
+        assert_eq!(archive.current_version(&table()).unwrap(), 0);
+        assert_eq!(log.replay(&table()).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn compaction_continues_from_the_previous_record() {
+        let records = SharedRecords::default();
+        let mut log = log_with(InMemoryLogStore::default(), &records, 1..=8);
+        let mut archive = InMemoryLogStore::default();
+        let invariants = invariants();
+
+        compact(&mut log, &table(), 4, &invariants, &mut archive, &signer()).unwrap();
+        for v in 9..=12 {
+            log.import(event(v)).unwrap();
+        }
+        let record = compact(&mut log, &table(), 10, &invariants, &mut archive, &signer()).unwrap();
+
+        let mut full = log_with(
+            InMemoryLogStore::default(),
+            &SharedRecords::default(),
+            1..=12,
+        );
+        let expected = compact(
+            &mut full,
+            &table(),
+            10,
+            &invariants,
+            &mut InMemoryLogStore::default(),
+            &signer(),
+        )
+        .unwrap();
+        assert_eq!(
+            (&record.machine, &record.lineage),
+            (&expected.machine, &expected.lineage)
+        );
+
+        let archived = MetadataLog::new(archive).replay(&table()).unwrap();
+        assert_eq!(
+            archived.iter().map(|e| e.version).collect::<Vec<_>>(),
+            (1..=10).collect::<Vec<_>>()
+        );
+
+        assert!(matches!(
+            compact(
+                &mut log,
+                &table(),
+                10,
+                &invariants,
+                &mut InMemoryLogStore::default(),
+                &signer()
+            ),
+            Err(ReplayError::Compaction(CompactionError::InvalidBoundary {
+                compacted: 10,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            compact(
+                &mut log,
+                &table(),
+                13,
+                &invariants,
+                &mut InMemoryLogStore::default(),
+                &signer()
+            ),
+            Err(ReplayError::Compaction(CompactionError::InvalidBoundary {
+                head: 12,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn appends_continue_after_compaction() {
+        let records = SharedRecords::default();
+        let mut log = log_with(InMemoryLogStore::default(), &records, 1..=7);
+        let invariants = invariants();
+        compact(
+            &mut log,
+            &table(),
+            7,
+            &invariants,
+            &mut InMemoryLogStore::default(),
+            &signer(),
+        )
+        .unwrap();
+
+        log.append(event(8)).unwrap();
+        log.append(event(9)).unwrap();
+
+        let reference = log_with(
+            InMemoryLogStore::default(),
+            &SharedRecords::default(),
+            1..=9,
+        );
+        assert_eq!(
+            replay_table_state(&log, &table(), &invariants).unwrap(),
+            replay_table_state(&reference, &table(), &invariants).unwrap()
+        );
+    }
+
+    #[test]
+    fn altered_or_untrusted_records_are_rejected() {
+        let records = SharedRecords::default();
+        let mut log = log_with(InMemoryLogStore::default(), &records, 1..=6);
+        let invariants = invariants();
+        let record = compact(
+            &mut log,
+            &table(),
+            4,
+            &invariants,
+            &mut InMemoryLogStore::default(),
+            &signer(),
+        )
+        .unwrap();
+
+        let mut trust = TrustStore::new();
+        trust.trust(signer().actor().clone(), signer().verifying_key());
+        let log = log.with_trust_store(trust);
+        replay_table_state(&log, &table(), &invariants).unwrap();
+
+        // A record whose content was edited fails its signature.
+        let mut forged = record.clone();
+        forged.machine = TableStateMachine::resume(TableState::RollingBack, None);
+        records.clone().save(&forged).unwrap();
+        assert!(matches!(
+            replay_table_state(&log, &table(), &invariants),
+            Err(ReplayError::Log(LogError::SignatureRejected {
+                version: 4,
+                ..
+            }))
+        ));
+
+        // A record that does not match its anchor event fails the chain.
+        let mut detached = record.clone();
+        detached.hash = "00".repeat(32);
+        detached.sign(&signer()).unwrap();
+        records.clone().save(&detached).unwrap();
+        assert!(matches!(
+            replay_table_state(&log, &table(), &invariants),
+            Err(ReplayError::Log(LogError::Tampered { version: 4, .. }))
+        ));
+
+        // A record signed by an unknown actor is rejected in strict mode.
+        let mut untrusted = record.clone();
+        untrusted
+            .sign(&EventSigner::from_seed(ActorId("mallory".into()), &[9; 32]))
+            .unwrap();
+        records.clone().save(&untrusted).unwrap();
+        assert!(matches!(
+            replay_table_state(&log, &table(), &invariants),
+            Err(ReplayError::Log(LogError::SignatureRejected { .. }))
+        ));
+
+        // Compacted events were checked under these invariants only.
+        records.clone().save(&record).unwrap();
+        replay_table_state(&log, &table(), &invariants).unwrap();
+        assert!(matches!(
+            replay_table_state(&log, &table(), &InvariantEngine::new()),
+            Err(ReplayError::Compaction(
+                CompactionError::InvariantMismatch { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn checkpoints_older_than_the_compaction_are_skipped() {
+        let records = SharedRecords::default();
+        let mut log = log_with(InMemoryLogStore::default(), &records, 1..=12);
+        let invariants = invariants();
+        let mut checkpoints = InMemoryCheckpointStore::default();
+        let options = CheckpointOptions {
+            interval: 2,
+            verify: true,
+        };
+
+        let full = replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &options)
+            .unwrap();
+        compact(
+            &mut log,
+            &table(),
+            10,
+            &invariants,
+            &mut InMemoryLogStore::default(),
+            &signer(),
+        )
+        .unwrap();
+
+        // Resumes from the checkpoint at 12, inside a mutation, and
+        // verifies it from the record.
+        assert_eq!(
+            replay_with_checkpoints(&log, &table(), &invariants, &mut checkpoints, &options)
+                .unwrap(),
+            full
+        );
+
+        // Checkpoints before the record are ignored.
+        let mut old = InMemoryCheckpointStore::default();
+        for checkpoint in checkpoints.list(&table()).unwrap() {
+            if checkpoint.version < 10 {
+                old.save(&checkpoint).unwrap();
+            }
+        }
+        assert_eq!(
+            replay_with_checkpoints(&log, &table(), &invariants, &mut old, &options).unwrap(),
+            full
+        );
+    }
+
+    #[test]
+    fn segmented_store_discards_whole_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SegmentConfig {
+            max_segment_bytes: 1024,
+            index_interval_bytes: 256,
+            ..SegmentConfig::default()
+        };
+        let open = || SegmentedLogStore::open_with_config(dir.path().join("live"), config).unwrap();
+        let records = crate::log::FileCompactionStore::open(dir.path().join("records")).unwrap();
+        let invariants = invariants();
+
+        let mut log = MetadataLog::new(open()).with_compaction_store(records);
+        for v in 1..=60 {
+            log.append(event(v)).unwrap();
+        }
+        let before = replay_table_state(&log, &table(), &invariants).unwrap();
+        let mut archive = SegmentedLogStore::open_with_config(
+            dir.path().join("archive"),
+            SegmentConfig {
+                dedup_window: 0,
+                ..config
+            },
+        )
+        .unwrap();
+        compact(&mut log, &table(), 49, &invariants, &mut archive, &signer()).unwrap();
+        drop(log);
+
+        // Without its compaction records, the table cannot be replayed.
+        assert!(matches!(
+            replay_table_state(&MetadataLog::new(open()), &table(), &invariants),
+            Err(ReplayError::Log(LogError::Compacted { .. }))
+        ));
+
+        let records = crate::log::FileCompactionStore::open(dir.path().join("records")).unwrap();
+        let log = MetadataLog::new(open()).with_compaction_store(records);
+        let first = log.first_version(&table()).unwrap();
+        assert!((2..=50).contains(&first), "first readable version {first}");
+        assert_eq!(
+            replay_table_state(&log, &table(), &invariants).unwrap(),
+            before
+        );
+        assert_eq!(
+            MetadataLog::new(archive).replay(&table()).unwrap().len(),
+            49
+        );
+    }
+}
@@ -4,9 +4,18 @@
 // producing a final derived table state.
 
 use crate::invariants::{InvariantEngine, InvariantViolation};
-use crate::log::{LogError, MetadataLog, MetadataLogStore};
+use crate::log::{
+    LogError, MetadataLog, MetadataLogStore, TableEvent, TableId, Version, VersionRange,
+};
+use crate::state::model::TableModel;
 use crate::state::{StateError, TableState, TableStateMachine};
 
+pub mod checkpoint;
+pub mod compaction;
+
+use checkpoint::CheckpointError;
+use compaction::{compaction_base, CompactionError};
+
 /// Errors that can occur during replay.
 #[derive(Debug, thiserror::Error)]
 pub enum ReplayError {
@@ -18,38 +27,95 @@ pub enum ReplayError {
 
     #[error("log error: {0}")]
     Log(#[from] LogError),
+
+    #[error("checkpoint error: {0}")]
+    Checkpoint(#[from] CheckpointError),
+
+    #[error("compaction error: {0}")]
+    Compaction(#[from] CompactionError),
 }
 
-/// Replay the metadata log and derive the final table state.
+/// Replay a table's metadata log and derive its final model: state,
+/// schema, snapshots, refs, properties and last writer.
 ///
-/// This is the *only* supported way to derive table state.
+/// This is the *only* supported way to derive table state. Compacted
+/// tables are replayed from their latest compaction record.
+pub fn replay_table<S: MetadataLogStore>(
+    log: &MetadataLog<S>,
+    table_id: &TableId,
+    invariants: &InvariantEngine,
+) -> Result<TableModel, ReplayError> {
+    let (initial, after) = compaction_base(log, table_id, invariants)?;
+    let (machine, _) = replay_range(
+        log,
+        table_id,
+        invariants,
+        initial,
+        VersionRange::starting_at(after + 1),
+        |_, _| Ok(()),
+    )?;
+
+    Ok(machine.model().clone())
+}
+
+/// Replay a table's metadata log and derive its final state.
 pub fn replay_table_state<S: MetadataLogStore>(
     log: &MetadataLog<S>,
+    table_id: &TableId,
     invariants: &InvariantEngine,
 ) -> Result<TableState, ReplayError> {
-    let mut state_machine = TableStateMachine::new();
-    let mut current_state = state_machine.current_state().clone();
+    Ok(replay_table(log, table_id, invariants)?.state)
+}
 
-    for event in log.replay()? {
-        // Apply event to state machine
-        state_machine.apply(&event)?;
-        let next_state = state_machine.current_state().clone();
+/// Replay the events in `range` on top of the `initial` state machine.
+///
+/// `on_applied` is called after every committed transition. Returns the
+/// final state machine and the last applied version
+/// (`range.from_version - 1` if the range was empty).
+pub(crate) fn replay_range<S: MetadataLogStore>(
+    log: &MetadataLog<S>,
+    table_id: &TableId,
+    invariants: &InvariantEngine,
+    initial: TableStateMachine,
+    range: VersionRange,
+    mut on_applied: impl FnMut(&TableEvent, &TableStateMachine) -> Result<(), ReplayError>,
+) -> Result<(TableStateMachine, Version), ReplayError> {
+    let mut current = initial;
+    let mut last_version = range.from_version.saturating_sub(1);
+
+    for event in log.stream(table_id, range)? {
+        let event = event?;
+        let previous_state = current.current_state().clone();
+
+        // Apply event to state machine. A failed transition or invariant
+        // ends replay, so the machine is not restored.
+        let transition = current.apply_in(invariants.lifecycle(), &event)?;
 
-        // Enforce invariants
-        invariants.evaluate(&current_state, &event, &next_state)?;
+        // Enforce invariants, and the guards of the transition
+        invariants.evaluate(&previous_state, &event, current.model())?;
+        invariants.evaluate_guards(&transition.guards, &previous_state, &event, current.model())?;
 
         // Commit transition
-        current_state = next_state;
+        last_version = event.version;
+        on_applied(&event, &current)?;
     }
 
-    Ok(current_state)
+    Ok((current, last_version))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::invariants::{Invariant, InvariantResult};
-    use crate::log::{EventType, InMemoryLogStore, MetadataLog, TableEvent, TableId};
+    use crate::log::payload::{
+        MutationEndedPayload, MutationKind, MutationStartedPayload, RollbackPayload,
+        SnapshotAddedPayload, SnapshotOperation, TableDroppedPayload,
+    };
+    use crate::log::{
+        ActorId, EventEnvelope, EventPayload, EventType, InMemoryLogStore, MetadataLog,
+        TypedPayload,
+    };
+    use crate::state::lifecycle::Lifecycle;
     use crate::state::TableState;
     use uuid::Uuid;
 
@@ -74,13 +140,36 @@ mod tests {
         }
     }
 
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
+    /// An event of mutation `m-1`, where applicable.
     fn event(version: u64, event_type: EventType) -> TableEvent {
-        TableEvent {
-            table_id: TableId(Uuid::new_v4()),
-            version,
-            event_type,
-            payload: vec![],
-        }
+        let payload: EventPayload = match event_type {
+            EventType::MutationStarted => TypedPayload::MutationStarted(MutationStartedPayload {
+                mutation_id: "m-1".into(),
+                kind: MutationKind::Append,
+                writer: ActorId("spark".into()),
+            })
+            .into(),
+            EventType::MutationCommitted => TypedPayload::MutationCommitted(MutationEndedPayload {
+                mutation_id: "m-1".into(),
+                reason: None,
+            })
+            .into(),
+            EventType::SnapshotAdded => TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id: version as i64,
+                parent_snapshot_id: None,
+                operation: SnapshotOperation::Append,
+                schema_id: None,
+                mutation_id: Some("m-1".into()),
+                branch: None,
+            })
+            .into(),
+            _ => vec![].into(),
+        };
+        TableEvent::new(table(), version, event_type, payload)
     }
 
     #[test]
@@ -89,16 +178,217 @@ mod tests {
         let mut log = MetadataLog::new(store);
 
         log.append(event(1, EventType::TableCreated)).unwrap();
-        log.append(event(2, EventType::SchemaUpdated)).unwrap();
+        log.append(event(2, EventType::MutationStarted)).unwrap();
         log.append(event(3, EventType::SnapshotAdded)).unwrap();
+        log.append(event(4, EventType::MutationCommitted)).unwrap();
 
         let mut invariants = InvariantEngine::new();
         invariants.register(NoMutateFromCreated);
 
-        let state = replay_table_state(&log, &invariants).unwrap();
+        let state = replay_table_state(&log, &table(), &invariants).unwrap();
+        assert_eq!(state, TableState::Active);
+    }
+
+    #[test]
+    fn model_only_keeps_committed_changes() {
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.append(event(1, EventType::TableCreated)).unwrap();
+        log.append(event(2, EventType::MutationStarted)).unwrap();
+        log.append(event(3, EventType::SnapshotAdded)).unwrap();
+        log.append(event(4, EventType::MutationCommitted)).unwrap();
+        log.append(event(5, EventType::MutationStarted)).unwrap();
+        log.append(event(6, EventType::SnapshotAdded)).unwrap();
+
+        let invariants = InvariantEngine::new();
+        let model = replay_table(&log, &table(), &invariants).unwrap();
+        assert_eq!(model.state, TableState::Mutating);
+        assert_eq!(model.current_snapshot_id(), Some(3));
+        assert_eq!(model.staged().current_snapshot_id(), Some(6));
+
+        let aborted = TypedPayload::MutationAborted(MutationEndedPayload {
+            mutation_id: "m-1".into(),
+            reason: Some("conflict".into()),
+        });
+        log.append(TableEvent::new(
+            table(),
+            7,
+            EventType::MutationAborted,
+            aborted,
+        ))
+        .unwrap();
+
+        let model = replay_table(&log, &table(), &invariants).unwrap();
+        assert_eq!(model.state, TableState::Active);
+        assert_eq!(model.snapshots.keys().collect::<Vec<_>>(), vec![&3]);
+        assert!(model.pending.is_empty());
+
+        let writer = model.last_writer.unwrap();
+        assert_eq!(writer.actor, Some(ActorId("spark".into())));
+        assert_eq!(writer.version, 4);
+    }
+
+    #[test]
+    fn rollbacks_replay_through_rolling_back() {
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        let rollback = |version, event_type, reason: Option<&str>| {
+            let payload = RollbackPayload {
+                target_snapshot_id: 3,
+                reason: reason.map(Into::into),
+            };
+            let payload = match event_type {
+                EventType::RollbackStarted => TypedPayload::RollbackStarted(payload),
+                EventType::RollbackFailed => TypedPayload::RollbackFailed(payload),
+                _ => TypedPayload::RollbackCompleted(payload),
+            };
+            TableEvent::new(table(), version, event_type, payload)
+        };
+
+        log.append(event(1, EventType::TableCreated)).unwrap();
+        log.append(event(2, EventType::MutationStarted)).unwrap();
+        log.append(event(3, EventType::SnapshotAdded)).unwrap();
+        log.append(event(4, EventType::MutationCommitted)).unwrap();
+        log.append(rollback(5, EventType::RollbackStarted, Some("bad batch")))
+            .unwrap();
+        log.append(rollback(6, EventType::RollbackFailed, Some("timeout")))
+            .unwrap();
+
+        let invariants = InvariantEngine::new();
+        let state = replay_table_state(&log, &table(), &invariants).unwrap();
+        assert_eq!(state, TableState::RollingBack);
+
+        log.append(rollback(7, EventType::RollbackStarted, None))
+            .unwrap();
+        log.append(rollback(8, EventType::RollbackCompleted, None))
+            .unwrap();
+        let state = replay_table_state(&log, &table(), &invariants).unwrap();
         assert_eq!(state, TableState::Active);
     }
 
+    #[test]
+    fn replay_rejects_writes_to_frozen_tables() {
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.append(event(1, EventType::TableCreated)).unwrap();
+        log.append(event(2, EventType::TableFrozen)).unwrap();
+
+        let invariants = InvariantEngine::new();
+        assert_eq!(
+            replay_table_state(&log, &table(), &invariants).unwrap(),
+            TableState::Frozen
+        );
+
+        log.append(event(3, EventType::MutationStarted)).unwrap();
+        assert!(matches!(
+            replay_table_state(&log, &table(), &invariants),
+            Err(ReplayError::State(StateError::ReadOnly { version: 3, .. }))
+        ));
+    }
+
+    #[test]
+    fn backdated_undrops_are_rejected() {
+        let at = |event: TableEvent, committed_at| {
+            event.with_envelope(EventEnvelope {
+                committed_at: Some(committed_at),
+                ..Default::default()
+            })
+        };
+        let dropped = TableEvent::new(
+            table(),
+            2,
+            EventType::TableDropped,
+            TypedPayload::TableDropped(TableDroppedPayload {
+                grace_period_ms: 1_000,
+                reason: None,
+            }),
+        );
+
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.import(at(event(1, EventType::TableCreated), 1_000))
+            .unwrap();
+        log.import(at(dropped, 2_000)).unwrap();
+
+        // Imports keep their commit time, but not one before the drop
+        assert!(matches!(
+            log.import(at(event(3, EventType::TableUndropped), 1_500)),
+            Err(LogError::Backdated {
+                previous: 2_000,
+                ..
+            })
+        ));
+
+        // Appends are stamped by the log, long after the grace period
+        log.append(at(event(3, EventType::TableUndropped), 2_500))
+            .unwrap();
+        assert!(matches!(
+            replay_table_state(&log, &table(), &InvariantEngine::new()),
+            Err(ReplayError::State(StateError::GracePeriodExpired {
+                version: 3
+            }))
+        ));
+    }
+
+    struct FreezeNeedsData;
+
+    impl Invariant for FreezeNeedsData {
+        fn name(&self) -> &'static str {
+            "freeze-needs-data"
+        }
+
+        fn validate(&self, _: &TableState, _: &TableEvent, _: &TableState) -> InvariantResult {
+            InvariantResult::Pass
+        }
+
+        fn validate_model(
+            &self,
+            _previous: &TableState,
+            _event: &TableEvent,
+            next: &TableModel,
+        ) -> InvariantResult {
+            match next.current_snapshot_id() {
+                Some(_) => InvariantResult::Pass,
+                None => InvariantResult::Fail("nothing to freeze".into()),
+            }
+        }
+    }
+
+    #[test]
+    fn replay_enforces_lifecycle_guards() {
+        let mut definition = Lifecycle::builtin().definition().clone();
+        for transition in &mut definition.transitions {
+            if transition.event == EventType::TableFrozen {
+                transition.guards.push("freeze-needs-data".into());
+            }
+        }
+        let mut invariants = InvariantEngine::new();
+        invariants.register_guard(FreezeNeedsData);
+        invariants
+            .set_lifecycle(Lifecycle::try_from(definition).unwrap())
+            .unwrap();
+
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.append(event(1, EventType::TableCreated)).unwrap();
+        log.append(event(2, EventType::TableFrozen)).unwrap();
+        assert!(matches!(
+            replay_table_state(&log, &table(), &invariants),
+            Err(ReplayError::Invariant(InvariantViolation {
+                invariant: "freeze-needs-data",
+                ..
+            }))
+        ));
+
+        // The built-in lifecycle has no guard
+        let state = replay_table_state(&log, &table(), &InvariantEngine::new()).unwrap();
+        assert_eq!(state, TableState::Frozen);
+
+        let mut log = MetadataLog::new(InMemoryLogStore::default());
+        log.append(event(1, EventType::TableCreated)).unwrap();
+        log.append(event(2, EventType::MutationStarted)).unwrap();
+        log.append(event(3, EventType::SnapshotAdded)).unwrap();
+        log.append(event(4, EventType::MutationCommitted)).unwrap();
+        log.append(event(5, EventType::TableFrozen)).unwrap();
+        let state = replay_table_state(&log, &table(), &invariants).unwrap();
+        assert_eq!(state, TableState::Frozen);
+    }
+
     #[test]
     fn replay_fails_on_invalid_transition_or_invariant() {
         let store = InMemoryLogStore::default();
@@ -110,7 +400,7 @@ mod tests {
         let mut invariants = InvariantEngine::new();
         invariants.register(NoMutateFromCreated);
 
-        let err = replay_table_state(&log, &invariants).unwrap_err();
+        let err = replay_table_state(&log, &table(), &invariants).unwrap_err();
         let msg = err.to_string();
 
         assert!(
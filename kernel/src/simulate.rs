@@ -5,17 +5,17 @@
 
 use crate::adapters::iceberg::IcebergTableState;
 use crate::invariants::InvariantEngine;
-use crate::log::{MetadataLog, MetadataLogStore};
-use crate::replay::{replay_table_state, ReplayError};
+use crate::log::{MetadataLog, MetadataLogStore, TableId};
+use crate::replay::{replay_table, ReplayError};
 use crate::state::drift::{detect_drift, DriftReport};
+use crate::state::model::TableModel;
 use crate::state::policy::{evaluate_drift_policy_with_config, DecisionPlan};
 use crate::state::policy_config::PolicyConfig;
-use crate::state::TableState;
 
 /// Result of a full simulation run.
 #[derive(Debug)]
 pub struct SimulationResult {
-    pub expected_state: TableState,
+    pub expected: TableModel,
     pub drift_report: DriftReport,
     pub decision_plan: DecisionPlan,
 }
@@ -35,22 +35,23 @@ pub enum SimulationError {
 /// - safe to run repeatedly
 pub fn simulate_table<S: MetadataLogStore>(
     log: &MetadataLog<S>,
+    table_id: &TableId,
     invariants: &InvariantEngine,
     actual_state: &IcebergTableState,
     policy: &PolicyConfig,
 ) -> Result<SimulationResult, SimulationError> {
     // 1. Derive expected state
-    let expected_state = replay_table_state(log, invariants)?;
+    let expected = replay_table(log, table_id, invariants)?;
 
     // 2. Detect drift
-    let drift_report = detect_drift(&expected_state, actual_state);
+    let drift_report = detect_drift(&expected, actual_state);
 
     // 3. Evaluate policy (dry-run)
     let decision_plan = evaluate_drift_policy_with_config(&drift_report, policy);
 
 
     Ok(SimulationResult {
-        expected_state,
+        expected,
         drift_report,
         decision_plan,
     })
@@ -60,7 +61,11 @@ pub fn simulate_table<S: MetadataLogStore>(
 mod tests {
     use super::*;
     use crate::invariants::{Invariant, InvariantResult};
-    use crate::log::{EventType, InMemoryLogStore, MetadataLog, TableEvent, TableId};
+    use crate::log::payload::{MutationKind, MutationStartedPayload, Schema, SchemaUpdatedPayload};
+    use crate::log::{
+        ActorId, EventType, InMemoryLogStore, MetadataLog, TableEvent, TableId, TypedPayload,
+    };
+    use crate::state::TableState;
     use uuid::Uuid;
 
     struct NoMutateFromCreated;
@@ -84,13 +89,12 @@ mod tests {
         }
     }
 
+    fn table() -> TableId {
+        TableId(Uuid::nil())
+    }
+
     fn event(version: u64, event_type: EventType) -> TableEvent {
-        TableEvent {
-            table_id: TableId(Uuid::new_v4()),
-            version,
-            event_type,
-            payload: vec![],
-        }
+        TableEvent::new(table(), version, event_type, vec![])
     }
 
     #[test]
@@ -100,7 +104,30 @@ mod tests {
         let mut log = MetadataLog::new(store);
 
         log.append(event(1, EventType::TableCreated)).unwrap();
-        log.append(event(2, EventType::SchemaUpdated)).unwrap();
+        log.append(TableEvent::new(
+            table(),
+            2,
+            EventType::MutationStarted,
+            TypedPayload::MutationStarted(MutationStartedPayload {
+                mutation_id: "m-1".into(),
+                kind: MutationKind::SchemaChange,
+                writer: ActorId("spark".into()),
+            }),
+        ))
+        .unwrap();
+        log.append(TableEvent::new(
+            table(),
+            3,
+            EventType::SchemaUpdated,
+            TypedPayload::SchemaUpdated(SchemaUpdatedPayload {
+                schema: Schema {
+                    schema_id: 1,
+                    fields: vec![],
+                },
+                mutation_id: Some("m-1".into()),
+            }),
+        ))
+        .unwrap();
 
         // Invariants
         let mut invariants = InvariantEngine::new();
@@ -109,7 +136,7 @@ mod tests {
         // Actual Iceberg state (simulated)
         let actual = IcebergTableState {
             table_uuid: Uuid::new_v4(),
-            current_snapshot_id: Some(42),
+            current_snapshot_id: None,
             current_schema_id: 1,
         };
 
@@ -117,10 +144,10 @@ mod tests {
 
         let policy = PolicyConfig::default_policy();
 
-        let result = simulate_table(&log, &invariants, &actual, &policy).unwrap();
+        let result = simulate_table(&log, &table(), &invariants, &actual, &policy).unwrap();
 
 
-        assert_eq!(result.expected_state, TableState::Mutating);
+        assert_eq!(result.expected.state, TableState::Mutating);
 
         // During a valid mutation, there should be no drift
         assert!(result.drift_report.is_clean());
@@ -5,6 +5,7 @@
 // by severity and intent.
 
 use crate::adapters::iceberg::IcebergTableState;
+use crate::state::model::TableModel;
 use crate::state::TableState;
 use serde::{Serialize, Deserialize};
 
@@ -28,6 +29,9 @@ pub enum DriftType {
     UnexpectedMutation,
     SchemaMismatch,
     SnapshotMismatch,
+
+    /// The table is being rolled back; snapshot changes are expected.
+    RollbackInProgress,
 }
 /// A single drift finding.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -60,26 +64,85 @@ impl DriftReport {
     }
 }
 
-/// Detect and classify drift between expected and actual state.
-pub fn detect_drift(expected: &TableState, actual: &IcebergTableState) -> DriftReport {
+/// Detect and classify drift between the model derived by replay and
+/// actual state.
+pub fn detect_drift(expected: &TableModel, actual: &IcebergTableState) -> DriftReport {
     let mut findings = Vec::new();
 
-    // Rule 1: Unexpected mutation while ACTIVE
-    if expected == &TableState::Active && actual.current_snapshot_id.is_some() {
-        findings.push(DriftFinding {
-            drift_type: DriftType::UnexpectedMutation,
-            severity: DriftSeverity::Warning,
-            message: "table snapshot changed while expected state is ACTIVE".into(),
-        });
+    // An open mutation may already have written its changes.
+    let staged = match expected.state {
+        TableState::Mutating => Some(expected.staged()),
+        _ => None,
+    };
+    let expected_snapshot = |id| {
+        expected.current_snapshot_id() == id
+            || staged
+                .as_ref()
+                .is_some_and(|s| s.current_snapshot_id() == id)
+    };
+
+    match expected.state {
+        // Rule 1: Unexpected mutation while nothing may write
+        TableState::Active | TableState::Deprecated | TableState::Frozen
+            if !expected_snapshot(actual.current_snapshot_id) =>
+        {
+            findings.push(DriftFinding {
+                drift_type: DriftType::UnexpectedMutation,
+                severity: DriftSeverity::Warning,
+                message: format!(
+                    "table snapshot changed while expected state is {:?}: expected {:?}, found {:?}",
+                    expected.state,
+                    expected.current_snapshot_id(),
+                    actual.current_snapshot_id
+                ),
+            });
+        }
+
+        // Rule 2: Snapshot the open mutation did not record
+        TableState::Mutating if !expected_snapshot(actual.current_snapshot_id) => {
+            findings.push(DriftFinding {
+                drift_type: DriftType::SnapshotMismatch,
+                severity: DriftSeverity::Warning,
+                message: format!(
+                    "snapshot {:?} was not recorded by the open mutation",
+                    actual.current_snapshot_id
+                ),
+            });
+        }
+
+        // Rule 3: Rollback in progress (recorded so it is audited)
+        TableState::RollingBack => {
+            findings.push(DriftFinding {
+                drift_type: DriftType::RollbackInProgress,
+                severity: DriftSeverity::Info,
+                message: "table is rolling back; snapshot changes are expected".into(),
+            });
+        }
+
+        _ => {}
     }
 
-    // Rule 2: Schema mismatch (future: compare with expected schema id)
+    // Rule 4: Schema mismatch
+    let expected_schema = |id| {
+        expected.schema_id() == Some(id)
+            || staged.as_ref().is_some_and(|s| s.schema_id() == Some(id))
+    };
     if actual.current_schema_id < 0 {
         findings.push(DriftFinding {
             drift_type: DriftType::SchemaMismatch,
             severity: DriftSeverity::Critical,
             message: "invalid schema identifier detected".into(),
         });
+    } else if expected.schema.is_some() && !expected_schema(actual.current_schema_id) {
+        findings.push(DriftFinding {
+            drift_type: DriftType::SchemaMismatch,
+            severity: DriftSeverity::Critical,
+            message: format!(
+                "expected schema {:?}, found {}",
+                expected.schema_id(),
+                actual.current_schema_id
+            ),
+        });
     }
 
     DriftReport { findings }
@@ -88,11 +151,36 @@ pub fn detect_drift(expected: &TableState, actual: &IcebergTableState) -> DriftR
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::log::payload::{Schema, SnapshotAddedPayload, SnapshotOperation};
+    use crate::log::TypedPayload;
     use uuid::Uuid;
 
+    fn model(state: TableState, snapshot_id: Option<i64>, schema_id: Option<i32>) -> TableModel {
+        TableModel {
+            state,
+            schema: schema_id.map(|schema_id| Schema {
+                schema_id,
+                fields: vec![],
+            }),
+            refs: snapshot_id
+                .map(|id| ("main".into(), id))
+                .into_iter()
+                .collect(),
+            ..TableModel::default()
+        }
+    }
+
+    fn actual(snapshot_id: Option<i64>, schema_id: i32) -> IcebergTableState {
+        IcebergTableState {
+            table_uuid: Uuid::new_v4(),
+            current_snapshot_id: snapshot_id,
+            current_schema_id: schema_id,
+        }
+    }
+
     #[test]
     fn warning_drift_detected() {
-        let expected = TableState::Active;
+        let expected = model(TableState::Active, None, None);
 
         let actual = IcebergTableState {
             table_uuid: Uuid::new_v4(),
@@ -105,6 +193,55 @@ mod tests {
         assert_eq!(report.findings[0].severity, DriftSeverity::Warning);
     }
 
+    #[test]
+    fn rollback_is_reported_not_flagged_as_mutation() {
+        let actual = IcebergTableState {
+            table_uuid: Uuid::new_v4(),
+            current_snapshot_id: Some(99),
+            current_schema_id: 1,
+        };
+
+        let report = detect_drift(&model(TableState::RollingBack, None, None), &actual);
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].drift_type, DriftType::RollbackInProgress);
+        assert_eq!(report.highest_severity(), Some(&DriftSeverity::Info));
+    }
+
+    #[test]
+    fn matching_model_is_clean() {
+        let expected = model(TableState::Active, Some(42), Some(1));
+        assert!(detect_drift(&expected, &actual(Some(42), 1)).is_clean());
+
+        let report = detect_drift(&expected, &actual(Some(43), 2));
+        let types: Vec<_> = report.findings.iter().map(|f| &f.drift_type).collect();
+        assert_eq!(
+            types,
+            vec![&DriftType::UnexpectedMutation, &DriftType::SchemaMismatch]
+        );
+    }
+
+    #[test]
+    fn open_mutation_may_have_written_its_snapshot() {
+        let mut expected = model(TableState::Mutating, Some(41), Some(1));
+        expected
+            .pending
+            .push(TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id: 42,
+                parent_snapshot_id: Some(41),
+                operation: SnapshotOperation::Append,
+                schema_id: Some(1),
+                mutation_id: Some("m-1".into()),
+                branch: None,
+            }));
+
+        assert!(detect_drift(&expected, &actual(Some(41), 1)).is_clean());
+        assert!(detect_drift(&expected, &actual(Some(42), 1)).is_clean());
+
+        let report = detect_drift(&expected, &actual(Some(43), 1));
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].drift_type, DriftType::SnapshotMismatch);
+    }
+
     #[test]
     fn highest_severity_computed_correctly() {
         let report = DriftReport {
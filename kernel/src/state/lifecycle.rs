@@ -0,0 +1,580 @@
+// Table Lifecycles
+//
+// A lifecycle declares which states a class of tables goes through, which
+// events it accepts and which transitions each event causes. Streaming
+// sinks, curated tables and scratch tables can each follow their own;
+// the lifecycle described on `TableStateMachine` is the built-in default.
+//
+// Lifecycles are data, loaded from JSON and validated as they are
+// decoded: every state must be reachable from `Created`, every state but
+// the declared terminal ones must have a way out, and no transition may
+// contradict what the kernel does with its event (see below).
+//
+// A lifecycle only decides *whether* an event is allowed and where it
+// leads. What an event does to the table (opening a mutation, staging a
+// change, recording an allow-list) and the meaning of the states stay
+// fixed by the kernel:
+// - every table starts out `Created`
+// - a table with an engine allow-list settles in `Deprecated` wherever a
+//   transition leads to `Active`
+// - events without a transition out of `Frozen` or `Dropped` are
+//   rejected as writes to a read-only table, and writes cannot be given
+//   one
+// - a mutation is open exactly while a table is `Mutating`, so
+//   `MutationStarted` must lead there and `MutationCommitted` and
+//   `MutationAborted` out of it
+
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::{is_write, TableState};
+use crate::log::EventType;
+
+/// Errors produced when a lifecycle definition is invalid.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum LifecycleError {
+    #[error("lifecycle `{lifecycle}` does not declare the initial state Created")]
+    NoInitialState { lifecycle: String },
+
+    #[error("lifecycle `{lifecycle}` uses undeclared state {state:?}")]
+    UndeclaredState {
+        lifecycle: String,
+        state: TableState,
+    },
+
+    #[error("lifecycle `{lifecycle}` uses undeclared event {event:?}")]
+    UndeclaredEvent { lifecycle: String, event: EventType },
+
+    #[error("lifecycle `{lifecycle}` declares event {event:?} without a transition")]
+    UnusedEvent { lifecycle: String, event: EventType },
+
+    #[error("lifecycle `{lifecycle}` has more than one transition for {event:?} in {state:?}")]
+    AmbiguousTransition {
+        lifecycle: String,
+        state: TableState,
+        event: EventType,
+    },
+
+    #[error("lifecycle `{lifecycle}` cannot reach {states:?} from Created")]
+    UnreachableStates {
+        lifecycle: String,
+        states: Vec<TableState>,
+    },
+
+    #[error("lifecycle `{lifecycle}` cannot leave non-terminal states {states:?}")]
+    DeadEndStates {
+        lifecycle: String,
+        states: Vec<TableState>,
+    },
+
+    #[error("lifecycle `{lifecycle}` allows write {event:?} in read-only state {state:?}")]
+    ReadOnlyWrite {
+        lifecycle: String,
+        state: TableState,
+        event: EventType,
+    },
+
+    #[error("lifecycle `{lifecycle}` leads {event:?} to {to:?}, but a mutation is open exactly while Mutating")]
+    MutationTarget {
+        lifecycle: String,
+        event: EventType,
+        to: TableState,
+    },
+
+    #[error("lifecycle `{lifecycle}` guards a transition with unknown invariant `{guard}`")]
+    UnknownGuard { lifecycle: String, guard: String },
+}
+
+/// An allowed transition: `event` moves a table in any of the `from`
+/// states to `to`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub from: Vec<TableState>,
+    pub event: EventType,
+    pub to: TableState,
+
+    /// Names of guard invariants that must hold after the transition,
+    /// on top of the invariants checked on every transition.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guards: Vec<String>,
+}
+
+/// A lifecycle as written in a config file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LifecycleDefinition {
+    pub name: String,
+    pub states: Vec<TableState>,
+    pub events: Vec<EventType>,
+    pub transitions: Vec<Transition>,
+
+    /// States a table may never leave.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terminal: Vec<TableState>,
+}
+
+/// A validated lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "LifecycleDefinition", into = "LifecycleDefinition")]
+pub struct Lifecycle {
+    definition: LifecycleDefinition,
+}
+
+impl Lifecycle {
+    /// The lifecycle of tables without a configured one.
+    pub fn builtin() -> &'static Lifecycle {
+        static BUILTIN: OnceLock<Lifecycle> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            Lifecycle::try_from(builtin_definition()).expect("built-in lifecycle is valid")
+        })
+    }
+
+    /// Parse and validate a JSON lifecycle definition.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    pub fn definition(&self) -> &LifecycleDefinition {
+        &self.definition
+    }
+
+    /// The transition `event` causes in `state`, if it is allowed.
+    pub fn transition(&self, state: &TableState, event: &EventType) -> Option<&Transition> {
+        self.definition
+            .transitions
+            .iter()
+            .find(|t| t.event == *event && t.from.contains(state))
+    }
+
+    /// Guard names referenced by any transition, in order of appearance.
+    pub fn guards(&self) -> impl Iterator<Item = &str> {
+        self.definition
+            .transitions
+            .iter()
+            .flat_map(|t| t.guards.iter().map(String::as_str))
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::builtin().clone()
+    }
+}
+
+impl TryFrom<LifecycleDefinition> for Lifecycle {
+    type Error = LifecycleError;
+
+    fn try_from(definition: LifecycleDefinition) -> Result<Self, LifecycleError> {
+        validate(&definition)?;
+        Ok(Self { definition })
+    }
+}
+
+impl From<Lifecycle> for LifecycleDefinition {
+    fn from(lifecycle: Lifecycle) -> Self {
+        lifecycle.definition
+    }
+}
+
+fn validate(definition: &LifecycleDefinition) -> Result<(), LifecycleError> {
+    let lifecycle = || definition.name.clone();
+    let declared = |state: &TableState| {
+        if definition.states.contains(state) {
+            Ok(())
+        } else {
+            Err(LifecycleError::UndeclaredState {
+                lifecycle: lifecycle(),
+                state: state.clone(),
+            })
+        }
+    };
+
+    if !definition.states.contains(&TableState::Created) {
+        return Err(LifecycleError::NoInitialState {
+            lifecycle: lifecycle(),
+        });
+    }
+    definition.terminal.iter().try_for_each(declared)?;
+
+    for (i, transition) in definition.transitions.iter().enumerate() {
+        transition.from.iter().try_for_each(declared)?;
+        declared(&transition.to)?;
+
+        if !definition.events.contains(&transition.event) {
+            return Err(LifecycleError::UndeclaredEvent {
+                lifecycle: lifecycle(),
+                event: transition.event.clone(),
+            });
+        }
+
+        let read_only = transition
+            .from
+            .iter()
+            .find(|state| matches!(state, TableState::Frozen | TableState::Dropped));
+        if let Some(state) = read_only.filter(|_| is_write(&transition.event)) {
+            return Err(LifecycleError::ReadOnlyWrite {
+                lifecycle: lifecycle(),
+                state: state.clone(),
+                event: transition.event.clone(),
+            });
+        }
+
+        let mutating = transition.to == TableState::Mutating;
+        let consistent = match transition.event {
+            EventType::MutationStarted => mutating,
+            EventType::MutationCommitted | EventType::MutationAborted => !mutating,
+            _ => true,
+        };
+        if !consistent {
+            return Err(LifecycleError::MutationTarget {
+                lifecycle: lifecycle(),
+                event: transition.event.clone(),
+                to: transition.to.clone(),
+            });
+        }
+
+        let earlier = &definition.transitions[..i];
+        let repeated = transition.from.iter().find(|state| {
+            earlier
+                .iter()
+                .any(|t| t.event == transition.event && t.from.contains(state))
+        });
+        if let Some(state) = repeated {
+            return Err(LifecycleError::AmbiguousTransition {
+                lifecycle: lifecycle(),
+                state: state.clone(),
+                event: transition.event.clone(),
+            });
+        }
+    }
+
+    if let Some(event) = definition
+        .events
+        .iter()
+        .find(|e| !definition.transitions.iter().any(|t| t.event == **e))
+    {
+        return Err(LifecycleError::UnusedEvent {
+            lifecycle: lifecycle(),
+            event: event.clone(),
+        });
+    }
+
+    // Walk the transitions from Created
+    let mut reached = vec![TableState::Created];
+    let mut next = 0;
+    while let Some(state) = reached.get(next).cloned() {
+        next += 1;
+        for transition in &definition.transitions {
+            if transition.from.contains(&state) && !reached.contains(&transition.to) {
+                reached.push(transition.to.clone());
+            }
+        }
+    }
+    let unreachable: Vec<_> = definition
+        .states
+        .iter()
+        .filter(|state| !reached.contains(state))
+        .cloned()
+        .collect();
+    if !unreachable.is_empty() {
+        return Err(LifecycleError::UnreachableStates {
+            lifecycle: lifecycle(),
+            states: unreachable,
+        });
+    }
+
+    // Transitions back into the same state do not count as a way out
+    let dead_ends: Vec<_> = definition
+        .states
+        .iter()
+        .filter(|state| !definition.terminal.contains(state))
+        .filter(|state| {
+            !definition
+                .transitions
+                .iter()
+                .any(|t| t.from.contains(state) && t.to != **state)
+        })
+        .cloned()
+        .collect();
+    if !dead_ends.is_empty() {
+        return Err(LifecycleError::DeadEndStates {
+            lifecycle: lifecycle(),
+            states: dead_ends,
+        });
+    }
+
+    Ok(())
+}
+
+/// Explicit mutations, rollbacks and governance, as described on
+/// `TableStateMachine`. Changing it requires bumping `SEMANTICS_VERSION`.
+fn builtin_definition() -> LifecycleDefinition {
+    use EventType::*;
+    use TableState::*;
+
+    let rule = |from: &[TableState], event: EventType, to: TableState| Transition {
+        from: from.to_vec(),
+        event,
+        to,
+        guards: Vec::new(),
+    };
+    let idle = [Active, Deprecated];
+
+    LifecycleDefinition {
+        name: "default".into(),
+        states: vec![
+            Created,
+            Active,
+            Mutating,
+            RollingBack,
+            Frozen,
+            Deprecated,
+            Dropped,
+        ],
+        events: vec![
+            TableCreated,
+            MutationStarted,
+            MutationCommitted,
+            MutationAborted,
+            SchemaUpdated,
+            SnapshotAdded,
+            SnapshotRemoved,
+            PropertiesUpdated,
+            RollbackStarted,
+            RollbackCompleted,
+            RollbackFailed,
+            TableFrozen,
+            TableUnfrozen,
+            TableDeprecated,
+            TableDropped,
+            TableUndropped,
+        ],
+        transitions: vec![
+            rule(&[Created], TableCreated, Active),
+            // Only one mutation can be open at a time
+            rule(&idle, MutationStarted, Mutating),
+            rule(&[Mutating], SchemaUpdated, Mutating),
+            rule(&[Mutating], SnapshotAdded, Mutating),
+            rule(&[Mutating], SnapshotRemoved, Mutating),
+            rule(&[Mutating], PropertiesUpdated, Mutating),
+            rule(&[Mutating], MutationCommitted, Active),
+            rule(&[Mutating], MutationAborted, Active),
+            // Legacy changes of logs written before mutations were
+            // explicit open an implicit mutation, which the next one
+            // closes; the state machine rejects any other change here
+            rule(&[Active], SchemaUpdated, Mutating),
+            rule(&[Active], SnapshotAdded, Mutating),
+            rule(&[Active], SnapshotRemoved, Mutating),
+            // In-flight or committed mutations can be rolled back; a
+            // failed rollback keeps the table blocked until a retry
+            // completes
+            rule(
+                &[Active, Deprecated, Mutating],
+                RollbackStarted,
+                RollingBack,
+            ),
+            rule(&[RollingBack], RollbackStarted, RollingBack),
+            rule(&[RollingBack], RollbackFailed, RollingBack),
+            rule(&[RollingBack], RollbackCompleted, Active),
+            // Governance applies to idle tables only
+            rule(&idle, TableFrozen, Frozen),
+            rule(&[Frozen], TableUnfrozen, Active),
+            rule(&idle, TableDeprecated, Deprecated),
+            rule(&idle, TableDropped, Dropped),
+            rule(&[Dropped], TableUndropped, Active),
+        ],
+        terminal: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create, write directly, drop for good.
+    fn scratch() -> LifecycleDefinition {
+        use EventType::*;
+        use TableState::*;
+
+        let rule = |from: TableState, event: EventType, to: TableState| Transition {
+            from: vec![from],
+            event,
+            to,
+            guards: Vec::new(),
+        };
+        LifecycleDefinition {
+            name: "scratch".into(),
+            states: vec![Created, Active, Dropped],
+            events: vec![TableCreated, SnapshotAdded, TableDropped],
+            transitions: vec![
+                rule(Created, TableCreated, Active),
+                rule(Active, SnapshotAdded, Active),
+                rule(Active, TableDropped, Dropped),
+            ],
+            terminal: vec![Dropped],
+        }
+    }
+
+    #[test]
+    fn builtin_lifecycle_round_trips_through_json() {
+        let builtin = Lifecycle::builtin();
+        let json = serde_json::to_string(builtin).unwrap();
+        assert_eq!(&Lifecycle::from_json(&json).unwrap(), builtin);
+
+        let transition = builtin
+            .transition(&TableState::Deprecated, &EventType::MutationStarted)
+            .unwrap();
+        assert_eq!(transition.to, TableState::Mutating);
+        assert!(builtin
+            .transition(&TableState::Deprecated, &EventType::SnapshotAdded)
+            .is_none());
+    }
+
+    #[test]
+    fn dead_ends_are_rejected_unless_terminal() {
+        Lifecycle::try_from(scratch()).unwrap();
+
+        let mut definition = scratch();
+        definition.terminal.clear();
+        assert_eq!(
+            Lifecycle::try_from(definition),
+            Err(LifecycleError::DeadEndStates {
+                lifecycle: "scratch".into(),
+                states: vec![TableState::Dropped],
+            })
+        );
+    }
+
+    #[test]
+    fn unreachable_states_are_rejected() {
+        let mut definition = scratch();
+        definition.states.push(TableState::Frozen);
+        definition.terminal.push(TableState::Frozen);
+        assert_eq!(
+            Lifecycle::try_from(definition),
+            Err(LifecycleError::UnreachableStates {
+                lifecycle: "scratch".into(),
+                states: vec![TableState::Frozen],
+            })
+        );
+    }
+
+    #[test]
+    fn read_only_states_cannot_be_written() {
+        let mut definition = scratch();
+        definition.transitions.push(Transition {
+            from: vec![TableState::Dropped],
+            event: EventType::SnapshotAdded,
+            to: TableState::Dropped,
+            guards: Vec::new(),
+        });
+        assert_eq!(
+            Lifecycle::try_from(definition),
+            Err(LifecycleError::ReadOnlyWrite {
+                lifecycle: "scratch".into(),
+                state: TableState::Dropped,
+                event: EventType::SnapshotAdded,
+            })
+        );
+    }
+
+    #[test]
+    fn mutation_events_must_open_and_close_mutations() {
+        use EventType::*;
+        use TableState::*;
+
+        let with_mutations = |started: TableState, committed: TableState| {
+            let mut definition = scratch();
+            definition.states.push(Mutating);
+            definition
+                .events
+                .extend([MutationStarted, MutationCommitted]);
+            definition.transitions.extend([
+                Transition {
+                    from: vec![Active],
+                    event: MutationStarted,
+                    to: started,
+                    guards: Vec::new(),
+                },
+                Transition {
+                    from: vec![Mutating],
+                    event: MutationCommitted,
+                    to: committed,
+                    guards: Vec::new(),
+                },
+            ]);
+            Lifecycle::try_from(definition)
+        };
+
+        with_mutations(Mutating, Active).unwrap();
+        assert!(matches!(
+            with_mutations(Active, Active),
+            Err(LifecycleError::MutationTarget {
+                event: MutationStarted,
+                to: Active,
+                ..
+            })
+        ));
+        assert!(matches!(
+            with_mutations(Mutating, Mutating),
+            Err(LifecycleError::MutationTarget {
+                event: MutationCommitted,
+                to: Mutating,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn transitions_must_be_declared_and_unambiguous() {
+        let mut definition = scratch();
+        definition.transitions[1].to = TableState::Mutating;
+        assert!(matches!(
+            Lifecycle::try_from(definition),
+            Err(LifecycleError::UndeclaredState {
+                state: TableState::Mutating,
+                ..
+            })
+        ));
+
+        let mut definition = scratch();
+        definition.events.pop();
+        assert!(matches!(
+            Lifecycle::try_from(definition),
+            Err(LifecycleError::UndeclaredEvent {
+                event: EventType::TableDropped,
+                ..
+            })
+        ));
+
+        let mut definition = scratch();
+        definition.events.push(EventType::TableFrozen);
+        assert!(matches!(
+            Lifecycle::try_from(definition),
+            Err(LifecycleError::UnusedEvent { .. })
+        ));
+
+        let mut definition = scratch();
+        let duplicate = definition.transitions[2].clone();
+        definition.transitions.push(duplicate);
+        assert!(matches!(
+            Lifecycle::try_from(definition),
+            Err(LifecycleError::AmbiguousTransition {
+                state: TableState::Active,
+                event: EventType::TableDropped,
+                ..
+            })
+        ));
+
+        // Validation also runs when decoding
+        let mut definition = scratch();
+        definition.states.retain(|s| s != &TableState::Created);
+        let json = serde_json::to_string(&definition).unwrap();
+        let err = Lifecycle::from_json(&json).unwrap_err();
+        assert!(err.to_string().contains("initial state"));
+    }
+}
@@ -3,25 +3,65 @@
 // Derives the current table state from a sequence of metadata events.
 // This module is pure, deterministic, and side-effect free.
 
-use crate::log::{EventType, TableEvent};
+use serde::{Deserialize, Serialize};
+
+use crate::log::payload::MutationKind;
+use crate::log::{ActorId, EngineKind, EventType, TableEvent, TypedPayload, Version};
 pub mod drift;
+pub mod lifecycle;
+pub mod model;
 pub mod policy;
 pub mod policy_config;
 
+use lifecycle::{Lifecycle, Transition};
+use model::{TableModel, Writer};
+
 /// High-level lifecycle state of a table.
 ///
 /// NOTE:
 /// States are intentionally coarse-grained in early versions.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TableState {
     /// Table exists but has no committed data yet.
+    #[default]
     Created,
 
     /// Table is readable and stable.
     Active,
 
-    /// Table is undergoing a mutation (schema change, rewrite, etc.).
+    /// A mutation (schema change, rewrite, etc.) is open and has been
+    /// neither committed nor aborted.
     Mutating,
+
+    /// Table is being restored to an earlier snapshot. No other mutation
+    /// is allowed until the rollback completes.
+    RollingBack,
+
+    /// Table is read-only until it is unfrozen.
+    Frozen,
+
+    /// Table is readable, but only allow-listed engines may write to it.
+    Deprecated,
+
+    /// Table was dropped. It can only be undropped, within the grace
+    /// period of the drop.
+    Dropped,
+}
+
+/// The mutation a `Mutating` table is undergoing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenMutation {
+    pub mutation_id: String,
+    pub kind: MutationKind,
+    pub writer: ActorId,
+
+    /// Version of the `MutationStarted` event.
+    pub started_at: Version,
+
+    /// Opened by a change of a log written before mutations were
+    /// explicit, rather than by `MutationStarted`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub implicit: bool,
 }
 
 /// Errors produced during state transitions.
@@ -29,68 +69,472 @@ pub enum TableState {
 pub enum StateError {
     #[error("illegal state transition: {0}")]
     IllegalTransition(String),
+
+    #[error("{event_type:?} at version {version} references mutation {referenced:?}, but the open mutation is {open:?}")]
+    ForeignMutation {
+        event_type: EventType,
+        version: Version,
+        referenced: Option<String>,
+        open: Option<String>,
+    },
+
+    #[error("{event_type:?} at version {version} rejected: table is {state:?}")]
+    ReadOnly {
+        state: TableState,
+        event_type: EventType,
+        version: Version,
+    },
+
+    #[error("{event_type:?} at version {version} rejected: engine {engine:?} may not write to a deprecated table")]
+    EngineNotAllowed {
+        event_type: EventType,
+        version: Version,
+        engine: Option<EngineKind>,
+    },
+
+    #[error("undrop at version {version} is outside the grace period of the drop")]
+    GracePeriodExpired { version: Version },
+
+    #[error("rollback at version {version} to snapshot {target} rejected: {reason}")]
+    InvalidRollback {
+        version: Version,
+        target: i64,
+        reason: String,
+    },
 }
 
+/// Version of the rules `TableStateMachine` applies events by, the
+/// built-in lifecycle included.
+///
+/// Bump it whenever replaying the same events may derive a different
+/// state, so that checkpoints and compaction records derived under older
+/// rules are not reused (see `InvariantEngine::fingerprint`).
+pub const SEMANTICS_VERSION: u32 = 1;
+
 /// Stateful reducer for table events.
-#[derive(Debug)]
+///
+/// Which events a table accepts in which state is decided by its
+/// `Lifecycle`; the rules below are those of the built-in one, which
+/// `apply` follows.
+///
+/// Mutations are explicit: `MutationStarted` opens one, schema and
+/// snapshot events must reference it by id, and `MutationCommitted` or
+/// `MutationAborted` closes it. Logs written before mutations were
+/// explicit, where every other schema or snapshot event toggled the table
+/// between `Active` and `Mutating`, still replay: a legacy change (see
+/// `TableEvent::legacy`) opens an implicit mutation with a generated id,
+/// and the next one closes it. Both take effect at once. Any other change
+/// referencing no mutation is rejected.
+///
+/// A rollback must target a live snapshot and complete to the one it
+/// started for.
+///
+/// Frozen and dropped tables reject every write. A deprecated table
+/// returns to `Deprecated` rather than `Active` after a mutation or
+/// rollback, and keeps its engine allow-list across a drop and undrop.
+///
+/// Alongside the state, the machine derives the table's `TableModel`
+/// from typed payloads.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TableStateMachine {
-    state: TableState,
+    #[serde(flatten)]
+    model: TableModel,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mutation: Option<OpenMutation>,
+
+    /// Engines that may write to a deprecated table.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allowed_engines: Option<Vec<EngineKind>>,
+
+    /// Unix milliseconds until which a dropped table can be undropped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    undrop_until: Option<u64>,
+
+    /// Snapshot the rollback in progress restores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rollback_target: Option<i64>,
 }
 
 impl TableStateMachine {
     /// Create a new state machine for a freshly created table.
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resume a state machine from a previously derived state and the
+    /// mutation that was open at the time, if any.
+    pub fn resume(state: TableState, mutation: Option<OpenMutation>) -> Self {
         Self {
-            state: TableState::Created,
+            model: TableModel {
+                state,
+                ..TableModel::default()
+            },
+            mutation,
+            ..Self::default()
         }
     }
 
-    /// Apply a single metadata event to the state machine.
+    /// Apply a single metadata event under the built-in lifecycle.
     pub fn apply(&mut self, event: &TableEvent) -> Result<(), StateError> {
-        use EventType::*;
-        use TableState::*;
+        self.apply_in(Lifecycle::builtin(), event).map(|_| ())
+    }
 
-        self.state = match (&self.state, &event.event_type) {
-            // Table creation
-            (Created, TableCreated) => Active,
+    /// Apply a single metadata event under `lifecycle`, returning the
+    /// transition it took.
+    pub fn apply_in<'l>(
+        &mut self,
+        lifecycle: &'l Lifecycle,
+        event: &TableEvent,
+    ) -> Result<&'l Transition, StateError> {
+        use TableState::*;
 
-            // Schema changes or snapshots cause mutations
-            (Active, SchemaUpdated | SnapshotAdded | SnapshotRemoved) => Mutating,
+        // Frozen and dropped tables reject writes below, whatever the
+        // engine
+        let read_only = matches!(self.model.state, Frozen | Dropped);
+        if !read_only {
+            self.check_writer(event)?;
+        }
 
-            // Completing mutation returns to Active
-            (Mutating, SchemaUpdated | SnapshotAdded | SnapshotRemoved) => Active,
+        let Some(transition) = lifecycle.transition(&self.model.state, &event.event_type) else {
+            // Nothing else may touch a frozen or dropped table
+            if read_only {
+                return Err(StateError::ReadOnly {
+                    state: self.model.state.clone(),
+                    event_type: event.event_type.clone(),
+                    version: event.version,
+                });
+            }
 
             // Anything else is illegal
-            (state, evt) => {
-                return Err(StateError::IllegalTransition(format!(
-                    "cannot apply {:?} while in {:?}",
-                    evt, state
-                )))
-            }
+            return Err(StateError::IllegalTransition(format!(
+                "cannot apply {:?} while in {:?}",
+                event.event_type, self.model.state
+            )));
+        };
+
+        let implicit = self.mutation.as_ref().is_some_and(|m| m.implicit);
+        self.take_effect(event, &transition.to)?;
+        self.model.state = match transition.to {
+            // Deprecated tables return to Deprecated instead
+            Active => self.idle(),
+            // The change closed an implicit mutation
+            Mutating if implicit && self.mutation.is_none() => self.idle(),
+            ref to => to.clone(),
         };
 
+        Ok(transition)
+    }
+
+    /// What an allowed event leading to `to` does to the table.
+    fn take_effect(&mut self, event: &TableEvent, to: &TableState) -> Result<(), StateError> {
+        use EventType::*;
+
+        match event.event_type {
+            TableCreated => self.record_write(event, event.envelope.actor.clone()),
+
+            // Only one mutation can be open at a time
+            MutationStarted => {
+                if let Some(open) = &self.mutation {
+                    return Err(StateError::IllegalTransition(format!(
+                        "MutationStarted at version {} while mutation {} is open",
+                        event.version, open.mutation_id
+                    )));
+                }
+                self.mutation = Some(Self::open(event)?);
+            }
+
+            // Changes belong to the open mutation and take effect when it
+            // commits. Lifecycles without mutations apply them at once, the
+            // reference only naming the write, and so do implicit
+            // mutations, which the next legacy change closes.
+            SchemaUpdated | SnapshotAdded | SnapshotRemoved | PropertiesUpdated => {
+                let referenced = event.payload.typed().and_then(TypedPayload::mutation_id);
+                let unreferenced_legacy = event.legacy && referenced.is_none();
+                match &self.mutation {
+                    None if *to != TableState::Mutating
+                        && (referenced.is_some() || event.legacy) =>
+                    {
+                        self.record_write(event, event.envelope.actor.clone());
+                    }
+                    None if unreferenced_legacy => {
+                        self.record_write(event, event.envelope.actor.clone());
+                        self.mutation = Some(Self::open_implicit(event));
+                    }
+                    Some(open) if open.implicit && unreferenced_legacy => {
+                        self.record_write(event, event.envelope.actor.clone());
+                        self.mutation = None;
+                    }
+                    _ => {
+                        self.check_reference(event)?;
+                        if let Some(payload) = event.payload.typed() {
+                            self.model.pending.push(payload.clone());
+                        }
+                    }
+                }
+            }
+
+            MutationCommitted => {
+                self.check_reference(event)?;
+                let writer = self.mutation.take().map(|m| m.writer);
+                for payload in std::mem::take(&mut self.model.pending) {
+                    self.model.apply(&payload);
+                }
+                self.record_write(event, writer);
+            }
+            MutationAborted => {
+                self.check_reference(event)?;
+                self.mutation = None;
+                self.model.pending.clear();
+            }
+
+            // An open mutation is abandoned
+            RollbackStarted => {
+                self.rollback_target = self.check_rollback(event)?;
+                self.mutation = None;
+                self.model.pending.clear();
+            }
+            RollbackCompleted => {
+                self.check_rollback(event)?;
+                self.rollback_target = None;
+                self.record_write(event, event.envelope.actor.clone());
+            }
+
+            RollbackFailed | TableFrozen | TableUnfrozen => {}
+
+            TableDeprecated => {
+                self.allowed_engines = Some(match event.payload.typed() {
+                    Some(TypedPayload::TableDeprecated(p)) => p.allowed_engines.clone(),
+                    _ => Vec::new(),
+                });
+            }
+
+            TableDropped => {
+                let grace_period_ms = match event.payload.typed() {
+                    Some(TypedPayload::TableDropped(p)) => p.grace_period_ms,
+                    _ => 0,
+                };
+                self.undrop_until = event
+                    .envelope
+                    .committed_at
+                    .map(|at| at.saturating_add(grace_period_ms));
+            }
+
+            TableUndropped => {
+                let within_grace = matches!(
+                    (event.envelope.committed_at, self.undrop_until),
+                    (Some(at), Some(until)) if at <= until
+                );
+                if !within_grace {
+                    return Err(StateError::GracePeriodExpired {
+                        version: event.version,
+                    });
+                }
+                self.undrop_until = None;
+            }
+        }
+
         Ok(())
     }
 
     /// Get the current derived state.
     pub fn current_state(&self) -> &TableState {
-        &self.state
+        &self.model.state
+    }
+
+    /// Get the derived table model.
+    pub fn model(&self) -> &TableModel {
+        &self.model
+    }
+
+    /// The mutation in progress, if the table is `Mutating`.
+    pub fn open_mutation(&self) -> Option<&OpenMutation> {
+        self.mutation.as_ref()
+    }
+
+    /// Engines that may still write, if the table was deprecated.
+    pub fn allowed_engines(&self) -> Option<&[EngineKind]> {
+        self.allowed_engines.as_deref()
+    }
+
+    /// A rollback's target must be live and, once the rollback started,
+    /// stay the same. Returns the target, if the payload names one.
+    fn check_rollback(&self, event: &TableEvent) -> Result<Option<i64>, StateError> {
+        let target = match event.payload.typed() {
+            Some(TypedPayload::RollbackStarted(p) | TypedPayload::RollbackCompleted(p)) => {
+                p.target_snapshot_id
+            }
+            _ => return Ok(None),
+        };
+        let reject = |reason: String| StateError::InvalidRollback {
+            version: event.version,
+            target,
+            reason,
+        };
+
+        if !self.model.snapshots.contains_key(&target) {
+            return Err(reject("the snapshot is not live".into()));
+        }
+        if event.event_type == EventType::RollbackCompleted {
+            if let Some(started) = self.rollback_target.filter(|&s| s != target) {
+                return Err(reject(format!(
+                    "the rollback started for snapshot {started}"
+                )));
+            }
+        }
+        Ok(Some(target))
+    }
+
+    /// Fold a committed event's payload into the model and remember who
+    /// wrote it.
+    fn record_write(&mut self, event: &TableEvent, actor: Option<ActorId>) {
+        if let Some(payload) = event.payload.typed() {
+            self.model.apply(payload);
+        }
+        self.model.last_writer = Some(Writer {
+            actor,
+            engine: event.envelope.engine.clone(),
+            version: event.version,
+            committed_at: event.envelope.committed_at,
+        });
+    }
+
+    /// State a table settles in when nothing is in progress.
+    fn idle(&self) -> TableState {
+        match self.allowed_engines {
+            Some(_) => TableState::Deprecated,
+            None => TableState::Active,
+        }
+    }
+
+    /// Writes to a deprecated table must come from an allow-listed engine.
+    fn check_writer(&self, event: &TableEvent) -> Result<(), StateError> {
+        let Some(allowed) = &self.allowed_engines else {
+            return Ok(());
+        };
+        let engine = event.envelope.engine.as_ref();
+
+        if !is_write(&event.event_type) || engine.is_some_and(|e| allowed.contains(e)) {
+            return Ok(());
+        }
+        Err(StateError::EngineNotAllowed {
+            event_type: event.event_type.clone(),
+            version: event.version,
+            engine: engine.cloned(),
+        })
+    }
+
+    fn open(event: &TableEvent) -> Result<OpenMutation, StateError> {
+        match event.payload.typed() {
+            Some(TypedPayload::MutationStarted(p)) => Ok(OpenMutation {
+                mutation_id: p.mutation_id.clone(),
+                kind: p.kind,
+                writer: p.writer.clone(),
+                started_at: event.version,
+                implicit: false,
+            }),
+            _ => Err(StateError::IllegalTransition(format!(
+                "MutationStarted at version {} has no mutation payload",
+                event.version
+            ))),
+        }
+    }
+
+    /// Mutation opened by a change of a log written before mutations were
+    /// explicit, named after the change's version.
+    fn open_implicit(event: &TableEvent) -> OpenMutation {
+        let kind = match event.event_type {
+            EventType::SchemaUpdated => MutationKind::SchemaChange,
+            EventType::SnapshotRemoved => MutationKind::Expire,
+            EventType::PropertiesUpdated => MutationKind::UpdateProperties,
+            _ => MutationKind::Append,
+        };
+        OpenMutation {
+            mutation_id: format!("implicit-{}", event.version),
+            kind,
+            writer: event.envelope.actor.clone().unwrap_or_default(),
+            started_at: event.version,
+            implicit: true,
+        }
+    }
+
+    fn check_reference(&self, event: &TableEvent) -> Result<(), StateError> {
+        let open = self.mutation.as_ref().map(|m| m.mutation_id.as_str());
+        let referenced = event.payload.typed().and_then(TypedPayload::mutation_id);
+
+        if referenced.is_some() && referenced == open {
+            return Ok(());
+        }
+        Err(StateError::ForeignMutation {
+            event_type: event.event_type.clone(),
+            version: event.version,
+            referenced: referenced.map(Into::into),
+            open: open.map(Into::into),
+        })
     }
 }
 
+/// Events that change a table's data or metadata, as opposed to those
+/// that govern it.
+fn is_write(event_type: &EventType) -> bool {
+    use EventType::*;
+
+    matches!(
+        event_type,
+        MutationStarted
+            | MutationCommitted
+            | MutationAborted
+            | SchemaUpdated
+            | SnapshotAdded
+            | SnapshotRemoved
+            | PropertiesUpdated
+            | RollbackStarted
+            | RollbackCompleted
+            | RollbackFailed
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::log::{EventType, TableEvent, TableId};
+    use crate::log::payload::{
+        MutationEndedPayload, MutationStartedPayload, RollbackPayload, SnapshotAddedPayload,
+        SnapshotOperation, TableDeprecatedPayload, TableDroppedPayload,
+    };
+    use crate::log::{EventEnvelope, EventPayload, EventType, TableEvent, TableId};
     use uuid::Uuid;
 
     fn event(event_type: EventType) -> TableEvent {
-        TableEvent {
-            table_id: TableId(Uuid::new_v4()),
-            version: 1,
-            event_type,
-            payload: vec![],
-        }
+        event_in("m-1", event_type)
+    }
+
+    /// An event that belongs to mutation `mutation_id`, where applicable.
+    fn event_in(mutation_id: &str, event_type: EventType) -> TableEvent {
+        let ended = || MutationEndedPayload {
+            mutation_id: mutation_id.into(),
+            reason: None,
+        };
+        let payload: EventPayload = match event_type {
+            EventType::MutationStarted => TypedPayload::MutationStarted(MutationStartedPayload {
+                mutation_id: mutation_id.into(),
+                kind: MutationKind::Append,
+                writer: ActorId("spark".into()),
+            })
+            .into(),
+            EventType::MutationCommitted => TypedPayload::MutationCommitted(ended()).into(),
+            EventType::MutationAborted => TypedPayload::MutationAborted(ended()).into(),
+            EventType::SnapshotAdded => TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id: 1,
+                parent_snapshot_id: None,
+                operation: SnapshotOperation::Append,
+                schema_id: None,
+                mutation_id: Some(mutation_id.into()),
+                branch: None,
+            })
+            .into(),
+            _ => vec![].into(),
+        };
+        TableEvent::new(TableId(Uuid::new_v4()), 1, event_type, payload)
     }
 
     #[test]
@@ -100,11 +544,366 @@ mod tests {
         sm.apply(&event(EventType::TableCreated)).unwrap();
         assert_eq!(sm.current_state(), &TableState::Active);
 
-        sm.apply(&event(EventType::SchemaUpdated)).unwrap();
+        sm.apply(&event(EventType::MutationStarted)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Mutating);
+        assert_eq!(sm.open_mutation().unwrap().mutation_id, "m-1");
+
+        sm.apply(&event(EventType::SnapshotAdded)).unwrap();
+        sm.apply(&event(EventType::SnapshotAdded)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Mutating);
+
+        sm.apply(&event(EventType::MutationCommitted)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Active);
+        assert_eq!(sm.open_mutation(), None);
+
+        sm.apply(&event_in("m-2", EventType::MutationStarted))
+            .unwrap();
+        sm.apply(&event_in("m-2", EventType::MutationAborted))
+            .unwrap();
+        assert_eq!(sm.current_state(), &TableState::Active);
+    }
+
+    #[test]
+    fn changes_must_reference_the_open_mutation() {
+        let mut sm = TableStateMachine::new();
+        sm.apply(&event(EventType::TableCreated)).unwrap();
+
+        // Outside a mutation
+        assert!(sm.apply(&event(EventType::SnapshotAdded)).is_err());
+
+        sm.apply(&event(EventType::MutationStarted)).unwrap();
+
+        // Mutations do not nest
+        assert!(sm
+            .apply(&event_in("m-2", EventType::MutationStarted))
+            .is_err());
+
+        assert_eq!(
+            sm.apply(&event_in("m-2", EventType::SnapshotAdded)),
+            Err(StateError::ForeignMutation {
+                event_type: EventType::SnapshotAdded,
+                version: 1,
+                referenced: Some("m-2".into()),
+                open: Some("m-1".into()),
+            })
+        );
+        assert!(sm
+            .apply(&event_in("m-2", EventType::MutationCommitted))
+            .is_err());
+
+        // Opaque payloads cannot reference a mutation
+        assert!(matches!(
+            sm.apply(&event(EventType::SchemaUpdated)),
+            Err(StateError::ForeignMutation {
+                referenced: None,
+                ..
+            })
+        ));
+
+        assert_eq!(sm.current_state(), &TableState::Mutating);
+    }
+
+    #[test]
+    fn toggle_pairs_of_old_logs_are_implicit_mutations() {
+        let unreferenced = |snapshot_id| {
+            let payload: EventPayload = TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id,
+                parent_snapshot_id: None,
+                operation: SnapshotOperation::Append,
+                schema_id: None,
+                mutation_id: None,
+                branch: None,
+            })
+            .into();
+            let table_id = TableId(Uuid::new_v4());
+            TableEvent::new(table_id, 2, EventType::SnapshotAdded, payload)
+        };
+        let legacy = |snapshot_id| TableEvent {
+            legacy: true,
+            ..unreferenced(snapshot_id)
+        };
+        let mut sm = TableStateMachine::new();
+        sm.apply(&event(EventType::TableCreated)).unwrap();
+
+        // Only changes of old logs may leave out the mutation
+        assert!(matches!(
+            sm.apply(&unreferenced(1)),
+            Err(StateError::ForeignMutation {
+                referenced: None,
+                open: None,
+                ..
+            })
+        ));
+
+        sm.apply(&legacy(1)).unwrap();
         assert_eq!(sm.current_state(), &TableState::Mutating);
+        assert_eq!(sm.open_mutation().unwrap().mutation_id, "implicit-2");
+        assert!(sm.open_mutation().unwrap().implicit);
+        assert_eq!(sm.model().current_snapshot_id(), Some(1));
+
+        // Explicit mutations cannot be opened inside an implicit one
+        assert!(sm.apply(&event(EventType::MutationStarted)).is_err());
+
+        assert!(sm.apply(&unreferenced(2)).is_err());
+        sm.apply(&legacy(2)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Active);
+        assert_eq!(sm.open_mutation(), None);
+        assert_eq!(sm.model().current_snapshot_id(), Some(2));
+        assert!(sm.model().pending.is_empty());
+    }
+
+    #[test]
+    fn rollback_lifecycle() {
+        let mut sm = TableStateMachine::new();
+        sm.apply(&event(EventType::TableCreated)).unwrap();
+        sm.apply(&event(EventType::MutationStarted)).unwrap();
+
+        sm.apply(&event(EventType::RollbackStarted)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::RollingBack);
+        assert_eq!(sm.open_mutation(), None);
+
+        sm.apply(&event(EventType::RollbackFailed)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::RollingBack);
 
+        // Nothing but the rollback may touch the table meanwhile.
+        assert!(sm.apply(&event(EventType::SnapshotAdded)).is_err());
+        assert!(sm.apply(&event(EventType::MutationStarted)).is_err());
+
+        sm.apply(&event(EventType::RollbackStarted)).unwrap();
+        sm.apply(&event(EventType::RollbackCompleted)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Active);
+
+        assert!(sm.apply(&event(EventType::RollbackCompleted)).is_err());
+    }
+
+    #[test]
+    fn rollbacks_target_the_live_snapshot_they_started_for() {
+        let rollback = |event_type, target_snapshot_id| {
+            let payload = RollbackPayload {
+                target_snapshot_id,
+                reason: None,
+            };
+            let payload: EventPayload = match event_type {
+                EventType::RollbackStarted => TypedPayload::RollbackStarted(payload),
+                _ => TypedPayload::RollbackCompleted(payload),
+            }
+            .into();
+            TableEvent::new(TableId(Uuid::new_v4()), 5, event_type, payload)
+        };
+        let mut sm = TableStateMachine::new();
+        sm.apply(&event(EventType::TableCreated)).unwrap();
+        sm.apply(&event(EventType::MutationStarted)).unwrap();
         sm.apply(&event(EventType::SnapshotAdded)).unwrap();
+        sm.apply(&event(EventType::MutationCommitted)).unwrap();
+
+        assert_eq!(
+            sm.apply(&rollback(EventType::RollbackStarted, 7)),
+            Err(StateError::InvalidRollback {
+                version: 5,
+                target: 7,
+                reason: "the snapshot is not live".into(),
+            })
+        );
+
+        sm.apply(&rollback(EventType::RollbackStarted, 1)).unwrap();
+        assert!(matches!(
+            sm.apply(&rollback(EventType::RollbackCompleted, 7)),
+            Err(StateError::InvalidRollback { target: 7, .. })
+        ));
+        assert_eq!(sm.current_state(), &TableState::RollingBack);
+
+        sm.apply(&rollback(EventType::RollbackCompleted, 1))
+            .unwrap();
+        assert_eq!(sm.current_state(), &TableState::Active);
+        assert_eq!(sm.model().current_snapshot_id(), Some(1));
+    }
+
+    /// `event` as written by `engine` at `committed_at`.
+    fn written(event: TableEvent, engine: EngineKind, committed_at: u64) -> TableEvent {
+        event.with_envelope(EventEnvelope {
+            engine: Some(engine),
+            committed_at: Some(committed_at),
+            ..Default::default()
+        })
+    }
+
+    fn active() -> TableStateMachine {
+        TableStateMachine::resume(TableState::Active, None)
+    }
+
+    #[test]
+    fn frozen_tables_reject_writes_until_unfrozen() {
+        let mut sm = active();
+        sm.apply(&event(EventType::TableFrozen)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Frozen);
+
+        for event_type in [
+            EventType::MutationStarted,
+            EventType::RollbackStarted,
+            EventType::TableDropped,
+        ] {
+            assert_eq!(
+                sm.apply(&event(event_type.clone())),
+                Err(StateError::ReadOnly {
+                    state: TableState::Frozen,
+                    event_type,
+                    version: 1,
+                })
+            );
+        }
+
+        sm.apply(&event(EventType::TableUnfrozen)).unwrap();
+        sm.apply(&event(EventType::MutationStarted)).unwrap();
+
+        // An open mutation has to finish before the table can be frozen.
+        assert!(sm.apply(&event(EventType::TableFrozen)).is_err());
+    }
+
+    #[test]
+    fn deprecated_tables_only_accept_allow_listed_engines() {
+        let mut sm = active();
+        let deprecated = TableEvent::new(
+            TableId(Uuid::new_v4()),
+            1,
+            EventType::TableDeprecated,
+            TypedPayload::TableDeprecated(TableDeprecatedPayload {
+                allowed_engines: vec![EngineKind::Spark],
+                reason: None,
+            }),
+        );
+        sm.apply(&deprecated).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Deprecated);
+        assert_eq!(sm.allowed_engines(), Some(&[EngineKind::Spark][..]));
+
+        let by = |engine, event_type| written(event(event_type), engine, 0);
+        assert!(matches!(
+            sm.apply(&by(EngineKind::Flink, EventType::MutationStarted)),
+            Err(StateError::EngineNotAllowed {
+                engine: Some(EngineKind::Flink),
+                ..
+            })
+        ));
+        assert!(matches!(
+            sm.apply(&event(EventType::MutationStarted)),
+            Err(StateError::EngineNotAllowed { engine: None, .. })
+        ));
+
+        sm.apply(&by(EngineKind::Spark, EventType::MutationStarted))
+            .unwrap();
+        sm.apply(&by(EngineKind::Spark, EventType::SnapshotAdded))
+            .unwrap();
+        sm.apply(&by(EngineKind::Spark, EventType::MutationCommitted))
+            .unwrap();
+        assert_eq!(sm.current_state(), &TableState::Deprecated);
+
+        // The allow-list survives a checkpoint.
+        let resumed: TableStateMachine =
+            serde_json::from_value(serde_json::to_value(&sm).unwrap()).unwrap();
+        assert_eq!(resumed, sm);
+    }
+
+    #[test]
+    fn dropped_tables_can_only_be_undropped_within_the_grace_period() {
+        let drop = |grace_period_ms| {
+            let event = TableEvent::new(
+                TableId(Uuid::new_v4()),
+                1,
+                EventType::TableDropped,
+                TypedPayload::TableDropped(TableDroppedPayload {
+                    grace_period_ms,
+                    reason: None,
+                }),
+            );
+            written(event, EngineKind::Spark, 1_000)
+        };
+        let undrop_at = |at| written(event(EventType::TableUndropped), EngineKind::Spark, at);
+
+        let mut sm = active();
+        sm.apply(&drop(500)).unwrap();
+        assert_eq!(sm.current_state(), &TableState::Dropped);
+        assert!(matches!(
+            sm.apply(&event(EventType::MutationStarted)),
+            Err(StateError::ReadOnly { .. })
+        ));
+        sm.apply(&undrop_at(1_500)).unwrap();
         assert_eq!(sm.current_state(), &TableState::Active);
+
+        let mut sm = active();
+        sm.apply(&drop(500)).unwrap();
+        assert_eq!(
+            sm.apply(&undrop_at(1_501)),
+            Err(StateError::GracePeriodExpired { version: 1 })
+        );
+
+        // Without commit times the grace period cannot be checked.
+        let mut sm = active();
+        sm.apply(&drop(500)).unwrap();
+        assert!(sm.apply(&event(EventType::TableUndropped)).is_err());
+        assert_eq!(sm.current_state(), &TableState::Dropped);
+    }
+
+    #[test]
+    fn custom_lifecycles_decide_which_events_are_allowed() {
+        let scratch = Lifecycle::from_json(
+            &serde_json::json!({
+                "name": "scratch",
+                "states": ["Created", "Active", "Dropped"],
+                "events": ["TableCreated", "SnapshotAdded", "TableDropped"],
+                "transitions": [
+                    { "from": ["Created"], "event": "TableCreated", "to": "Active" },
+                    { "from": ["Active"], "event": "SnapshotAdded", "to": "Active" },
+                    { "from": ["Active"], "event": "TableDropped", "to": "Dropped" }
+                ],
+                "terminal": ["Dropped"]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let unreferenced = TableEvent::new(
+            TableId(Uuid::new_v4()),
+            1,
+            EventType::SnapshotAdded,
+            TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+                snapshot_id: 7,
+                parent_snapshot_id: None,
+                operation: SnapshotOperation::Append,
+                schema_id: None,
+                mutation_id: None,
+                branch: None,
+            }),
+        );
+
+        let mut sm = TableStateMachine::new();
+        sm.apply_in(&scratch, &event(EventType::TableCreated))
+            .unwrap();
+
+        // Without mutations, changes take effect at once, but still name
+        // the write they belong to
+        let transition = sm
+            .apply_in(&scratch, &event(EventType::SnapshotAdded))
+            .unwrap();
+        assert_eq!(transition.to, TableState::Active);
+        assert_eq!(sm.model().current_snapshot_id(), Some(1));
+        assert!(matches!(
+            sm.apply_in(&scratch, &unreferenced),
+            Err(StateError::ForeignMutation {
+                referenced: None,
+                open: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            sm.apply_in(&scratch, &event(EventType::MutationStarted)),
+            Err(StateError::IllegalTransition(_))
+        ));
+
+        sm.apply_in(&scratch, &event(EventType::TableDropped))
+            .unwrap();
+        assert!(matches!(
+            sm.apply_in(&scratch, &event(EventType::TableUndropped)),
+            Err(StateError::ReadOnly { .. })
+        ));
     }
 
     #[test]
@@ -112,7 +911,19 @@ mod tests {
         let mut sm = TableStateMachine::new();
 
         let err = sm.apply(&event(EventType::SchemaUpdated)).unwrap_err();
-
         assert!(matches!(err, StateError::IllegalTransition(_)));
+
+        // Opening a mutation needs its payload.
+        let mut sm = TableStateMachine::resume(TableState::Active, None);
+        let untyped = TableEvent::new(
+            TableId(Uuid::new_v4()),
+            1,
+            EventType::MutationStarted,
+            vec![],
+        );
+        assert!(matches!(
+            sm.apply(&untyped),
+            Err(StateError::IllegalTransition(_))
+        ));
     }
 }
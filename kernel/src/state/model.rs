@@ -0,0 +1,248 @@
+// Derived Table Model
+//
+// What replay knows about a table beyond its lifecycle state: the schema
+// in effect, the snapshots and their parents, branch refs, properties
+// and who last wrote to it. The model is built from typed payloads only;
+// opaque payloads change the state but not the model.
+//
+// Changes made inside a mutation are held back as `pending` and only
+// folded into the model when the mutation commits, so an aborted or
+// rolled back mutation leaves no trace.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::TableState;
+use crate::log::payload::{Schema, SnapshotOperation, TypedPayload, MAIN_BRANCH};
+use crate::log::{ActorId, EngineKind, Version};
+
+/// A snapshot committed to the table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub snapshot_id: i64,
+    pub parent_snapshot_id: Option<i64>,
+    pub operation: SnapshotOperation,
+
+    /// Schema the snapshot was written with.
+    pub schema_id: Option<i32>,
+}
+
+/// The writer of the latest committed change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Writer {
+    pub actor: Option<ActorId>,
+    pub engine: Option<EngineKind>,
+
+    /// Version of the event that committed the change.
+    pub version: Version,
+
+    /// Unix milliseconds.
+    pub committed_at: Option<u64>,
+}
+
+/// Table state and content derived by replay.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableModel {
+    pub state: TableState,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Schema>,
+
+    /// Live snapshots by id.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "snapshot_list"
+    )]
+    pub snapshots: BTreeMap<i64, SnapshotEntry>,
+
+    /// Current snapshot of each branch.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub refs: BTreeMap<String, i64>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_writer: Option<Writer>,
+
+    /// Changes of the open mutation, in commit order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<TypedPayload>,
+}
+
+impl TableModel {
+    /// Current snapshot of the main branch.
+    pub fn current_snapshot_id(&self) -> Option<i64> {
+        self.refs.get(MAIN_BRANCH).copied()
+    }
+
+    pub fn schema_id(&self) -> Option<i32> {
+        self.schema.as_ref().map(|schema| schema.schema_id)
+    }
+
+    /// `snapshot_id` followed by its live ancestors, newest first.
+    pub fn ancestry(&self, snapshot_id: i64) -> impl Iterator<Item = i64> + '_ {
+        let mut next = self
+            .snapshots
+            .contains_key(&snapshot_id)
+            .then_some(snapshot_id);
+        std::iter::from_fn(move || {
+            let id = next?;
+            next = self
+                .snapshots
+                .get(&id)
+                .and_then(|s| s.parent_snapshot_id)
+                .filter(|parent| self.snapshots.contains_key(parent));
+            Some(id)
+        })
+    }
+
+    /// The model as it will be if the open mutation commits.
+    pub fn staged(&self) -> TableModel {
+        let mut staged = self.clone();
+        for payload in std::mem::take(&mut staged.pending) {
+            staged.apply(&payload);
+        }
+        staged
+    }
+
+    /// Fold a committed change into the model.
+    pub(super) fn apply(&mut self, payload: &TypedPayload) {
+        match payload {
+            TypedPayload::TableCreated(p) => {
+                self.schema = Some(p.schema.clone());
+                self.properties = p.properties.clone();
+            }
+            TypedPayload::SchemaUpdated(p) => self.schema = Some(p.schema.clone()),
+            TypedPayload::SnapshotAdded(p) => {
+                self.snapshots.insert(
+                    p.snapshot_id,
+                    SnapshotEntry {
+                        snapshot_id: p.snapshot_id,
+                        parent_snapshot_id: p.parent_snapshot_id,
+                        operation: p.operation,
+                        schema_id: p.schema_id,
+                    },
+                );
+                self.refs.insert(p.branch().to_string(), p.snapshot_id);
+            }
+            TypedPayload::SnapshotRemoved(p) => {
+                for id in &p.snapshot_ids {
+                    self.snapshots.remove(id);
+                }
+                self.refs.retain(|_, id| !p.snapshot_ids.contains(id));
+            }
+            TypedPayload::PropertiesUpdated(p) => {
+                self.properties.extend(p.updates.clone());
+                for key in &p.removals {
+                    self.properties.remove(key);
+                }
+            }
+            TypedPayload::RollbackCompleted(p) => {
+                self.refs
+                    .insert(MAIN_BRANCH.to_string(), p.target_snapshot_id);
+            }
+            TypedPayload::MutationStarted(_)
+            | TypedPayload::MutationCommitted(_)
+            | TypedPayload::MutationAborted(_)
+            | TypedPayload::RollbackStarted(_)
+            | TypedPayload::RollbackFailed(_)
+            | TypedPayload::TableFrozen(_)
+            | TypedPayload::TableUnfrozen(_)
+            | TypedPayload::TableDeprecated(_)
+            | TypedPayload::TableDropped(_)
+            | TypedPayload::TableUndropped(_) => {}
+        }
+    }
+}
+
+/// Snapshots persist as a list: the model is flattened into checkpoints
+/// and compaction records, and flattened maps only have string keys.
+mod snapshot_list {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::SnapshotEntry;
+
+    pub fn serialize<S: Serializer>(
+        snapshots: &BTreeMap<i64, SnapshotEntry>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(snapshots.values())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<i64, SnapshotEntry>, D::Error> {
+        let entries = Vec::<SnapshotEntry>::deserialize(deserializer)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.snapshot_id, entry))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::payload::{
+        PropertiesUpdatedPayload, SnapshotAddedPayload, SnapshotRemovedPayload,
+    };
+
+    fn added(snapshot_id: i64, parent: Option<i64>, branch: Option<&str>) -> TypedPayload {
+        TypedPayload::SnapshotAdded(SnapshotAddedPayload {
+            snapshot_id,
+            parent_snapshot_id: parent,
+            operation: SnapshotOperation::Append,
+            schema_id: Some(0),
+            mutation_id: None,
+            branch: branch.map(Into::into),
+        })
+    }
+
+    #[test]
+    fn snapshots_refs_and_properties_are_tracked() {
+        let mut model = TableModel::default();
+        model.apply(&added(1, None, None));
+        model.apply(&added(2, Some(1), None));
+        model.apply(&added(3, Some(2), Some("audit")));
+        model.apply(&added(4, Some(2), None));
+
+        assert_eq!(model.current_snapshot_id(), Some(4));
+        assert_eq!(model.refs.get("audit"), Some(&3));
+        assert_eq!(model.ancestry(4).collect::<Vec<_>>(), vec![4, 2, 1]);
+
+        model.apply(&TypedPayload::SnapshotRemoved(SnapshotRemovedPayload {
+            snapshot_ids: vec![1, 3],
+            mutation_id: None,
+        }));
+        assert_eq!(model.ancestry(4).collect::<Vec<_>>(), vec![4, 2]);
+        assert_eq!(model.refs.get("audit"), None);
+        assert_eq!(model.ancestry(3).count(), 0);
+
+        model.apply(&TypedPayload::PropertiesUpdated(PropertiesUpdatedPayload {
+            updates: [("owner".to_string(), "etl".to_string())].into(),
+            removals: vec![],
+            mutation_id: None,
+        }));
+        assert_eq!(
+            model.properties.get("owner").map(String::as_str),
+            Some("etl")
+        );
+    }
+
+    #[test]
+    fn staged_applies_pending_changes() {
+        let mut model = TableModel::default();
+        model.apply(&added(1, None, None));
+        model.pending.push(added(2, Some(1), None));
+
+        assert_eq!(model.current_snapshot_id(), Some(1));
+        let staged = model.staged();
+        assert_eq!(staged.current_snapshot_id(), Some(2));
+        assert!(staged.pending.is_empty());
+    }
+}
@@ -55,7 +55,7 @@ pub fn evaluate_drift_policy_with_config(
     let mut decisions = Vec::new();
 
     for finding in &report.findings {
-        if let Some(rule) = config.rules.iter().find(|r| r.severity == finding.severity) {
+        if let Some(rule) = config.rules.iter().find(|r| r.matches(finding)) {
             decisions.push(PolicyDecision {
                 severity: finding.severity.clone(),
                 action: rule.action.clone(),
@@ -98,6 +98,26 @@ mod tests {
         assert_eq!(plan.decisions[1].action, IntendedAction::Enforce);
     }
 
+    #[test]
+    fn drift_type_rules_take_precedence() {
+        let finding = |drift_type| DriftFinding {
+            drift_type,
+            severity: DriftSeverity::Info,
+            message: "info".into(),
+        };
+        let report = DriftReport {
+            findings: vec![
+                finding(DriftType::RollbackInProgress),
+                finding(DriftType::SnapshotMismatch),
+            ],
+        };
+
+        let plan = evaluate_drift_policy_with_config(&report, &PolicyConfig::default_policy());
+        assert_eq!(plan.decisions[0].action, IntendedAction::Alert);
+        assert_eq!(plan.decisions[0].reason, "rollback in progress");
+        assert_eq!(plan.decisions[1].action, IntendedAction::Observe);
+    }
+
     #[test]
     fn empty_report_produces_empty_plan() {
         let report = DriftReport { findings: vec![] };
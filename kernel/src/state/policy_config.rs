@@ -5,7 +5,7 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::state::drift::DriftSeverity;
+use crate::state::drift::{DriftFinding, DriftSeverity, DriftType};
 use crate::state::policy::IntendedAction;
 
 /// Policy configuration loaded from JSON/YAML.
@@ -14,13 +14,29 @@ pub struct PolicyConfig {
     pub rules: Vec<PolicyRule>,
 }
 
+/// Maps findings to an action. The first matching rule wins.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PolicyRule {
     pub severity: DriftSeverity,
+
+    /// Restricts the rule to one kind of drift; `None` matches any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drift_type: Option<DriftType>,
+
     pub action: IntendedAction,
     pub reason: String,
 }
 
+impl PolicyRule {
+    pub fn matches(&self, finding: &DriftFinding) -> bool {
+        self.severity == finding.severity
+            && self
+                .drift_type
+                .as_ref()
+                .is_none_or(|drift_type| *drift_type == finding.drift_type)
+    }
+}
+
 impl PolicyConfig {
     /// Default built-in policy (used if no config is provided).
     pub fn default_policy() -> Self {
@@ -28,16 +44,25 @@ impl PolicyConfig {
             rules: vec![
                 PolicyRule {
                     severity: DriftSeverity::Info,
+                    drift_type: Some(DriftType::RollbackInProgress),
+                    action: IntendedAction::Alert,
+                    reason: "rollback in progress".into(),
+                },
+                PolicyRule {
+                    severity: DriftSeverity::Info,
+                    drift_type: None,
                     action: IntendedAction::Observe,
                     reason: "informational drift".into(),
                 },
                 PolicyRule {
                     severity: DriftSeverity::Warning,
+                    drift_type: None,
                     action: IntendedAction::Alert,
                     reason: "warning-level drift".into(),
                 },
                 PolicyRule {
                     severity: DriftSeverity::Critical,
+                    drift_type: None,
                     action: IntendedAction::Enforce,
                     reason: "critical drift".into(),
                 },